@@ -17,7 +17,7 @@
 
 //! Initial configuration for a PBFT node
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 
 use hex;
@@ -27,6 +27,41 @@ use sawtooth_sdk::consensus::{
 };
 use serde_json;
 
+use crate::error::PbftError;
+use crate::hash::{self, HashAlgorithm};
+use crate::signing;
+
+/// Setting that lists the PBFT peers, unless another one is configured
+pub const DEFAULT_PEERS_SETTING_KEY: &str = "sawtooth.consensus.pbft.peers";
+
+/// Setting that gives the peers' weights, for weighted quorums
+pub const PEER_WEIGHTS_SETTING_KEY: &str = "sawtooth.consensus.pbft.peer_weights";
+
+/// Setting that lists the peers that are observers, which don't vote
+pub const OBSERVERS_SETTING_KEY: &str = "sawtooth.consensus.pbft.observers";
+
+/// How long a node may stay in each phase before it's considered stuck
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseTimeouts {
+    pub pre_preparing: Duration,
+    pub preparing: Duration,
+    pub checking: Duration,
+    pub committing: Duration,
+    pub finished: Duration,
+}
+
+impl Default for PhaseTimeouts {
+    fn default() -> Self {
+        PhaseTimeouts {
+            pre_preparing: Duration::from_secs(30),
+            preparing: Duration::from_secs(10),
+            checking: Duration::from_secs(10),
+            committing: Duration::from_secs(20),
+            finished: Duration::from_secs(10),
+        }
+    }
+}
+
 /// Contains the initial configuration loaded from on-chain settings, if present, or defaults in
 /// their absence.
 #[derive(Debug)]
@@ -34,6 +69,10 @@ pub struct PbftConfig {
     // Peers that this node is connected to
     pub peers: Vec<PeerId>,
 
+    /// On-chain setting that the peers are read from, both at startup and when checking for
+    /// membership changes and consensus seals
+    pub peers_setting_key: String,
+
     /// How long to wait in between trying to publish blocks
     pub block_duration: Duration,
 
@@ -44,68 +83,515 @@ pub struct PbftConfig {
     /// Should be longer than block_duration
     pub faulty_primary_timeout: Duration,
 
-    /// How many blocks to commit before forcing a view change
+    /// How much longer to make the faulty primary timeout after each view change that doesn't
+    /// lead to a committed block (1 disables the backoff)
+    pub view_change_backoff_factor: u32,
+
+    /// The longest the faulty primary timeout may grow to through view change backoff
+    pub max_faulty_primary_timeout: Duration,
+
+    /// How long to wait for the working block to be committed before determining the network is
+    /// stuck and starting a view change
+    pub commit_timeout: Duration,
+
+    /// How long to wait for a view change to complete before giving up on the target view and
+    /// trying the next one; the wait grows with each view skipped
+    pub view_change_duration: Duration,
+
+    /// Shortest time the primary waits after publishing a block before it publishes another;
+    /// must be less than `faulty_primary_timeout`
+    pub min_block_publish_interval: Duration,
+
+    /// How many blocks to commit before forcing a view change (0 never forces one, keeping the
+    /// primary stable)
     pub forced_view_change_period: u64,
 
+    /// How many blocks each node's forced view changes may be shifted by, so that they don't all
+    /// happen at the same sequence number; each node derives its own shift from its ID (0
+    /// disables the jitter)
+    pub forced_view_change_jitter: u64,
+
+    /// How many `ViewChange` messages for a later view make this node join the view change before
+    /// its own timers expire; must be at least `f + 1`, so that at least one of them is from an
+    /// honest node (0 uses `f + 1`)
+    pub view_change_join_threshold: u64,
+
+    /// How many views past the one the last committed block was committed in a view change may
+    /// go before it's reported as a fault; the node still follows the view change (0 disables the
+    /// check)
+    pub max_view_jump: u64,
+
     /// How large the PbftLog is allowed to get
     pub max_log_size: u64,
 
+    /// Absolute limit on the number of messages in the log, in case garbage collection falls
+    /// behind (for instance, because no blocks are being committed); past it, the messages farthest
+    /// from the current sequence number and view that aren't still needed are evicted
+    pub max_log_messages: u64,
+
+    /// Maximum number of messages waiting in the backlog; once it's full, the oldest messages are
+    /// dropped to make room for new ones
+    pub max_backlog_size: u64,
+
+    /// How far past the current sequence number a message may be and still be added to the log;
+    /// messages further ahead are rejected, so that peers can't exhaust memory (0 disables the
+    /// limit)
+    pub max_future_seq_distance: u64,
+
+    /// How many of the most recently committed sequence numbers to keep messages and seals for
+    /// when garbage collecting the log
+    pub gc_retain_depth: u64,
+
+    /// How often, in committed blocks, to broadcast a `Checkpoint`. When set, garbage collection
+    /// never removes messages or seals at or above the latest stable checkpoint. (0 disables
+    /// checkpoints)
+    pub checkpoint_period: u64,
+
+    /// How many sequence numbers past the last stable checkpoint (the low watermark) messages and
+    /// blocks are accepted for; the window moves forward as checkpoints become stable. Must be at
+    /// least `checkpoint_period` when set. (0 disables watermarks)
+    pub watermark_window: u64,
+
+    /// How far past the current sequence number a new block may be for its consensus seal to be
+    /// verified as soon as it arrives; seals of blocks further ahead are verified once the node
+    /// gets this close to them. (0 verifies every block's seal right away)
+    pub seal_prefetch_depth: u64,
+
+    /// Most blocks a node that has fallen behind commits, or asks the validator for, at once while
+    /// catching up, before going back to handling messages; the rest are handled on later passes
+    /// of the engine loop. (0 handles them all at once)
+    pub max_catchup_blocks_per_loop: u64,
+
+    /// First block that carries a consensus seal; blocks below it are published and accepted
+    /// without one, and only block 1 has to have an empty payload. The default of 2 leaves block 1
+    /// unsealed, since nobody votes on the genesis block; chains that start sealed (such as ones
+    /// joined partway through) can lower it to 1.
+    pub first_sealed_block: u64,
+
+    /// View the network starts in, which picks the first primary; a network that's restarted can
+    /// use it to resume at the view it was last in, or to have a different node lead right away
+    pub initial_view: u64,
+
     /// Where to store PbftState
     pub storage: String,
+
+    /// How many completed view changes a node may be the target of before it's reported as
+    /// chronically faulty (0 disables the report)
+    ///
+    /// Each node counts strikes from the view changes it saw itself, so nodes can disagree on
+    /// them; they're left out of primary rotation, which every node has to agree on. To take a
+    /// faulty node out of rotation, add it to `primary_ineligible_peers`.
+    pub faulty_primary_strike_threshold: u64,
+
+    /// Peers that vote and count toward `f`, but are never made primary
+    pub primary_ineligible_peers: Vec<PeerId>,
+
+    /// Peers that follow consensus without voting; they don't count toward `f` and are never made
+    /// primary
+    pub observers: Vec<PeerId>,
+
+    /// Voting weight of each peer (for instance, its stake); if set, quorums are measured by the
+    /// total weight of the votes instead of by how many nodes cast them
+    pub peer_weights: HashMap<PeerId, u64>,
+
+    /// How many threads may be verifying consensus seal votes at once; if this is less than
+    /// `vote_verification_threads`, the extra threads wait for a slot
+    pub max_concurrent_verifications: usize,
+
+    /// How many threads verify the votes of a single consensus seal
+    pub vote_verification_threads: usize,
+
+    /// How long to stay in each phase before the phase watchdog reports the node as stuck
+    pub phase_timeouts: PhaseTimeouts,
+
+    /// Identifier of the PBFT network; messages tagged with a different one are rejected
+    pub network_id: String,
+
+    /// How old a message's timestamp may be before the message is rejected (0 disables the check)
+    pub max_message_age: Duration,
+
+    /// How far apart this node's clock and a message sender's clock may be
+    pub max_clock_skew: Duration,
+
+    /// Allow a network made up of just this node, with consensus effectively disabled. INSECURE;
+    /// only meant for development.
+    pub allow_single_node: bool,
+
+    /// Whether to check the signatures of the votes in consensus seals. Turning this off is
+    /// UNSAFE outside of trusted test or benchmarking networks.
+    pub verify_signatures: bool,
+
+    /// Name of the algorithm the votes in consensus seals are signed with
+    pub signing_algorithm: String,
+
+    /// Name of the digest that a vote's header holds of the vote's message. Whatever it is, the
+    /// digest is kept in the header's `content_sha512` field, so the validator must be configured
+    /// with the same algorithm.
+    pub hash_algorithm: String,
+
+    /// How many times the wait between reads of the on-chain settings is doubled while they keep
+    /// failing
+    pub settings_retries: u32,
+
+    /// How long to wait before retrying a failed settings read; doubled after each failure, up to
+    /// `settings_retries` times
+    pub settings_retry_delay: Duration,
+
+    /// Only follow the chain and verify the consensus seal of every block, without ever sending
+    /// consensus messages, building blocks, or becoming primary. This is chosen per node (see
+    /// `PbftEngine::set_verify_only`), so it isn't an on-chain setting; a verify-only node isn't
+    /// meant to be one of the peers.
+    pub verify_only: bool,
 }
 
 impl PbftConfig {
     pub fn default() -> Self {
         PbftConfig {
             peers: Vec::new(),
+            peers_setting_key: DEFAULT_PEERS_SETTING_KEY.into(),
             block_duration: Duration::from_millis(200),
             message_timeout: Duration::from_millis(10),
             faulty_primary_timeout: Duration::from_secs(30),
+            view_change_backoff_factor: 2,
+            max_faulty_primary_timeout: Duration::from_secs(300),
+            commit_timeout: Duration::from_secs(30),
+            view_change_duration: Duration::from_secs(5),
+            min_block_publish_interval: Duration::from_millis(0),
             forced_view_change_period: 30,
+            forced_view_change_jitter: 0,
+            view_change_join_threshold: 0,
+            max_view_jump: 0,
             max_log_size: 1000,
+            max_log_messages: 10000,
+            max_backlog_size: 1000,
+            max_future_seq_distance: 10,
+            gc_retain_depth: 1,
+            checkpoint_period: 0,
+            watermark_window: 0,
+            seal_prefetch_depth: 0,
+            max_catchup_blocks_per_loop: 100,
+            first_sealed_block: 2,
+            initial_view: 0,
             storage: "memory".into(),
+            faulty_primary_strike_threshold: 0,
+            primary_ineligible_peers: Vec::new(),
+            observers: Vec::new(),
+            peer_weights: HashMap::new(),
+            max_concurrent_verifications: 4,
+            vote_verification_threads: 4,
+            phase_timeouts: PhaseTimeouts::default(),
+            network_id: String::new(),
+            verify_signatures: true,
+            signing_algorithm: signing::DEFAULT_SIGNING_ALGORITHM.into(),
+            hash_algorithm: hash::DEFAULT_HASH_ALGORITHM.into(),
+            max_message_age: Duration::from_millis(0),
+            max_clock_skew: Duration::from_secs(5),
+            allow_single_node: false,
+            settings_retries: 3,
+            settings_retry_delay: Duration::from_millis(100),
+            verify_only: false,
         }
     }
+
+    /// Check that the configuration is usable
+    ///
+    /// Rejects peer lists that contain the same `PeerId` more than once, since duplicates would
+    /// skew the computation of `f` and all of the quorum math that depends on it.
+    pub fn validate(&self) -> Result<(), PbftError> {
+        let mut seen = HashSet::new();
+        if let Some(dup) = self.peers.iter().find(|id| !seen.insert(*id)) {
+            return Err(PbftError::InvalidConfig(format!(
+                "Peer {} is listed more than once",
+                hex::encode(dup)
+            )));
+        }
+
+        // Messages for the last committed block are needed to build the next consensus seal
+        if self.gc_retain_depth == 0 {
+            return Err(PbftError::InvalidConfig(
+                "Garbage collection must retain at least the last committed block".into(),
+            ));
+        }
+
+        if let Some(id) = self.observers.iter().find(|id| !self.peers.contains(id)) {
+            return Err(PbftError::InvalidConfig(format!(
+                "Observer {} isn't a peer",
+                hex::encode(id)
+            )));
+        }
+
+        if !self.peers.is_empty()
+            && self
+                .peers
+                .iter()
+                .filter(|id| !self.observers.contains(id))
+                .all(|id| self.primary_ineligible_peers.contains(id))
+        {
+            return Err(PbftError::InvalidConfig(
+                "At least one voting peer must be eligible to be primary".into(),
+            ));
+        }
+
+        check_peer_weights(&self.peer_weights, &self.peers).map_err(PbftError::InvalidConfig)?;
+
+        signing::verifier_for(&self.signing_algorithm)?;
+        HashAlgorithm::from_name(&self.hash_algorithm)?;
+
+        if self.forced_view_change_period > 0
+            && self.forced_view_change_jitter >= self.forced_view_change_period
+        {
+            return Err(PbftError::InvalidConfig(
+                "Forced view change jitter must be less than the forced view change period".into(),
+            ));
+        }
+
+        // The low watermark only moves when a checkpoint becomes stable, so the window has to
+        // reach at least as far as the next checkpoint
+        if self.watermark_window > 0
+            && (self.checkpoint_period == 0 || self.watermark_window < self.checkpoint_period)
+        {
+            return Err(PbftError::InvalidConfig(format!(
+                "Watermark window ({}) needs checkpoints and must be at least the checkpoint \
+                 period ({})",
+                self.watermark_window, self.checkpoint_period
+            )));
+        }
+
+        if self.view_change_join_threshold > 0 {
+            let voting_peers = self
+                .peers
+                .iter()
+                .filter(|id| !self.observers.contains(id))
+                .count() as u64;
+            let f = voting_peers.saturating_sub(1) / 3;
+            if self.view_change_join_threshold < f + 1 {
+                return Err(PbftError::InvalidConfig(format!(
+                    "View change join threshold ({}) must be at least f + 1 ({})",
+                    self.view_change_join_threshold,
+                    f + 1
+                )));
+            }
+        }
+
+        if self.first_sealed_block == 0 {
+            return Err(PbftError::InvalidConfig(
+                "The genesis block can't carry a consensus seal".into(),
+            ));
+        }
+
+        if self.max_concurrent_verifications == 0 {
+            return Err(PbftError::InvalidConfig(
+                "At least one concurrent verification must be allowed".into(),
+            ));
+        }
+
+        if self.max_log_messages < self.max_log_size {
+            return Err(PbftError::InvalidConfig(
+                "The message limit must be at least the log size that triggers garbage collection"
+                    .into(),
+            ));
+        }
+
+        if self.max_backlog_size == 0 {
+            return Err(PbftError::InvalidConfig(
+                "The backlog must have room for at least one message".into(),
+            ));
+        }
+
+        if self.vote_verification_threads == 0 {
+            return Err(PbftError::InvalidConfig(
+                "At least one vote verification thread is needed".into(),
+            ));
+        }
+
+        if self.block_duration >= self.faulty_primary_timeout {
+            return Err(PbftError::InvalidConfig(
+                "Block duration must be less than the view change timeout".into(),
+            ));
+        }
+
+        // Otherwise, the other nodes would give up on the primary while it's still waiting for
+        // the interval to pass
+        if self.min_block_publish_interval >= self.faulty_primary_timeout {
+            return Err(PbftError::InvalidConfig(
+                "Minimum block publish interval must be less than the view change timeout".into(),
+            ));
+        }
+
+        if self.view_change_backoff_factor == 0 {
+            return Err(PbftError::InvalidConfig(
+                "View change backoff factor must be at least 1".into(),
+            ));
+        }
+
+        if self.max_faulty_primary_timeout < self.faulty_primary_timeout {
+            return Err(PbftError::InvalidConfig(
+                "Maximum faulty primary timeout must not be less than the faulty primary timeout"
+                    .into(),
+            ));
+        }
+
+        if self.commit_timeout == Duration::from_secs(0) {
+            return Err(PbftError::InvalidConfig(
+                "Commit timeout must be longer than 0".into(),
+            ));
+        }
+
+        if self.view_change_duration == Duration::from_secs(0) {
+            return Err(PbftError::InvalidConfig(
+                "View change duration must be longer than 0".into(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 /// Load configuration from on-chain Sawtooth settings.
 ///
 /// Configuration loads the following settings:
-/// + `sawtooth.consensus.pbft.peers` (required)
+/// + `sawtooth.consensus.pbft.peers` (required; read from `peers_setting_key` instead, if it's
+///   given)
 /// + `sawtooth.consensus.pbft.block_duration` (optional, default 200 ms)
 /// + `sawtooth.consensus.pbft.faulty_primary_timeout` (optional, default 30s)
-/// + `sawtooth.consensus.pbft.forced_view_change_period` (optional, default 30 blocks)
+/// + `sawtooth.consensus.pbft.view_change_backoff_factor` (optional, default 2)
+/// + `sawtooth.consensus.pbft.max_faulty_primary_timeout` (optional, default 300s)
+/// + `sawtooth.consensus.pbft.commit_timeout` (optional, default 30s)
+/// + `sawtooth.consensus.pbft.view_change_duration` (optional, default 5000 ms)
+/// + `sawtooth.consensus.pbft.min_block_publish_interval` (optional, default 0 ms (no limit))
+/// + `sawtooth.consensus.pbft.forced_view_change_period` (optional, default 30 blocks; 0
+///   disables forced view changes)
+/// + `sawtooth.consensus.pbft.forced_view_change_jitter` (optional, default 0 blocks)
+/// + `sawtooth.consensus.pbft.view_change_join_threshold` (optional, default 0 (`f + 1`))
+/// + `sawtooth.consensus.pbft.max_view_jump` (optional, default 0 views (disabled))
 /// + `sawtooth.consensus.pbft.message_timeout` (optional, default 100 blocks)
 /// + `sawtooth.consensus.pbft.max_log_size` (optional, default 1000 messages)
+/// + `sawtooth.consensus.pbft.max_log_messages` (optional, default 10000 messages)
+/// + `sawtooth.consensus.pbft.max_backlog_size` (optional, default 1000 messages)
+/// + `sawtooth.consensus.pbft.max_future_seq_distance` (optional, default 10 blocks; 0 for no
+///   limit)
+/// + `sawtooth.consensus.pbft.gc_retain_depth` (optional, default 1 block)
+/// + `sawtooth.consensus.pbft.checkpoint_period` (optional, default 0 blocks (disabled))
+/// + `sawtooth.consensus.pbft.watermark_window` (optional, default 0 blocks (disabled))
+/// + `sawtooth.consensus.pbft.seal_prefetch_depth` (optional, default 0 blocks (unlimited))
+/// + `sawtooth.consensus.pbft.max_catchup_blocks_per_loop` (optional, default 100 blocks; 0 for
+///   no limit)
+/// + `sawtooth.consensus.pbft.first_sealed_block` (optional, default 2)
+/// + `sawtooth.consensus.pbft.initial_view` (optional, default 0)
 /// + `sawtooth.consensus.pbft.storage` (optional, default `"memory"`)
+/// + `sawtooth.consensus.pbft.faulty_primary_strike_threshold` (optional, default 0 (disabled))
+/// + `sawtooth.consensus.pbft.primary_ineligible_peers` (optional, default `[]`; same format as
+///   `sawtooth.consensus.pbft.peers`)
+/// + `sawtooth.consensus.pbft.observers` (optional, default `[]`; same format as
+///   `sawtooth.consensus.pbft.peers`)
+/// + `sawtooth.consensus.pbft.peer_weights` (optional, default `{}` (unweighted); a JSON object
+///   from hex-encoded peer IDs to integer weights)
+/// + `sawtooth.consensus.pbft.max_concurrent_verifications` (optional, default 4)
+/// + `sawtooth.consensus.pbft.vote_verification_threads` (optional, default 4)
+/// + `sawtooth.consensus.pbft.phase_timeout.{pre_preparing, preparing, checking, committing,
+///   finished}` (optional, default 30s, 10s, 10s, 20s, 10s; set in ms)
+/// + `sawtooth.consensus.pbft.network_id` (optional, default `""`)
+/// + `sawtooth.consensus.pbft.verify_signatures` (optional, default `true`; unsafe to disable
+///   outside of test networks)
+/// + `sawtooth.consensus.pbft.signing_algorithm` (optional, default `"secp256k1"`)
+/// + `sawtooth.consensus.pbft.hash_algorithm` (optional, default `"sha512"`; must match the
+///   validator's)
+/// + `sawtooth.consensus.pbft.max_message_age` (optional, default 0 ms (disabled))
+/// + `sawtooth.consensus.pbft.max_clock_skew` (optional, default 5000 ms)
+/// + `sawtooth.consensus.pbft.allow_single_node` (optional, default `false`; development only)
+/// + `sawtooth.consensus.pbft.settings_retries` (optional, default 3)
+/// + `sawtooth.consensus.pbft.settings_retry_delay` (optional, default 100 ms)
 ///
 /// # Panics
 /// + If the `sawtooth.consensus.pbft.peers` setting is not provided
 /// + If settings loading fails entirely
 /// + If block duration is greater than the view change timeout
-pub fn load_pbft_config(block_id: BlockId, service: &mut Service) -> PbftConfig {
+/// + If the list of peers contains duplicates
+pub fn load_pbft_config(
+    block_id: BlockId,
+    service: &mut Service,
+    peers_setting_key: &str,
+) -> PbftConfig {
     let mut config = PbftConfig::default();
+    config.peers_setting_key = peers_setting_key.into();
 
     let settings: HashMap<String, String> = service
         .get_settings(
             block_id,
             vec![
-                String::from("sawtooth.consensus.pbft.peers"),
+                String::from(peers_setting_key),
                 String::from("sawtooth.consensus.pbft.block_duration"),
                 String::from("sawtooth.consensus.pbft.faulty_primary_timeout"),
+                String::from("sawtooth.consensus.pbft.view_change_backoff_factor"),
+                String::from("sawtooth.consensus.pbft.max_faulty_primary_timeout"),
+                String::from("sawtooth.consensus.pbft.commit_timeout"),
+                String::from("sawtooth.consensus.pbft.view_change_duration"),
+                String::from("sawtooth.consensus.pbft.min_block_publish_interval"),
                 String::from("sawtooth.consensus.pbft.forced_view_change_period"),
+                String::from("sawtooth.consensus.pbft.forced_view_change_jitter"),
+                String::from("sawtooth.consensus.pbft.view_change_join_threshold"),
+                String::from("sawtooth.consensus.pbft.max_view_jump"),
                 String::from("sawtooth.consensus.pbft.message_timeout"),
                 String::from("sawtooth.consensus.pbft.max_log_size"),
+                String::from("sawtooth.consensus.pbft.max_log_messages"),
+                String::from("sawtooth.consensus.pbft.max_backlog_size"),
+                String::from("sawtooth.consensus.pbft.max_future_seq_distance"),
+                String::from("sawtooth.consensus.pbft.gc_retain_depth"),
+                String::from("sawtooth.consensus.pbft.checkpoint_period"),
+                String::from("sawtooth.consensus.pbft.watermark_window"),
+                String::from("sawtooth.consensus.pbft.seal_prefetch_depth"),
+                String::from("sawtooth.consensus.pbft.max_catchup_blocks_per_loop"),
+                String::from("sawtooth.consensus.pbft.first_sealed_block"),
+                String::from("sawtooth.consensus.pbft.initial_view"),
+                String::from("sawtooth.consensus.pbft.faulty_primary_strike_threshold"),
+                String::from("sawtooth.consensus.pbft.primary_ineligible_peers"),
+                String::from(OBSERVERS_SETTING_KEY),
+                String::from(PEER_WEIGHTS_SETTING_KEY),
+                String::from("sawtooth.consensus.pbft.max_concurrent_verifications"),
+                String::from("sawtooth.consensus.pbft.vote_verification_threads"),
+                String::from("sawtooth.consensus.pbft.phase_timeout.pre_preparing"),
+                String::from("sawtooth.consensus.pbft.phase_timeout.preparing"),
+                String::from("sawtooth.consensus.pbft.phase_timeout.checking"),
+                String::from("sawtooth.consensus.pbft.phase_timeout.committing"),
+                String::from("sawtooth.consensus.pbft.phase_timeout.finished"),
+                String::from("sawtooth.consensus.pbft.network_id"),
+                String::from("sawtooth.consensus.pbft.verify_signatures"),
+                String::from("sawtooth.consensus.pbft.signing_algorithm"),
+                String::from("sawtooth.consensus.pbft.hash_algorithm"),
+                String::from("sawtooth.consensus.pbft.max_message_age"),
+                String::from("sawtooth.consensus.pbft.max_clock_skew"),
+                String::from("sawtooth.consensus.pbft.allow_single_node"),
+                String::from("sawtooth.consensus.pbft.settings_retries"),
+                String::from("sawtooth.consensus.pbft.settings_retry_delay"),
             ],
         )
         .expect("Failed to get on-chain settings");
 
     // Get the peers associated with this node (including ourselves). Panic if it is not provided;
     // the network cannot function without this setting.
-    let peers = get_peers_from_settings(&settings);
+    let peers = get_peers_from_settings(&settings, peers_setting_key)
+        .unwrap_or_else(|err| panic!("{}", err));
 
     config.peers = peers;
 
+    if let Some(ineligible) = settings.get("sawtooth.consensus.pbft.primary_ineligible_peers") {
+        let ineligible: Vec<String> = serde_json::from_str(ineligible)
+            .expect("Invalid value at 'sawtooth.consensus.pbft.primary_ineligible_peers'");
+        config.primary_ineligible_peers = ineligible
+            .into_iter()
+            .map(|s| hex::decode(s).expect("PeerId is not valid hex"))
+            .collect();
+    }
+
+    if let Some(observers) =
+        get_observers_from_settings(&settings).unwrap_or_else(|err| panic!("{}", err))
+    {
+        config.observers = observers;
+    }
+
+    config.peer_weights =
+        get_peer_weights_from_settings(&settings).unwrap_or_else(|err| panic!("{}", err));
+
     // Get various durations
     merge_millis_setting_if_set(
         &settings,
@@ -122,11 +608,66 @@ pub fn load_pbft_config(block_id: BlockId, service: &mut Service) -> PbftConfig
         &mut config.faulty_primary_timeout,
         "sawtooth.consensus.pbft.faulty_primary_timeout",
     );
-
-    // Check to make sure block_duration < faulty_primary_timeout
-    if config.block_duration >= config.faulty_primary_timeout {
-        panic!("Block duration must be less than the view change timeout");
-    }
+    merge_secs_setting_if_set(
+        &settings,
+        &mut config.max_faulty_primary_timeout,
+        "sawtooth.consensus.pbft.max_faulty_primary_timeout",
+    );
+    merge_secs_setting_if_set(
+        &settings,
+        &mut config.commit_timeout,
+        "sawtooth.consensus.pbft.commit_timeout",
+    );
+    merge_millis_setting_if_set(
+        &settings,
+        &mut config.view_change_duration,
+        "sawtooth.consensus.pbft.view_change_duration",
+    );
+    merge_millis_setting_if_set(
+        &settings,
+        &mut config.phase_timeouts.pre_preparing,
+        "sawtooth.consensus.pbft.phase_timeout.pre_preparing",
+    );
+    merge_millis_setting_if_set(
+        &settings,
+        &mut config.phase_timeouts.preparing,
+        "sawtooth.consensus.pbft.phase_timeout.preparing",
+    );
+    merge_millis_setting_if_set(
+        &settings,
+        &mut config.phase_timeouts.checking,
+        "sawtooth.consensus.pbft.phase_timeout.checking",
+    );
+    merge_millis_setting_if_set(
+        &settings,
+        &mut config.phase_timeouts.committing,
+        "sawtooth.consensus.pbft.phase_timeout.committing",
+    );
+    merge_millis_setting_if_set(
+        &settings,
+        &mut config.phase_timeouts.finished,
+        "sawtooth.consensus.pbft.phase_timeout.finished",
+    );
+    merge_millis_setting_if_set(
+        &settings,
+        &mut config.min_block_publish_interval,
+        "sawtooth.consensus.pbft.min_block_publish_interval",
+    );
+    merge_millis_setting_if_set(
+        &settings,
+        &mut config.max_message_age,
+        "sawtooth.consensus.pbft.max_message_age",
+    );
+    merge_millis_setting_if_set(
+        &settings,
+        &mut config.max_clock_skew,
+        "sawtooth.consensus.pbft.max_clock_skew",
+    );
+    merge_millis_setting_if_set(
+        &settings,
+        &mut config.settings_retry_delay,
+        "sawtooth.consensus.pbft.settings_retry_delay",
+    );
 
     // Get various integer constants
     merge_setting_if_set(
@@ -134,11 +675,130 @@ pub fn load_pbft_config(block_id: BlockId, service: &mut Service) -> PbftConfig
         &mut config.forced_view_change_period,
         "sawtooth.consensus.pbft.forced_view_change_period",
     );
+    merge_setting_if_set(
+        &settings,
+        &mut config.forced_view_change_jitter,
+        "sawtooth.consensus.pbft.forced_view_change_jitter",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.view_change_join_threshold,
+        "sawtooth.consensus.pbft.view_change_join_threshold",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.max_view_jump,
+        "sawtooth.consensus.pbft.max_view_jump",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.view_change_backoff_factor,
+        "sawtooth.consensus.pbft.view_change_backoff_factor",
+    );
     merge_setting_if_set(
         &settings,
         &mut config.max_log_size,
         "sawtooth.consensus.pbft.max_log_size",
     );
+    merge_setting_if_set(
+        &settings,
+        &mut config.max_log_messages,
+        "sawtooth.consensus.pbft.max_log_messages",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.max_backlog_size,
+        "sawtooth.consensus.pbft.max_backlog_size",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.max_future_seq_distance,
+        "sawtooth.consensus.pbft.max_future_seq_distance",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.gc_retain_depth,
+        "sawtooth.consensus.pbft.gc_retain_depth",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.checkpoint_period,
+        "sawtooth.consensus.pbft.checkpoint_period",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.watermark_window,
+        "sawtooth.consensus.pbft.watermark_window",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.seal_prefetch_depth,
+        "sawtooth.consensus.pbft.seal_prefetch_depth",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.max_catchup_blocks_per_loop,
+        "sawtooth.consensus.pbft.max_catchup_blocks_per_loop",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.first_sealed_block,
+        "sawtooth.consensus.pbft.first_sealed_block",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.initial_view,
+        "sawtooth.consensus.pbft.initial_view",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.faulty_primary_strike_threshold,
+        "sawtooth.consensus.pbft.faulty_primary_strike_threshold",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.max_concurrent_verifications,
+        "sawtooth.consensus.pbft.max_concurrent_verifications",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.vote_verification_threads,
+        "sawtooth.consensus.pbft.vote_verification_threads",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.network_id,
+        "sawtooth.consensus.pbft.network_id",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.verify_signatures,
+        "sawtooth.consensus.pbft.verify_signatures",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.signing_algorithm,
+        "sawtooth.consensus.pbft.signing_algorithm",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.hash_algorithm,
+        "sawtooth.consensus.pbft.hash_algorithm",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.allow_single_node,
+        "sawtooth.consensus.pbft.allow_single_node",
+    );
+    merge_setting_if_set(
+        &settings,
+        &mut config.settings_retries,
+        "sawtooth.consensus.pbft.settings_retries",
+    );
+
+    if let Err(err) = config.validate() {
+        panic!("Invalid PBFT configuration: {}", err);
+    }
 
     config
 }
@@ -193,25 +853,107 @@ fn merge_millis_setting_if_set(
     )
 }
 
-/// Get the peers as a Vec<PeerId> from settings
+/// Get the peers as a Vec<PeerId> from the given setting
+///
+/// The setting must be a JSON list of hex-encoded peer IDs; if it's missing, isn't such a list, or
+/// has an entry that isn't valid hex, an `InvalidPeersSetting` error is returned.
 pub fn get_peers_from_settings<S: std::hash::BuildHasher>(
     settings: &HashMap<String, String, S>,
-) -> Vec<PeerId> {
+    key: &str,
+) -> Result<Vec<PeerId>, PbftError> {
+    let invalid = |problem: String| PbftError::InvalidPeersSetting(key.into(), problem);
+
     let peers_setting_value = settings
-        .get("sawtooth.consensus.pbft.peers")
-        .expect("'sawtooth.consensus.pbft.peers' must be set to use PBFT");
+        .get(key)
+        .ok_or_else(|| invalid("must be set to use PBFT".into()))?;
 
     warn!("Peers setting: {:?}", peers_setting_value);
 
     let peers: Vec<String> = serde_json::from_str(peers_setting_value)
-        .expect("Invalid value at 'sawtooth.consensus.pbft.peers'");
+        .map_err(|err| invalid(format!("not a JSON list of strings ({})", err)))?;
 
     peers
         .into_iter()
-        .map(|s| hex::decode(s).expect("PeerId is not valid hex"))
+        .map(|s| hex::decode(&s).map_err(|_| invalid(format!("entry {:?} is not valid hex", s))))
+        .collect()
+}
+
+/// Get the observers from the observers setting, or `None` if it isn't set
+pub fn get_observers_from_settings<S: std::hash::BuildHasher>(
+    settings: &HashMap<String, String, S>,
+) -> Result<Option<Vec<PeerId>>, PbftError> {
+    match settings.get(OBSERVERS_SETTING_KEY) {
+        Some(_) => get_peers_from_settings(settings, OBSERVERS_SETTING_KEY).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Get the peers' weights from the peer weights setting; without it, quorums aren't weighted
+/// and the map is empty
+pub fn get_peer_weights_from_settings<S: std::hash::BuildHasher>(
+    settings: &HashMap<String, String, S>,
+) -> Result<HashMap<PeerId, u64>, PbftError> {
+    let invalid =
+        |problem: String| PbftError::InvalidPeersSetting(PEER_WEIGHTS_SETTING_KEY.into(), problem);
+
+    let weights: HashMap<String, u64> = match settings.get(PEER_WEIGHTS_SETTING_KEY) {
+        Some(weights) => serde_json::from_str(weights)
+            .map_err(|err| invalid(format!("not a JSON object of numbers ({})", err)))?,
+        None => return Ok(HashMap::new()),
+    };
+
+    weights
+        .into_iter()
+        .map(|(s, weight)| match hex::decode(&s) {
+            Ok(id) => Ok((id, weight)),
+            Err(_) => Err(invalid(format!("key {:?} is not valid hex", s))),
+        })
         .collect()
 }
 
+/// Check that peer weights, if there are any, give every one of the peers a weight, don't give
+/// one to anyone else, and aren't all zero
+pub fn check_peer_weights<S: std::hash::BuildHasher>(
+    weights: &HashMap<PeerId, u64, S>,
+    peers: &[PeerId],
+) -> Result<(), String> {
+    if weights.is_empty() {
+        return Ok(());
+    }
+    if let Some(id) = peers.iter().find(|id| !weights.contains_key(*id)) {
+        return Err(format!("Peer {} has no weight", hex::encode(id)));
+    }
+    if let Some(id) = weights.keys().find(|id| !peers.contains(id)) {
+        return Err(format!(
+            "{} has a weight, but isn't a peer",
+            hex::encode(id)
+        ));
+    }
+    if weights.values().all(|weight| *weight == 0) {
+        return Err("At least one peer must have a non-zero weight".into());
+    }
+    Ok(())
+}
+
+/// Remove duplicate entries from a list of peers, keeping the first occurrence of each
+pub fn dedup_peers(peers: &[PeerId]) -> Vec<PeerId> {
+    let mut seen = HashSet::new();
+    let deduped: Vec<PeerId> = peers
+        .iter()
+        .filter(|id| seen.insert(*id))
+        .cloned()
+        .collect();
+
+    if deduped.len() != peers.len() {
+        warn!(
+            "Peer list contains {} duplicate entries; ignoring them",
+            peers.len() - deduped.len()
+        );
+    }
+
+    deduped
+}
+
 /// Create a mock configuration, given a number of nodes. PeerIds are generated using a Sha256
 /// hash.
 #[cfg(test)]
@@ -220,3 +962,132 @@ pub fn mock_config(num_nodes: usize) -> PbftConfig {
     config.peers = (0..num_nodes).map(|id| vec![id as u8]).collect();
     config
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make sure that peers can be read from any setting
+    #[test]
+    fn peers_from_settings() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            "example.pbft.members".to_string(),
+            r#"["00", "0a"]"#.to_string(),
+        );
+
+        assert_eq!(
+            get_peers_from_settings(&settings, "example.pbft.members").unwrap(),
+            vec![vec![0], vec![10]]
+        );
+    }
+
+    /// Make sure that an empty peers list is read as such, and that a missing or malformed one is
+    /// an error rather than a panic
+    #[test]
+    fn malformed_peers_setting() {
+        let key = "sawtooth.consensus.pbft.members";
+        let peers_for = |value: &str| {
+            let mut settings = HashMap::new();
+            settings.insert(key.to_string(), value.to_string());
+            get_peers_from_settings(&settings, key)
+        };
+
+        assert_eq!(peers_for("[]").unwrap(), Vec::<PeerId>::new());
+
+        match peers_for(r#"["00", "zz"]"#) {
+            Err(PbftError::InvalidPeersSetting(setting, problem)) => {
+                assert_eq!(setting, key);
+                assert!(problem.contains("zz"));
+            }
+            res => panic!("Expected InvalidPeersSetting, got {:?}", res),
+        }
+        assert!(peers_for("not json").is_err());
+        assert!(peers_for(r#"{"peer": "00"}"#).is_err());
+        assert!(get_peers_from_settings(&HashMap::new(), key).is_err());
+    }
+
+    /// Make sure that the view change join threshold can't be set below f + 1
+    #[test]
+    fn view_change_join_threshold() {
+        let mut config = mock_config(4);
+        assert!(config.validate().is_ok());
+
+        config.view_change_join_threshold = 1;
+        assert!(config.validate().is_err());
+
+        config.view_change_join_threshold = 2;
+        assert!(config.validate().is_ok());
+
+        // Observers don't count toward f
+        config.peers = (0..7).map(|i| vec![i]).collect();
+        config.view_change_join_threshold = 2;
+        assert!(config.validate().is_err());
+        config.observers = vec![vec![5], vec![6]];
+        assert!(config.validate().is_ok());
+    }
+
+    /// Make sure that observers must be peers, and that some voting peer can still be primary
+    #[test]
+    fn observers() {
+        let mut config = mock_config(5);
+        config.observers = vec![vec![4]];
+        assert!(config.validate().is_ok());
+
+        config.observers.push(vec![9]);
+        assert!(config.validate().is_err());
+
+        config.observers = vec![vec![4]];
+        config.primary_ineligible_peers = (0..4).map(|i| vec![i]).collect();
+        assert!(config.validate().is_err());
+    }
+
+    /// Make sure that weights, if they're set, must cover every peer and only the peers
+    #[test]
+    fn peer_weights() {
+        let mut config = mock_config(4);
+        config.peer_weights = (0..3).map(|i| (vec![i], 1)).collect();
+        assert!(config.validate().is_err());
+
+        config.peer_weights.insert(vec![3], 0);
+        assert!(config.validate().is_ok());
+
+        config.peer_weights = (0..4).map(|i| (vec![i], 0)).collect();
+        assert!(config.validate().is_err());
+
+        // Weights for nodes that aren't peers would skew every quorum
+        config.peer_weights = (0..5).map(|i| (vec![i], 1)).collect();
+        assert!(config.validate().is_err());
+    }
+
+    /// Make sure that the primary can't be made to wait between blocks for longer than the other
+    /// nodes wait for it
+    #[test]
+    fn min_block_publish_interval() {
+        let mut config = mock_config(4);
+        config.faulty_primary_timeout = Duration::from_secs(30);
+        config.min_block_publish_interval = Duration::from_secs(29);
+        assert!(config.validate().is_ok());
+
+        config.min_block_publish_interval = Duration::from_secs(30);
+        assert!(config.validate().is_err());
+    }
+
+    /// Make sure that a peer list with duplicate entries fails validation
+    #[test]
+    fn duplicate_peers() {
+        let mut config = mock_config(4);
+        assert!(config.validate().is_ok());
+
+        config.peers.push(vec![2]);
+        match config.validate() {
+            Err(PbftError::InvalidConfig(_)) => {}
+            res => panic!("Expected InvalidConfig, got {:?}", res),
+        }
+
+        assert_eq!(
+            dedup_peers(&config.peers),
+            (0..4).map(|i| vec![i]).collect::<Vec<_>>()
+        );
+    }
+}
@@ -17,24 +17,70 @@
 
 //! Entry point for the consensus algorithm, including the main event loop
 
+use std::path::PathBuf;
 use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
 
 use sawtooth_sdk::consensus::{engine::*, service::Service};
 
 use crate::config;
 use crate::error::PbftError;
-use crate::message_type::ParsedMessage;
-use crate::node::PbftNode;
+use crate::metrics::PbftMetrics;
+use crate::node::{handle_pbft_result, PbftNode};
+use crate::recorder::FileRecorder;
 use crate::state::PbftState;
 use crate::storage::get_storage;
 use crate::timing;
 
-#[derive(Default)]
-pub struct PbftEngine {}
+pub struct PbftEngine {
+    /// On-chain setting that lists the peers
+    peers_setting_key: String,
+
+    /// Whether the node only follows and verifies the chain
+    verify_only: bool,
+
+    /// Consensus health metrics, recorded by the node once the engine is started
+    metrics: Arc<PbftMetrics>,
+
+    /// Where to record the updates the node handles, if anywhere
+    update_recording: Option<PathBuf>,
+}
 
 impl PbftEngine {
     pub fn new() -> Self {
-        PbftEngine {}
+        Self::with_peers_setting_key(config::DEFAULT_PEERS_SETTING_KEY)
+    }
+
+    /// Create an engine that reads the list of peers from the given setting
+    pub fn with_peers_setting_key(peers_setting_key: &str) -> Self {
+        PbftEngine {
+            peers_setting_key: peers_setting_key.into(),
+            verify_only: false,
+            metrics: Arc::new(PbftMetrics::new()),
+            update_recording: None,
+        }
+    }
+
+    /// Only follow the chain and verify its consensus seals, without taking part in consensus
+    pub fn set_verify_only(&mut self, enabled: bool) {
+        self.verify_only = enabled;
+    }
+
+    /// Record every update the node handles, and every tick of the working timer, to the given
+    /// file, so the run can be replayed for debugging
+    pub fn set_update_recording(&mut self, path: Option<PathBuf>) {
+        self.update_recording = path;
+    }
+
+    /// Get the engine's consensus health metrics, so they can be reported while it runs
+    pub fn metrics(&self) -> Arc<PbftMetrics> {
+        Arc::clone(&self.metrics)
+    }
+}
+
+impl Default for PbftEngine {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -47,12 +93,17 @@ impl Engine for PbftEngine {
     ) -> Result<(), Error> {
         let StartupState {
             chain_head,
-            peers: _peers,
+            peers,
             local_peer_info,
         } = startup_state;
 
         // Load on-chain settings
-        let config = config::load_pbft_config(chain_head.block_id.clone(), &mut *service);
+        let mut config = config::load_pbft_config(
+            chain_head.block_id.clone(),
+            &mut *service,
+            &self.peers_setting_key,
+        );
+        config.verify_only = self.verify_only;
 
         let mut pbft_state = get_storage(&config.storage, || {
             PbftState::new(
@@ -62,15 +113,30 @@ impl Engine for PbftEngine {
             )
         })
         .expect("Couldn't load state!");
+        pbft_state.write().apply_config(&config);
 
         let mut working_ticker = timing::Ticker::new(config.block_duration);
         let mut backlog_ticker = timing::Ticker::new(config.message_timeout);
 
-        let mut node = PbftNode::new(&config, service, pbft_state.read().is_primary());
+        let mut node = PbftNode::new(&config, service, Arc::clone(&self.metrics));
+        if let Some(ref path) = self.update_recording {
+            match FileRecorder::create(path) {
+                Ok(recorder) => node.set_update_recorder(Box::new(recorder)),
+                Err(err) => error!("Not recording updates: {}", err),
+            }
+        }
 
         debug!("Starting state: {:#?}", **pbft_state.read());
 
+        // The peers the validator is already connected to won't be announced again; once enough
+        // of them are live, the primary starts building a block
+        for peer in peers {
+            node.on_peer_connected(peer.peer_id, &mut pbft_state.write());
+        }
+        node.initialize_block_if_ready(&pbft_state.read());
+
         node.start_faulty_primary_timeout(&mut pbft_state.write());
+        handle_pbft_result(node.resume_view_change(&mut pbft_state.write()));
 
         // Event loop. Keep going until we receive a shutdown message.
         loop {
@@ -80,27 +146,22 @@ impl Engine for PbftEngine {
             match handle_update(&mut node, incoming_message, state) {
                 Ok(again) => {
                     if !again {
+                        // The state is persisted when it's let go of, on the way out of the loop
+                        node.shutdown(state);
                         break;
                     }
                 }
                 Err(err) => handle_pbft_result(Err(err)),
             }
 
-            working_ticker.tick(|| {
-                if let Err(e) = node.try_publish(state) {
-                    error!("{}", e);
-                }
+            // A node that is far behind catches up a batch of blocks at a time
+            handle_pbft_result(node.continue_catchup(state));
 
-                // Every so often, check to see if the faulty primary timeout has expired; initiate
-                // ViewChange if necessary
-                if node.check_faulty_primary_timeout_expired(state) {
-                    warn!("Faulty primary timeout expired; proposing view change");
-                    handle_pbft_result(node.propose_view_change(state));
-                }
-            });
+            working_ticker.tick(|| node.on_tick(state));
 
+            // Retry all backlogged messages that are ready
             backlog_ticker.tick(|| {
-                handle_pbft_result(node.retry_backlog(state));
+                node.drain_backlog(state);
             })
         }
 
@@ -122,46 +183,11 @@ fn handle_update(
     state: &mut PbftState,
 ) -> Result<bool, PbftError> {
     match incoming_message {
-        Ok(Update::BlockNew(block)) => node.on_block_new(block, state)?,
-        Ok(Update::BlockValid(block_id)) => node.on_block_valid(&block_id, state)?,
-        Ok(Update::BlockInvalid(_)) => {
-            warn!("{}: BlockInvalid received, starting view change", state);
-            node.propose_view_change(state)?
-        }
-        Ok(Update::BlockCommit(block_id)) => node.on_block_commit(block_id, state),
-        Ok(Update::PeerMessage(message, sender_id)) => {
-            let parsed_message = ParsedMessage::from_peer_message(message, false)?;
-            let signer_id = parsed_message.info().get_signer_id().to_vec();
-
-            if signer_id != sender_id {
-                return Err(PbftError::InternalError(format!(
-                    "Mismatch between sender ID ({:?}) and signer ID ({:?})!",
-                    sender_id, signer_id
-                )));
-            }
-
-            node.on_peer_message(parsed_message, state)?
-        }
-        Ok(Update::Shutdown) => return Ok(false),
-        Ok(Update::PeerConnected(_)) | Ok(Update::PeerDisconnected(_)) => {
-            debug!("Received PeerConnected/PeerDisconnected message");
-        }
-        Err(RecvTimeoutError::Timeout) => return Err(PbftError::Timeout),
+        Ok(update) => node.apply_update(update, state),
+        Err(RecvTimeoutError::Timeout) => Err(PbftError::Timeout),
         Err(RecvTimeoutError::Disconnected) => {
             error!("Disconnected from validator");
-            return Ok(false);
-        }
-    }
-
-    Ok(true)
-}
-
-fn handle_pbft_result(res: Result<(), PbftError>) {
-    if let Err(e) = res {
-        match e {
-            PbftError::Timeout => (),
-            PbftError::WrongNumMessages(_, _, _) | PbftError::NotReadyForMessage => trace!("{}", e),
-            _ => error!("{}", e),
+            Ok(false)
         }
     }
 }
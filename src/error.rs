@@ -19,18 +19,21 @@
 
 use std::error::Error;
 use std::fmt;
+use std::time::Duration;
 
 use hex;
 use protobuf::error::ProtobufError;
 
 use crate::message_type::PbftMessageType;
 use crate::protos::pbft_message::PbftBlock;
+use crate::state::PbftPhase;
 
 /// Errors that might occur in a PbftNode
 #[derive(Debug)]
 pub enum PbftError {
-    /// An error occured while serializing or deserializing a Protobuf message
-    SerializationError(ProtobufError),
+    /// An error occured while serializing or deserializing a Protobuf message (what was being
+    /// done, error)
+    SerializationError(&'static str, ProtobufError),
 
     /// Too many or too few messages recieved so far (expected, got)
     WrongNumMessages(PbftMessageType, usize, usize),
@@ -70,13 +73,165 @@ pub enum PbftError {
 
     /// Got a PrePrepare without a matching BlockNew
     NoBlockNew,
+
+    /// The message belongs to a different PBFT network (expected, got)
+    WrongNetwork(String, String),
+
+    /// A different consensus seal is already stored for this sequence number
+    ConflictingSeal(u64),
+
+    /// The configuration can't be used (description)
+    InvalidConfig(String),
+
+    /// The block at this height failed verification while auditing the chain (block num, reason)
+    ChainVerificationFailed(u64, Box<PbftError>),
+
+    /// The message's timestamp is too far in the past or future (message time, local time; in ms)
+    InvalidTimestamp(u64, u64),
+
+    /// A membership change would leave too few voting peers to tolerate a fault (number of peers)
+    InsufficientPeers(usize),
+
+    /// A consensus seal contains more than one vote from the same signer
+    DuplicateVote(Vec<u8>),
+
+    /// The signer sent conflicting messages for the same view and sequence number (message type,
+    /// signer)
+    Equivocation(PbftMessageType, Vec<u8>),
+
+    /// A signature can't be checked because its public key is malformed (key, reason)
+    InvalidPublicKey(Vec<u8>, String),
+
+    /// A peer's message is malformed, so it can't be handled
+    InvalidMessage(String),
+
+    /// The node can't go straight from one phase to the other (from, to)
+    PhaseTransition(PbftPhase, PbftPhase),
+
+    /// A block doesn't build on this node's chain head (block ID, previous ID)
+    ForkedBlock(Vec<u8>, Vec<u8>),
+
+    /// A consensus seal isn't for its block's predecessor (seal's previous ID, block's previous
+    /// ID)
+    SealPreviousIdMismatch(Vec<u8>, Vec<u8>),
+
+    /// A consensus seal's summary doesn't match its block's (seal's summary, block's summary)
+    SealSummaryMismatch(Vec<u8>, Vec<u8>),
+
+    /// A consensus seal doesn't have enough votes for a quorum (required, found); with peer
+    /// weights, both are weights
+    InsufficientVotes(usize, usize),
+
+    /// A consensus seal has votes from nodes that aren't peers, or from the block's publisher
+    UnknownVoters(Vec<Vec<u8>>),
+
+    /// A consensus seal has no votes at all (seal's previous ID)
+    EmptySeal(Vec<u8>),
+
+    /// The on-chain setting that lists the peers is missing or malformed (setting, problem)
+    InvalidPeersSetting(String, String),
+
+    /// The node was asked to commit a block it isn't prepared for (block ID)
+    NotPrepared(Vec<u8>),
+
+    /// The block comes before the first sealed block, so there's no consensus seal for it (block
+    /// number)
+    NoConsensusSeal(u64),
+
+    /// The message is for a sequence number too far past the current one (message's, current)
+    SeqNumTooFarAhead(u64, u64),
+
+    /// The on-chain settings couldn't be read; they should be read again later (failed reads in a
+    /// row, last error)
+    SettingsUnavailable(u32, String),
+
+    /// The message or block is for a sequence number past the high watermark (sequence number,
+    /// high watermark)
+    AboveHighWatermark(u64, u64),
+
+    /// A view change would go too many views past the view the last committed block was committed
+    /// in (target view, committed view)
+    ViewJump(u64, u64),
+
+    /// The summary of the block the primary proposed in its `PrePrepare` doesn't match the one the
+    /// validator has for the block (proposed summary, validator's summary)
+    BlockSummaryMismatch(Vec<u8>, Vec<u8>),
+
+    /// A message in a batch added to the log was rejected, so none of the batch was added (index
+    /// in the batch, reason)
+    BatchRejected(usize, Box<PbftError>),
+
+    /// The block comes before the first sealed block, so its payload should be empty, but isn't
+    /// (block number)
+    UnexpectedPayload(u64),
+
+    /// A consensus seal is in a newer format than this node knows how to verify (seal's version)
+    UnsupportedSealVersion(u32),
+
+    /// A signed vote was signed by a different node than the one its message names (header's
+    /// signer, message's signer)
+    SignerMismatch(Vec<u8>, Vec<u8>),
+}
+
+impl PbftError {
+    /// Suggest how long to wait before retrying the operation that failed with this error
+    ///
+    /// Errors caused by conditions that clear up on their own (such as a block or message that
+    /// hasn't arrived yet) get a hint; errors that retrying won't fix return `None`.
+    pub fn retry_after(&self) -> Option<Duration> {
+        use self::PbftError::*;
+        match self {
+            Timeout => Some(Duration::from_millis(0)),
+            NotReadyForMessage | WrongNumMessages(_, _, _) => Some(Duration::from_millis(10)),
+            NoBlockNew | NoWorkingBlock | SettingsUnavailable(_, _) => {
+                Some(Duration::from_millis(100))
+            }
+            SerializationError(_, _)
+            | WrongNumSeals(_, _)
+            | BlockMismatch(_, _)
+            | MessageMismatch(_)
+            | ViewMismatch(_, _)
+            | InternalError(_)
+            | NodeNotFound
+            | WrongNumBlocks
+            | NotFromPrimary
+            | WrongNetwork(_, _)
+            | ConflictingSeal(_)
+            | InvalidConfig(_)
+            | ChainVerificationFailed(_, _)
+            | InvalidTimestamp(_, _)
+            | InsufficientPeers(_)
+            | DuplicateVote(_)
+            | Equivocation(_, _)
+            | InvalidPublicKey(_, _)
+            | InvalidMessage(_)
+            | PhaseTransition(_, _)
+            | ForkedBlock(_, _)
+            | SealPreviousIdMismatch(_, _)
+            | SealSummaryMismatch(_, _)
+            | InsufficientVotes(_, _)
+            | UnknownVoters(_)
+            | EmptySeal(_)
+            | InvalidPeersSetting(_, _)
+            | NotPrepared(_)
+            | NoConsensusSeal(_)
+            | SeqNumTooFarAhead(_, _)
+            | AboveHighWatermark(_, _)
+            | ViewJump(_, _)
+            | BlockSummaryMismatch(_, _)
+            | BatchRejected(_, _)
+            | UnexpectedPayload(_)
+            | UnsupportedSealVersion(_)
+            | SignerMismatch(_, _) => None,
+        }
+    }
 }
 
 impl Error for PbftError {
     fn description(&self) -> &str {
         use self::PbftError::*;
         match self {
-            SerializationError(_) => "SerializationError",
+            SerializationError(_, _) => "SerializationError",
             WrongNumMessages(_, _, _) => "WrongNumMessages",
             WrongNumSeals(_, _) => "WrongNumSeals",
             BlockMismatch(_, _) => "BlockMismatch",
@@ -90,6 +245,44 @@ impl Error for PbftError {
             NotReadyForMessage => "NotReadyForMessage",
             NotFromPrimary => "NotFromPrimary",
             NoBlockNew => "NoBlockNew",
+            WrongNetwork(_, _) => "WrongNetwork",
+            ConflictingSeal(_) => "ConflictingSeal",
+            InvalidConfig(_) => "InvalidConfig",
+            ChainVerificationFailed(_, _) => "ChainVerificationFailed",
+            InvalidTimestamp(_, _) => "InvalidTimestamp",
+            InsufficientPeers(_) => "InsufficientPeers",
+            DuplicateVote(_) => "DuplicateVote",
+            Equivocation(_, _) => "Equivocation",
+            InvalidPublicKey(_, _) => "InvalidPublicKey",
+            InvalidMessage(_) => "InvalidMessage",
+            PhaseTransition(_, _) => "PhaseTransition",
+            ForkedBlock(_, _) => "ForkedBlock",
+            SealPreviousIdMismatch(_, _) => "SealPreviousIdMismatch",
+            SealSummaryMismatch(_, _) => "SealSummaryMismatch",
+            InsufficientVotes(_, _) => "InsufficientVotes",
+            UnknownVoters(_) => "UnknownVoters",
+            EmptySeal(_) => "EmptySeal",
+            InvalidPeersSetting(_, _) => "InvalidPeersSetting",
+            NotPrepared(_) => "NotPrepared",
+            NoConsensusSeal(_) => "NoConsensusSeal",
+            SeqNumTooFarAhead(_, _) => "SeqNumTooFarAhead",
+            SettingsUnavailable(_, _) => "SettingsUnavailable",
+            AboveHighWatermark(_, _) => "AboveHighWatermark",
+            ViewJump(_, _) => "ViewJump",
+            BlockSummaryMismatch(_, _) => "BlockSummaryMismatch",
+            BatchRejected(_, _) => "BatchRejected",
+            UnexpectedPayload(_) => "UnexpectedPayload",
+            UnsupportedSealVersion(_) => "UnsupportedSealVersion",
+            SignerMismatch(_, _) => "SignerMismatch",
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match self {
+            PbftError::SerializationError(_, pb_err) => Some(pb_err),
+            PbftError::ChainVerificationFailed(_, err) => Some(&**err),
+            PbftError::BatchRejected(_, err) => Some(&**err),
+            _ => None,
         }
     }
 }
@@ -98,7 +291,9 @@ impl fmt::Display for PbftError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}: ", self.description())?;
         match self {
-            PbftError::SerializationError(pb_err) => pb_err.fmt(f),
+            PbftError::SerializationError(context, pb_err) => {
+                write!(f, "Error while {}: {}", context, pb_err)
+            }
             PbftError::WrongNumMessages(t, exp, got) => write!(
                 f,
                 "Wrong number of {:?} messages in this sequence (expected {}, got {})",
@@ -114,8 +309,8 @@ impl fmt::Display for PbftError {
             PbftError::BlockMismatch(exp, got) => write!(
                 f,
                 "{:?} != {:?}",
-                &hex::encode(exp.get_block_id())[..6],
-                &hex::encode(got.get_block_id())[..6]
+                hex::encode(&exp.get_block_id()[..exp.get_block_id().len().min(3)]),
+                hex::encode(&got.get_block_id()[..got.get_block_id().len().min(3)])
             ),
             PbftError::NodeNotFound => write!(f, "Couldn't find node in the network"),
             PbftError::WrongNumBlocks => write!(f, "Incorrect number of blocks"),
@@ -128,6 +323,221 @@ impl fmt::Display for PbftError {
                 "Message should be from primary, but was sent by secondary"
             ),
             PbftError::NoBlockNew => write!(f, "Got a PrePrepare without a matching BlockNew"),
+            PbftError::WrongNetwork(exp, got) => write!(
+                f,
+                "Message is for network {:?}, but this node is on network {:?}",
+                got, exp
+            ),
+            PbftError::ConflictingSeal(seq_num) => write!(
+                f,
+                "A different consensus seal is already stored for seq number {}",
+                seq_num
+            ),
+            PbftError::InvalidConfig(description) => write!(f, "{}", description),
+            PbftError::ChainVerificationFailed(block_num, err) => {
+                write!(f, "Block {} failed verification: {}", block_num, err)
+            }
+            PbftError::InvalidTimestamp(msg_time, local_time) => write!(
+                f,
+                "Message timestamp {} is too far from local time {}",
+                msg_time, local_time
+            ),
+            PbftError::InsufficientPeers(peers) => write!(
+                f,
+                "A network of {} voting peers can't tolerate any faults",
+                peers
+            ),
+            PbftError::DuplicateVote(signer) => write!(
+                f,
+                "Seal contains more than one vote from {}",
+                hex::encode(signer)
+            ),
+            PbftError::Equivocation(msg_type, signer) => write!(
+                f,
+                "{} sent conflicting {:?} messages for the same view and seq number",
+                hex::encode(signer),
+                msg_type
+            ),
+            PbftError::InvalidPublicKey(key, reason) => {
+                write!(
+                    f,
+                    "Public key {} is malformed: {}",
+                    hex::encode(key),
+                    reason
+                )
+            }
+            PbftError::InvalidMessage(description) => write!(f, "{}", description),
+            PbftError::PhaseTransition(from, to) => {
+                write!(f, "Can't switch from phase {:?} to {:?}", from, to)
+            }
+            PbftError::ForkedBlock(block_id, previous_id) => write!(
+                f,
+                "Block {} builds on {}, which isn't the chain head",
+                hex::encode(block_id),
+                hex::encode(previous_id)
+            ),
+            PbftError::SealPreviousIdMismatch(seal_id, block_id) => write!(
+                f,
+                "Seal's previous ID {} doesn't match block's previous ID {}",
+                hex::encode(seal_id),
+                hex::encode(block_id)
+            ),
+            PbftError::SealSummaryMismatch(seal_summary, block_summary) => write!(
+                f,
+                "Seal's summary {} doesn't match block's summary {}",
+                hex::encode(seal_summary),
+                hex::encode(block_summary)
+            ),
+            PbftError::InsufficientVotes(required, found) => {
+                write!(f, "Need {} votes, only found {}", required, found)
+            }
+            PbftError::UnknownVoters(ids) => write!(
+                f,
+                "Got votes from unexpected nodes: {}",
+                ids.iter().map(hex::encode).collect::<Vec<_>>().join(", ")
+            ),
+            PbftError::EmptySeal(previous_id) => write!(
+                f,
+                "Consensus seal for block {} has no votes",
+                hex::encode(previous_id)
+            ),
+            PbftError::InvalidPeersSetting(setting, problem) => {
+                write!(f, "Invalid peers setting '{}': {}", setting, problem)
+            }
+            PbftError::NotPrepared(block_id) => {
+                write!(f, "Not prepared to commit block {}", hex::encode(block_id))
+            }
+            PbftError::NoConsensusSeal(block_num) => {
+                write!(f, "Block {} has no consensus seal", block_num)
+            }
+            PbftError::SeqNumTooFarAhead(seq_num, current) => write!(
+                f,
+                "Message for sequence number {} is too far past the current one ({})",
+                seq_num, current
+            ),
+            PbftError::SettingsUnavailable(attempts, err) => write!(
+                f,
+                "Couldn't get on-chain settings ({} failed reads in a row): {}",
+                attempts, err
+            ),
+            PbftError::AboveHighWatermark(seq_num, high) => write!(
+                f,
+                "Sequence number {} is past the high watermark ({})",
+                seq_num, high
+            ),
+            PbftError::ViewJump(view, committed_view) => write!(
+                f,
+                "View change to view {} is too far past the last committed block's view ({})",
+                view, committed_view
+            ),
+            PbftError::BlockSummaryMismatch(proposed, validated) => write!(
+                f,
+                "Proposed block's summary {} doesn't match validated block's summary {}",
+                hex::encode(proposed),
+                hex::encode(validated)
+            ),
+            PbftError::BatchRejected(index, err) => {
+                write!(f, "Message {} of the batch was rejected: {}", index, err)
+            }
+            PbftError::UnexpectedPayload(block_num) => write!(
+                f,
+                "Block {} comes before the first sealed block, but has a payload",
+                block_num
+            ),
+            PbftError::UnsupportedSealVersion(version) => {
+                write!(f, "Consensus seal version {} isn't supported", version)
+            }
+            PbftError::SignerMismatch(header_signer, signer) => write!(
+                f,
+                "Vote from {} was signed by {}",
+                hex::encode(signer),
+                hex::encode(header_signer)
+            ),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make sure that only errors that can clear up on their own suggest a retry
+    #[test]
+    fn retry_after() {
+        let recoverable = vec![
+            (PbftError::Timeout, 0),
+            (PbftError::NotReadyForMessage, 10),
+            (
+                PbftError::WrongNumMessages(PbftMessageType::Commit, 3, 2),
+                10,
+            ),
+            (PbftError::NoBlockNew, 100),
+            (PbftError::NoWorkingBlock, 100),
+            (PbftError::SettingsUnavailable(4, "error".into()), 100),
+        ];
+        for (err, millis) in recoverable {
+            assert_eq!(err.retry_after(), Some(Duration::from_millis(millis)));
+        }
+
+        let fatal = vec![
+            PbftError::WrongNumSeals(1, 2),
+            PbftError::BlockMismatch(PbftBlock::new(), PbftBlock::new()),
+            PbftError::MessageMismatch(PbftMessageType::Prepare),
+            PbftError::ViewMismatch(1, 2),
+            PbftError::InternalError("error".into()),
+            PbftError::NodeNotFound,
+            PbftError::WrongNumBlocks,
+            PbftError::NotFromPrimary,
+            PbftError::WrongNetwork("a".into(), "b".into()),
+            PbftError::ConflictingSeal(1),
+            PbftError::InvalidConfig("error".into()),
+            PbftError::ChainVerificationFailed(1, Box::new(PbftError::NodeNotFound)),
+            PbftError::InvalidTimestamp(1, 2),
+            PbftError::InsufficientPeers(3),
+            PbftError::DuplicateVote(vec![1]),
+            PbftError::Equivocation(PbftMessageType::Prepare, vec![1]),
+            PbftError::InvalidPublicKey(vec![1], "error".into()),
+            PbftError::InvalidMessage("error".into()),
+            PbftError::PhaseTransition(PbftPhase::Finished, PbftPhase::Checking),
+            PbftError::ForkedBlock(vec![2], vec![1]),
+            PbftError::SealPreviousIdMismatch(vec![1], vec![2]),
+            PbftError::SealSummaryMismatch(vec![1], vec![2]),
+            PbftError::InsufficientVotes(2, 1),
+            PbftError::UnknownVoters(vec![vec![1]]),
+            PbftError::EmptySeal(vec![1]),
+            PbftError::InvalidPeersSetting("setting".into(), "error".into()),
+            PbftError::NotPrepared(vec![1]),
+            PbftError::NoConsensusSeal(1),
+            PbftError::SeqNumTooFarAhead(100, 1),
+            PbftError::AboveHighWatermark(10, 5),
+            PbftError::ViewJump(10, 1),
+            PbftError::BlockSummaryMismatch(vec![1], vec![2]),
+            PbftError::BatchRejected(1, Box::new(PbftError::NotFromPrimary)),
+            PbftError::UnexpectedPayload(1),
+            PbftError::UnsupportedSealVersion(2),
+            PbftError::SignerMismatch(vec![1], vec![2]),
+        ];
+        for err in fatal {
+            assert_eq!(err.retry_after(), None);
+        }
+    }
+
+    /// Make sure that wrapped errors can be reached through `source()`, and that serialization
+    /// errors say what was being parsed
+    #[test]
+    fn source() {
+        let err = PbftError::SerializationError(
+            "parsing vote message",
+            ProtobufError::MessageNotInitialized {
+                message: "PbftMessage",
+            },
+        );
+        assert!(err.source().is_some());
+        assert!(err.to_string().contains("parsing vote message"));
+
+        let err = PbftError::ChainVerificationFailed(3, Box::new(err));
+        assert!(err.source().and_then(|source| source.source()).is_some());
+
+        assert!(PbftError::NodeNotFound.source().is_none());
+    }
+}
@@ -20,7 +20,7 @@
 use std::convert::From;
 
 use hex;
-use sawtooth_sdk::consensus::engine::{Block, PeerId};
+use sawtooth_sdk::consensus::engine::{Block, BlockId, PeerId};
 use sawtooth_sdk::consensus::service::Service;
 
 use crate::error::PbftError;
@@ -28,7 +28,7 @@ use crate::message_log::PbftLog;
 use crate::message_type::ParsedMessage;
 use crate::message_type::PbftMessageType;
 use crate::protos::pbft_message::{PbftBlock, PbftMessageInfo};
-use crate::state::{PbftPhase, PbftState};
+use crate::state::{PbftMode, PbftPhase, PbftState};
 
 /// Handle a `PrePrepare` message
 ///
@@ -47,8 +47,10 @@ pub fn pre_prepare(
     msg_log: &mut PbftLog,
     message: &ParsedMessage,
 ) -> Result<(), PbftError> {
-    // Check that message is from the current primary
-    if PeerId::from(message.info().get_signer_id()) != state.get_primary_id() {
+    // Check that message is from the primary of the message's view; the primary changes with the
+    // view, so this node's current view can't be used for the check
+    let primary = state.get_primary_id_for_view(message.info().get_view());
+    if PeerId::from(message.info().get_signer_id()) != primary {
         error!(
             "Got PrePrepare from a secondary node {:?}; ignoring message",
             message.info().get_signer_id()
@@ -85,33 +87,70 @@ pub fn pre_prepare(
     // We only switch to Preparing and stop the faulty primary timeout if this is the PrePrepare
     // for the current sequence number
     if message.info().get_seq_num() == state.seq_num {
-        state.switch_phase(PbftPhase::Preparing);
+        state
+            .switch_phase(PbftPhase::Preparing)
+            .or_else(|err| match err {
+                PbftError::PhaseTransition(ref from, ref to) if from == to => Ok(to.clone()),
+                err => Err(err),
+            })?;
         state.faulty_primary_timeout.stop();
     }
 
     Ok(())
 }
 
+/// Handle a `Checkpoint` message
+///
+/// Once `2f + 1` nodes have sent matching checkpoints for a sequence number, it becomes the stable
+/// checkpoint, which bounds how much of the log may be garbage collected.
+pub fn checkpoint(state: &mut PbftState, msg_log: &mut PbftLog, message: &ParsedMessage) {
+    if msg_log.add_checkpoint(message.clone(), state) {
+        let seq_num = message.info().get_seq_num();
+        info!("{}: Checkpoint at seq {} is now stable", state, seq_num);
+        state.advance_low_watermark(seq_num);
+    }
+}
+
+/// Decide whether the block a `Commit` message is for can be committed, and get its ID
+///
+/// The node must be in the `Committing` phase (or already `Finished`, if the block is committed
+/// again); otherwise a `PhaseTransition` error is returned. Nothing is changed, so this can be
+/// checked without a validator.
+pub fn prepare_commit(state: &PbftState, message: &ParsedMessage) -> Result<BlockId, PbftError> {
+    match state.phase {
+        PbftPhase::Committing | PbftPhase::Finished => {
+            Ok(BlockId::from(message.get_block().get_block_id()))
+        }
+        ref phase => Err(PbftError::PhaseTransition(
+            phase.clone(),
+            PbftPhase::Finished,
+        )),
+    }
+}
+
 /// Handle a `Commit` message
 ///
-/// We have received `2f + 1` `Commit` messages so we are ready to commit the block to the chain.
+/// We have received `2f + 1` `Commit` messages so we are ready to commit the block to the chain,
+/// as long as `prepare_commit` agrees.
 #[allow(clippy::ptr_arg)]
 pub fn commit(
     state: &mut PbftState,
     service: &mut Service,
     message: &ParsedMessage,
 ) -> Result<(), PbftError> {
-    info!(
-        "{}: Committing block {:?}",
-        state,
-        message.get_block().block_id.clone()
-    );
+    let block_id = prepare_commit(state, message)?;
+    info!("{}: Committing block {:?}", state, block_id);
 
     service
-        .commit_block(message.get_block().block_id.clone())
+        .commit_block(block_id)
         .map_err(|e| PbftError::InternalError(format!("Failed to commit block: {:?}", e)))?;
 
-    state.switch_phase(PbftPhase::Finished);
+    state
+        .switch_phase(PbftPhase::Finished)
+        .or_else(|err| match err {
+            PbftError::PhaseTransition(ref from, ref to) if from == to => Ok(to.clone()),
+            err => Err(err),
+        })?;
 
     Ok(())
 }
@@ -130,10 +169,19 @@ pub fn view_change(
         return Ok(());
     }
 
+    // The primary being replaced is the target of this view change; only count it once, even if
+    // more `ViewChange` messages arrive after the quorum was reached
+    if vc_message.info().get_view() > state.view {
+        let old_primary = state.get_primary_id();
+        state.add_faulty_primary_strike(&old_primary);
+    }
+
     set_current_view_from_msg(state, vc_message);
 
-    // Upgrade this node to primary, if its ID is correct
+    // Upgrade this node to primary, if its ID is correct. A new primary has to build on the
+    // current chain head right away, so make sure it's caught up first.
     if check_is_primary(state) {
+        sync_to_chain_head(state, service)?;
         become_primary(state, service)
     } else {
         become_secondary(state)
@@ -144,6 +192,21 @@ pub fn view_change(
     Ok(())
 }
 
+/// Adopt the view announced by a `NewView` message, whose `ViewChange` messages have already been
+/// verified
+///
+/// This is how a node that missed some of the `ViewChange` messages learns that the view change
+/// happened. The sender is the new primary, so this node becomes a secondary.
+pub fn new_view(state: &mut PbftState, nv_message: &ParsedMessage) {
+    let old_primary = state.get_primary_id();
+    state.add_faulty_primary_strike(&old_primary);
+
+    set_current_view_from_msg(state, nv_message);
+    become_secondary(state);
+
+    state.discard_current_block();
+}
+
 pub fn force_view_change(state: &mut PbftState, service: &mut Service) {
     let next_view = state.view + 1;
     set_current_view(state, next_view);
@@ -156,6 +219,26 @@ pub fn force_view_change(state: &mut PbftState, service: &mut Service) {
     }
 }
 
+/// Move straight to a view that the rest of the network has already changed to (for instance,
+/// because this node missed the `ViewChange` messages), and take the appropriate role for it
+pub fn catch_up_to_view(state: &mut PbftState, view: u64) {
+    set_current_view(state, view);
+
+    // The rest of the network has settled on this view, so this node's own failed view changes
+    // don't say anything about how long to wait for the new primary
+    state.reset_view_change_backoff();
+
+    if check_is_primary(state) {
+        state.upgrade_role();
+        warn!("{}: I'm now a primary", state);
+    } else {
+        become_secondary(state);
+    }
+
+    state.mode = PbftMode::Normal;
+    state.view_change_timeout.stop();
+}
+
 fn check_received_enough_view_changes(
     state: &PbftState,
     msg_log: &PbftLog,
@@ -166,6 +249,7 @@ fn check_received_enough_view_changes(
         vc_message,
         false,
         2 * state.f + 1,
+        state,
     )
 }
 
@@ -174,7 +258,7 @@ fn set_current_view_from_msg(state: &mut PbftState, vc_message: &ParsedMessage)
 }
 
 fn set_current_view(state: &mut PbftState, view: u64) {
-    state.view = view;
+    state.set_view(view);
     warn!("{}: Updating to view {}", state, state.view);
 }
 
@@ -204,6 +288,32 @@ fn become_primary(state: &mut PbftState, service: &mut Service) {
         .unwrap_or_else(|err| error!("Couldn't initialize block: {}", err));
 }
 
+/// Make sure this node's sequence number follows the validator's chain head
+///
+/// If blocks were committed without this node's state keeping up, it can't propose the next
+/// block, so its sequence number is moved up to the chain head. As when a block is committed, the
+/// working block is dropped, the node goes back to `PrePreparing`, and the commit timeout stops.
+/// The node has none of the head's `Commit`s to seal its next block with; the caller has to ask
+/// the other nodes for them.
+fn sync_to_chain_head(state: &mut PbftState, service: &mut Service) -> Result<(), PbftError> {
+    let head = service
+        .get_chain_head()
+        .map_err(|err| PbftError::InternalError(format!("Couldn't get chain head: {}", err)))?;
+
+    if head.block_num + 1 != state.seq_num {
+        warn!(
+            "{}: Not synced with chain head {}; catching up before becoming primary",
+            state, head.block_num
+        );
+        state.seq_num = head.block_num + 1;
+        state.advance_low_watermark(head.block_num);
+        state.last_committed_block = Some(head.block_id);
+        state.discard_current_block();
+    }
+
+    Ok(())
+}
+
 fn become_secondary(state: &mut PbftState) {
     warn!("{}: I'm now a secondary", state);
     state.downgrade_role();
@@ -241,7 +351,6 @@ mod tests {
     use crate::config;
     use crate::hash::hash_sha256;
     use crate::protos::pbft_message::PbftMessage;
-    use sawtooth_sdk::consensus::engine::BlockId;
 
     fn mock_block_id(num: u64) -> BlockId {
         BlockId::from(hash_sha256(
@@ -301,4 +410,69 @@ mod tests {
         assert_eq!(state0.seq_num, 1);
         assert_eq!(state1.seq_num, 1);
     }
+
+    /// Make sure that a `PrePrepare` is only accepted from the primary of the message's view
+    #[test]
+    fn pre_prepare_from_non_primary() {
+        let cfg = config::mock_config(4);
+        let mut state = PbftState::new(vec![2], 0, &cfg);
+        let mut log = PbftLog::new(&cfg);
+        let block_new = mock_msg(&PbftMessageType::BlockNew, 0, 1, mock_block(1), vec![0]);
+        log.add_message(block_new, &state).unwrap();
+
+        let forged = mock_msg(&PbftMessageType::PrePrepare, 0, 1, mock_block(1), vec![3]);
+        match pre_prepare(&mut state, &mut log, &forged) {
+            Err(PbftError::NotFromPrimary) => {}
+            res => panic!("Expected NotFromPrimary, got {:?}", res),
+        }
+        assert!(log
+            .get_messages_of_type_seq(&PbftMessageType::PrePrepare, 1)
+            .is_empty());
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+
+        // Node 1 is the primary of view 1, so its PrePrepare only fails for being in a view this
+        // node isn't in yet
+        let future = mock_msg(&PbftMessageType::PrePrepare, 1, 1, mock_block(1), vec![1]);
+        match pre_prepare(&mut state, &mut log, &future) {
+            Err(PbftError::ViewMismatch(1, 0)) => {}
+            res => panic!("Expected ViewMismatch, got {:?}", res),
+        }
+
+        // Once this node is in view 1, node 0 is no longer the primary
+        state.set_view(1);
+        let stale = mock_msg(&PbftMessageType::PrePrepare, 1, 1, mock_block(1), vec![0]);
+        match pre_prepare(&mut state, &mut log, &stale) {
+            Err(PbftError::NotFromPrimary) => {}
+            res => panic!("Expected NotFromPrimary, got {:?}", res),
+        }
+        assert!(pre_prepare(&mut state, &mut log, &future).is_ok());
+        assert_eq!(state.phase, PbftPhase::Preparing);
+    }
+
+    /// Make sure that a block can only be committed from the `Committing` (or `Finished`) phase,
+    /// and that it's the block from the `Commit`
+    #[test]
+    fn prepare_commit_phases() {
+        let cfg = config::mock_config(4);
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        let msg = mock_msg(&PbftMessageType::Commit, 0, 1, mock_block(1), vec![0]);
+
+        for phase in &[
+            PbftPhase::PrePreparing,
+            PbftPhase::Preparing,
+            PbftPhase::Checking,
+        ] {
+            state.set_phase(phase.clone());
+            match prepare_commit(&state, &msg) {
+                Err(PbftError::PhaseTransition(ref from, PbftPhase::Finished)) if from == phase => {
+                }
+                res => panic!("Expected PhaseTransition, got {:?}", res),
+            }
+        }
+
+        for phase in &[PbftPhase::Committing, PbftPhase::Finished] {
+            state.set_phase(phase.clone());
+            assert_eq!(prepare_commit(&state, &msg).unwrap(), mock_block_id(1));
+        }
+    }
 }
@@ -3,6 +3,48 @@ use openssl::sha::{Sha256, Sha512};
 
 use crate::error::PbftError;
 
+/// Name of the hash algorithm used when none is configured
+pub const DEFAULT_HASH_ALGORITHM: &str = "sha512";
+
+/// Digest used to check that a signed message's content matches its header
+///
+/// Validators put the digest in the `content_sha512` field of `ConsensusPeerMessageHeader`
+/// whatever the algorithm is, so a node must only be configured with an algorithm other than
+/// SHA-512 if its validator is configured to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl Default for HashAlgorithm {
+    fn default() -> Self {
+        HashAlgorithm::Sha512
+    }
+}
+
+impl HashAlgorithm {
+    /// Get the hash algorithm with the given name
+    pub fn from_name(name: &str) -> Result<Self, PbftError> {
+        match name {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            _ => Err(PbftError::InvalidConfig(format!(
+                "Unsupported hash algorithm: {}",
+                name
+            ))),
+        }
+    }
+
+    /// Hashes the given bytes with this algorithm
+    pub fn hash(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha256 => hash_sha256(bytes),
+            HashAlgorithm::Sha512 => hash_sha512(bytes),
+        }
+    }
+}
+
 /// Hashes the given bytes with SHA-256
 pub fn hash_sha256(bytes: &[u8]) -> Vec<u8> {
     let mut sha = Sha256::new();
@@ -23,9 +65,16 @@ pub fn hash_sha512(bytes: &[u8]) -> Vec<u8> {
 
 /// Verifies that the SHA-512 hash of the given content matches the given hash
 pub fn verify_sha512(content: &[u8], content_hash: &[u8]) -> Result<(), PbftError> {
-    let computed_sha512 = hash_sha512(&content);
+    verify_digest(HashAlgorithm::Sha512, content, content_hash)
+}
 
-    if computed_sha512 != content_hash {
+/// Verifies that the hash of the given content, computed with `algorithm`, matches the given hash
+pub fn verify_digest(
+    algorithm: HashAlgorithm,
+    content: &[u8],
+    content_hash: &[u8],
+) -> Result<(), PbftError> {
+    if algorithm.hash(content) != content_hash {
         Err(PbftError::InternalError(format!(
             "Hash verification failed! Content: `{:?}`, Hash: `{:?}`",
             content, content_hash
@@ -34,3 +83,47 @@ pub fn verify_sha512(content: &[u8], content_hash: &[u8]) -> Result<(), PbftErro
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make sure that a digest only verifies with the algorithm it was computed with, and only for
+    /// the content it was computed from
+    #[test]
+    fn verify_digest_per_algorithm() {
+        let content = b"block".to_vec();
+        for algorithm in &[HashAlgorithm::Sha256, HashAlgorithm::Sha512] {
+            let digest = algorithm.hash(&content);
+            assert!(verify_digest(*algorithm, &content, &digest).is_ok());
+            assert!(verify_digest(*algorithm, b"other", &digest).is_err());
+
+            let mut corrupted = digest.clone();
+            corrupted[0] ^= 1;
+            assert!(verify_digest(*algorithm, &content, &corrupted).is_err());
+        }
+
+        let sha512 = hash_sha512(&content);
+        assert!(verify_sha512(&content, &sha512).is_ok());
+        assert!(verify_digest(HashAlgorithm::Sha256, &content, &sha512).is_err());
+        assert!(verify_digest(HashAlgorithm::Sha512, &content, &hash_sha256(&content)).is_err());
+    }
+
+    /// Make sure that only known hash algorithms can be picked, and that SHA-512 is the default
+    #[test]
+    fn supported_algorithms() {
+        assert_eq!(
+            HashAlgorithm::from_name("sha256").unwrap(),
+            HashAlgorithm::Sha256
+        );
+        assert_eq!(
+            HashAlgorithm::from_name("sha512").unwrap(),
+            HashAlgorithm::Sha512
+        );
+        assert!(HashAlgorithm::from_name("md5").is_err());
+        assert_eq!(
+            HashAlgorithm::from_name(DEFAULT_HASH_ALGORITHM).unwrap(),
+            HashAlgorithm::default()
+        );
+    }
+}
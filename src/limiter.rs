@@ -0,0 +1,129 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Bounds the number of threads that can be verifying consensus seal votes at once
+
+use std::sync::{Condvar, Mutex};
+
+/// A counting semaphore limiting how many verifications may be in progress at the same time
+///
+/// Each thread verifying votes holds a slot while it works; threads beyond the limit block in
+/// `acquire` until a running one finishes, so a flood of blocks queues up instead of exhausting
+/// the node's resources.
+#[derive(Debug)]
+pub struct VerificationLimiter {
+    max_concurrent: usize,
+    active: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Permission to run one verification; the slot is released when this is dropped
+pub struct VerificationPermit<'a> {
+    limiter: &'a VerificationLimiter,
+}
+
+impl VerificationLimiter {
+    /// Create a limiter allowing `max_concurrent` verifications at once (at least 1)
+    pub fn new(max_concurrent: usize) -> Self {
+        VerificationLimiter {
+            max_concurrent: max_concurrent.max(1),
+            active: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait for a free slot and take it
+    pub fn acquire(&self) -> VerificationPermit<'_> {
+        let mut active = self
+            .active
+            .lock()
+            .expect("Verification limiter lock poisoned");
+        while *active >= self.max_concurrent {
+            active = self
+                .released
+                .wait(active)
+                .expect("Verification limiter lock poisoned");
+        }
+        *active += 1;
+
+        VerificationPermit { limiter: self }
+    }
+
+    /// How many verifications are currently running
+    pub fn active(&self) -> usize {
+        *self
+            .active
+            .lock()
+            .expect("Verification limiter lock poisoned")
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+}
+
+impl<'a> Drop for VerificationPermit<'a> {
+    fn drop(&mut self) {
+        let mut active = self
+            .limiter
+            .active
+            .lock()
+            .expect("Verification limiter lock poisoned");
+        *active -= 1;
+        self.limiter.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Submit more verifications than the limit allows and make sure that no more than the limit
+    /// ever run at once, and that all of them eventually run
+    #[test]
+    fn concurrency_cap() {
+        let limiter = Arc::new(VerificationLimiter::new(3));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let completed = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..12)
+            .map(|_| {
+                let limiter = limiter.clone();
+                let peak = peak.clone();
+                let completed = completed.clone();
+                thread::spawn(move || {
+                    let _permit = limiter.acquire();
+                    peak.fetch_max(limiter.active(), Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    completed.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        assert_eq!(completed.load(Ordering::SeqCst), 12);
+        assert_eq!(limiter.active(), 0);
+    }
+}
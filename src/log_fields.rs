@@ -0,0 +1,103 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Key-value fields for log records, so that logs can be filtered by view, block, and so on
+
+use std::fmt;
+
+use hex;
+
+use crate::state::PbftState;
+
+/// Fields attached to a log record, written after its message as `key=value` pairs
+///
+/// The pairs are separated by spaces and values never contain spaces, so a log pipeline can pick
+/// them out of the record without knowing what the message says.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFields {
+    fields: Vec<(&'static str, String)>,
+}
+
+impl LogFields {
+    /// Start with the view and sequence number of the given state
+    pub fn for_state(state: &PbftState) -> Self {
+        LogFields {
+            fields: vec![
+                ("view", state.view.to_string()),
+                ("seq_num", state.seq_num.to_string()),
+            ],
+        }
+    }
+
+    /// Add the ID of the block the record is about
+    pub fn block_id(mut self, block_id: &[u8]) -> Self {
+        self.fields.push(("block_id", hex::encode(block_id)));
+        self
+    }
+
+    /// Add the type of the message the record is about
+    pub fn msg_type(mut self, msg_type: &str) -> Self {
+        self.fields.push(("msg_type", msg_type.to_string()));
+        self
+    }
+
+    /// The fields, in the order they were added
+    pub fn fields(&self) -> &[(&'static str, String)] {
+        &self.fields
+    }
+}
+
+impl fmt::Display for LogFields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pairs: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        write!(f, "[{}]", pairs.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::mock_config;
+
+    /// Make sure that the fields for a state, block, and message type are all there, in order
+    #[test]
+    fn fields() {
+        let mut state = PbftState::new(vec![0], 4, &mock_config(4));
+        state.view = 2;
+
+        let fields = LogFields::for_state(&state)
+            .block_id(&[0xab, 0xcd])
+            .msg_type("Commit");
+        assert_eq!(
+            fields.fields(),
+            &[
+                ("view", "2".to_string()),
+                ("seq_num", "5".to_string()),
+                ("block_id", "abcd".to_string()),
+                ("msg_type", "Commit".to_string()),
+            ]
+        );
+        assert_eq!(
+            fields.to_string(),
+            "[view=2 seq_num=5 block_id=abcd msg_type=Commit]"
+        );
+    }
+}
@@ -26,6 +26,7 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+use std::path::PathBuf;
 use std::process;
 
 use clap::{clap_app, crate_version};
@@ -39,14 +40,20 @@ pub mod engine;
 pub mod error;
 pub mod handlers;
 pub mod hash;
+pub mod limiter;
+pub mod log_fields;
 pub mod message_extensions;
 pub mod message_log;
 pub mod message_type;
+pub mod metrics;
 pub mod node;
 mod protos;
+pub mod recorder;
+pub mod signing;
 pub mod state;
 pub mod storage;
 pub mod timing;
+pub mod transport;
 
 fn main() {
     let args = parse_args();
@@ -85,7 +92,9 @@ fn main() {
 
     warn!("Sawtooth PBFT Engine ({})", env!("CARGO_PKG_VERSION"));
 
-    let pbft_engine = engine::PbftEngine::new();
+    let mut pbft_engine = engine::PbftEngine::with_peers_setting_key(&args.peers_setting_key);
+    pbft_engine.set_verify_only(args.verify_only);
+    pbft_engine.set_update_recording(args.record_updates);
 
     let (driver, _stop) = ZmqDriver::new();
 
@@ -122,7 +131,13 @@ fn parse_args() -> PbftCliArgs {
         (@arg verbose: -v --verbose +multiple
          "increase output verbosity")
         (@arg logconfig: -L --log_config +takes_value
-         "path to logging config file"))
+         "path to logging config file")
+        (@arg peers_setting_key: --peers_setting_key +takes_value
+         "on-chain setting that lists the PBFT peers")
+        (@arg verify_only: --verify_only
+         "only follow and verify the chain, without taking part in consensus")
+        (@arg record_updates: --record_updates +takes_value
+         "file to record the updates the engine handles to, for replaying later"))
     .get_matches();
 
     let log_config = matches.value_of("logconfig").map(|s| s.into());
@@ -140,10 +155,23 @@ fn parse_args() -> PbftCliArgs {
             .unwrap_or("tcp://localhost:5050"),
     );
 
+    let peers_setting_key = String::from(
+        matches
+            .value_of("peers_setting_key")
+            .unwrap_or(config::DEFAULT_PEERS_SETTING_KEY),
+    );
+
+    let verify_only = matches.is_present("verify_only");
+
+    let record_updates = matches.value_of("record_updates").map(PathBuf::from);
+
     PbftCliArgs {
         log_config,
         log_level,
         endpoint,
+        peers_setting_key,
+        verify_only,
+        record_updates,
     }
 }
 
@@ -151,4 +179,7 @@ pub struct PbftCliArgs {
     log_config: Option<String>,
     log_level: log::LevelFilter,
     endpoint: String,
+    peers_setting_key: String,
+    verify_only: bool,
+    record_updates: Option<PathBuf>,
 }
@@ -28,12 +28,15 @@ use hex;
 
 use crate::message_type::PbftMessageType;
 use crate::protos::pbft_message::{
-    PbftBlock, PbftMessage, PbftMessageInfo, PbftSeal, PbftSignedCommitVote, PbftViewChange,
+    PbftBlock, PbftMessage, PbftMessageInfo, PbftNewView, PbftSeal, PbftSignedCommitVote,
+    PbftViewChange, PbftVoteRequest,
 };
 
 impl Eq for PbftMessage {}
 impl Eq for PbftSeal {}
 impl Eq for PbftViewChange {}
+impl Eq for PbftNewView {}
+impl Eq for PbftVoteRequest {}
 
 impl Hash for PbftMessageInfo {
     fn hash<H: Hasher>(&self, state: &mut H) {
@@ -85,6 +88,22 @@ impl Hash for PbftViewChange {
     }
 }
 
+impl Hash for PbftNewView {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.get_info().hash(state);
+        for view_change in self.get_view_changes() {
+            view_change.hash(state);
+        }
+    }
+}
+
+impl Hash for PbftVoteRequest {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.get_info().hash(state);
+        self.get_vote_type().hash(state);
+    }
+}
+
 impl fmt::Display for PbftMessageInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -93,7 +112,7 @@ impl fmt::Display for PbftMessageInfo {
             PbftMessageType::from(self.get_msg_type()),
             self.get_seq_num(),
             self.get_view(),
-            &hex::encode(self.get_signer_id())[..6],
+            hex::encode(&self.get_signer_id()[..self.get_signer_id().len().min(3)]),
         )
     }
 }
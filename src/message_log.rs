@@ -19,7 +19,8 @@
 
 #![allow(unknown_lints)]
 
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
 use std::fmt;
 
 use hex;
@@ -27,10 +28,13 @@ use itertools::Itertools;
 
 use crate::config::PbftConfig;
 use crate::error::PbftError;
-use crate::message_type::{ParsedMessage, PbftMessageType};
-use crate::protos::pbft_message::{PbftMessageInfo, PbftSeal};
+use crate::message_type::{ParsedMessage, PbftMessageType, PbftMessageWrapper};
+use crate::protos::pbft_message::{PbftBlock, PbftMessageInfo, PbftSeal};
 use crate::state::PbftState;
-use sawtooth_sdk::consensus::engine::BlockId;
+use sawtooth_sdk::consensus::engine::{BlockId, PeerId};
+
+/// How many pairs of conflicting votes are kept as evidence
+const EQUIVOCATION_HISTORY: usize = 100;
 
 /// Stores a consensus seal along with its associated sequence number and block ID
 #[derive(Clone, Eq, Hash, PartialEq)]
@@ -40,26 +44,77 @@ struct PbftSealEntry {
     seal: PbftSeal,
 }
 
+/// Summary of the contents of a `PbftLog`, for diagnostics
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogStats {
+    /// Number of messages in the log
+    pub len: usize,
+
+    /// Number of messages waiting in the backlog
+    pub backlog_len: usize,
+
+    /// Number of stored consensus seals
+    pub seal_count: usize,
+
+    /// Lowest and highest sequence numbers of the messages in the log, if there are any
+    pub seq_range: Option<(u64, u64)>,
+
+    /// Number of messages in the log of each message type
+    pub message_type_counts: HashMap<String, usize>,
+}
+
 /// Struct for storing messages that a PbftNode receives
 pub struct PbftLog {
-    /// Generic messages (BlockNew, PrePrepare, Prepare, Commit)
-    messages: HashSet<ParsedMessage>,
+    /// Generic messages (BlockNew, PrePrepare, Prepare, Commit), by their contents, so that a
+    /// message that arrives more than once is found without scanning the log
+    messages: HashMap<PbftMessageWrapper, ParsedMessage>,
 
     /// Maximum log size, defined from on-chain settings
     max_log_size: u64,
 
+    /// Number of messages past which the oldest ones are evicted, regardless of garbage collection
+    max_log_messages: u64,
+
+    /// Voting weight of each peer, if quorums are weighted; empty if every node counts once
+    peer_weights: HashMap<PeerId, u64>,
+
+    /// How many of the most recently committed sequence numbers survive garbage collection
+    gc_retain_depth: u64,
+
     /// Backlog of messages (from peers) with sender's ID
     backlog: VecDeque<ParsedMessage>,
 
+    /// Maximum number of messages in the backlog, defined from on-chain settings
+    max_backlog_size: u64,
+
+    /// How far ahead of the current sequence number a message may be (0 for no limit)
+    max_future_seq_distance: u64,
+
     /// PBFT consensus seals that are stored in case a view change is needed
     seals: HashSet<PbftSealEntry>,
+
+    /// Whether checkpoints are in use, and so bound garbage collection
+    checkpoints_enabled: bool,
+
+    /// `Checkpoint` messages for sequence numbers above the stable checkpoint
+    checkpoints: HashSet<ParsedMessage>,
+
+    /// Highest sequence number for which `2f + 1` matching checkpoints have been received
+    stable_checkpoint: u64,
+
+    /// Pairs of conflicting votes from the same signer, oldest first; the first of each was
+    /// accepted into the log, the second was rejected
+    equivocations: VecDeque<(ParsedMessage, ParsedMessage)>,
+
+    /// Sequence number and view that each recently committed block was committed in, by block ID
+    commit_views: HashMap<BlockId, (u64, u64)>,
 }
 
 impl fmt::Display for PbftLog {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg_infos: Vec<PbftMessageInfo> = self
             .messages
-            .iter()
+            .values()
             .map(|ref msg| msg.info().clone())
             .collect();
         let string_infos: Vec<String> = msg_infos
@@ -82,10 +137,20 @@ impl fmt::Display for PbftLog {
 impl PbftLog {
     pub fn new(config: &PbftConfig) -> Self {
         PbftLog {
-            messages: HashSet::new(),
+            messages: HashMap::new(),
             max_log_size: config.max_log_size,
+            max_log_messages: config.max_log_messages,
+            peer_weights: config.peer_weights.clone(),
+            gc_retain_depth: config.gc_retain_depth,
             backlog: VecDeque::new(),
+            max_backlog_size: config.max_backlog_size,
+            max_future_seq_distance: config.max_future_seq_distance,
             seals: HashSet::new(),
+            checkpoints_enabled: config.checkpoint_period > 0,
+            checkpoints: HashSet::new(),
+            stable_checkpoint: 0,
+            equivocations: VecDeque::new(),
+            commit_views: HashMap::new(),
         }
     }
 
@@ -94,11 +159,15 @@ impl PbftLog {
     ///  + A `PrePrepare` message matching the original message (in the current view)
     ///  + `2f + 1` matching `Prepare` messages from different nodes that match
     ///    `PrePrepare` message above (including its own)
-    pub fn check_prepared(&self, info: &PbftMessageInfo, f: u64) -> bool {
+    pub fn check_prepared(&self, info: &PbftMessageInfo, state: &PbftState) -> bool {
         match self.get_one_msg(info, &PbftMessageType::PrePrepare) {
-            Some(msg) => {
-                self.log_has_required_msgs(&PbftMessageType::Prepare, &msg, true, 2 * f + 1)
-            }
+            Some(msg) => self.log_has_required_msgs(
+                &PbftMessageType::Prepare,
+                &msg,
+                true,
+                2 * state.f + 1,
+                state,
+            ),
             None => false,
         }
     }
@@ -109,16 +178,17 @@ impl PbftLog {
     ///   + `check_prepared` is true
     ///   + This node has accepted `2f + 1` `Commit` messages, including its own, that match the
     ///     corresponding `PrePrepare` message
-    pub fn check_committable(&self, info: &PbftMessageInfo, f: u64) -> bool {
+    pub fn check_committable(&self, info: &PbftMessageInfo, state: &PbftState) -> bool {
         // Check if Prepared predicate is true
-        self.check_prepared(info, f)
+        self.check_prepared(info, state)
             && self.log_has_required_msgs(
                 &PbftMessageType::Commit,
                 &self
                     .get_one_msg(info, &PbftMessageType::PrePrepare)
                     .unwrap(),
                 true,
-                2 * f + 1,
+                2 * state.f + 1,
+                state,
             )
     }
 
@@ -135,12 +205,17 @@ impl PbftLog {
 
     /// Check if the log contains `required` number of messages with type `msg_type` that match the
     /// sequence and view number of the provided `ref_msg`, as well as its block (optional)
+    ///
+    /// Each signer is only counted once, so this node's own vote counts toward the quorum exactly
+    /// once no matter how many copies of it are in the log. Observers don't vote, so their
+    /// messages aren't counted.
     pub fn log_has_required_msgs(
         &self,
         msg_type: &PbftMessageType,
         ref_msg: &ParsedMessage,
         check_block: bool,
         required: u64,
+        state: &PbftState,
     ) -> bool {
         let msgs = self.get_messages_of_type_seq_view(
             msg_type,
@@ -148,20 +223,228 @@ impl PbftLog {
             ref_msg.info().get_view(),
         );
 
-        let msgs = if check_block {
-            msgs.iter()
-                .filter(|msg| msg.get_block() == ref_msg.get_block())
-                .cloned()
-                .collect()
-        } else {
-            msgs
-        };
+        let signers: HashSet<&[u8]> = msgs
+            .iter()
+            .filter(|msg| !check_block || msg.get_block() == ref_msg.get_block())
+            .filter(|msg| !is_observer(msg, state))
+            .map(|msg| msg.info().get_signer_id())
+            .collect();
+
+        self.has_quorum(signers, required)
+    }
+
+    /// Count the distinct signers of the messages of type `msg_type` for the given view and
+    /// sequence number, regardless of which block they're for; observers aren't counted
+    pub fn count_distinct_signers(
+        &self,
+        msg_type: &PbftMessageType,
+        view: u64,
+        seq_num: u64,
+        state: &PbftState,
+    ) -> usize {
+        self.get_messages_of_type_seq_view(msg_type, seq_num, view)
+            .iter()
+            .filter(|msg| !is_observer(msg, state))
+            .map(|msg| msg.info().get_signer_id())
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// Check if the log has a `Commit` signed by the given peer for the given view and sequence
+    /// number, whatever block it's for
+    pub fn has_commit_from(&self, peer: &PeerId, view: u64, seq_num: u64) -> bool {
+        self.get_messages_of_type_seq_view(&PbftMessageType::Commit, seq_num, view)
+            .iter()
+            .any(|msg| msg.info().get_signer_id() == peer.as_slice())
+    }
+
+    /// Get the `ViewChange` the given peer sent for the given view, whatever sequence number it's
+    /// for
+    pub fn view_change_from(&self, peer: &[u8], view: u64) -> Option<&ParsedMessage> {
+        self.iter_matching(|msg| {
+            let info = msg.info();
+            info.get_msg_type() == PbftMessageType::ViewChange.as_str()
+                && info.get_view() == view
+                && info.get_signer_id() == peer
+        })
+        .next()
+    }
+
+    /// Check if the given distinct signers are enough to meet a quorum of `required` nodes
+    ///
+    /// Without peer weights, that's just a count. With them, the signers' total weight must be at
+    /// least the same fraction of the network's total weight as `required` is of the number of
+    /// peers, so a network where every peer has the same weight behaves as if it were unweighted.
+    pub fn has_quorum<'a, I: IntoIterator<Item = &'a [u8]>>(
+        &self,
+        signers: I,
+        required: u64,
+    ) -> bool {
+        if self.peer_weights.is_empty() {
+            return signers.into_iter().count() as u64 >= required;
+        }
+
+        self.signer_weight(signers) >= self.quorum_weight(required)
+    }
 
-        msgs.len() as u64 >= required
+    /// Get the total weight of the given distinct signers; without peer weights, each counts as 1
+    pub fn signer_weight<'a, I: IntoIterator<Item = &'a [u8]>>(&self, signers: I) -> u64 {
+        if self.peer_weights.is_empty() {
+            return signers.into_iter().count() as u64;
+        }
+
+        signers
+            .into_iter()
+            .map(|signer| *self.peer_weights.get(signer).unwrap_or(&0))
+            .fold(0u64, u64::saturating_add)
+    }
+
+    /// Get the weight that signers need between them to meet a quorum of `required` nodes (see
+    /// `has_quorum`); without peer weights, that's `required` itself
+    pub fn quorum_weight(&self, required: u64) -> u64 {
+        if self.peer_weights.is_empty() {
+            return required;
+        }
+
+        // The weights are checked to cover exactly the peers (see `check_peer_weights`)
+        let total: u128 = self.peer_weights.values().map(|w| u128::from(*w)).sum();
+        let peers = self.peer_weights.len() as u128;
+
+        // The smallest weight with weight / total >= required / peers
+        let weight = (u128::from(required) * total).div_ceil(peers);
+        u64::try_from(weight).unwrap_or(u64::MAX)
+    }
+
+    /// Replace the peer weights that quorums are measured by; with none, quorums are counted in
+    /// nodes
+    pub fn set_peer_weights(&mut self, peer_weights: HashMap<PeerId, u64>) {
+        self.peer_weights = peer_weights;
+    }
+
+    /// Tell if quorums are measured by peer weights instead of node counts
+    pub fn is_weighted(&self) -> bool {
+        !self.peer_weights.is_empty()
     }
 
     /// Add a generic PBFT message to the log
+    ///
+    /// A `PrePrepare`, `Prepare`, or `Commit` for a different block than one already in the log
+    /// from the same signer, view, and sequence number is rejected as an equivocation, so that it
+    /// can't count toward a quorum; the pair is kept as evidence.
+    ///
+    /// Messages other than `ViewChange`s and `BlockNew`s for more than `max_future_seq_distance`
+    /// sequence numbers past the current one are rejected with a `SeqNumTooFarAhead` error; ones
+    /// just ahead are kept, since they're needed to catch up. A `BlockNew` is only added once the
+    /// block's seal has been verified, and a node catching up needs them however far ahead they
+    /// are.
     pub fn add_message(&mut self, msg: ParsedMessage, state: &PbftState) -> Result<(), PbftError> {
+        self.check_message(&msg, state)?;
+        self.insert_message(msg, state);
+
+        Ok(())
+    }
+
+    /// Add a batch of votes to the log, such as the ones from a consensus seal
+    ///
+    /// The votes must all be of one type and for one sequence number. The view and sequence
+    /// number are checked once for the whole batch: like with `add_seal_vote`, the votes may be
+    /// from an earlier view than this node's, but none may be from a later one, and the sequence
+    /// number can't be more than `max_future_seq_distance` past the current one. Each vote is
+    /// then checked for equivocations, against the log and against the rest of the batch.
+    ///
+    /// Nothing is added unless every vote passes, and a rejected batch leaves the log (including
+    /// its equivocations) as it was; the first vote that doesn't pass is reported with its index
+    /// in a `BatchRejected` error.
+    pub fn add_messages(
+        &mut self,
+        msgs: Vec<ParsedMessage>,
+        state: &PbftState,
+    ) -> Result<(), PbftError> {
+        if let Err((index, err)) = self.check_batch(&msgs, state) {
+            warn!("Rejecting batch of {} messages: {}", msgs.len(), err);
+            return Err(PbftError::BatchRejected(index, Box::new(err)));
+        }
+
+        for msg in msgs {
+            self.insert_message(msg, state);
+        }
+
+        Ok(())
+    }
+
+    /// Check that a batch of votes can be added to the log: see `add_messages`
+    fn check_batch(
+        &self,
+        msgs: &[ParsedMessage],
+        state: &PbftState,
+    ) -> Result<(), (usize, PbftError)> {
+        let first = match msgs.first() {
+            Some(msg) => msg.info(),
+            None => return Ok(()),
+        };
+        let msg_type = PbftMessageType::from(first.get_msg_type());
+        let seq_num = first.get_seq_num();
+        if !msg_type.is_multicast() {
+            return Err((
+                0,
+                PbftError::InvalidMessage(format!("{:?}s can't be added as a batch", msg_type)),
+            ));
+        }
+        if let Some(index) = msgs.iter().position(|msg| {
+            msg.info().get_msg_type() != first.get_msg_type() || msg.info().get_seq_num() != seq_num
+        }) {
+            return Err((index, PbftError::MessageMismatch(msg_type)));
+        }
+
+        if let Some((index, view)) = msgs
+            .iter()
+            .map(|msg| msg.info().get_view())
+            .enumerate()
+            .max_by_key(|(_, view)| *view)
+            .filter(|(_, view)| *view > state.view)
+        {
+            return Err((
+                index,
+                PbftError::ViewMismatch(view as usize, state.view as usize),
+            ));
+        }
+        if self.max_future_seq_distance > 0
+            && seq_num > state.seq_num + self.max_future_seq_distance
+        {
+            return Err((0, PbftError::SeqNumTooFarAhead(seq_num, state.seq_num)));
+        }
+
+        // The block each signer voted for in each view, first from the log and then from the
+        // batch itself
+        let mut votes: HashMap<(u64, &[u8]), &PbftBlock> = self
+            .iter_matching(|msg| {
+                msg.info().get_msg_type() == first.get_msg_type()
+                    && msg.info().get_seq_num() == seq_num
+            })
+            .map(|msg| {
+                (
+                    (msg.info().get_view(), msg.info().get_signer_id()),
+                    msg.get_block(),
+                )
+            })
+            .collect();
+        for (index, msg) in msgs.iter().enumerate() {
+            let key = (msg.info().get_view(), msg.info().get_signer_id());
+            match votes.insert(key, msg.get_block()) {
+                Some(block) if block != msg.get_block() => {
+                    return Err((index, PbftError::Equivocation(msg_type, key.1.to_vec())));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that a message can be added to the log: see `add_message`
+    ///
+    /// A conflicting vote is kept as evidence of the equivocation.
+    fn check_message(&mut self, msg: &ParsedMessage, state: &PbftState) -> Result<(), PbftError> {
         // Except for ViewChanges, the message must be for the current view to be accepted
         let msg_type = PbftMessageType::from(msg.info().get_msg_type());
         if msg_type != PbftMessageType::ViewChange && msg.info().get_view() != state.view {
@@ -176,19 +459,198 @@ impl PbftLog {
             ));
         }
 
-        self.messages.insert(msg);
-        trace!("{}", self);
+        let seq_num = msg.info().get_seq_num();
+        if msg_type != PbftMessageType::ViewChange
+            && msg_type != PbftMessageType::BlockNew
+            && self.max_future_seq_distance > 0
+            && seq_num > state.seq_num + self.max_future_seq_distance
+        {
+            warn!(
+                "Got message for sequence number {}, too far past {}",
+                seq_num, state.seq_num
+            );
+            return Err(PbftError::SeqNumTooFarAhead(seq_num, state.seq_num));
+        }
+
+        if msg_type.is_multicast() {
+            if let Some(existing) = self.find_conflicting_vote(msg) {
+                warn!(
+                    "Got conflicting {:?} messages from {} at view {} and seq num {}",
+                    msg_type,
+                    hex::encode(msg.info().get_signer_id()),
+                    msg.info().get_view(),
+                    msg.info().get_seq_num(),
+                );
+                let signer = msg.info().get_signer_id().to_vec();
+                self.record_equivocation(existing, msg.clone());
+                return Err(PbftError::Equivocation(msg_type, signer));
+            }
+        }
 
         Ok(())
     }
 
+    /// Find a message in the log of the same type, view, sequence number, and signer as `msg`,
+    /// but for a different block
+    fn find_conflicting_vote(&self, msg: &ParsedMessage) -> Option<ParsedMessage> {
+        self.iter_matching(|existing| votes_conflict(existing, msg))
+            .next()
+            .cloned()
+    }
+
+    /// Keep a vote that conflicts with an accepted one as evidence, unless it's already kept;
+    /// once `EQUIVOCATION_HISTORY` pairs are kept, the oldest is forgotten
+    fn record_equivocation(&mut self, accepted: ParsedMessage, rejected: ParsedMessage) {
+        if self
+            .equivocations
+            .iter()
+            .any(|(_, kept)| kept.message == rejected.message)
+        {
+            return;
+        }
+        if self.equivocations.len() >= EQUIVOCATION_HISTORY {
+            self.equivocations.pop_front();
+        }
+        self.equivocations.push_back((accepted, rejected));
+    }
+
+    /// Get the conflicting votes that have been received from the same signers, as pairs of the
+    /// vote that was accepted and the one that was rejected, oldest first
+    pub fn equivocations(&self) -> &VecDeque<(ParsedMessage, ParsedMessage)> {
+        &self.equivocations
+    }
+
+    /// Add a vote taken from a consensus seal to the log
+    ///
+    /// Unlike `add_message`, the vote doesn't have to be for the current view: when catching up,
+    /// the votes for a block may have been cast before a view change this node has already seen.
+    pub fn add_seal_vote(&mut self, msg: ParsedMessage, state: &PbftState) {
+        self.insert_message(msg, state);
+    }
+
+    fn insert_message(&mut self, msg: ParsedMessage, state: &PbftState) {
+        // The same message can reach the log more than once (e.g. this node's own message through
+        // the self-send path, or a peer's vote both from the network and from a consensus seal).
+        // Only keep one copy, unless the new copy is signed and the existing one is an unsigned
+        // peer message; the signed copy is needed for building consensus seals.
+        if let Some(existing) = self.messages.get(&msg.message) {
+            let replace = !existing.from_self
+                && existing.header_bytes.is_empty()
+                && !msg.from_self
+                && !msg.header_bytes.is_empty();
+            if !replace {
+                trace!("Ignoring duplicate message: {}", msg.info());
+                return;
+            }
+        }
+
+        self.messages.insert(msg.message.clone(), msg);
+        trace!("{}", self);
+
+        if self.messages.len() as u64 > self.max_log_messages {
+            self.evict_farthest(state);
+        }
+    }
+
+    /// Remove the message that is farthest from the current sequence number and then the current
+    /// view (ties broken by type and signer), among those that aren't needed for the current
+    /// sequence number or for building the seal for the previous one
+    fn evict_farthest(&mut self, state: &PbftState) {
+        let oldest = self
+            .messages
+            .values()
+            .filter(|msg| Self::is_evictable(msg, state))
+            .max_by_key(|msg| {
+                let info = msg.info();
+                (
+                    info.get_seq_num().abs_diff(state.seq_num),
+                    info.get_view().abs_diff(state.view),
+                    info.get_msg_type().to_string(),
+                    info.get_signer_id().to_vec(),
+                )
+            })
+            .cloned();
+
+        match oldest {
+            Some(msg) => {
+                warn!(
+                    "Log is over its limit of {} messages; evicting {}",
+                    self.max_log_messages,
+                    msg.info()
+                );
+                self.messages.remove(&msg.message);
+            }
+            None => warn!(
+                "Log has {} messages, over its limit of {}, but all of them are still needed",
+                self.messages.len(),
+                self.max_log_messages
+            ),
+        }
+    }
+
+    /// Check whether a message can be dropped without affecting the current block or the seal
+    /// for the previous one
+    ///
+    /// Everything from before the previous sequence number can go. While a block is stuck, so
+    /// can `ViewChange`s for views this node has already reached, and the `PrePrepare`s and
+    /// `Prepare`s for the current sequence number from earlier views, since they can't count
+    /// toward its quorum in this view; the ones for the working block are kept, though, since
+    /// they're what shows this node is prepared for it. `Commit`s are always kept, since a
+    /// consensus seal may be built from ones cast in an earlier view.
+    fn is_evictable(msg: &ParsedMessage, state: &PbftState) -> bool {
+        let info = msg.info();
+        if info.get_seq_num() < state.seq_num.saturating_sub(1) {
+            return true;
+        }
+
+        match PbftMessageType::from(info.get_msg_type()) {
+            PbftMessageType::ViewChange => info.get_view() <= state.view,
+            PbftMessageType::PrePrepare | PbftMessageType::Prepare => {
+                let for_working_block = state
+                    .working_block
+                    .as_ref()
+                    .is_some_and(|block| block.get_block_id() == msg.get_block().get_block_id());
+                info.get_seq_num() == state.seq_num
+                    && info.get_view() < state.view
+                    && !for_working_block
+            }
+            _ => false,
+        }
+    }
+
     /// Add a PBFT consensus seal to the log
-    pub fn add_consensus_seal(&mut self, block_id: BlockId, seq_num: u64, seal: PbftSeal) {
-        self.seals.insert(PbftSealEntry {
+    ///
+    /// Adding the same seal more than once has no effect; adding a seal that differs from one that
+    /// is already stored for the same sequence number is an error.
+    pub fn add_consensus_seal(
+        &mut self,
+        block_id: BlockId,
+        seq_num: u64,
+        seal: PbftSeal,
+    ) -> Result<(), PbftError> {
+        let entry = PbftSealEntry {
             block_id,
             seq_num,
             seal,
-        });
+        };
+
+        if let Some(existing) = self
+            .seals
+            .iter()
+            .find(|existing| existing.seq_num == seq_num && **existing != entry)
+        {
+            error!(
+                "Got conflicting consensus seals for seq number {}: blocks {} and {}",
+                seq_num,
+                hex::encode(&existing.block_id),
+                hex::encode(&entry.block_id),
+            );
+            return Err(PbftError::ConflictingSeal(seq_num));
+        }
+
+        self.seals.insert(entry);
+
+        Ok(())
     }
 
     pub fn get_consensus_seal(&self, seq_num: u64) -> Result<PbftSeal, PbftError> {
@@ -210,20 +672,75 @@ impl PbftLog {
         Ok(possible_seals.first().unwrap().clone().seal)
     }
 
+    /// Get the consensus seal stored for the given block, if there is one
+    ///
+    /// Seals are only stored once they have been verified (or come from a committed block), so
+    /// this doubles as a cache of verified seals; `garbage_collect` evicts the old ones.
+    pub fn get_consensus_seal_for_block(&self, block_id: &[u8]) -> Option<&PbftSeal> {
+        self.seals
+            .iter()
+            .find(|entry| entry.block_id == block_id)
+            .map(|entry| &entry.seal)
+    }
+
+    /// Record the view that the block at the given sequence number was committed in
+    pub fn record_commit_view(&mut self, block_id: BlockId, seq_num: u64, view: u64) {
+        self.commit_views.insert(block_id, (seq_num, view));
+    }
+
+    /// Get the block this node committed at the given sequence number, if it was committed
+    /// recently enough to not have been garbage collected
+    fn committed_block(&self, seq_num: u64) -> Option<&BlockId> {
+        self.commit_views
+            .iter()
+            .find(|(_, (committed_seq, _))| *committed_seq == seq_num)
+            .map(|(block_id, _)| block_id)
+    }
+
+    /// Get the view that the given block was committed in, if it was committed recently enough to
+    /// not have been garbage collected
+    pub fn view_for_block(&self, block_id: &[u8]) -> Option<u64> {
+        self.commit_views.get(block_id).map(|(_, view)| *view)
+    }
+
+    /// Iterate over the messages in the log that match the given predicate
+    ///
+    /// This is what the other accessors are built on; it only borrows the log, so any number of
+    /// readers can scan it at once.
+    pub fn iter_matching<F>(&self, pred: F) -> impl Iterator<Item = &ParsedMessage>
+    where
+        F: Fn(&ParsedMessage) -> bool,
+    {
+        self.messages.values().filter(move |msg| pred(msg))
+    }
+
     /// Obtain all messages from the log that match a given type and sequence_number
     pub fn get_messages_of_type_seq(
         &self,
         msg_type: &PbftMessageType,
         sequence_number: u64,
     ) -> Vec<&ParsedMessage> {
-        self.messages
-            .iter()
-            .filter(|&msg| {
-                let info = (*msg).info();
-                info.get_msg_type() == String::from(msg_type)
-                    && info.get_seq_num() == sequence_number
+        self.iter_matching(|msg| {
+            let info = msg.info();
+            info.get_msg_type() == String::from(msg_type) && info.get_seq_num() == sequence_number
+        })
+        .collect()
+    }
+
+    /// Obtain every message in the log for the given sequence number, whatever its type
+    ///
+    /// The messages are ordered by view, type, and signer, so that dumps of them are easy to
+    /// compare.
+    pub fn messages_for_seq(&self, seq_num: u64) -> Vec<&ParsedMessage> {
+        self.iter_matching(|msg| msg.info().get_seq_num() == seq_num)
+            .sorted_by_key(|msg| {
+                let info = msg.info();
+                (
+                    info.get_view(),
+                    info.get_msg_type().to_string(),
+                    info.get_signer_id().to_vec(),
+                )
             })
-            .collect()
     }
 
     /// Obtain messages from the log that match a given type, sequence number, and view
@@ -233,21 +750,19 @@ impl PbftLog {
         sequence_number: u64,
         view: u64,
     ) -> Vec<&ParsedMessage> {
-        self.messages
-            .iter()
-            .filter(|&msg| {
-                let info = (*msg).info();
-                info.get_msg_type() == String::from(msg_type)
-                    && info.get_seq_num() == sequence_number
-                    && info.get_view() == view
-            })
-            .collect()
+        self.iter_matching(|msg| {
+            let info = msg.info();
+            info.get_msg_type() == String::from(msg_type)
+                && info.get_seq_num() == sequence_number
+                && info.get_view() == view
+        })
+        .collect()
     }
 
     /// Get sufficient messages for the given type and sequence number
     ///
     /// Gets all messages that match the given type and sequence number,
-    /// groups them by the view number, filters out view number groups
+    /// and are for `block_id` if one is given, groups them by the view number, filters out view number groups
     /// that don't have enough messages, and then sorts by view number
     /// and returns the highest one found, as an option in case there's
     /// no matching view number groups.
@@ -257,87 +772,336 @@ impl PbftLog {
     /// but we've forced a view change before the publishing could happen
     /// and we don't have any/enough messages for the current view num.
     ///
-    /// Considers messages from self to not count towards being enough,
-    /// as the current usage of this function is building a seal, where
-    /// the publishing node's approval is implicit via publishing.
+    /// A group is enough if its signers, together with this node, are a quorum of `required`
+    /// nodes (see `has_quorum`). Messages from self aren't returned, as the current usage of this
+    /// function is building a seal, where the publishing node's approval is implicit via
+    /// publishing; it still counts, with its weight, towards the quorum. Messages from observers
+    /// don't count, since observers don't vote.
     pub fn get_enough_messages(
         &self,
         msg_type: &PbftMessageType,
         sequence_number: u64,
-        minimum: u64,
+        block_id: Option<&[u8]>,
+        required: u64,
+        state: &PbftState,
     ) -> Option<Vec<&ParsedMessage>> {
-        self.messages
+        self.iter_matching(|msg| {
+            let info = msg.info();
+            info.get_msg_type() == String::from(msg_type)
+                && info.get_seq_num() == sequence_number
+                && (block_id.is_none() || block_id == Some(msg.get_block().get_block_id()))
+                && !msg.from_self
+                && !is_observer(msg, state)
+        })
+        .map(|msg| (msg.info().get_view(), msg))
+        .into_group_map()
+        .into_iter()
+        .filter(|(_, msgs)| {
+            let signers: HashSet<&[u8]> = msgs
+                .iter()
+                .map(|msg| msg.info().get_signer_id())
+                .chain(std::iter::once(state.id.as_slice()))
+                .collect();
+            self.has_quorum(signers, required)
+        })
+        .sorted_by_key(|(view, _)| *view)
+        .pop()
+        .map(|(_, msgs)| msgs)
+    }
+
+    /// Add a `Checkpoint` message to the log
+    ///
+    /// Checkpoints aren't tied to a view, so they're kept apart from the other messages. Only
+    /// voting peers' checkpoints up to the high watermark (or `max_future_seq_distance` past the
+    /// current sequence number, if watermarks are disabled) are kept, and a checkpoint for a block
+    /// other than the one this node committed at that sequence number is ignored. Returns `true`
+    /// if this checkpoint made its sequence number the new stable checkpoint.
+    pub fn add_checkpoint(&mut self, msg: ParsedMessage, state: &PbftState) -> bool {
+        let seq_num = msg.info().get_seq_num();
+        if seq_num <= self.stable_checkpoint {
+            trace!("Ignoring checkpoint for old sequence number {}", seq_num);
+            return false;
+        }
+
+        let high = state.high_watermark().or_else(|| {
+            if self.max_future_seq_distance > 0 {
+                Some(state.seq_num + self.max_future_seq_distance)
+            } else {
+                None
+            }
+        });
+        if let Some(high) = high {
+            if seq_num > high {
+                trace!(
+                    "Ignoring checkpoint for far future sequence number {}",
+                    seq_num
+                );
+                return false;
+            }
+        }
+
+        if !state
+            .peer_ids
             .iter()
-            .filter_map(|msg| {
-                let info = msg.info();
-                let same_type = info.get_msg_type() == String::from(msg_type);
-                let same_seq = info.get_seq_num() == sequence_number;
+            .any(|peer| peer.as_slice() == msg.info().get_signer_id())
+        {
+            trace!(
+                "Ignoring checkpoint from {}, which isn't a voting peer",
+                hex::encode(msg.info().get_signer_id())
+            );
+            return false;
+        }
 
-                if same_type && same_seq && !msg.from_self {
-                    Some((info.get_view(), msg))
-                } else {
-                    None
-                }
+        let block_id = msg.get_block().get_block_id().to_vec();
+        if let Some(committed) = self.committed_block(seq_num) {
+            if committed != &block_id {
+                warn!(
+                    "Ignoring checkpoint from {} for block {} at sequence number {}, where block \
+                     {} was committed",
+                    hex::encode(msg.info().get_signer_id()),
+                    hex::encode(&block_id),
+                    seq_num,
+                    hex::encode(committed)
+                );
+                return false;
+            }
+        }
+
+        self.checkpoints.insert(msg);
+
+        if !self.is_stable(seq_num, &block_id, state.f) {
+            return false;
+        }
+
+        self.stable_checkpoint = seq_num;
+        self.checkpoints = self
+            .checkpoints
+            .iter()
+            .filter(|cp| cp.info().get_seq_num() > seq_num)
+            .cloned()
+            .collect();
+
+        true
+    }
+
+    /// Check if `2f + 1` different nodes have sent checkpoints for this sequence number and block
+    ///
+    /// Sequence numbers at or below the stable checkpoint are always stable.
+    pub fn is_stable(&self, seq_num: u64, block_id: &[u8], f: u64) -> bool {
+        if seq_num <= self.stable_checkpoint {
+            return true;
+        }
+
+        let signers: HashSet<&[u8]> = self
+            .checkpoints
+            .iter()
+            .filter(|cp| {
+                cp.info().get_seq_num() == seq_num && cp.get_block().get_block_id() == block_id
             })
-            .into_group_map()
-            .into_iter()
-            .filter(|(_, msgs)| msgs.len() >= minimum as usize)
-            .sorted_by_key(|(view, _)| *view)
-            .pop()
-            .map(|(_, msgs)| msgs)
+            .map(|cp| cp.info().get_signer_id())
+            .collect();
+
+        signers.len() as u64 > 2 * f
+    }
+
+    /// Highest sequence number that `2f + 1` nodes have checkpointed
+    pub fn stable_checkpoint(&self) -> u64 {
+        self.stable_checkpoint
     }
 
     /// Garbage collect the log after we've committed a block
+    ///
+    /// Messages and seals for the last `gc_retain_depth` committed sequence numbers are kept, so
+    /// that recent history can be reconstructed if needed. If checkpoints are in use, nothing at
+    /// or above the stable checkpoint is removed either, so that nodes that are still catching
+    /// up to it can be served.
     #[allow(clippy::ptr_arg)]
     pub fn garbage_collect(&mut self, current_seq_num: u64, block_id: &BlockId) {
+        let mut oldest_retained = current_seq_num.saturating_sub(self.gc_retain_depth);
+        if self.checkpoints_enabled {
+            oldest_retained = oldest_retained.min(self.stable_checkpoint);
+        }
+
         // If we've reached the max log size, filter out all old messages
         if self.messages.len() as u64 >= self.max_log_size {
-            self.messages = self
-                .messages
-                .iter()
-                .filter(|ref msg| {
-                    // We need to keep messages from the previous sequence number to build the next
-                    // consensus seal
-                    msg.info().get_seq_num() >= current_seq_num - 1
-                })
-                .cloned()
-                .collect();
+            self.messages.retain(|_, msg| {
+                // We need to keep messages from at least the previous sequence number to build
+                // the next consensus seal
+                msg.info().get_seq_num() >= oldest_retained
+            });
+        }
+
+        self.equivocations
+            .retain(|(accepted, _)| accepted.info().get_seq_num() >= oldest_retained);
+        self.commit_views
+            .retain(|id, (seq_num, _)| id == block_id || *seq_num >= oldest_retained);
+
+        // Seals of blocks past the one we just committed were verified ahead of time; they're
+        // only still useful if they extend the chain that was actually committed
+        let mut extending_ids: HashSet<&[u8]> = HashSet::new();
+        extending_ids.insert(block_id);
+        for seal in self
+            .seals
+            .iter()
+            .filter(|seal| seal.seq_num >= current_seq_num)
+            .sorted_by_key(|seal| seal.seq_num)
+        {
+            if extending_ids.contains(seal.seal.get_previous_id()) {
+                extending_ids.insert(&seal.block_id);
+            }
         }
+        let future_ids: HashSet<BlockId> = extending_ids
+            .into_iter()
+            .filter(|id| id != block_id)
+            .map(|id| id.to_vec())
+            .collect();
 
-        // Remove all seals except for the one in the block we just committed
+        // Remove all seals except for the one in the block we just committed, those of the
+        // blocks before it that are still retained, and those that extend it
         self.seals = self
             .seals
             .iter()
-            .filter(|seal| &seal.block_id == block_id)
+            .filter(|seal| {
+                &seal.block_id == block_id
+                    || (seal.seq_num >= oldest_retained
+                        && seal.seq_num < current_seq_num.saturating_sub(1))
+                    || future_ids.contains(&seal.block_id)
+            })
             .cloned()
             .collect();
     }
 
-    pub fn push_backlog(&mut self, msg: ParsedMessage) {
-        self.backlog.push_back(msg);
+    /// Number of messages in the log
+    pub fn len(&self) -> usize {
+        self.messages.len()
     }
 
-    pub fn pop_backlog(&mut self) -> Option<ParsedMessage> {
-        self.backlog.pop_front()
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config;
-    use crate::hash::hash_sha256;
-    use crate::protos::pbft_message::{PbftBlock, PbftMessage};
-    use sawtooth_sdk::consensus::engine::PeerId;
+    /// Number of messages waiting in the backlog
+    pub fn backlog_len(&self) -> usize {
+        self.backlog.len()
+    }
 
-    /// Create a PbftMessage, given its type, view, sequence number, and who it's from
-    fn make_msg(
-        msg_type: &PbftMessageType,
-        view: u64,
-        seq_num: u64,
-        signer_id: PeerId,
-        block_signer_id: PeerId,
-    ) -> ParsedMessage {
+    /// Number of stored consensus seals
+    pub fn seal_count(&self) -> usize {
+        self.seals.len()
+    }
+
+    /// Lowest and highest sequence numbers of the messages in the log
+    pub fn seq_range(&self) -> Option<(u64, u64)> {
+        let seq_nums = self.messages.values().map(|msg| msg.info().get_seq_num());
+        Some((seq_nums.clone().min()?, seq_nums.max()?))
+    }
+
+    /// Number of messages in the log of each message type
+    pub fn message_type_counts(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for msg in self.messages.values() {
+            *counts.entry(msg.info().get_msg_type().into()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Gather all of the log's statistics in a single pass over the messages
+    pub fn stats(&self) -> LogStats {
+        let mut seq_range: Option<(u64, u64)> = None;
+        let mut message_type_counts = HashMap::new();
+
+        for msg in self.messages.values() {
+            let seq_num = msg.info().get_seq_num();
+            seq_range = Some(match seq_range {
+                Some((low, high)) => (low.min(seq_num), high.max(seq_num)),
+                None => (seq_num, seq_num),
+            });
+            *message_type_counts
+                .entry(msg.info().get_msg_type().into())
+                .or_insert(0) += 1;
+        }
+
+        LogStats {
+            len: self.messages.len(),
+            backlog_len: self.backlog.len(),
+            seal_count: self.seals.len(),
+            seq_range,
+            message_type_counts,
+        }
+    }
+
+    /// Remove all messages, backlogged messages, and seals from the log
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.backlog.clear();
+        self.seals.clear();
+        self.checkpoints.clear();
+        self.equivocations.clear();
+        self.commit_views.clear();
+    }
+
+    /// Check if the message with the given bytes is in the backlog
+    pub fn is_backlogged(&self, message_bytes: &[u8]) -> bool {
+        self.backlog
+            .iter()
+            .any(|msg| msg.message_bytes == message_bytes)
+    }
+
+    /// Add a message to the backlog, dropping the oldest backlogged message if it's full
+    pub fn push_backlog(&mut self, msg: ParsedMessage) {
+        if self.backlog.len() as u64 >= self.max_backlog_size {
+            if let Some(dropped) = self.backlog.pop_front() {
+                warn!(
+                    "Backlog is full ({} messages); dropping oldest message: {}",
+                    self.max_backlog_size,
+                    dropped.info()
+                );
+            }
+        }
+        self.backlog.push_back(msg);
+    }
+
+    pub fn pop_backlog(&mut self) -> Option<ParsedMessage> {
+        self.backlog.pop_front()
+    }
+}
+
+/// Tell if two messages are of the same type, view, sequence number, and signer, but for
+/// different blocks
+fn votes_conflict(a: &ParsedMessage, b: &ParsedMessage) -> bool {
+    let (a_info, b_info) = (a.info(), b.info());
+    a_info.get_msg_type() == b_info.get_msg_type()
+        && a_info.get_view() == b_info.get_view()
+        && a_info.get_seq_num() == b_info.get_seq_num()
+        && a_info.get_signer_id() == b_info.get_signer_id()
+        && a.get_block() != b.get_block()
+}
+
+/// Tell if a message was signed by one of the network's observers
+fn is_observer(msg: &ParsedMessage, state: &PbftState) -> bool {
+    let signer = msg.info().get_signer_id();
+    state
+        .observers
+        .iter()
+        .any(|observer| observer.as_slice() == signer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::hash::hash_sha256;
+    use crate::protos::pbft_message::{PbftBlock, PbftMessage, PbftViewChange};
+    use sawtooth_sdk::consensus::engine::PeerId;
+
+    /// Create a PbftMessage, given its type, view, sequence number, and who it's from
+    fn make_msg(
+        msg_type: &PbftMessageType,
+        view: u64,
+        seq_num: u64,
+        signer_id: PeerId,
+        block_signer_id: PeerId,
+    ) -> ParsedMessage {
         let mut info = PbftMessageInfo::new();
         info.set_msg_type(String::from(msg_type));
         info.set_view(view);
@@ -402,8 +1166,8 @@ mod tests {
         );
         log.add_message(msg.clone(), &state).unwrap();
 
-        assert!(!log.check_prepared(&msg.info(), 1 as u64));
-        assert!(!log.check_committable(&msg.info(), 1 as u64));
+        assert!(!log.check_prepared(&msg.info(), &state));
+        assert!(!log.check_committable(&msg.info(), &state));
 
         let msg = make_msg(
             &PbftMessageType::PrePrepare,
@@ -413,8 +1177,8 @@ mod tests {
             get_peer_id(&cfg, 0),
         );
         log.add_message(msg.clone(), &state).unwrap();
-        assert!(!log.check_prepared(&msg.info(), 1 as u64));
-        assert!(!log.check_committable(&msg.info(), 1 as u64));
+        assert!(!log.check_prepared(&msg.info(), &state));
+        assert!(!log.check_committable(&msg.info(), &state));
 
         for peer in 0..4 {
             let msg = make_msg(
@@ -427,11 +1191,11 @@ mod tests {
 
             log.add_message(msg.clone(), &state).unwrap();
             if peer < 2 {
-                assert!(!log.check_prepared(&msg.info(), 1 as u64));
-                assert!(!log.check_committable(&msg.info(), 1 as u64));
+                assert!(!log.check_prepared(&msg.info(), &state));
+                assert!(!log.check_committable(&msg.info(), &state));
             } else {
-                assert!(log.check_prepared(&msg.info(), 1 as u64));
-                assert!(!log.check_committable(&msg.info(), 1 as u64));
+                assert!(log.check_prepared(&msg.info(), &state));
+                assert!(!log.check_committable(&msg.info(), &state));
             }
         }
 
@@ -446,11 +1210,518 @@ mod tests {
 
             log.add_message(msg.clone(), &state).unwrap();
             if peer < 2 {
-                assert!(!log.check_committable(&msg.info(), 1 as u64));
+                assert!(!log.check_committable(&msg.info(), &state));
             } else {
-                assert!(log.check_committable(&msg.info(), 1 as u64));
+                assert!(log.check_committable(&msg.info(), &state));
+            }
+        }
+    }
+
+    /// Make sure that a node's own `Prepare` and `Commit` count toward the quorum exactly once, even
+    /// when multiple copies of them are in the log
+    #[test]
+    fn own_votes_counted_once() {
+        let cfg = config::mock_config(4);
+        let mut log = PbftLog::new(&cfg);
+        let state = PbftState::new(get_peer_id(&cfg, 1), 0, &cfg);
+
+        let pre_prepare = make_msg(
+            &PbftMessageType::PrePrepare,
+            0,
+            1,
+            get_peer_id(&cfg, 0),
+            get_peer_id(&cfg, 0),
+        );
+        log.add_message(pre_prepare.clone(), &state).unwrap();
+
+        for msg_type in &[PbftMessageType::Prepare, PbftMessageType::Commit] {
+            // This node's own vote, from the self-send path...
+            let mut own = make_msg(msg_type, 0, 1, get_peer_id(&cfg, 1), get_peer_id(&cfg, 0));
+            own.from_self = true;
+            log.add_message(own.clone(), &state).unwrap();
+
+            // ...and again as a signed copy
+            own.from_self = false;
+            own.header_bytes = vec![1, 2, 3];
+            log.add_message(own.clone(), &state).unwrap();
+
+            // A peer's vote, received twice
+            let peer = make_msg(msg_type, 0, 1, get_peer_id(&cfg, 0), get_peer_id(&cfg, 0));
+            log.add_message(peer.clone(), &state).unwrap();
+            log.add_message(peer.clone(), &state).unwrap();
+
+            assert_eq!(log.get_messages_of_type_seq_view(msg_type, 1, 0).len(), 2);
+            assert!(!log.log_has_required_msgs(msg_type, &pre_prepare, true, 3, &state));
+
+            // Own vote + 2 others is 2f + 1
+            let peer = make_msg(msg_type, 0, 1, get_peer_id(&cfg, 2), get_peer_id(&cfg, 0));
+            log.add_message(peer, &state).unwrap();
+            assert!(log.log_has_required_msgs(msg_type, &pre_prepare, true, 3, &state));
+        }
+
+        assert!(log.check_prepared(pre_prepare.info(), &state));
+        assert!(log.check_committable(pre_prepare.info(), &state));
+
+        // The self-constructed copy is the one that was kept
+        assert!(log
+            .get_messages_of_type_seq_view(&PbftMessageType::Commit, 1, 0)
+            .iter()
+            .any(|msg| msg.from_self));
+    }
+
+    /// Make sure that messages from observers don't count toward any quorum
+    #[test]
+    fn observer_votes_not_counted() {
+        let mut cfg = config::mock_config(5);
+        cfg.observers = vec![get_peer_id(&cfg, 4)];
+        let mut log = PbftLog::new(&cfg);
+        let state = PbftState::new(get_peer_id(&cfg, 1), 0, &cfg);
+        assert_eq!(state.f, 1);
+
+        let pre_prepare = make_msg(
+            &PbftMessageType::PrePrepare,
+            0,
+            1,
+            get_peer_id(&cfg, 0),
+            get_peer_id(&cfg, 0),
+        );
+        log.add_message(pre_prepare.clone(), &state).unwrap();
+
+        for msg_type in &[
+            PbftMessageType::Prepare,
+            PbftMessageType::Commit,
+            PbftMessageType::ViewChange,
+        ] {
+            for peer in &[0, 1, 4] {
+                let msg = make_msg(
+                    msg_type,
+                    0,
+                    1,
+                    get_peer_id(&cfg, *peer),
+                    get_peer_id(&cfg, 0),
+                );
+                log.add_message(msg, &state).unwrap();
+            }
+            assert!(!log.log_has_required_msgs(msg_type, &pre_prepare, false, 3, &state));
+            assert_eq!(log.count_distinct_signers(msg_type, 0, 1, &state), 2);
+        }
+        assert!(!log.check_prepared(pre_prepare.info(), &state));
+        assert!(log
+            .get_enough_messages(&PbftMessageType::Commit, 1, None, 3, &state)
+            .is_none());
+
+        for msg_type in &[PbftMessageType::Prepare, PbftMessageType::Commit] {
+            let msg = make_msg(msg_type, 0, 1, get_peer_id(&cfg, 2), get_peer_id(&cfg, 0));
+            log.add_message(msg, &state).unwrap();
+        }
+        assert!(log.check_prepared(pre_prepare.info(), &state));
+        assert!(log.check_committable(pre_prepare.info(), &state));
+        assert_eq!(
+            log.get_enough_messages(&PbftMessageType::Commit, 1, None, 3, &state)
+                .map(|msgs| msgs.len()),
+            Some(3)
+        );
+        assert!(log
+            .get_enough_messages(&PbftMessageType::Commit, 1, Some(&[0xff]), 3, &state)
+            .is_none());
+    }
+
+    /// Make sure that each signer is only counted once, and only for the requested view and
+    /// sequence number
+    #[test]
+    fn count_distinct_signers() {
+        let cfg = config::mock_config(4);
+        let mut log = PbftLog::new(&cfg);
+        let mut state = PbftState::new(get_peer_id(&cfg, 1), 0, &cfg);
+        let prepare = PbftMessageType::Prepare;
+
+        // This node's own vote, both self-sent and as a signed copy
+        let mut own = make_msg(&prepare, 0, 1, get_peer_id(&cfg, 1), get_peer_id(&cfg, 0));
+        own.from_self = true;
+        log.add_message(own.clone(), &state).unwrap();
+        own.from_self = false;
+        own.header_bytes = vec![1, 2, 3];
+        log.add_message(own, &state).unwrap();
+
+        for peer in &[0, 2] {
+            let msg = make_msg(
+                &prepare,
+                0,
+                1,
+                get_peer_id(&cfg, *peer),
+                get_peer_id(&cfg, 0),
+            );
+            log.add_message(msg, &state).unwrap();
+        }
+
+        // Votes from another view and sequence number
+        for &(view, seq_num) in &[(1, 1), (0, 2)] {
+            state.set_view(view);
+            let msg = make_msg(
+                &prepare,
+                view,
+                seq_num,
+                get_peer_id(&cfg, 3),
+                get_peer_id(&cfg, 0),
+            );
+            log.add_message(msg, &state).unwrap();
+        }
+
+        assert_eq!(log.count_distinct_signers(&prepare, 0, 1, &state), 3);
+        assert_eq!(log.count_distinct_signers(&prepare, 1, 1, &state), 1);
+        assert_eq!(log.count_distinct_signers(&prepare, 0, 2, &state), 1);
+        assert_eq!(
+            log.count_distinct_signers(&PbftMessageType::Commit, 0, 1, &state),
+            0
+        );
+    }
+
+    /// Make sure that each peer's `Commit` is only reported for the view and sequence number it
+    /// was sent for
+    #[test]
+    fn has_commit_from() {
+        let cfg = config::mock_config(4);
+        let mut log = PbftLog::new(&cfg);
+        let mut state = PbftState::new(get_peer_id(&cfg, 1), 0, &cfg);
+        let commit = PbftMessageType::Commit;
+
+        for peer in &[0, 2] {
+            let msg = make_msg(
+                &commit,
+                0,
+                1,
+                get_peer_id(&cfg, *peer),
+                get_peer_id(&cfg, 0),
+            );
+            log.add_message(msg, &state).unwrap();
+        }
+        let prepare = make_msg(
+            &PbftMessageType::Prepare,
+            0,
+            1,
+            get_peer_id(&cfg, 3),
+            get_peer_id(&cfg, 0),
+        );
+        log.add_message(prepare, &state).unwrap();
+        state.set_view(1);
+        let later = make_msg(&commit, 1, 1, get_peer_id(&cfg, 1), get_peer_id(&cfg, 0));
+        log.add_message(later, &state).unwrap();
+
+        assert!(log.has_commit_from(&get_peer_id(&cfg, 0), 0, 1));
+        assert!(log.has_commit_from(&get_peer_id(&cfg, 2), 0, 1));
+        assert!(!log.has_commit_from(&get_peer_id(&cfg, 3), 0, 1));
+        assert!(!log.has_commit_from(&get_peer_id(&cfg, 1), 0, 1));
+        assert!(log.has_commit_from(&get_peer_id(&cfg, 1), 1, 1));
+        assert!(!log.has_commit_from(&get_peer_id(&cfg, 0), 1, 1));
+        assert!(!log.has_commit_from(&get_peer_id(&cfg, 0), 0, 2));
+    }
+
+    /// Make sure that a batch is only added if every message in it is valid, and that the first
+    /// invalid one is reported by its index
+    #[test]
+    fn add_messages() {
+        let cfg = config::mock_config(4);
+        let mut log = PbftLog::new(&cfg);
+        let state = PbftState::new(vec![], 0, &cfg);
+        let commit = |view, peer, block_signer| {
+            make_msg(
+                &PbftMessageType::Commit,
+                view,
+                1,
+                get_peer_id(&cfg, peer),
+                get_peer_id(&cfg, block_signer),
+            )
+        };
+
+        let batch = vec![
+            commit(0, 0, 0),
+            commit(0, 1, 0),
+            commit(1, 2, 0),
+            commit(0, 3, 0),
+        ];
+        match log.add_messages(batch, &state) {
+            Err(PbftError::BatchRejected(2, ref err)) => match **err {
+                PbftError::ViewMismatch(1, 0) => {}
+                ref err => panic!("Expected ViewMismatch, got {:?}", err),
+            },
+            res => panic!("Expected BatchRejected, got {:?}", res),
+        }
+        assert_eq!(log.len(), 0);
+
+        // Conflicting votes within the batch are caught too
+        let batch = vec![commit(0, 0, 0), commit(0, 0, 1)];
+        match log.add_messages(batch, &state) {
+            Err(PbftError::BatchRejected(1, ref err)) => match **err {
+                PbftError::Equivocation(PbftMessageType::Commit, _) => {}
+                ref err => panic!("Expected Equivocation, got {:?}", err),
+            },
+            res => panic!("Expected BatchRejected, got {:?}", res),
+        }
+        assert_eq!(log.len(), 0);
+
+        // So are votes that conflict with the log, without recording the equivocation
+        log.add_message(commit(0, 3, 1), &state).unwrap();
+        let batch = vec![commit(0, 0, 0), commit(0, 3, 0)];
+        match log.add_messages(batch, &state) {
+            Err(PbftError::BatchRejected(1, ref err)) => match **err {
+                PbftError::Equivocation(PbftMessageType::Commit, _) => {}
+                ref err => panic!("Expected Equivocation, got {:?}", err),
+            },
+            res => panic!("Expected BatchRejected, got {:?}", res),
+        }
+        assert_eq!(log.len(), 1);
+        assert!(log.equivocations().is_empty());
+
+        let mut log = PbftLog::new(&cfg);
+        let batch = (0..4).map(|peer| commit(0, peer, 0)).collect();
+        log.add_messages(batch, &state).unwrap();
+        assert_eq!(
+            log.get_messages_of_type_seq(&PbftMessageType::Commit, 1)
+                .len(),
+            4
+        );
+    }
+
+    /// Make sure that a predicate picks out exactly the messages it matches
+    #[test]
+    fn iter_matching() {
+        let cfg = config::mock_config(4);
+        let mut log = PbftLog::new(&cfg);
+        let state = PbftState::new(vec![], 0, &cfg);
+        let signer = get_peer_id(&cfg, 2);
+
+        for seq in 1..4 {
+            for peer in 0..4 {
+                for msg_type in &[PbftMessageType::Prepare, PbftMessageType::Commit] {
+                    let msg = make_msg(
+                        msg_type,
+                        0,
+                        seq,
+                        get_peer_id(&cfg, peer),
+                        get_peer_id(&cfg, 0),
+                    );
+                    log.add_message(msg, &state).unwrap();
+                }
+            }
+        }
+
+        let commits: Vec<&ParsedMessage> = log
+            .iter_matching(|msg| {
+                msg.info().get_msg_type() == "Commit" && msg.info().get_signer_id() == &signer[..]
+            })
+            .collect();
+        assert_eq!(commits.len(), 3);
+        let mut seq_nums: Vec<u64> = commits.iter().map(|msg| msg.info().get_seq_num()).collect();
+        seq_nums.sort();
+        assert_eq!(seq_nums, vec![1, 2, 3]);
+
+        assert_eq!(log.iter_matching(|_| true).count(), log.len());
+        assert_eq!(log.iter_matching(|_| false).count(), 0);
+    }
+
+    /// Make sure that all of the messages for a sequence number are returned, whatever their type
+    #[test]
+    fn messages_for_seq() {
+        let cfg = config::mock_config(4);
+        let mut log = PbftLog::new(&cfg);
+        let state = PbftState::new(vec![], 0, &cfg);
+
+        let msg_types = [
+            PbftMessageType::BlockNew,
+            PbftMessageType::PrePrepare,
+            PbftMessageType::Prepare,
+            PbftMessageType::Commit,
+        ];
+        for msg_type in &msg_types {
+            for seq in 3..5 {
+                let msg = make_msg(msg_type, 0, seq, get_peer_id(&cfg, 0), get_peer_id(&cfg, 0));
+                log.add_message(msg, &state).unwrap();
+            }
+        }
+        log.add_message(
+            make_msg(
+                &PbftMessageType::Prepare,
+                0,
+                3,
+                get_peer_id(&cfg, 1),
+                get_peer_id(&cfg, 0),
+            ),
+            &state,
+        )
+        .unwrap();
+
+        let msgs = log.messages_for_seq(3);
+        assert_eq!(msgs.len(), 5);
+        assert!(msgs.iter().all(|msg| msg.info().get_seq_num() == 3));
+        for msg_type in &msg_types {
+            assert!(msgs
+                .iter()
+                .any(|msg| msg.info().get_msg_type() == String::from(msg_type)));
+        }
+        assert!(log.messages_for_seq(5).is_empty());
+    }
+
+    /// Make sure that a signer's conflicting votes only count once toward a quorum, and that
+    /// they're kept as evidence
+    #[test]
+    fn equivocating_votes() {
+        let cfg = config::mock_config(4);
+        let mut log = PbftLog::new(&cfg);
+        let state = PbftState::new(get_peer_id(&cfg, 1), 0, &cfg);
+
+        let prepare = make_msg(
+            &PbftMessageType::Prepare,
+            0,
+            1,
+            get_peer_id(&cfg, 2),
+            get_peer_id(&cfg, 0),
+        );
+        let conflicting = make_msg(
+            &PbftMessageType::Prepare,
+            0,
+            1,
+            get_peer_id(&cfg, 2),
+            get_peer_id(&cfg, 3),
+        );
+        log.add_message(prepare.clone(), &state).unwrap();
+        match log.add_message(conflicting.clone(), &state) {
+            Err(PbftError::Equivocation(PbftMessageType::Prepare, ref signer))
+                if *signer == get_peer_id(&cfg, 2) => {}
+            res => panic!("Expected Equivocation, got {:?}", res),
+        }
+
+        // Sending the conflicting vote again doesn't add more evidence
+        assert!(log.add_message(conflicting.clone(), &state).is_err());
+        assert_eq!(
+            log.equivocations().iter().cloned().collect::<Vec<_>>(),
+            vec![(prepare.clone(), conflicting.clone())]
+        );
+
+        assert_eq!(
+            log.get_messages_of_type_seq_view(&PbftMessageType::Prepare, 1, 0)
+                .len(),
+            1
+        );
+        assert!(log.log_has_required_msgs(&PbftMessageType::Prepare, &prepare, false, 1, &state));
+        assert!(!log.log_has_required_msgs(&PbftMessageType::Prepare, &prepare, false, 2, &state));
+        assert!(!log.log_has_required_msgs(
+            &PbftMessageType::Prepare,
+            &conflicting,
+            true,
+            1,
+            &state
+        ));
+    }
+
+    /// Make sure that only the most recent `EQUIVOCATION_HISTORY` pairs of conflicting votes are
+    /// kept
+    #[test]
+    fn equivocation_history_is_capped() {
+        let cfg = config::mock_config(4);
+        let mut log = PbftLog::new(&cfg);
+
+        let pair = |seq| {
+            (
+                make_msg(
+                    &PbftMessageType::Prepare,
+                    0,
+                    seq,
+                    get_peer_id(&cfg, 2),
+                    get_peer_id(&cfg, 0),
+                ),
+                make_msg(
+                    &PbftMessageType::Prepare,
+                    0,
+                    seq,
+                    get_peer_id(&cfg, 2),
+                    get_peer_id(&cfg, 3),
+                ),
+            )
+        };
+        for seq in 1..=(EQUIVOCATION_HISTORY as u64 + 5) {
+            let (accepted, rejected) = pair(seq);
+            log.record_equivocation(accepted, rejected);
+        }
+
+        assert_eq!(log.equivocations().len(), EQUIVOCATION_HISTORY);
+        assert_eq!(log.equivocations().front(), Some(&pair(6)));
+        assert_eq!(
+            log.equivocations().back(),
+            Some(&pair(EQUIVOCATION_HISTORY as u64 + 5))
+        );
+    }
+
+    /// Make sure that re-adding a seal is harmless, but that conflicting seals for the same
+    /// sequence number are rejected
+    #[test]
+    fn conflicting_seals() {
+        let cfg = config::mock_config(4);
+        let mut log = PbftLog::new(&cfg);
+
+        let mut seal = PbftSeal::new();
+        seal.set_previous_id(vec![1]);
+        seal.set_summary(vec![1, 2, 3]);
+
+        log.add_consensus_seal(vec![2], 2, seal.clone()).unwrap();
+        log.add_consensus_seal(vec![2], 2, seal.clone()).unwrap();
+        assert_eq!(log.get_consensus_seal(2).unwrap(), seal);
+
+        let mut other_seal = seal.clone();
+        other_seal.set_summary(vec![4, 5, 6]);
+        match log.add_consensus_seal(vec![2], 2, other_seal.clone()) {
+            Err(PbftError::ConflictingSeal(2)) => {}
+            res => panic!("Expected ConflictingSeal, got {:?}", res),
+        }
+        assert!(log.add_consensus_seal(vec![3], 2, seal.clone()).is_err());
+
+        // The original seal is still the only one for that sequence number
+        assert_eq!(log.get_consensus_seal(2).unwrap(), seal);
+
+        log.add_consensus_seal(vec![3], 3, other_seal).unwrap();
+    }
+
+    /// Make sure that the bundled stats match the individually-computed values
+    #[test]
+    fn stats() {
+        let cfg = config::mock_config(4);
+        let mut log = PbftLog::new(&cfg);
+        let state = PbftState::new(vec![], 0, &cfg);
+
+        let empty = log.stats();
+        assert_eq!(empty.len, 0);
+        assert_eq!(empty.seq_range, None);
+        assert!(empty.message_type_counts.is_empty());
+
+        for seq in 2..5 {
+            log.add_message(
+                make_msg(&PbftMessageType::PrePrepare, 0, seq, vec![0], vec![0]),
+                &state,
+            )
+            .unwrap();
+            for peer in 0..3 {
+                log.add_message(
+                    make_msg(&PbftMessageType::Prepare, 0, seq, vec![peer], vec![0]),
+                    &state,
+                )
+                .unwrap();
             }
         }
+        log.push_backlog(make_msg(&PbftMessageType::Commit, 0, 5, vec![1], vec![0]));
+        log.add_consensus_seal(vec![1], 1, PbftSeal::new()).unwrap();
+
+        let stats = log.stats();
+        assert_eq!(stats.len, log.len());
+        assert_eq!(stats.backlog_len, log.backlog_len());
+        assert_eq!(stats.seal_count, log.seal_count());
+        assert_eq!(stats.seq_range, log.seq_range());
+        assert_eq!(stats.message_type_counts, log.message_type_counts());
+
+        assert_eq!(stats.len, 12);
+        assert_eq!(stats.backlog_len, 1);
+        assert_eq!(stats.seal_count, 1);
+        assert_eq!(stats.seq_range, Some((2, 4)));
+        assert_eq!(stats.message_type_counts["PrePrepare"], 3);
+        assert_eq!(stats.message_type_counts["Prepare"], 9);
     }
 
     /// Make sure that log garbage collection works as expected
@@ -530,4 +1801,532 @@ mod tests {
             assert_eq!(log.get_messages_of_type_seq_view(&msg_type, 4, 0).len(), 4);
         }
     }
+
+    /// Make sure that with unequal peer weights, quorums depend on the weight of the votes rather
+    /// than how many there are, and that equal weights behave like no weights at all
+    #[test]
+    fn weighted_quorum() {
+        let mut cfg = config::mock_config(4);
+        let state = PbftState::new(vec![0], 0, &cfg);
+        let weights = [1, 1, 1, 7];
+        cfg.peer_weights = (0..4)
+            .map(|i| (get_peer_id(&cfg, i), weights[i as usize]))
+            .collect();
+
+        let prepared_with = |cfg: &config::PbftConfig, signers: &[u64]| {
+            let mut log = PbftLog::new(cfg);
+            let pre_prepare = make_msg(
+                &PbftMessageType::PrePrepare,
+                0,
+                1,
+                get_peer_id(cfg, 0),
+                get_peer_id(cfg, 0),
+            );
+            log.add_message(pre_prepare.clone(), &state).unwrap();
+            for i in signers {
+                let msg = make_msg(
+                    &PbftMessageType::Prepare,
+                    0,
+                    1,
+                    get_peer_id(cfg, *i),
+                    get_peer_id(cfg, 0),
+                );
+                log.add_message(msg, &state).unwrap();
+            }
+            log.check_prepared(pre_prepare.info(), &state)
+        };
+
+        // Three of the four nodes only have 3/10 of the weight
+        assert!(!prepared_with(&cfg, &[0, 1, 2]));
+        assert!(prepared_with(&cfg, &[0, 3]));
+        assert!(!prepared_with(&cfg, &[3]));
+
+        let log = PbftLog::new(&cfg);
+        let peer = |i| get_peer_id(&cfg, i);
+        assert_eq!(log.quorum_weight(3), 8);
+        assert_eq!(
+            log.signer_weight([peer(0), peer(1), peer(3)].iter().map(Vec::as_slice)),
+            9
+        );
+
+        cfg.peer_weights = (0..4).map(|i| (get_peer_id(&cfg, i), 5)).collect();
+        assert!(prepared_with(&cfg, &[0, 1, 2]));
+        assert!(!prepared_with(&cfg, &[0, 3]));
+
+        cfg.peer_weights.clear();
+        assert!(prepared_with(&cfg, &[0, 1, 2]));
+        assert!(!prepared_with(&cfg, &[0, 3]));
+    }
+
+    /// Make sure that the log never holds more than `max_log_messages`, and that the messages for
+    /// the current and previous sequence numbers are never evicted
+    #[test]
+    fn max_log_messages() {
+        let mut cfg = config::mock_config(4);
+        cfg.max_log_size = 10;
+        cfg.max_log_messages = 10;
+        let mut log = PbftLog::new(&cfg);
+        let state = PbftState::new(vec![0], 4, &cfg);
+        assert_eq!(state.seq_num, 5);
+
+        for seq in 1..6 {
+            for peer in 0..4 {
+                let msg = make_msg(
+                    &PbftMessageType::Prepare,
+                    0,
+                    seq,
+                    get_peer_id(&cfg, peer),
+                    get_peer_id(&cfg, 0),
+                );
+                log.add_message(msg, &state).unwrap();
+                assert!(log.len() <= 10);
+            }
+        }
+
+        assert_eq!(log.len(), 10);
+        for seq in 4..6 {
+            assert_eq!(
+                log.get_messages_of_type_seq(&PbftMessageType::Prepare, seq)
+                    .len(),
+                4
+            );
+        }
+        // Only the newest of the older messages are left
+        assert!(log
+            .get_messages_of_type_seq(&PbftMessageType::Prepare, 1)
+            .is_empty());
+        assert_eq!(
+            log.get_messages_of_type_seq(&PbftMessageType::Prepare, 3)
+                .len(),
+            2
+        );
+
+        // Messages that are all still needed can't be evicted
+        for peer in 0..4 {
+            let msg = make_msg(
+                &PbftMessageType::Commit,
+                0,
+                5,
+                get_peer_id(&cfg, peer),
+                get_peer_id(&cfg, 0),
+            );
+            log.add_message(msg, &state).unwrap();
+        }
+        assert_eq!(log.len(), 12);
+        assert!(log
+            .get_messages_of_type_seq(&PbftMessageType::Prepare, 3)
+            .is_empty());
+    }
+
+    /// Make sure that the messages evicted first are the ones farthest from the current sequence
+    /// number and view, even if they're for a later sequence number
+    #[test]
+    fn evict_farthest() {
+        let mut cfg = config::mock_config(4);
+        cfg.max_log_messages = 2;
+        let mut log = PbftLog::new(&cfg);
+        let mut state = PbftState::new(vec![0], 4, &cfg);
+
+        let prepare = make_msg(
+            &PbftMessageType::Prepare,
+            0,
+            5,
+            get_peer_id(&cfg, 1),
+            get_peer_id(&cfg, 0),
+        );
+        let mut info = PbftMessageInfo::new();
+        info.set_msg_type(String::from(&PbftMessageType::ViewChange));
+        info.set_view(2);
+        info.set_seq_num(7);
+        info.set_signer_id(get_peer_id(&cfg, 1));
+        let mut vc = PbftViewChange::new();
+        vc.set_info(info);
+        let commit = make_msg(
+            &PbftMessageType::Commit,
+            3,
+            5,
+            get_peer_id(&cfg, 1),
+            get_peer_id(&cfg, 0),
+        );
+
+        log.add_message(prepare.clone(), &state).unwrap();
+        state.view = 3;
+        log.add_message(ParsedMessage::from_view_change_message(vc), &state)
+            .unwrap();
+        log.add_message(commit, &state).unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log.view_change_from(&get_peer_id(&cfg, 1), 2).is_none());
+        assert!(log.messages.contains_key(&prepare.message));
+    }
+
+    /// Make sure that the votes for the working block aren't evicted, even from an earlier view
+    #[test]
+    fn evict_keeps_working_block_votes() {
+        let mut cfg = config::mock_config(4);
+        cfg.max_log_messages = 2;
+        let mut log = PbftLog::new(&cfg);
+        let mut state = PbftState::new(vec![0], 4, &cfg);
+        let commit = |peer| {
+            make_msg(
+                &PbftMessageType::Commit,
+                1,
+                5,
+                get_peer_id(&cfg, peer),
+                get_peer_id(&cfg, 0),
+            )
+        };
+
+        let prepare = make_msg(
+            &PbftMessageType::Prepare,
+            0,
+            5,
+            get_peer_id(&cfg, 1),
+            get_peer_id(&cfg, 0),
+        );
+        log.add_message(prepare.clone(), &state).unwrap();
+        state.working_block = Some(prepare.get_block().clone());
+        state.view = 1;
+
+        log.add_message(commit(1), &state).unwrap();
+        log.add_message(commit(2), &state).unwrap();
+        assert_eq!(log.len(), 3);
+        assert!(log.messages.contains_key(&prepare.message));
+
+        state.working_block = None;
+        log.add_message(commit(3), &state).unwrap();
+        assert_eq!(log.len(), 3);
+        assert!(!log.messages.contains_key(&prepare.message));
+    }
+
+    /// Make sure that messages just past the current sequence number are kept, but ones far past
+    /// it are rejected unless the limit is disabled
+    #[test]
+    fn max_future_seq_distance() {
+        let mut cfg = config::mock_config(4);
+        let state = PbftState::new(vec![0], 0, &cfg);
+        let mut log = PbftLog::new(&cfg);
+        let peer = get_peer_id(&cfg, 1);
+
+        let next = make_msg(&PbftMessageType::Prepare, 0, 2, peer.clone(), peer.clone());
+        assert!(log.add_message(next, &state).is_ok());
+        let edge = make_msg(&PbftMessageType::Prepare, 0, 11, peer.clone(), peer.clone());
+        assert!(log.add_message(edge, &state).is_ok());
+
+        let far = make_msg(
+            &PbftMessageType::Prepare,
+            0,
+            1_000_000,
+            peer.clone(),
+            peer.clone(),
+        );
+        match log.add_message(far.clone(), &state) {
+            Err(PbftError::SeqNumTooFarAhead(1_000_000, 1)) => {}
+            res => panic!("Expected SeqNumTooFarAhead, got {:?}", res),
+        }
+        assert_eq!(log.messages.len(), 2);
+
+        // A BlockNew has had its seal verified, and is needed for catching up
+        let block_new = make_msg(
+            &PbftMessageType::BlockNew,
+            0,
+            1_000_000,
+            peer.clone(),
+            peer.clone(),
+        );
+        assert!(log.add_message(block_new, &state).is_ok());
+
+        cfg.max_future_seq_distance = 0;
+        let mut log = PbftLog::new(&cfg);
+        assert!(log.add_message(far, &state).is_ok());
+    }
+
+    /// Make sure that a log that keeps getting messages while its block is stuck stays within
+    /// `max_log_messages`, without losing the messages the block's quorum needs
+    /// Make sure that messages at either end of the sequence number range are checked for
+    /// eviction without overflowing
+    #[test]
+    fn evictable_seq_num_bounds() {
+        let cfg = config::mock_config(4);
+        let mut state = PbftState::new(vec![0], 4, &cfg);
+        let far_ahead = make_msg(
+            &PbftMessageType::Commit,
+            0,
+            u64::MAX,
+            get_peer_id(&cfg, 1),
+            get_peer_id(&cfg, 0),
+        );
+        assert!(!PbftLog::is_evictable(&far_ahead, &state));
+
+        state.seq_num = 0;
+        let first = make_msg(
+            &PbftMessageType::Commit,
+            0,
+            0,
+            get_peer_id(&cfg, 1),
+            get_peer_id(&cfg, 0),
+        );
+        assert!(!PbftLog::is_evictable(&first, &state));
+    }
+
+    #[test]
+    fn max_log_messages_stalled() {
+        let mut cfg = config::mock_config(4);
+        cfg.max_log_size = 12;
+        cfg.max_log_messages = 12;
+        let mut log = PbftLog::new(&cfg);
+        let mut state = PbftState::new(vec![0], 4, &cfg);
+        assert_eq!(state.seq_num, 5);
+
+        // Block 5 went through a few views without being committed
+        for view in 0..3 {
+            state.view = view;
+            for peer in 0..4 {
+                let msg = make_msg(
+                    &PbftMessageType::Prepare,
+                    view,
+                    5,
+                    get_peer_id(&cfg, peer),
+                    get_peer_id(&cfg, 0),
+                );
+                log.add_message(msg, &state).unwrap();
+            }
+        }
+
+        // It's now prepared and committed in view 3
+        state.view = 3;
+        let primary = state.get_primary_id_for_view(3);
+        let mut msgs = vec![make_msg(
+            &PbftMessageType::PrePrepare,
+            3,
+            5,
+            primary.clone(),
+            get_peer_id(&cfg, 0),
+        )];
+        for msg_type in &[PbftMessageType::Prepare, PbftMessageType::Commit] {
+            for peer in 0..4 {
+                msgs.push(make_msg(
+                    msg_type,
+                    3,
+                    5,
+                    get_peer_id(&cfg, peer),
+                    get_peer_id(&cfg, 0),
+                ));
+            }
+        }
+
+        // ViewChanges keep coming in, for views that were already reached and for the next one
+        for (view, peer) in (1..4)
+            .flat_map(|view| (0..4).map(move |peer| (view, peer)))
+            .chain((1..4).map(|peer| (4, peer)))
+        {
+            let mut info = PbftMessageInfo::new();
+            info.set_msg_type(String::from(&PbftMessageType::ViewChange));
+            info.set_view(view);
+            info.set_seq_num(5);
+            info.set_signer_id(get_peer_id(&cfg, peer));
+            let mut vc = PbftViewChange::new();
+            vc.set_info(info);
+            msgs.push(ParsedMessage::from_view_change_message(vc));
+        }
+
+        for msg in msgs {
+            log.add_message(msg, &state).unwrap();
+            assert!(log.len() <= 12);
+        }
+
+        let mut info = PbftMessageInfo::new();
+        info.set_view(3);
+        info.set_seq_num(5);
+        info.set_signer_id(primary);
+        assert!(log.check_prepared(&info, &state));
+        assert!(log.check_committable(&info, &state));
+        assert!(log
+            .get_messages_of_type_seq_view(&PbftMessageType::Prepare, 5, 0)
+            .is_empty());
+        assert_eq!(
+            log.get_messages_of_type_seq_view(&PbftMessageType::ViewChange, 5, 4)
+                .len(),
+            3
+        );
+    }
+
+    /// Make sure that garbage collection keeps messages and seals for the last `gc_retain_depth`
+    /// committed sequence numbers
+    #[test]
+    fn garbage_collection_retain_depth() {
+        let mut cfg = config::mock_config(4);
+        cfg.gc_retain_depth = 3;
+        cfg.max_log_size = 1;
+        let mut log = PbftLog::new(&cfg);
+        let state = PbftState::new(vec![], 0, &cfg);
+
+        for seq in 1..8 {
+            for peer in 0..4 {
+                let msg = make_msg(
+                    &PbftMessageType::Commit,
+                    0,
+                    seq,
+                    get_peer_id(&cfg, peer),
+                    get_peer_id(&cfg, 0),
+                );
+                log.add_message(msg, &state).unwrap();
+            }
+            log.add_consensus_seal(vec![seq as u8], seq, PbftSeal::new())
+                .unwrap();
+            log.record_commit_view(vec![seq as u8], seq, 0);
+        }
+
+        // Block 7 was just committed, so 5, 6, and 7 are the last 3 committed sequence numbers
+        log.garbage_collect(8, &vec![7]);
+
+        for seq in 1..5 {
+            assert!(log
+                .get_messages_of_type_seq(&PbftMessageType::Commit, seq)
+                .is_empty());
+            assert!(log.get_consensus_seal(seq).is_err());
+            assert_eq!(log.view_for_block(&[seq as u8]), None);
+        }
+        for seq in 5..8 {
+            assert_eq!(
+                log.get_messages_of_type_seq(&PbftMessageType::Commit, seq)
+                    .len(),
+                4
+            );
+            assert!(log.get_consensus_seal(seq).is_ok());
+            assert_eq!(log.view_for_block(&[seq as u8]), Some(0));
+        }
+    }
+
+    /// Make sure that the stored seals stay bounded over a long chain, while the seal needed for
+    /// proposing a view change at the next sequence number is always kept
+    #[test]
+    fn seal_garbage_collection() {
+        let mut cfg = config::mock_config(4);
+        cfg.gc_retain_depth = 3;
+        let mut log = PbftLog::new(&cfg);
+
+        for seq in 1..200u64 {
+            let block_id = format!("block {}", seq).into_bytes();
+            let mut seal = PbftSeal::new();
+            seal.set_previous_id(format!("block {}", seq - 1).into_bytes());
+            log.add_consensus_seal(block_id.clone(), seq, seal).unwrap();
+
+            log.garbage_collect(seq + 1, &block_id);
+
+            assert!(log.seal_count() <= 3);
+            assert!(log.get_consensus_seal(seq).is_ok());
+        }
+    }
+
+    /// Make sure that a checkpoint only becomes stable with `2f + 1` matching checkpoints, and
+    /// that garbage collection keeps everything from the stable checkpoint on
+    #[test]
+    fn checkpoints() {
+        let mut cfg = config::mock_config(4);
+        cfg.checkpoint_period = 2;
+        cfg.max_log_size = 1;
+        let mut log = PbftLog::new(&cfg);
+        let state = PbftState::new(vec![], 0, &cfg);
+        let block_id =
+            |seq: u64| hash_sha256(format!("I'm a block with block num {}", seq).as_bytes());
+        let checkpoint = |seq: u64, peer: u64| {
+            make_msg(
+                &PbftMessageType::Checkpoint,
+                0,
+                seq,
+                get_peer_id(&cfg, peer),
+                get_peer_id(&cfg, 0),
+            )
+        };
+
+        for seq in 1..8 {
+            for peer in 0..4 {
+                let msg = make_msg(
+                    &PbftMessageType::Commit,
+                    0,
+                    seq,
+                    get_peer_id(&cfg, peer),
+                    get_peer_id(&cfg, 0),
+                );
+                log.add_message(msg, &state).unwrap();
+            }
+            log.add_consensus_seal(vec![seq as u8], seq, PbftSeal::new())
+                .unwrap();
+        }
+
+        // The same node's checkpoint only counts once
+        assert!(!log.add_checkpoint(checkpoint(4, 0), &state));
+        assert!(!log.add_checkpoint(checkpoint(4, 0), &state));
+        assert!(!log.add_checkpoint(checkpoint(4, 1), &state));
+        assert!(!log.is_stable(4, &block_id(4), 1));
+        assert!(log.add_checkpoint(checkpoint(4, 2), &state));
+        assert!(log.is_stable(4, &block_id(4), 1));
+        assert!(!log.is_stable(6, &block_id(6), 1));
+        assert_eq!(log.stable_checkpoint(), 4);
+
+        // Checkpoints at or below the stable checkpoint are ignored
+        assert!(!log.add_checkpoint(checkpoint(2, 3), &state));
+        assert_eq!(log.stable_checkpoint(), 4);
+
+        // Block 7 was just committed, but nothing from the stable checkpoint on is removed
+        log.garbage_collect(8, &vec![7]);
+        for seq in 1..4 {
+            assert!(log
+                .get_messages_of_type_seq(&PbftMessageType::Commit, seq)
+                .is_empty());
+            assert!(log.get_consensus_seal(seq).is_err());
+        }
+        for seq in 4..8 {
+            assert_eq!(
+                log.get_messages_of_type_seq(&PbftMessageType::Commit, seq)
+                    .len(),
+                4
+            );
+            assert!(log.get_consensus_seal(seq).is_ok());
+        }
+    }
+
+    /// Make sure that checkpoints past the high watermark, from nodes that don't vote, or for a
+    /// block other than the one this node committed are ignored
+    #[test]
+    fn checkpoint_bounds() {
+        let mut cfg = config::mock_config(5);
+        cfg.checkpoint_period = 2;
+        cfg.observers = vec![get_peer_id(&cfg, 4)];
+        let mut log = PbftLog::new(&cfg);
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        let block_id =
+            |seq: u64| hash_sha256(format!("I'm a block with block num {}", seq).as_bytes());
+        let checkpoint = |seq: u64, peer: u64| {
+            make_msg(
+                &PbftMessageType::Checkpoint,
+                0,
+                seq,
+                get_peer_id(&cfg, peer),
+                get_peer_id(&cfg, 0),
+            )
+        };
+
+        // Without watermarks, checkpoints are bounded by max_future_seq_distance
+        assert!(!log.add_checkpoint(checkpoint(12, 0), &state));
+        assert!(log.checkpoints.is_empty());
+        state.watermark_window = 4;
+        assert!(!log.add_checkpoint(checkpoint(6, 0), &state));
+        assert!(log.checkpoints.is_empty());
+
+        // Observers don't vote, so their checkpoints don't count
+        assert!(!log.add_checkpoint(checkpoint(4, 4), &state));
+        assert!(log.checkpoints.is_empty());
+
+        // This node committed a different block at sequence number 2
+        log.record_commit_view(vec![9], 2, 0);
+        assert!(!log.add_checkpoint(checkpoint(2, 0), &state));
+        assert!(log.checkpoints.is_empty());
+
+        log.record_commit_view(block_id(4), 4, 0);
+        for peer in 0..3 {
+            log.add_checkpoint(checkpoint(4, peer), &state);
+        }
+        assert_eq!(log.stable_checkpoint(), 4);
+    }
 }
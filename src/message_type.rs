@@ -27,13 +27,20 @@ use sawtooth_sdk::consensus::engine::PeerMessage;
 
 use crate::error::PbftError;
 use crate::hash::verify_sha512;
-use crate::protos::pbft_message::{PbftBlock, PbftMessage, PbftMessageInfo, PbftViewChange};
+use crate::protos::pbft_message::{
+    PbftBlock, PbftMessage, PbftMessageInfo, PbftNewView, PbftViewChange, PbftVoteRequest,
+};
+
+/// Longest signer or block ID that's accepted in a message
+const MAX_ID_LENGTH: usize = 256;
 
 /// Wrapper enum for all of the possible PBFT-related messages
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum PbftMessageWrapper {
     Message(PbftMessage),
     ViewChange(PbftViewChange),
+    NewView(PbftNewView),
+    VoteRequest(PbftVoteRequest),
 }
 
 /// Container for a received PeerMessage and the PBFT message parsed from it
@@ -65,6 +72,8 @@ impl Hash for ParsedMessage {
         match &self.message {
             PbftMessageWrapper::Message(m) => m.hash(state),
             PbftMessageWrapper::ViewChange(m) => m.hash(state),
+            PbftMessageWrapper::NewView(m) => m.hash(state),
+            PbftMessageWrapper::VoteRequest(m) => m.hash(state),
         }
     }
 }
@@ -96,10 +105,38 @@ impl ParsedMessage {
         }
     }
 
+    /// Constructs a `ParsedMessage` from the given `PbftNewView`.
+    ///
+    /// Does not add metadata necessary for adding this message to the consensus seal.
+    pub fn from_new_view_message(message: PbftNewView) -> Self {
+        Self {
+            from_self: false,
+            header_bytes: vec![],
+            header_signature: vec![],
+            message_bytes: message.write_to_bytes().unwrap(),
+            message: PbftMessageWrapper::NewView(message),
+        }
+    }
+
+    /// Constructs a `ParsedMessage` from the given `PbftVoteRequest`.
+    ///
+    /// Does not add metadata necessary for adding this message to the consensus seal.
+    pub fn from_vote_request_message(message: PbftVoteRequest) -> Self {
+        Self {
+            from_self: false,
+            header_bytes: vec![],
+            header_signature: vec![],
+            message_bytes: message.write_to_bytes().unwrap(),
+            message: PbftMessageWrapper::VoteRequest(message),
+        }
+    }
+
     pub fn info(&self) -> &PbftMessageInfo {
         match &self.message {
             PbftMessageWrapper::Message(m) => &m.get_info(),
             PbftMessageWrapper::ViewChange(m) => &m.get_info(),
+            PbftMessageWrapper::NewView(m) => &m.get_info(),
+            PbftMessageWrapper::VoteRequest(m) => &m.get_info(),
         }
     }
 
@@ -107,30 +144,46 @@ impl ParsedMessage {
         match self.message {
             PbftMessageWrapper::Message(ref mut m) => m.mut_info(),
             PbftMessageWrapper::ViewChange(ref mut m) => m.mut_info(),
+            PbftMessageWrapper::NewView(ref mut m) => m.mut_info(),
+            PbftMessageWrapper::VoteRequest(ref mut m) => m.mut_info(),
         }
     }
 
     /// Returns the `PbftBlock` for this message's wrapped `PbftMessage`.
     ///
-    /// Panics if it encounters a view change message, as that should never happen.
+    /// Panics if it encounters a view change or vote request message, as that should never
+    /// happen.
     pub fn get_block(&self) -> &PbftBlock {
         match &self.message {
             PbftMessageWrapper::Message(m) => m.get_block(),
             PbftMessageWrapper::ViewChange(_) => {
                 panic!("ParsedPeerMessage.get_block found a view change message!")
             }
+            PbftMessageWrapper::NewView(_) => {
+                panic!("ParsedPeerMessage.get_block found a new view message!")
+            }
+            PbftMessageWrapper::VoteRequest(_) => {
+                panic!("ParsedPeerMessage.get_block found a vote request message!")
+            }
         }
     }
 
     /// Returns the wrapped `PbftMessage`.
     ///
-    /// Panics if it encounters a view change message, as that should never happen.
+    /// Panics if it encounters a view change or vote request message, as that should never
+    /// happen.
     pub fn get_pbft_message(&self) -> &PbftMessage {
         match &self.message {
             PbftMessageWrapper::Message(m) => m,
             PbftMessageWrapper::ViewChange(_) => {
                 panic!("ParsedPeerMessage.get_pbft_message found a view change message!")
             }
+            PbftMessageWrapper::NewView(_) => {
+                panic!("ParsedPeerMessage.get_pbft_message found a new view message!")
+            }
+            PbftMessageWrapper::VoteRequest(_) => {
+                panic!("ParsedPeerMessage.get_pbft_message found a vote request message!")
+            }
         }
     }
 
@@ -143,13 +196,39 @@ impl ParsedMessage {
                 panic!("ParsedPeerMessage.get_view_change_message found a pbft message!")
             }
             PbftMessageWrapper::ViewChange(m) => m,
+            PbftMessageWrapper::NewView(_) => {
+                panic!("ParsedPeerMessage.get_view_change_message found a new view message!")
+            }
+            PbftMessageWrapper::VoteRequest(_) => {
+                panic!("ParsedPeerMessage.get_view_change_message found a vote request message!")
+            }
+        }
+    }
+
+    /// Returns the wrapped `PbftNewView`.
+    ///
+    /// Panics if it encounters any other kind of message, as that should never happen.
+    pub fn get_new_view_message(&self) -> &PbftNewView {
+        match &self.message {
+            PbftMessageWrapper::NewView(m) => m,
+            _ => panic!("ParsedPeerMessage.get_new_view_message found another message type!"),
+        }
+    }
+
+    /// Returns the wrapped `PbftVoteRequest`.
+    ///
+    /// Panics if it encounters any other kind of message, as that should never happen.
+    pub fn get_vote_request_message(&self) -> &PbftVoteRequest {
+        match &self.message {
+            PbftMessageWrapper::VoteRequest(m) => m,
+            _ => panic!("ParsedPeerMessage.get_vote_request_message found another message type!"),
         }
     }
 
     /// Constructs a `ParsedMessage` from the given `PeerMessage`.
     ///
-    /// Attempts to parse the message contents as either a `PbftMessage` or a `PbftViewChange`,
-    /// and wraps that in an internal enum.
+    /// Attempts to parse the message contents as a `PbftMessage`, `PbftVoteRequest`,
+    /// `PbftNewView`, or `PbftViewChange`, and wraps that in an internal enum.
     pub fn from_peer_message(message: PeerMessage, from_self: bool) -> Result<Self, PbftError> {
         // Self-constructed messages aren't signed, since we don't have access to
         // the validator key necessary for signing them.
@@ -159,16 +238,35 @@ impl ParsedMessage {
         // This complex parsing is due to the fact that proto3 doesn't have any way of requiring
         // fields, so a `PbftViewChange` can get parsed as a `PbftMessage` that doesn't have
         // the `block` field defined. So, we try parsing a PbftMessage first, and if that fails
-        // or has a ViewChange message type, then try parsing it as a view change message, and
-        // if that fails, then it's probably a bad message.
+        // or has a ViewChange or VoteRequest message type, then try parsing it as the message
+        // that type calls for, and if that fails, then it's probably a bad message.
         let parsed_message = protobuf::parse_from_bytes::<PbftMessage>(&message.content)
             .ok()
-            .and_then(|m| {
-                if m.get_info().get_msg_type() == "ViewChange" {
-                    None
-                } else {
-                    Some(PbftMessageWrapper::Message(m))
-                }
+            .and_then(|m| match m.get_info().get_msg_type() {
+                "ViewChange" | "NewView" | "VoteRequest" => None,
+                _ => Some(PbftMessageWrapper::Message(m)),
+            })
+            .or_else(|| {
+                protobuf::parse_from_bytes::<PbftVoteRequest>(&message.content)
+                    .ok()
+                    .and_then(|m| {
+                        if m.get_info().get_msg_type() == "VoteRequest" {
+                            Some(PbftMessageWrapper::VoteRequest(m))
+                        } else {
+                            None
+                        }
+                    })
+            })
+            .or_else(|| {
+                protobuf::parse_from_bytes::<PbftNewView>(&message.content)
+                    .ok()
+                    .and_then(|m| {
+                        if m.get_info().get_msg_type() == "NewView" {
+                            Some(PbftMessageWrapper::NewView(m))
+                        } else {
+                            None
+                        }
+                    })
             })
             .or_else(|| {
                 protobuf::parse_from_bytes::<PbftViewChange>(&message.content)
@@ -187,13 +285,66 @@ impl ParsedMessage {
     }
 
     /// Constructs a `ParsedMessage` from the given serialized `PbftMessage`
+    ///
+    /// The bytes may be truncated or garbage, so the message is validated once it's parsed; an
+    /// unparseable or malformed message is an error, never a message that later code would trip
+    /// over (such as an empty block ID).
     pub fn from_bytes(message: Vec<u8>) -> Result<Self, PbftError> {
         let peer_message = PeerMessage {
             content: message,
             ..Default::default()
         };
 
-        Self::from_peer_message(peer_message, true)
+        let parsed = Self::from_peer_message(peer_message, true)?;
+        parsed.validate()?;
+        Ok(parsed)
+    }
+
+    /// Check that this message is well-formed enough to be handled
+    ///
+    /// Messages from peers are untrusted, so this is checked before they reach the log or the
+    /// handlers: the signer ID must be present and of a sane length, the message type must be
+    /// known and match how the message was parsed, and messages about a block must have one.
+    pub fn validate(&self) -> Result<(), PbftError> {
+        let info = self.info();
+        check_id_length("signer ID", info.get_signer_id())?;
+
+        let msg_type = PbftMessageType::from(info.get_msg_type());
+        let parsed_as_type = match (&self.message, &msg_type) {
+            (PbftMessageWrapper::Message(_), PbftMessageType::PrePrepare)
+            | (PbftMessageWrapper::Message(_), PbftMessageType::Prepare)
+            | (PbftMessageWrapper::Message(_), PbftMessageType::Commit)
+            | (PbftMessageWrapper::Message(_), PbftMessageType::BlockNew)
+            | (PbftMessageWrapper::Message(_), PbftMessageType::Checkpoint)
+            | (PbftMessageWrapper::ViewChange(_), PbftMessageType::ViewChange)
+            | (PbftMessageWrapper::NewView(_), PbftMessageType::NewView)
+            | (PbftMessageWrapper::VoteRequest(_), PbftMessageType::VoteRequest) => true,
+            _ => false,
+        };
+        if let PbftMessageType::Unset | PbftMessageType::Unknown(_) = msg_type {
+            return Err(PbftError::InvalidMessage(format!(
+                "Unknown message type {:?}",
+                info.get_msg_type()
+            )));
+        }
+        if !parsed_as_type {
+            return Err(PbftError::InvalidMessage(format!(
+                "{:?} message doesn't have the fields of its type",
+                msg_type
+            )));
+        }
+
+        if let PbftMessageWrapper::Message(ref message) = self.message {
+            if !message.has_block() {
+                return Err(PbftError::InvalidMessage(format!(
+                    "{:?} message has no block",
+                    msg_type
+                )));
+            }
+            check_id_length("block ID", message.get_block().get_block_id())?;
+        }
+
+        Ok(())
     }
 
     /// Constructs a copy of this message with the given message type
@@ -214,6 +365,24 @@ impl ParsedMessage {
     }
 }
 
+/// Make sure an ID from a message is present and not implausibly long
+fn check_id_length(name: &str, id: &[u8]) -> Result<(), PbftError> {
+    if id.is_empty() {
+        return Err(PbftError::InvalidMessage(format!(
+            "Message has no {}",
+            name
+        )));
+    }
+    if id.len() > MAX_ID_LENGTH {
+        return Err(PbftError::InvalidMessage(format!(
+            "Message's {} is {} bytes long",
+            name,
+            id.len()
+        )));
+    }
+    Ok(())
+}
+
 // Messages related to PBFT consensus
 #[derive(Debug, PartialEq, PartialOrd)]
 pub enum PbftMessageType {
@@ -225,8 +394,14 @@ pub enum PbftMessageType {
     /// Auxiliary PBFT messages
     BlockNew,
     ViewChange,
+    NewView,
+    VoteRequest,
+    Checkpoint,
 
     Unset,
+
+    /// A message type this node doesn't know, with the type as it was received
+    Unknown(String),
 }
 
 impl fmt::Display for PbftMessageType {
@@ -237,7 +412,11 @@ impl fmt::Display for PbftMessageType {
             PbftMessageType::Commit => "Co",
             PbftMessageType::BlockNew => "BN",
             PbftMessageType::ViewChange => "VC",
+            PbftMessageType::NewView => "NV",
+            PbftMessageType::VoteRequest => "VR",
+            PbftMessageType::Checkpoint => "CP",
             PbftMessageType::Unset => "Un",
+            PbftMessageType::Unknown(_) => "??",
         };
         write!(f, "{}", txt)
     }
@@ -253,6 +432,22 @@ impl PbftMessageType {
             _ => false,
         }
     }
+
+    /// The name of the message type, as it's sent in `PbftMessageInfo::msg_type`
+    pub fn as_str(&self) -> &str {
+        match self {
+            PbftMessageType::PrePrepare => "PrePrepare",
+            PbftMessageType::Prepare => "Prepare",
+            PbftMessageType::Commit => "Commit",
+            PbftMessageType::BlockNew => "BlockNew",
+            PbftMessageType::ViewChange => "ViewChange",
+            PbftMessageType::NewView => "NewView",
+            PbftMessageType::VoteRequest => "VoteRequest",
+            PbftMessageType::Checkpoint => "Checkpoint",
+            PbftMessageType::Unset => "Unset",
+            PbftMessageType::Unknown(name) => name,
+        }
+    }
 }
 
 impl<'a> From<&'a str> for PbftMessageType {
@@ -263,16 +458,105 @@ impl<'a> From<&'a str> for PbftMessageType {
             "Commit" => PbftMessageType::Commit,
             "BlockNew" => PbftMessageType::BlockNew,
             "ViewChange" => PbftMessageType::ViewChange,
-            _ => {
-                warn!("Unhandled PBFT message type: {}", s);
-                PbftMessageType::Unset
-            }
+            "NewView" => PbftMessageType::NewView,
+            "VoteRequest" => PbftMessageType::VoteRequest,
+            "Checkpoint" => PbftMessageType::Checkpoint,
+            "Unset" => PbftMessageType::Unset,
+            _ => PbftMessageType::Unknown(s.into()),
         }
     }
 }
 
 impl<'a> From<&'a PbftMessageType> for String {
     fn from(mc_type: &'a PbftMessageType) -> String {
-        format!("{:?}", mc_type)
+        mc_type.as_str().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make sure that every known message type survives conversion to a string and back, and that
+    /// an unknown type keeps the string it came from
+    #[test]
+    fn message_type_round_trip() {
+        let known = vec![
+            PbftMessageType::PrePrepare,
+            PbftMessageType::Prepare,
+            PbftMessageType::Commit,
+            PbftMessageType::BlockNew,
+            PbftMessageType::ViewChange,
+            PbftMessageType::NewView,
+            PbftMessageType::VoteRequest,
+            PbftMessageType::Checkpoint,
+            PbftMessageType::Unset,
+        ];
+        for msg_type in known {
+            assert_eq!(PbftMessageType::from(msg_type.as_str()), msg_type);
+            assert_eq!(String::from(&msg_type), msg_type.as_str());
+        }
+
+        let unknown = PbftMessageType::from("Comit");
+        assert_eq!(unknown, PbftMessageType::Unknown("Comit".into()));
+        assert_eq!(String::from(&unknown), "Comit");
+        assert_eq!(
+            PbftMessageType::from(""),
+            PbftMessageType::Unknown("".into())
+        );
+    }
+
+    /// Feed `from_bytes` truncated, corrupted, and random bytes, and make sure that it never
+    /// panics, and that whatever it accepts can be handled without panicking either
+    #[test]
+    fn from_bytes_garbage() {
+        let mut info = PbftMessageInfo::new();
+        info.set_msg_type("Commit".into());
+        info.set_seq_num(5);
+        info.set_signer_id(vec![1]);
+        let mut block = PbftBlock::new();
+        block.set_block_id(vec![2]);
+        let mut msg = PbftMessage::new();
+        msg.set_info(info);
+        msg.set_block(block);
+        let valid = msg.write_to_bytes().unwrap();
+
+        let check = |bytes: Vec<u8>| {
+            if let Ok(parsed) = ParsedMessage::from_bytes(bytes) {
+                assert!(parsed.validate().is_ok());
+                let _ = format!("{}", parsed.info());
+                if let PbftMessageWrapper::Message(ref message) = parsed.message {
+                    let err = PbftError::BlockMismatch(
+                        message.get_block().clone(),
+                        message.get_block().clone(),
+                    );
+                    let _ = format!("{}", err);
+                }
+            }
+        };
+
+        // Short IDs are accepted, and don't break anything that displays them
+        assert!(ParsedMessage::from_bytes(valid.clone()).is_ok());
+        for len in 0..valid.len() {
+            check(valid[..len].to_vec());
+        }
+
+        // Simple xorshift, so that the test is the same every run
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for _ in 0..2000 {
+            let mut corrupted = valid.clone();
+            let pos = next() as usize % corrupted.len();
+            corrupted[pos] = next() as u8;
+            check(corrupted);
+
+            let random: Vec<u8> = (0..next() % 64).map(|_| next() as u8).collect();
+            check(random);
+        }
     }
 }
@@ -0,0 +1,227 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Consensus health metrics, updated by the node as it runs
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// How many recent blocks' missing voters are remembered
+const NON_VOTER_HISTORY: usize = 100;
+
+/// Why a peer message was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectionReason {
+    /// The message is malformed
+    Malformed,
+
+    /// The message is for a different PBFT network
+    WrongNetwork,
+
+    /// The message's timestamp is too old or too far in the future
+    InvalidTimestamp,
+
+    /// The message is for a view this node has already left
+    StaleView,
+
+    /// The message is for a view this node isn't in yet
+    FutureView,
+
+    /// The message's sequence number is too far past this node's, or past its high watermark
+    TooFarAhead,
+
+    /// The message should have come from the primary, but didn't
+    NotFromPrimary,
+
+    /// The sender already sent a conflicting `PrePrepare`, `Prepare`, or `Commit` for the same
+    /// view and sequence number
+    Equivocation,
+
+    /// The `NewView` isn't backed by a valid set of `ViewChange`s
+    InvalidNewView,
+
+    /// The message's type isn't one this node knows
+    UnknownType,
+}
+
+/// Point-in-time copy of a node's metrics, for reporting to a monitoring system
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Number of view changes this node has started
+    pub view_changes: u64,
+
+    /// Number of blocks committed after this node got them as its working block
+    pub commits: u64,
+
+    /// Time between the most recent committed block becoming the working block and its commit
+    pub last_commit_latency: Option<Duration>,
+
+    /// Average time between a block becoming the working block and its commit
+    pub average_commit_latency: Option<Duration>,
+
+    /// Number of messages in the backlog when it was last sampled
+    pub backlog_depth: usize,
+
+    /// Largest number of messages seen in the backlog
+    pub max_backlog_depth: usize,
+
+    /// Number of peer messages rejected for each reason
+    pub rejections: HashMap<RejectionReason, u64>,
+
+    /// Peers whose votes were missing from the consensus seals of recent blocks, by block number,
+    /// oldest first
+    pub non_voters: Vec<(u64, Vec<Vec<u8>>)>,
+}
+
+impl MetricsSnapshot {
+    /// Number of peer messages rejected for the given reason
+    pub fn rejections(&self, reason: RejectionReason) -> u64 {
+        *self.rejections.get(&reason).unwrap_or(&0)
+    }
+
+    /// Number of recent blocks whose consensus seal is missing the given peer's vote
+    pub fn missed_votes(&self, peer: &[u8]) -> usize {
+        self.non_voters
+            .iter()
+            .filter(|(_, non_voters)| non_voters.iter().any(|id| id.as_slice() == peer))
+            .count()
+    }
+}
+
+/// Counters shared between the node that updates them and whoever reports them
+///
+/// Every method takes `&self`, so a `PbftMetrics` can be wrapped in an `Arc` and read from
+/// another thread while the node is running.
+#[derive(Debug, Default)]
+pub struct PbftMetrics {
+    inner: Mutex<MetricsInner>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsInner {
+    view_changes: u64,
+    commits: u64,
+    total_commit_latency: Duration,
+    last_commit_latency: Option<Duration>,
+    backlog_depth: usize,
+    max_backlog_depth: usize,
+    rejections: HashMap<RejectionReason, u64>,
+    non_voters: VecDeque<(u64, Vec<Vec<u8>>)>,
+}
+
+impl PbftMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a view change started by this node
+    pub fn record_view_change(&self) {
+        self.lock().view_changes += 1;
+    }
+
+    /// Record how long a block took to commit after it became the working block
+    pub fn record_commit(&self, latency: Duration) {
+        let mut inner = self.lock();
+        inner.commits += 1;
+        inner.total_commit_latency += latency;
+        inner.last_commit_latency = Some(latency);
+    }
+
+    /// Record the current number of messages in the backlog
+    pub fn record_backlog_depth(&self, depth: usize) {
+        let mut inner = self.lock();
+        inner.backlog_depth = depth;
+        inner.max_backlog_depth = inner.max_backlog_depth.max(depth);
+    }
+
+    /// Count a peer message rejected for the given reason
+    pub fn record_rejection(&self, reason: RejectionReason) {
+        *self.lock().rejections.entry(reason).or_insert(0) += 1;
+    }
+
+    /// Record the peers that didn't vote for the given block, as shown by its consensus seal
+    ///
+    /// Only the most recent blocks are remembered; recording the same block again replaces it.
+    pub fn record_non_voters(&self, block_num: u64, non_voters: Vec<Vec<u8>>) {
+        let mut inner = self.lock();
+        inner.non_voters.retain(|(num, _)| *num != block_num);
+        if inner.non_voters.len() >= NON_VOTER_HISTORY {
+            inner.non_voters.pop_front();
+        }
+        inner.non_voters.push_back((block_num, non_voters));
+    }
+
+    /// Get a copy of the current values
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let inner = self.lock();
+        MetricsSnapshot {
+            view_changes: inner.view_changes,
+            commits: inner.commits,
+            last_commit_latency: inner.last_commit_latency,
+            average_commit_latency: if inner.commits > 0 {
+                Some(inner.total_commit_latency / inner.commits as u32)
+            } else {
+                None
+            },
+            backlog_depth: inner.backlog_depth,
+            max_backlog_depth: inner.max_backlog_depth,
+            rejections: inner.rejections.clone(),
+            non_voters: inner.non_voters.iter().cloned().collect(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<MetricsInner> {
+        self.inner.lock().expect("Metrics lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make sure that the snapshot reflects everything that was recorded
+    #[test]
+    fn snapshot() {
+        let metrics = PbftMetrics::new();
+        assert_eq!(metrics.snapshot(), MetricsSnapshot::default());
+
+        metrics.record_view_change();
+        metrics.record_commit(Duration::from_millis(10));
+        metrics.record_commit(Duration::from_millis(30));
+        metrics.record_backlog_depth(5);
+        metrics.record_backlog_depth(2);
+        metrics.record_rejection(RejectionReason::StaleView);
+        metrics.record_rejection(RejectionReason::StaleView);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.view_changes, 1);
+        assert_eq!(snapshot.commits, 2);
+        assert_eq!(
+            snapshot.last_commit_latency,
+            Some(Duration::from_millis(30))
+        );
+        assert_eq!(
+            snapshot.average_commit_latency,
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(snapshot.backlog_depth, 2);
+        assert_eq!(snapshot.max_backlog_depth, 5);
+        assert_eq!(snapshot.rejections(RejectionReason::StaleView), 2);
+        assert_eq!(snapshot.rejections(RejectionReason::WrongNetwork), 0);
+    }
+}
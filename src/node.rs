@@ -17,71 +17,749 @@
 
 //! The core PBFT algorithm
 
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::convert::From;
 use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use hex;
-use protobuf::{Message, ProtobufError, RepeatedField};
-use sawtooth_sdk::consensus::engine::{Block, BlockId, Error as EngineError, PeerId};
+use protobuf::{Message, RepeatedField};
+use sawtooth_sdk::consensus::engine::{Block, BlockId, Error as EngineError, PeerId, Update};
 use sawtooth_sdk::consensus::service::Service;
 use sawtooth_sdk::messages::consensus::ConsensusPeerMessageHeader;
-use sawtooth_sdk::signing::{create_context, secp256k1::Secp256k1PublicKey};
 
-use crate::config::{get_peers_from_settings, PbftConfig};
+use crate::config::{
+    check_peer_weights, dedup_peers, get_observers_from_settings, get_peer_weights_from_settings,
+    get_peers_from_settings, PbftConfig, OBSERVERS_SETTING_KEY, PEER_WEIGHTS_SETTING_KEY,
+};
 use crate::error::PbftError;
 use crate::handlers;
-use crate::hash::verify_sha512;
+use crate::hash::{verify_digest, HashAlgorithm};
+use crate::limiter::VerificationLimiter;
+use crate::log_fields::LogFields;
 use crate::message_log::PbftLog;
-use crate::message_type::{ParsedMessage, PbftMessageType};
+use crate::message_type::{ParsedMessage, PbftMessageType, PbftMessageWrapper};
+use crate::metrics::{PbftMetrics, RejectionReason};
 use crate::protos::pbft_message::{
-    PbftBlock, PbftMessage, PbftMessageInfo, PbftSeal, PbftSignedCommitVote, PbftViewChange,
+    PbftBlock, PbftMessage, PbftMessageInfo, PbftNewView, PbftSeal, PbftSignedCommitVote,
+    PbftViewChange, PbftVoteRequest,
 };
-use crate::state::{PbftMode, PbftPhase, PbftState};
+use crate::recorder::{UpdateRecord, UpdateRecorder};
+use crate::signing::{self, SignatureVerifier};
+use crate::state::{compute_f, PbftMode, PbftPhase, PbftState};
+use crate::timing;
+use crate::transport::MessageTransport;
+
+/// Why a view change was started
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ViewChangeReason {
+    /// The primary didn't publish a block before the faulty primary timeout expired
+    FaultyPrimaryTimeout,
+
+    /// `f + 1` `ViewChange` messages were received from other nodes
+    ViewChangeMessages,
+
+    /// The primary is rotated every `forced_view_change_period` blocks
+    ForcedRotation,
+
+    /// The on-chain list of peers changed
+    MembershipChange,
+
+    /// A block's consensus seal failed verification
+    InvalidSeal,
+
+    /// The validator reported the working block as invalid
+    InvalidBlock,
+
+    /// The primary stepped down on its own
+    Resigned,
+
+    /// The working block wasn't committed before the commit timeout expired
+    CommitTimeout,
+
+    /// The block for the current sequence number doesn't build on this node's chain head
+    ForkedBlock,
+
+    /// The working block's summary doesn't match the one the validator has for the block
+    SummaryMismatch,
+}
+
+/// How many recent seal verifications the rolling average covers
+const SEAL_TIMING_WINDOW: usize = 100;
+
+/// How far the wall clock may move from the monotonic clock between drift checks before it's
+/// reported
+const CLOCK_DRIFT_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// How long after a message is broadcast an identical broadcast is suppressed
+const BROADCAST_DEDUP_WINDOW: Duration = Duration::from_millis(500);
+
+/// How many blocks committed through catchup are remembered, until the validator reports them
+/// as committed
+const CATCHUP_COMMIT_WINDOW: usize = 16;
+
+/// How many observed faults are remembered
+const FAULT_HISTORY: usize = 100;
+
+/// How many blocks may be held back to be verified later
+const MAX_DEFERRED_BLOCKS: usize = 256;
+
+/// How many views past this node's current view a `NewView` may be for; a node that has fallen
+/// further behind catches up to the network's view from the votes in consensus seals instead
+const MAX_NEW_VIEW_DISTANCE: u64 = 100;
+
+/// Format version of the consensus seals this node builds; seals with a higher version are
+/// rejected, and ones without a version (0) are from before versions were added and are read as
+/// this format
+pub const SEAL_VERSION: u32 = 1;
+
+/// Evidence of a peer misbehaving, as kept by `PbftNode::recent_faults`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fault {
+    /// The peer held responsible: the publisher of a block with an invalid seal, the sender of
+    /// conflicting votes, the primary that proposed a block with the wrong summary, or the node
+    /// behind a view change that went too far
+    pub peer_id: PeerId,
+
+    /// The block the fault concerns
+    pub block_id: BlockId,
+
+    /// The error that exposed the fault
+    pub reason: String,
+}
+
+/// The check that a consensus seal failed, as reported by `PbftNode::explain_seal_failure`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SealFailure {
+    /// The block's payload couldn't be read as a seal (description)
+    Malformed(String),
+
+    /// The seal isn't for the block's predecessor (seal's previous ID, block's previous ID)
+    PreviousIdMismatch(BlockId, BlockId),
+
+    /// The seal's summary doesn't match the block's (seal's summary, block's summary)
+    SummaryMismatch(Vec<u8>, Vec<u8>),
+
+    /// The vote at this position in the seal failed verification (index, reason)
+    BadVote(usize, String),
+
+    /// Votes from nodes that aren't peers, or from the block's publisher
+    UnknownVoters(Vec<PeerId>),
+
+    /// Not enough distinct nodes voted (required, found); with peer weights, both are weights
+    InsufficientVotes(usize, usize),
+}
+
+impl fmt::Display for SealFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SealFailure::Malformed(description) => write!(f, "Malformed seal: {}", description),
+            SealFailure::PreviousIdMismatch(seal_id, block_id) => write!(
+                f,
+                "Seal's previous ID {} doesn't match block's previous ID {}",
+                hex::encode(seal_id),
+                hex::encode(block_id)
+            ),
+            SealFailure::SummaryMismatch(seal_summary, block_summary) => write!(
+                f,
+                "Seal's summary {} doesn't match block's summary {}",
+                hex::encode(seal_summary),
+                hex::encode(block_summary)
+            ),
+            SealFailure::BadVote(index, reason) => {
+                write!(f, "Vote {} is invalid: {}", index, reason)
+            }
+            SealFailure::UnknownVoters(ids) => write!(
+                f,
+                "Got votes from unexpected nodes: {:?}",
+                ids.iter().map(hex::encode).collect::<Vec<_>>()
+            ),
+            SealFailure::InsufficientVotes(required, found) => {
+                write!(f, "Need {} votes, only found {}", required, found)
+            }
+        }
+    }
+}
+
+impl From<PbftError> for SealFailure {
+    /// Classify the error from one of the seal verification checks
+    fn from(err: PbftError) -> Self {
+        match err {
+            PbftError::SealPreviousIdMismatch(seal_id, block_id) => {
+                SealFailure::PreviousIdMismatch(seal_id, block_id)
+            }
+            PbftError::SealSummaryMismatch(seal_summary, block_summary) => {
+                SealFailure::SummaryMismatch(seal_summary, block_summary)
+            }
+            PbftError::UnknownVoters(ids) => SealFailure::UnknownVoters(ids),
+            PbftError::InsufficientVotes(required, found) => {
+                SealFailure::InsufficientVotes(required, found)
+            }
+            err => SealFailure::Malformed(err.to_string()),
+        }
+    }
+}
+
+/// Read-only view of where a node is in the consensus process, as reported by
+/// `PbftNode::status`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConsensusStatus {
+    pub phase: PbftPhase,
+    pub mode: PbftMode,
+    pub view: u64,
+    pub seq_num: u64,
+
+    /// ID of the primary for the current view
+    pub primary_id: PeerId,
+
+    /// Whether this node is the primary for the current view
+    pub is_primary: bool,
+}
+
+/// Whether a node is making progress, as reported by `PbftNode::is_healthy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// The node is working through blocks as usual
+    Normal,
+
+    /// The node is changing views
+    ViewChanging,
+
+    /// The working block's commit timer ran out in the middle of consensus, and the log doesn't
+    /// have the votes needed to move on
+    Stuck,
+}
+
+/// Timings of recent consensus seal verifications
+#[derive(Debug, Default)]
+pub struct SealVerificationTiming {
+    /// Wall time and number of votes of the most recent verifications, oldest first
+    samples: VecDeque<(Duration, usize)>,
+
+    /// Total number of seals verified
+    pub count: u64,
+
+    /// Number of seals that had already been verified, so their votes weren't checked again
+    pub cache_hits: u64,
+}
+
+impl SealVerificationTiming {
+    fn record(&mut self, duration: Duration, num_votes: usize) {
+        if self.samples.len() == SEAL_TIMING_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((duration, num_votes));
+        self.count += 1;
+    }
+
+    /// Wall time and number of votes of the most recent verification
+    pub fn last(&self) -> Option<(Duration, usize)> {
+        self.samples.back().cloned()
+    }
+
+    /// Average wall time of the recent verifications
+    pub fn average(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let total: Duration = self.samples.iter().map(|(duration, _)| *duration).sum();
+        Some(total / self.samples.len() as u32)
+    }
+
+    /// Average number of votes in the recently verified seals
+    pub fn average_votes(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let total: usize = self.samples.iter().map(|(_, votes)| *votes).sum();
+        Some(total as f64 / self.samples.len() as f64)
+    }
+}
+
+/// Who could vote on a block, as of that block's on-chain settings
+struct Membership {
+    /// The network's members, less its observers
+    voters: Vec<PeerId>,
+
+    /// Maximum number of faulty voters
+    f: u64,
+}
 
 /// Contains all of the components for operating a PBFT node.
 pub struct PbftNode {
     /// Used for interactions with the validator
     pub service: Box<Service>,
 
+    /// Used for sending messages to peers instead of `service`, if set
+    transport: Option<Box<MessageTransport>>,
+
+    /// Told about every update before it's handled, if set
+    recorder: Option<Box<UpdateRecorder>>,
+
     /// Messages this node has received
     pub msg_log: PbftLog,
+
+    /// What triggered the most recent view change, if there has been one
+    last_view_change_reason: Option<ViewChangeReason>,
+
+    /// Whether this node has asked the validator to start building a block that it hasn't
+    /// published or cancelled yet
+    block_initialized: bool,
+
+    /// Bounds how many threads verify consensus seal votes at once
+    verification_limiter: VerificationLimiter,
+
+    /// Whether the signatures of consensus seal votes are checked
+    verify_signatures: bool,
+
+    /// Whether this node only verifies the chain, without sending messages or building blocks
+    verify_only: bool,
+
+    /// On-chain setting that lists the peers
+    peers_setting_key: String,
+
+    /// How many times the wait between reads of the peers setting is doubled while they keep
+    /// failing
+    settings_retries: u32,
+
+    /// How long to wait before the first retry of a failed settings read
+    settings_retry_delay: Duration,
+
+    /// Reads of the peers setting that have failed in a row
+    settings_failures: u32,
+
+    /// When the peers setting may be read again, after a failed read
+    settings_retry_at: Option<Instant>,
+
+    /// Checks the signatures of consensus seal votes
+    signature_verifier: Arc<SignatureVerifier>,
+
+    /// Digest that vote headers hold of their messages
+    hash_algorithm: HashAlgorithm,
+
+    /// How many threads verify the votes of a single consensus seal
+    vote_verification_threads: usize,
+
+    /// How long verifying recent consensus seals took
+    seal_timing: SealVerificationTiming,
+
+    /// Consensus health metrics, shared with whoever reports them
+    metrics: Arc<PbftMetrics>,
+
+    /// When the current working block was set, for measuring how long it takes to commit
+    working_block_since: Option<Instant>,
+
+    /// Checks that the wall clock keeps pace with the monotonic clock
+    clock_drift: timing::ClockDriftDetector,
+
+    /// How old a message may be before it's rejected (0 disables the check)
+    max_message_age: Duration,
+
+    /// Tolerance for differences between this node's clock and its peers' clocks
+    max_clock_skew: Duration,
+
+    /// How often, in committed blocks, to broadcast a `Checkpoint` (0 disables checkpoints)
+    checkpoint_period: u64,
+
+    /// How far ahead of the current sequence number seals are verified as blocks arrive (0 for no
+    /// limit)
+    seal_prefetch_depth: u64,
+
+    /// Most blocks committed by one call to `catchup_range`, or fetched by one call to
+    /// `request_missing_blocks` (0 for no limit)
+    max_catchup_blocks_per_loop: u64,
+
+    /// Blocks that `request_missing_blocks` found it needs, but didn't get to fetch yet
+    missing_blocks: Vec<BlockId>,
+
+    /// Blocks that `request_missing_blocks` fetched, held until the ones before them are fetched
+    fetched_blocks: Vec<Block>,
+
+    /// How far past the last committed block's view a view change may go before it's reported
+    /// (0 disables the check)
+    max_view_jump: u64,
+
+    /// Blocks that arrived too far ahead of the current sequence number or past the high watermark
+    /// to verify yet; at most `MAX_DEFERRED_BLOCKS` of them
+    deferred_blocks: Vec<Block>,
+
+    /// First block that carries a consensus seal
+    first_sealed_block: u64,
+
+    /// Recently broadcast messages, by type, view, sequence number, and block ID, along with when
+    /// they were sent
+    recent_broadcasts: VecDeque<((String, u64, u64, BlockId), Instant)>,
+
+    /// Blocks recently committed through catchup, whose `BlockCommit` from the validator will
+    /// arrive after this node has already moved on
+    catchup_commits: VecDeque<BlockId>,
+
+    /// The ID of the block a catch-up is going to, with the numbers and IDs of the blocks still
+    /// to commit before it, in order; found once by walking back from that block
+    catchup_path: Option<(BlockId, VecDeque<(u64, BlockId)>)>,
+
+    /// Recently observed faults by peers, oldest first
+    faults: VecDeque<Fault>,
+
+    /// Highest sequence number each peer has sent a `Commit` for, and the block it was for
+    latest_commits: HashMap<PeerId, (u64, BlockId)>,
+
+    /// Whether broadcast messages are also delivered to this node right away
+    self_send: bool,
+
+    /// Every message this node has broadcast
+    #[cfg(test)]
+    sent_messages: Vec<ParsedMessage>,
+
+    /// Every message this node has sent to a single peer, along with that peer
+    #[cfg(test)]
+    sent_direct: Vec<(PeerId, ParsedMessage)>,
 }
 
 impl PbftNode {
     /// Construct a new PBFT node.
-    /// After the node is created, if the node is primary, it initializes a new block on the chain.
-    pub fn new(config: &PbftConfig, service: Box<Service>, is_primary: bool) -> Self {
-        let mut n = PbftNode {
+    /// The node records its metrics in `metrics`. A primary doesn't start building a block until
+    /// `initialize_block_if_ready` sees enough live peers.
+    ///
+    /// # Panics
+    /// + If the configured signing algorithm isn't supported
+    pub fn new(config: &PbftConfig, service: Box<Service>, metrics: Arc<PbftMetrics>) -> Self {
+        let n = PbftNode {
             service,
+            transport: None,
+            recorder: None,
             msg_log: PbftLog::new(config),
+            last_view_change_reason: None,
+            block_initialized: false,
+            verification_limiter: VerificationLimiter::new(config.max_concurrent_verifications),
+            verify_signatures: config.verify_signatures,
+            verify_only: config.verify_only,
+            peers_setting_key: config.peers_setting_key.clone(),
+            settings_retries: config.settings_retries,
+            settings_retry_delay: config.settings_retry_delay,
+            settings_failures: 0,
+            settings_retry_at: None,
+            signature_verifier: signing::verifier_for(&config.signing_algorithm)
+                .unwrap_or_else(|err| panic!("{}", err)),
+            hash_algorithm: HashAlgorithm::from_name(&config.hash_algorithm)
+                .unwrap_or_else(|err| panic!("{}", err)),
+            vote_verification_threads: config.vote_verification_threads,
+            checkpoint_period: config.checkpoint_period,
+            seal_prefetch_depth: config.seal_prefetch_depth,
+            max_catchup_blocks_per_loop: config.max_catchup_blocks_per_loop,
+            missing_blocks: vec![],
+            fetched_blocks: vec![],
+            max_view_jump: config.max_view_jump,
+            deferred_blocks: vec![],
+            first_sealed_block: config.first_sealed_block,
+            recent_broadcasts: VecDeque::new(),
+            catchup_commits: VecDeque::new(),
+            catchup_path: None,
+            faults: VecDeque::new(),
+            latest_commits: HashMap::new(),
+            seal_timing: SealVerificationTiming::default(),
+            metrics,
+            working_block_since: None,
+            clock_drift: timing::ClockDriftDetector::new(CLOCK_DRIFT_THRESHOLD),
+            max_message_age: config.max_message_age,
+            max_clock_skew: config.max_clock_skew,
+            self_send: true,
+            #[cfg(test)]
+            sent_messages: vec![],
+            #[cfg(test)]
+            sent_direct: vec![],
         };
 
-        // Primary initializes a block
-        if is_primary {
-            n.service
-                .initialize_block(None)
-                .unwrap_or_else(|err| error!("Couldn't initialize block: {}", err));
+        if !config.verify_signatures {
+            warn!(
+                "SIGNATURE VERIFICATION IS DISABLED; consensus seals will be accepted without \
+                 checking their votes' signatures. This is unsafe outside of trusted test networks!"
+            );
         }
+
         n
     }
 
+    /// Have the primary start building a block, once enough peers are connected to vote on it
+    ///
+    /// Until a quorum of voting nodes is live, blocks the primary published would only time out, so
+    /// it waits; this is checked again whenever a peer connects.
+    pub fn initialize_block_if_ready(&mut self, state: &PbftState) {
+        if !state.is_primary()
+            || self.block_initialized
+            || state.phase != PbftPhase::PrePreparing
+            || !state.has_live_quorum()
+        {
+            return;
+        }
+        self.initialize_block(None);
+    }
+
+    /// Move straight to a view the rest of the network has already changed to, and if this node
+    /// is its primary, start building a block right away instead of waiting for the next commit
+    fn catch_up_to_view(&mut self, state: &mut PbftState, view: u64) {
+        handlers::catch_up_to_view(state, view);
+        if state.working_block.is_none() {
+            self.initialize_block_if_ready(state);
+        }
+    }
+
+    /// Ask the validator to start building a block on top of the given one (or the chain head)
+    fn initialize_block(&mut self, previous_id: Option<BlockId>) {
+        if self.verify_only {
+            return;
+        }
+        match self.service.initialize_block(previous_id) {
+            Ok(()) => self.block_initialized = true,
+            Err(err) => error!("Couldn't initialize block: {}", err),
+        }
+    }
+
+    /// Tell the validator to drop the block this node started building as primary, if there is
+    /// one, so that the next primary starts clean
+    fn cancel_initialized_block(&mut self, state: &PbftState) {
+        if !state.is_primary() || !self.block_initialized {
+            return;
+        }
+        info!("{}: Cancelling block", state);
+        self.service
+            .cancel_block()
+            .unwrap_or_else(|err| error!("Couldn't cancel block: {}", err));
+        self.block_initialized = false;
+    }
+
+    /// Get the node ready to stop, in whatever phase it's in
+    ///
+    /// Every timer is stopped, and a primary cancels the block it initialized so the validator
+    /// isn't left holding it. The state is persisted by the storage it's borrowed from once the
+    /// caller lets go of it; the engine does this right after shutting the node down.
+    pub fn shutdown(&mut self, state: &mut PbftState) {
+        warn!("{}: Shutting down", state);
+        self.cancel_initialized_block(state);
+        state.stop_timeouts();
+    }
+
     // ---------- Methods for handling Updates from the validator ----------
 
+    /// Handle an update from the validator, passing it to the method for its kind
+    ///
+    /// The update is given to the update recorder first, if there is one; a node fed the same
+    /// updates (and ticks) from a recording ends up in the same state. Returns `false` if the node
+    /// should shut down.
+    pub fn apply_update(
+        &mut self,
+        update: Update,
+        state: &mut PbftState,
+    ) -> Result<bool, PbftError> {
+        self.record(&UpdateRecord::from(&update), state);
+
+        match update {
+            Update::BlockNew(block) => self.on_block_new(block, state)?,
+            Update::BlockValid(block_id) => self.on_block_valid(&block_id, state)?,
+            Update::BlockInvalid(block_id) => self.on_block_invalid(&block_id, state)?,
+            Update::BlockCommit(block_id) => self.on_block_commit(block_id, state),
+            Update::PeerMessage(message, sender_id) => {
+                let parsed_message = ParsedMessage::from_peer_message(message, false)?;
+                let signer_id = parsed_message.info().get_signer_id().to_vec();
+
+                if signer_id != sender_id {
+                    return Err(PbftError::InternalError(format!(
+                        "Mismatch between sender ID ({:?}) and signer ID ({:?})!",
+                        sender_id, signer_id
+                    )));
+                }
+
+                self.on_peer_message(parsed_message, state)?
+            }
+            Update::Shutdown => return Ok(false),
+            Update::PeerConnected(info) => self.on_peer_connected(info.peer_id, state),
+            Update::PeerDisconnected(peer_id) => self.on_peer_disconnected(peer_id, state),
+        }
+
+        Ok(true)
+    }
+
+    /// Do the periodic work the engine does every time its working timer goes off: try to publish
+    /// a block, retry deferred blocks, fetch missed blocks, and check the node's timeouts
+    ///
+    /// The tick is given to the update recorder first, if there is one, so a replay takes the same
+    /// steps.
+    pub fn on_tick(&mut self, state: &mut PbftState) {
+        self.record(&UpdateRecord::Tick, state);
+
+        if let Err(e) = self.try_publish(state) {
+            error!("{}", e);
+        }
+
+        // Blocks whose seals couldn't be verified yet (for instance, because the validator
+        // couldn't read the settings) are tried again
+        self.release_deferred_blocks(state);
+
+        // If the network has moved on without this node, go get the blocks it missed
+        if let Err(err) = self.request_missing_blocks(state) {
+            error!("Couldn't request missing blocks: {}", err);
+        }
+
+        self.check_clock_drift();
+
+        // Every so often, check to see if the faulty primary timeout has expired; initiate
+        // ViewChange if necessary
+        if self.check_faulty_primary_timeout_expired(state) {
+            warn!("Faulty primary timeout expired; proposing view change");
+            handle_pbft_result(
+                self.propose_view_change(state, ViewChangeReason::FaultyPrimaryTimeout),
+            );
+        }
+
+        // Likewise, start a view change if the working block is taking too long to commit
+        if self.check_commit_timeout_expired(state) {
+            warn!("Commit timeout expired; proposing view change");
+            handle_pbft_result(self.propose_view_change(state, ViewChangeReason::CommitTimeout));
+        }
+
+        // If a view change has stalled, the next primary may be down too; move on to the view
+        // after it
+        if self.check_view_change_timeout_expired(state) {
+            warn!("View change timeout expired; trying the next view");
+            handle_pbft_result(self.retry_view_change(state));
+        }
+
+        // Report if this node has been stuck in its current phase for too long
+        if state.check_phase_timeout_expired() {
+            warn!(
+                "{}: Stuck in phase {:?} for longer than {:?} ({:?} in view {})",
+                state,
+                state.phase,
+                state.phase_timeout_for(&state.phase),
+                state.elapsed_in_view(),
+                state.view
+            );
+            state.phase_timeout.start();
+        }
+    }
+
+    /// Give an update or tick to the update recorder, if there is one
+    fn record(&mut self, record: &UpdateRecord, state: &PbftState) {
+        if let Some(ref mut recorder) = self.recorder {
+            if let Err(err) = recorder.record(record) {
+                error!("{}: Couldn't record update: {}", state, err);
+            }
+        }
+    }
+
     /// Handle a peer message from another PbftNode
     /// This method handles all messages from other nodes. Such messages may include `PrePrepare`,
     /// `Prepare`, `Commit`, or `ViewChange`. If a node receives a type of message before it is
     // ready to do so, the message is pushed into a backlog queue.
-    #[allow(clippy::needless_pass_by_value)]
+    ///
+    /// This is the same as handling a batch of just this message with `process_batch`.
     pub fn on_peer_message(
         &mut self,
         msg: ParsedMessage,
         state: &mut PbftState,
     ) -> Result<(), PbftError> {
-        info!("{}: Got peer message: {}", state, msg.info());
+        self.process_batch(vec![msg], state)
+    }
+
+    /// Log a message from a peer, and run the checks that every message must pass before it's
+    /// handled, whether it arrives on its own or in a batch
+    fn check_peer_message(
+        &mut self,
+        msg: &ParsedMessage,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
+        let mut fields = LogFields::for_state(state).msg_type(msg.info().get_msg_type());
+        if let PbftMessageWrapper::Message(ref pbft_msg) = msg.message {
+            fields = fields.block_id(pbft_msg.get_block().get_block_id());
+        }
+        info!("{}: Got peer message: {} {}", state, msg.info(), fields);
+
+        if let PbftMessageType::Unknown(msg_type) = PbftMessageType::from(msg.info().get_msg_type())
+        {
+            self.metrics.record_rejection(RejectionReason::UnknownType);
+            warn!("{}: Dropping message of unknown type {:?}", state, msg_type);
+            return Err(PbftError::InvalidMessage(format!(
+                "Unknown message type {:?}",
+                msg_type
+            )));
+        }
+
+        // This node's own messages were built by it moments ago, so only messages from peers need
+        // to be checked before they're handled
+        if !msg.from_self {
+            if let Err(err) = msg.validate() {
+                self.metrics.record_rejection(RejectionReason::Malformed);
+                warn!("{}: Dropping malformed message: {}", state, err);
+                return Err(err);
+            }
+            self.check_network(msg, state)?;
+            self.check_message_age(msg, state)?;
+        }
+
+        if PbftMessageType::from(msg.info().get_msg_type()) == PbftMessageType::Commit {
+            self.note_commit(msg, state);
+        }
+
+        if PbftMessageType::from(msg.info().get_msg_type()).is_multicast() {
+            if let Err(err) = state.check_watermark(msg.info().get_seq_num()) {
+                self.metrics.record_rejection(RejectionReason::TooFarAhead);
+                warn!("{}: Dropping message: {}", state, err);
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
 
+    /// Handle a message that has passed `check_peer_message`, other than a `Prepare` or `Commit`;
+    /// those are added to the log and evaluated by `process_batch`
+    #[allow(clippy::needless_pass_by_value)]
+    fn handle_message(
+        &mut self,
+        msg: ParsedMessage,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
         match PbftMessageType::from(msg.info().msg_type.as_str()) {
             PbftMessageType::PrePrepare => {
+                // PrePrepares for sequence numbers that are already behind us are for blocks that
+                // have already been committed
+                if msg.info().get_seq_num() < state.seq_num {
+                    debug!(
+                        "{}: Ignoring PrePrepare for old sequence number {}",
+                        state,
+                        msg.info().get_seq_num()
+                    );
+                    return Ok(());
+                }
+
+                // A node that's still waiting for its working block to commit past the commit
+                // deadline won't get anywhere by piling up the primary's later PrePrepares, so it
+                // gives up on the primary instead. Only the current primary's PrePrepares count for
+                // this; anyone else's are rejected as usual below.
+                if msg.info().get_seq_num() > state.seq_num
+                    && msg.info().get_view() == state.view
+                    && msg.info().get_signer_id() == state.get_primary_id().as_slice()
+                    && state.phase == PbftPhase::Committing
+                    && state.commit_timeout.check_expired()
+                {
+                    warn!(
+                        "{}: Got PrePrepare for sequence number {} while stuck committing; \
+                         starting view change",
+                        state,
+                        msg.info().get_seq_num()
+                    );
+                    return self.propose_view_change(state, ViewChangeReason::CommitTimeout);
+                }
+
+                // A PrePrepare for the current sequence number only needs to be answered with a
+                // Prepare once; a duplicate (such as the primary's own PrePrepare being delivered
+                // again) must not cause another Prepare to be broadcast
+                let already_prepared = msg.info().get_seq_num() == state.seq_num
+                    && state.phase != PbftPhase::PrePreparing;
+
                 // Message is added to log by handler if it is valid
                 match handlers::pre_prepare(state, &mut self.msg_log, &msg) {
                     Ok(()) => {}
@@ -90,35 +768,35 @@ impl PbftNode {
                         self.msg_log.push_backlog(msg);
                         return Ok(());
                     }
-                    err => {
-                        return err;
+                    Err(err) => {
+                        let reason = match err {
+                            PbftError::NotFromPrimary => Some(RejectionReason::NotFromPrimary),
+                            PbftError::BlockMismatch(_, _) => Some(RejectionReason::Equivocation),
+                            PbftError::ViewMismatch(got, exp) if got < exp => {
+                                Some(RejectionReason::StaleView)
+                            }
+                            PbftError::ViewMismatch(_, _) => Some(RejectionReason::FutureView),
+                            PbftError::SeqNumTooFarAhead(_, _) => {
+                                Some(RejectionReason::TooFarAhead)
+                            }
+                            _ => None,
+                        };
+                        if let Some(reason) = reason {
+                            self.metrics.record_rejection(reason);
+                        }
+                        return Err(err);
                     }
                 }
 
-                self.broadcast_pre_prepare(&msg, state)?;
-            }
-
-            PbftMessageType::Prepare => {
-                self.msg_log.add_message(msg.clone(), state)?;
-
-                // We only want to check the block if this message is for the current sequence
-                // number
-                if msg.info().get_seq_num() == state.seq_num
-                    && self.msg_log.check_prepared(&msg.info(), state.f)
-                {
-                    self.check_blocks_if_not_checking(&msg, state)?;
-                }
-            }
-
-            PbftMessageType::Commit => {
-                self.msg_log.add_message(msg.clone(), state)?;
-
-                // We only want to commit the block if this message is for the current sequence
-                // number
-                if msg.info().get_seq_num() == state.seq_num
-                    && self.msg_log.check_committable(&msg.info(), state.f)
-                {
-                    self.commit_block_if_committing(&msg, state)?;
+                if already_prepared {
+                    debug!(
+                        "{}: Already sent Prepare for sequence number {}",
+                        state,
+                        msg.info().get_seq_num()
+                    );
+                } else {
+                    self.broadcast_pre_prepare(&msg, state)?;
+                    self.replay_buffered_votes(state)?;
                 }
             }
 
@@ -132,28 +810,581 @@ impl PbftNode {
                     info.get_seq_num(),
                 );
 
+                // Once a view has been adopted, ViewChanges for it or any earlier view are stale;
+                // only a quorum for a higher view can move this node on
+                if info.get_view() <= state.view {
+                    debug!(
+                        "{}: Ignoring stale ViewChange for view {}",
+                        state,
+                        info.get_view()
+                    );
+                    return Ok(());
+                }
+
+                // Old ViewChanges replayed to this node mustn't push it into a view change: ones
+                // from before the previous sequence number are stale, and a peer's ViewChange only
+                // counts once per view
+                if info.get_seq_num() < state.seq_num.saturating_sub(1) {
+                    debug!(
+                        "{}: Ignoring ViewChange for old sequence number {}",
+                        state,
+                        info.get_seq_num()
+                    );
+                    return Ok(());
+                }
+                let first_from_peer = match self
+                    .msg_log
+                    .view_change_from(info.get_signer_id(), info.get_view())
+                {
+                    Some(existing) if existing.message != msg.message => {
+                        debug!(
+                            "{}: Ignoring another ViewChange for view {} from {}",
+                            state,
+                            info.get_view(),
+                            hex::encode(info.get_signer_id())
+                        );
+                        return Ok(());
+                    }
+                    Some(_) => false,
+                    None => true,
+                };
+                if first_from_peer {
+                    self.check_view_jump(info.get_view(), info.get_signer_id().to_vec(), state);
+                }
+
                 self.msg_log.add_message(msg.clone(), state)?;
 
                 if self.propose_view_change_if_enough_messages(&msg, state)? {
                     return Ok(());
                 }
 
+                let view = state.view;
                 handlers::view_change(state, &mut self.msg_log, &mut *self.service, &msg)?;
+                self.prune_deferred_blocks(state);
+
+                // The new primary has started a block, and has to prove to the other nodes that
+                // it was chosen legitimately
+                if state.view > view && state.is_primary() {
+                    self.block_initialized = true;
+                    self.broadcast_new_view(&msg, state)?;
+                    self.request_head_commits(state)?;
+                }
+            }
+
+            PbftMessageType::NewView => {
+                let view = msg.info().get_view();
+                if view <= state.view {
+                    debug!("{}: Ignoring stale NewView for view {}", state, view);
+                    return Ok(());
+                }
+
+                // An unjustified NewView doesn't change anything; if this node is waiting on a
+                // view change, it keeps waiting
+                if !msg.from_self {
+                    if let Err(err) = self.verify_new_view(&msg, state) {
+                        self.metrics
+                            .record_rejection(RejectionReason::InvalidNewView);
+                        warn!("{}: Rejecting NewView for view {}: {}", state, view, err);
+                        return Err(err);
+                    }
+                }
+
+                handlers::new_view(state, &msg);
+            }
+
+            PbftMessageType::VoteRequest => self.resend_vote(&msg, state)?,
+
+            PbftMessageType::Checkpoint => {
+                let low_watermark = state.low_watermark;
+                handlers::checkpoint(state, &mut self.msg_log, &msg);
+                if state.low_watermark > low_watermark {
+                    self.release_deferred_blocks(state);
+                }
             }
 
             _ => warn!("Message type not implemented"),
         }
+        debug_assert!(
+            state.check_invariants().is_ok(),
+            "{:?}",
+            state.check_invariants()
+        );
         Ok(())
     }
 
-    fn broadcast_pre_prepare(
+    /// Handle a batch of peer messages, such as a drained backlog or a recorded message stream
+    ///
+    /// Consecutive `Prepare` and `Commit` messages are all added to the log before the quorum
+    /// thresholds are evaluated, once for each distinct message type, view, and sequence number.
+    /// Any other message is handled by `on_peer_message` in its original position, after the votes
+    /// that precede it have been evaluated, so the outcome matches sequential processing.
+    ///
+    /// Every message goes through the same checks as one handled on its own. Every message in the
+    /// batch is processed; the first error encountered is returned.
+    pub fn process_batch(
         &mut self,
-        pbft_message: &ParsedMessage,
+        msgs: Vec<ParsedMessage>,
         state: &mut PbftState,
     ) -> Result<(), PbftError> {
-        info!(
-            "{}: PrePrepare, sequence number {}",
-            state,
+        let mut first_err = None;
+        let mut pending_votes: Vec<ParsedMessage> = vec![];
+
+        for msg in msgs {
+            let res = match PbftMessageType::from(msg.info().msg_type.as_str()) {
+                PbftMessageType::Prepare | PbftMessageType::Commit => self
+                    .check_peer_message(&msg, state)
+                    .and_then(|_| self.check_vote_view(&msg, state))
+                    .and_then(|current| {
+                        if current {
+                            self.add_vote(msg.clone(), state)?;
+                        }
+                        Ok(current)
+                    })
+                    .map(|current| {
+                        // Only keep one message per (type, view, seq) for evaluation
+                        if current
+                            && !pending_votes.iter().any(|pending| {
+                                pending.info().get_msg_type() == msg.info().get_msg_type()
+                                    && pending.info().get_view() == msg.info().get_view()
+                                    && pending.info().get_seq_num() == msg.info().get_seq_num()
+                            })
+                        {
+                            pending_votes.push(msg);
+                        }
+                    }),
+                _ => self
+                    .evaluate_votes(pending_votes.split_off(0), state)
+                    .and_then(|_| self.check_peer_message(&msg, state))
+                    .and_then(|_| self.handle_message(msg, state)),
+            };
+
+            if let Err(err) = res {
+                debug!("{}: Error while processing batch: {}", state, err);
+                first_err.get_or_insert(err);
+            }
+        }
+
+        if let Err(err) = self.evaluate_votes(pending_votes, state) {
+            first_err.get_or_insert(err);
+        }
+
+        debug_assert!(
+            state.check_invariants().is_ok(),
+            "{:?}",
+            state.check_invariants()
+        );
+
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Evaluate the `Prepare` and `Commit` thresholds for votes that have already been added to the
+    /// log; `Prepare`s are evaluated first, since a block must be checked before it is committed
+    fn evaluate_votes(
+        &mut self,
+        mut votes: Vec<ParsedMessage>,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
+        votes.sort_by_key(|msg| {
+            PbftMessageType::from(msg.info().msg_type.as_str()) != PbftMessageType::Prepare
+        });
+
+        for msg in votes {
+            if msg.info().get_seq_num() != state.seq_num {
+                continue;
+            }
+
+            match PbftMessageType::from(msg.info().msg_type.as_str()) {
+                PbftMessageType::Prepare if self.msg_log.check_prepared(msg.info(), state) => {
+                    self.check_blocks_if_not_checking(&msg, state)?;
+                }
+                PbftMessageType::Commit if self.msg_log.check_committable(msg.info(), state) => {
+                    self.commit_block_if_committing(&msg, state)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a `Prepare` or `Commit` from a peer to the log, counting it if it's rejected for
+    /// conflicting with one the same peer already sent or for being too far ahead
+    fn add_vote(&mut self, msg: ParsedMessage, state: &PbftState) -> Result<(), PbftError> {
+        let block_id = msg.get_block().get_block_id().to_vec();
+        let res = self.msg_log.add_message(msg, state);
+        if let Err(ref err) = res {
+            match *err {
+                PbftError::Equivocation(_, ref signer) => {
+                    self.metrics.record_rejection(RejectionReason::Equivocation);
+                    self.record_fault(signer.clone(), block_id, err);
+                }
+                PbftError::SeqNumTooFarAhead(_, _) => {
+                    self.metrics.record_rejection(RejectionReason::TooFarAhead);
+                }
+                _ => {}
+            }
+        }
+        res
+    }
+
+    /// Remember the sequence number of a peer's `Commit`, and move `highest_seen_seq_num` up once
+    /// enough peers have committed a later sequence number that at least one of them is honest
+    ///
+    /// This happens before the message can be dropped for being too far ahead, since a node that
+    /// has fallen far behind only finds out from exactly those messages.
+    fn note_commit(&mut self, msg: &ParsedMessage, state: &mut PbftState) {
+        let signer = PeerId::from(msg.info().get_signer_id());
+        let seq_num = msg.info().get_seq_num();
+        if msg.info().get_view() < state.view || !state.peer_ids.contains(&signer) {
+            return;
+        }
+        if let Some(&(latest, _)) = self.latest_commits.get(&signer) {
+            if seq_num <= latest {
+                return;
+            }
+        }
+        let block_id = BlockId::from(msg.get_block().get_block_id());
+        self.latest_commits.insert(signer, (seq_num, block_id));
+
+        self.latest_commits
+            .retain(|peer, _| state.peer_ids.contains(peer));
+        let mut seq_nums: Vec<u64> = self
+            .latest_commits
+            .values()
+            .map(|&(seq_num, _)| seq_num)
+            .collect();
+        seq_nums.sort_unstable_by(|a, b| b.cmp(a));
+        if let Some(&seen) = seq_nums.get(state.f as usize) {
+            if seen > state.highest_seen_seq_num {
+                state.highest_seen_seq_num = seen;
+            }
+        }
+    }
+
+    /// Remember a fault by a peer, forgetting the oldest one if too many are remembered
+    fn record_fault(&mut self, peer_id: PeerId, block_id: BlockId, reason: &PbftError) {
+        if self.faults.len() >= FAULT_HISTORY {
+            self.faults.pop_front();
+        }
+        self.faults.push_back(Fault {
+            peer_id,
+            block_id,
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Report a view change to a view more than `max_view_jump` views past the one the last
+    /// committed block was committed in
+    ///
+    /// Repeated failed view changes (for instance, in a partitioned network) can keep pushing the
+    /// view up. The node still follows the view change, but the jump is logged and, if a peer is
+    /// behind it, recorded as a fault of that peer so that operators can see it; this node's own
+    /// view changes are only logged. Nothing is checked if the view of the last committed block
+    /// isn't known.
+    fn check_view_jump(&mut self, view: u64, peer_id: PeerId, state: &PbftState) {
+        if self.max_view_jump == 0 {
+            return;
+        }
+        let block_id = match state.last_committed_block {
+            Some(ref block_id) => block_id.clone(),
+            None => return,
+        };
+        let committed_view = match self.msg_log.view_for_block(&block_id) {
+            Some(view) => view,
+            None => return,
+        };
+
+        if view > committed_view + self.max_view_jump {
+            let err = PbftError::ViewJump(view, committed_view);
+            error!(
+                "{}: RUNAWAY VIEW CHANGES: {} (from {})",
+                state,
+                err,
+                hex::encode(&peer_id)
+            );
+            if peer_id != state.id {
+                self.record_fault(peer_id, block_id, &err);
+            }
+        }
+    }
+
+    /// Get the faults by peers this node has recently observed, oldest first
+    pub fn recent_faults(&self) -> &VecDeque<Fault> {
+        &self.faults
+    }
+
+    /// Check whether a `Prepare` or `Commit` is for this node's current view, and so can count
+    /// towards a quorum
+    ///
+    /// Votes from an earlier view are ignored, except that a primary keeps the chain head's
+    /// `Commit`s for its seal (see `is_head_commit`). Votes from a later view are held in the
+    /// backlog if this node is changing views, since they'll be current once it's done; otherwise
+    /// they're rejected. Votes replayed from a seal while catching up don't go through this check,
+    /// since they may have been cast before a view change.
+    fn check_vote_view(
+        &mut self,
+        msg: &ParsedMessage,
+        state: &PbftState,
+    ) -> Result<bool, PbftError> {
+        let view = msg.info().get_view();
+        if view < state.view && Self::is_head_commit(msg, state) {
+            // The primary may still need the chain head's Commits to build its seal; they're
+            // usually from an earlier view, since a view change just made it primary
+            debug!(
+                "{}: Keeping Commit for the chain head: {}",
+                state,
+                msg.info()
+            );
+            self.msg_log.add_seal_vote(msg.clone(), state);
+            Ok(false)
+        } else if view < state.view {
+            self.metrics.record_rejection(RejectionReason::StaleView);
+            debug!("{}: Ignoring vote from earlier view: {}", state, msg.info());
+            Ok(false)
+        } else if view > state.view && state.mode == PbftMode::ViewChanging {
+            debug!("{}: Backlogging vote for later view: {}", state, msg.info());
+            self.msg_log.push_backlog(msg.clone());
+            Ok(false)
+        } else if view > state.view {
+            self.metrics.record_rejection(RejectionReason::FutureView);
+            Err(PbftError::ViewMismatch(view as usize, state.view as usize))
+        } else {
+            Ok(true)
+        }
+    }
+
+    /// Whether the message is a `Commit` for the chain head that this node, as a primary that has
+    /// yet to publish, may build the seal for its next block from
+    fn is_head_commit(msg: &ParsedMessage, state: &PbftState) -> bool {
+        PbftMessageType::from(msg.info().get_msg_type()) == PbftMessageType::Commit
+            && state.is_primary()
+            && state.phase == PbftPhase::PrePreparing
+            && state.seq_num.checked_sub(1) == Some(msg.info().get_seq_num())
+            && state.last_committed_block.as_deref() == Some(msg.get_block().get_block_id())
+    }
+
+    /// Act on the votes for the current phase that arrived before this node entered it
+    ///
+    /// Early votes are kept in the log, but their thresholds are normally only evaluated when
+    /// another vote of the same type arrives. Calling this on entering a phase means a quorum
+    /// that's already there is acted on right away, instead of waiting for one more vote.
+    fn replay_buffered_votes(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
+        let msg_type = match state.phase {
+            PbftPhase::Preparing => PbftMessageType::Prepare,
+            PbftPhase::Committing => PbftMessageType::Commit,
+            _ => return Ok(()),
+        };
+        let block = match state.working_block {
+            Some(ref block) => block.clone(),
+            None => return Ok(()),
+        };
+
+        let mut msg = PbftMessage::new();
+        msg.set_info(handlers::make_msg_info(
+            &msg_type,
+            state.view,
+            state.seq_num,
+            state.id.clone(),
+        ));
+        msg.set_block(block);
+
+        self.evaluate_votes(vec![ParsedMessage::from_pbft_message(msg)], state)
+    }
+
+    /// Handle a `VoteRequest` by sending this node's own vote of the requested type, sequence
+    /// number, and view back to the requesting node, if this node has logged one
+    ///
+    /// The vote may have been cast a while ago, so it's stamped with the current time before it's
+    /// re-sent; otherwise the requester could reject it as too old. A `Commit` for a block this
+    /// node has already committed may have been cast in an earlier view than the one asked for,
+    /// so the latest one up to that view is sent.
+    fn resend_vote(&mut self, msg: &ParsedMessage, state: &mut PbftState) -> Result<(), PbftError> {
+        let requester = PeerId::from(msg.info().get_signer_id());
+        let vote_type = msg.get_vote_request_message().get_vote_type();
+
+        if vote_type != "Prepare" && vote_type != "Commit" {
+            warn!("{}: Got request for non-vote type {}", state, vote_type);
+            return Ok(());
+        }
+
+        let seq_num = msg.info().get_seq_num();
+        let view = msg.info().get_view();
+        let committed = vote_type == "Commit" && seq_num < state.seq_num;
+        let own_vote = self
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::from(vote_type), seq_num)
+            .into_iter()
+            .filter(|vote| {
+                vote.info().get_signer_id() == &state.id[..]
+                    && (vote.info().get_view() == view
+                        || (committed && vote.info().get_view() < view))
+            })
+            .max_by_key(|vote| vote.info().get_view())
+            .map(|vote| vote.get_pbft_message().clone());
+
+        match own_vote {
+            Some(mut vote) => {
+                vote.mut_info().set_timestamp(timing::unix_millis());
+                let vote_bytes = vote
+                    .write_to_bytes()
+                    .map_err(|err| PbftError::SerializationError("serializing PbftMessage", err))?;
+                debug!(
+                    "{}: Re-sending {} for seq {} to {}",
+                    state,
+                    vote_type,
+                    msg.info().get_seq_num(),
+                    hex::encode(&requester)
+                );
+                self._send_to(&requester, vote_type, vote_bytes);
+            }
+            None => debug!(
+                "{}: No {} for seq {} to re-send",
+                state,
+                vote_type,
+                msg.info().get_seq_num()
+            ),
+        }
+
+        Ok(())
+    }
+
+    /// Ask the peers whose votes this node is still waiting on at the current sequence number and
+    /// view to send them again, in case they were lost
+    pub fn request_missing_votes(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
+        let vote_type = match state.check_msg_type() {
+            PbftMessageType::Prepare => PbftMessageType::Prepare,
+            PbftMessageType::Commit => PbftMessageType::Commit,
+            _ => return Ok(()),
+        };
+
+        let msg_bytes = Self::vote_request_bytes(&vote_type, state.seq_num, state)?;
+        for peer in self.missing_voters(state) {
+            if peer != state.id {
+                self._send_to(&peer, "VoteRequest", msg_bytes.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ask the other voting peers for their `Commit`s for the chain head, if this node, as the
+    /// primary, doesn't have enough of them to build the seal for its next block
+    ///
+    /// This happens when a view change makes a node primary right after it was synced to the chain
+    /// head without having taken part in committing it. The `Commit`s may be from an earlier view;
+    /// they're kept as they arrive (see `check_vote_view`).
+    fn request_head_commits(&mut self, state: &PbftState) -> Result<(), PbftError> {
+        if state.seq_num < self.first_sealed_block || state.is_single_node() {
+            return Ok(());
+        }
+        let previous_seq_num = state.previous_seq_num()?;
+        if self
+            .msg_log
+            .get_enough_messages(
+                &PbftMessageType::Commit,
+                previous_seq_num,
+                state.last_committed_block.as_deref(),
+                2 * state.f + 1,
+                state,
+            )
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        warn!(
+            "{}: Missing Commits for block {}; asking the other nodes for them",
+            state, previous_seq_num
+        );
+        let msg_bytes =
+            Self::vote_request_bytes(&PbftMessageType::Commit, previous_seq_num, state)?;
+        for peer in &state.peer_ids {
+            if *peer != state.id {
+                self._send_to(peer, "VoteRequest", msg_bytes.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build a `VoteRequest` for this node's peers' votes of the given type at the given sequence
+    /// number, in the current view
+    fn vote_request_bytes(
+        vote_type: &PbftMessageType,
+        seq_num: u64,
+        state: &PbftState,
+    ) -> Result<Vec<u8>, PbftError> {
+        let mut info = handlers::make_msg_info(
+            &PbftMessageType::VoteRequest,
+            state.view,
+            seq_num,
+            state.id.clone(),
+        );
+        info.set_network_id(state.network_id.clone());
+        info.set_timestamp(timing::unix_millis());
+
+        let mut request = PbftVoteRequest::new();
+        request.set_info(info);
+        request.set_vote_type(String::from(vote_type));
+        request
+            .write_to_bytes()
+            .map_err(|err| PbftError::SerializationError("serializing VoteRequest", err))
+    }
+
+    /// Make sure the message is meant for the network that this node belongs to
+    fn check_network(&self, msg: &ParsedMessage, state: &PbftState) -> Result<(), PbftError> {
+        if msg.info().get_network_id() != state.network_id {
+            self.metrics.record_rejection(RejectionReason::WrongNetwork);
+            warn!(
+                "{}: Rejecting message for network {:?}",
+                state,
+                msg.info().get_network_id()
+            );
+            return Err(PbftError::WrongNetwork(
+                state.network_id.clone(),
+                msg.info().get_network_id().into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Make sure the message's timestamp, if it has one, isn't implausibly old or in the future
+    fn check_message_age(&self, msg: &ParsedMessage, state: &PbftState) -> Result<(), PbftError> {
+        let timestamp = msg.info().get_timestamp();
+        if self.max_message_age == Duration::from_millis(0) || timestamp == 0 {
+            return Ok(());
+        }
+
+        let now = timing::unix_millis();
+        let (msg_time, local_time) = (Duration::from_millis(timestamp), Duration::from_millis(now));
+
+        let too_old = local_time > msg_time
+            && local_time - msg_time > self.max_message_age + self.max_clock_skew;
+        let too_new = msg_time > local_time && msg_time - local_time > self.max_clock_skew;
+
+        if too_old || too_new {
+            self.metrics
+                .record_rejection(RejectionReason::InvalidTimestamp);
+            warn!(
+                "{}: Rejecting message with timestamp {} (local time {})",
+                state, timestamp, now
+            );
+            return Err(PbftError::InvalidTimestamp(timestamp, now));
+        }
+        Ok(())
+    }
+
+    fn broadcast_pre_prepare(
+        &mut self,
+        pbft_message: &ParsedMessage,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
+        info!(
+            "{}: PrePrepare, sequence number {}",
+            state,
             pbft_message.info().get_seq_num()
         );
 
@@ -171,7 +1402,7 @@ impl PbftNode {
         state: &mut PbftState,
     ) -> Result<(), PbftError> {
         if state.phase != PbftPhase::Checking {
-            state.switch_phase(PbftPhase::Checking);
+            state.switch_phase(PbftPhase::Checking)?;
             debug!("{}: Checking blocks", state);
             self.service
                 .check_blocks(vec![pbft_message.get_block().clone().block_id])
@@ -187,6 +1418,9 @@ impl PbftNode {
         state: &mut PbftState,
     ) -> Result<(), PbftError> {
         if state.phase == PbftPhase::Committing {
+            // The next block's seal is built from these commits, so make sure it can be before
+            // the block is committed
+            self.check_seal_buildable(msg.get_block().get_block_id(), state)?;
             handlers::commit(state, &mut *self.service, msg)
         } else {
             debug!(
@@ -205,16 +1439,18 @@ impl PbftNode {
     ) -> Result<bool, PbftError> {
         if state.mode != PbftMode::ViewChanging {
             // Even if our own timer hasn't expired, still do a ViewChange if we've received
-            // f + 1 VC messages to prevent being late to the new view party
+            // f + 1 (or the configured threshold of) VC messages to prevent being late to the new
+            // view party
             if self.msg_log.log_has_required_msgs(
                 &PbftMessageType::ViewChange,
                 message,
                 false,
-                state.f + 1,
+                state.view_change_join_threshold(),
+                state,
             ) && message.info().get_view() > state.view
             {
                 warn!("{}: Starting ViewChange from a ViewChange message", state);
-                self.propose_view_change(state)?;
+                self.propose_view_change(state, ViewChangeReason::ViewChangeMessages)?;
                 Ok(false)
             } else {
                 Ok(true)
@@ -226,13 +1462,21 @@ impl PbftNode {
 
     /// Verifies an individual consensus vote
     ///
+    /// The header signature is only checked if a `verifier` is given; the vote's structure and its
+    /// link to the seal are always checked. The vote has to be a `Commit` for the sealed block,
+    /// which is at sequence number `seq_num`; any other vote for the block, such as a `Prepare`,
+    /// doesn't show that the block was committed.
+    ///
     /// Returns the signer ID of the wrapped PbftMessage, for use in further verification
     fn verify_consensus_vote(
         vote: &PbftSignedCommitVote,
         seal: &PbftSeal,
+        seq_num: u64,
+        verifier: Option<&Arc<SignatureVerifier>>,
+        algorithm: HashAlgorithm,
     ) -> Result<Vec<u8>, PbftError> {
         let message: PbftMessage = protobuf::parse_from_bytes(&vote.get_message_bytes())
-            .map_err(PbftError::SerializationError)?;
+            .map_err(|err| PbftError::SerializationError("parsing vote message", err))?;
 
         if message.get_block().block_id != seal.previous_id {
             return Err(PbftError::InternalError(format!(
@@ -242,1148 +1486,8199 @@ impl PbftNode {
             )));
         }
 
-        let header: ConsensusPeerMessageHeader =
-            protobuf::parse_from_bytes(&vote.get_header_bytes())
-                .map_err(PbftError::SerializationError)?;
+        let info = message.get_info();
+        if PbftMessageType::from(info.get_msg_type()) != PbftMessageType::Commit {
+            return Err(PbftError::InvalidMessage(format!(
+                "Seal has a {} vote; only Commits can seal a block",
+                info.get_msg_type()
+            )));
+        }
+        if info.get_seq_num() != seq_num {
+            return Err(PbftError::InvalidMessage(format!(
+                "Seal has a vote for sequence number {}, not {}",
+                info.get_seq_num(),
+                seq_num
+            )));
+        }
 
-        let key = Secp256k1PublicKey::from_hex(&hex::encode(&header.signer_id)).unwrap();
+        let signer_id = message.get_info().get_signer_id();
+        Self::verify_vote_signature(vote, signer_id, verifier, algorithm)?;
 
-        let context = create_context("secp256k1")
-            .map_err(|err| PbftError::InternalError(format!("Couldn't create context: {}", err)))?;
+        Ok(signer_id.to_vec())
+    }
 
-        match context.verify(
-            &hex::encode(vote.get_header_signature()),
-            vote.get_header_bytes(),
-            &key,
-        ) {
-            Ok(true) => {}
-            Ok(false) => {
-                return Err(PbftError::InternalError(
-                    "Header failed verification!".into(),
-                ))
-            }
-            Err(err) => {
-                return Err(PbftError::InternalError(format!(
-                    "Error while verifying header: {:?}",
-                    err
-                )))
-            }
+    /// Make sure a consensus vote was cast in the network that this node belongs to, so a seal
+    /// can't be made out of votes from another network with the same validators
+    fn check_vote_network(vote: &PbftSignedCommitVote, network_id: &str) -> Result<(), PbftError> {
+        let message: PbftMessage = protobuf::parse_from_bytes(vote.get_message_bytes())
+            .map_err(|err| PbftError::SerializationError("parsing vote message", err))?;
+        if message.get_info().get_network_id() != network_id {
+            return Err(PbftError::WrongNetwork(
+                network_id.into(),
+                message.get_info().get_network_id().into(),
+            ));
         }
+        Ok(())
+    }
 
-        verify_sha512(vote.get_message_bytes(), header.get_content_sha512())?;
-
-        Ok(message.get_info().get_signer_id().to_vec())
+    /// Make sure all of a seal's votes were cast in the network that this node belongs to
+    fn check_seal_network(seal: &PbftSeal, network_id: &str) -> Result<(), PbftError> {
+        seal.get_previous_commit_votes()
+            .iter()
+            .try_for_each(|vote| Self::check_vote_network(vote, network_id))
     }
 
-    /// Verifies the consensus seal from the current block, for the previous block
-    fn verify_consensus_seal(
-        &mut self,
-        block: &Block,
-        state: &mut PbftState,
-    ) -> Result<Option<PbftSeal>, PbftError> {
-        // We don't publish a consensus seal until block 1, so we don't verify it
-        // until block 2
-        if block.block_num < 2 {
-            return Ok(None);
-        }
+    /// Verifies that a signed vote's message is the one its header was signed for, and that it was
+    /// signed by `signer_id`, the signer the message itself names
+    ///
+    /// Without the signer check, one node's key could sign votes that claim to be from any other
+    /// node. The header signature is only checked if a `verifier` is given. The header's
+    /// `content_sha512` field is checked as a digest made with `algorithm`.
+    fn verify_vote_signature(
+        vote: &PbftSignedCommitVote,
+        signer_id: &[u8],
+        verifier: Option<&Arc<SignatureVerifier>>,
+        algorithm: HashAlgorithm,
+    ) -> Result<(), PbftError> {
+        let header: ConsensusPeerMessageHeader =
+            protobuf::parse_from_bytes(&vote.get_header_bytes())
+                .map_err(|err| PbftError::SerializationError("parsing vote header", err))?;
 
-        if block.payload.is_empty() {
-            return Err(PbftError::InternalError(
-                "Got empty payload for non-genesis block!".into(),
+        if header.get_signer_id() != signer_id {
+            return Err(PbftError::SignerMismatch(
+                header.get_signer_id().to_vec(),
+                signer_id.to_vec(),
             ));
         }
 
-        let seal: PbftSeal =
-            protobuf::parse_from_bytes(&block.payload).map_err(PbftError::SerializationError)?;
+        if let Some(verifier) = verifier {
+            let verified = verifier.verify(
+                vote.get_header_signature(),
+                vote.get_header_bytes(),
+                &header.signer_id,
+            )?;
+            if !verified {
+                return Err(PbftError::InternalError(
+                    "Header failed verification!".into(),
+                ));
+            }
+        }
 
-        if seal.previous_id != &block.previous_id[..] {
-            return Err(PbftError::InternalError(format!(
-                "Consensus seal failed verification. Seal's previous ID `{}` doesn't match block's previous ID `{}`",
-                hex::encode(&seal.previous_id[..3]), hex::encode(&block.previous_id[..3])
+        verify_digest(
+            algorithm,
+            vote.get_message_bytes(),
+            header.get_content_sha512(),
+        )
+    }
+
+    /// Verifies that a `NewView` message comes from the primary of its view, that the view isn't
+    /// more than `MAX_NEW_VIEW_DISTANCE` views past this node's, and that it carries `ViewChange`
+    /// messages for that view from `2f` other nodes, each signed by the node it's from
+    fn verify_new_view(&self, msg: &ParsedMessage, state: &PbftState) -> Result<(), PbftError> {
+        let new_view = msg.get_new_view_message();
+        let view = new_view.get_info().get_view();
+
+        if view > state.view + MAX_NEW_VIEW_DISTANCE {
+            return Err(PbftError::InvalidMessage(format!(
+                "NewView for view {} is too far past the current view ({})",
+                view, state.view
             )));
         }
 
-        if seal.summary != &block.summary[..] {
+        let primary = state.get_primary_id_for_view(view);
+        if new_view.get_info().get_signer_id() != primary.as_slice() {
+            return Err(PbftError::NotFromPrimary);
+        }
+        if primary == state.id {
             return Err(PbftError::InternalError(format!(
-                "Consensus seal failed verification. Seal's summary {:?} doesn't match block's summary {:?}",
-                seal.summary, block.summary
+                "Got a NewView for view {}, which this node is the primary of",
+                view
             )));
         }
 
-        // Verify each individual vote, and extract the signer ID from each PbftMessage that
-        // it contains, so that we can do some sanity checks on those IDs.
-        let voter_ids =
-            seal.get_previous_commit_votes()
-                .iter()
-                .try_fold(HashSet::new(), |mut ids, v| {
-                    Self::verify_consensus_vote(v, &seal).and_then(|vid| Ok(ids.insert(vid)))?;
-                    Ok(ids)
+        let mut voter_ids = HashSet::new();
+        for vote in new_view.get_view_changes() {
+            let vc_msg: PbftViewChange = protobuf::parse_from_bytes(vote.get_message_bytes())
+                .map_err(|err| {
+                    PbftError::SerializationError("parsing ViewChange in NewView", err)
                 })?;
+            let info = vc_msg.get_info();
+            Self::verify_vote_signature(
+                vote,
+                info.get_signer_id(),
+                self.vote_verifier(),
+                self.hash_algorithm,
+            )?;
+
+            if info.get_msg_type() != String::from(&PbftMessageType::ViewChange)
+                || info.get_view() != view
+            {
+                return Err(PbftError::InternalError(format!(
+                    "NewView for view {} contains a {} message for view {}",
+                    view,
+                    info.get_msg_type(),
+                    info.get_view()
+                )));
+            }
 
-        // All of the votes must come from known peers, and the primary can't explicitly
-        // vote itself, since publishing a block is an implicit vote. Check that the votes
-        // we've received are a subset of "peers - primary". We need to use the list of
-        // peers from the block we're verifying the seal for, since it may have changed.
-        let settings = self
-            .service
-            .get_settings(
-                block.previous_id.clone(),
-                vec![String::from("sawtooth.consensus.pbft.peers")],
-            )
-            .expect("Failed to get settings");
-        let peers = get_peers_from_settings(&settings);
-
-        let peer_ids: HashSet<_> = peers
-            .iter()
-            .cloned()
-            .filter(|pid| pid != &block.signer_id)
-            .collect();
-
-        if !voter_ids.is_subset(&peer_ids) {
-            return Err(PbftError::InternalError(format!(
-                "Got unexpected vote IDs: {:?}",
-                voter_ids.difference(&peer_ids).collect::<Vec<_>>()
-            )));
+            // The new primary's own view change is implicit, like its vote in a consensus seal
+            let signer = PeerId::from(info.get_signer_id());
+            if signer == primary || !state.peer_ids.contains(&signer) {
+                return Err(PbftError::InternalError(format!(
+                    "NewView contains a ViewChange from {}, which can't vote for view {}",
+                    hex::encode(&signer),
+                    view
+                )));
+            }
+            voter_ids.insert(signer);
         }
 
-        // Check that we've received 2f votes, since the primary vote is implicit
-        if voter_ids.len() < 2 * state.f as usize {
-            return Err(PbftError::InternalError(format!(
-                "Need {} votes, only found {}!",
-                2 * state.f,
-                voter_ids.len()
-            )));
+        let required = 2 * state.f as usize;
+        if voter_ids.len() < required {
+            return Err(PbftError::WrongNumMessages(
+                PbftMessageType::ViewChange,
+                required,
+                voter_ids.len(),
+            ));
         }
 
-        Ok(Some(seal))
+        Ok(())
     }
 
-    /// Use the given block's consensus seal to verify and commit the block this node is working on
-    fn catchup(&mut self, state: &mut PbftState, block: &Block) -> Result<(), PbftError> {
-        info!(
-            "{}: Trying catchup to #{} from BlockNew message #{}",
-            state, state.seq_num, block.block_num,
-        );
+    /// Verify all of the votes in a consensus seal for the block at `seq_num`, spreading them
+    /// across up to `threads` threads
+    ///
+    /// If there's a limiter, each thread waits for a slot from it before verifying anything.
+    /// Returns the signer IDs of the votes; if any vote fails verification, more than one vote
+    /// comes from the same signer, or the votes weren't all cast in the same view, so does the
+    /// seal.
+    fn verify_consensus_votes(
+        seal: &PbftSeal,
+        seq_num: u64,
+        verifier: Option<&Arc<SignatureVerifier>>,
+        algorithm: HashAlgorithm,
+        threads: usize,
+        limiter: Option<&VerificationLimiter>,
+    ) -> Result<HashSet<Vec<u8>>, PbftError> {
+        Self::check_seal_single_view(seal)?;
+
+        let votes = seal.get_previous_commit_votes();
+        if threads <= 1 || votes.len() <= 1 {
+            let _permit = limiter.map(VerificationLimiter::acquire);
+            let mut voter_ids = HashSet::new();
+            for vote in votes {
+                let id = Self::verify_consensus_vote(vote, seal, seq_num, verifier, algorithm)?;
+                if voter_ids.contains(&id) {
+                    return Err(PbftError::DuplicateVote(id));
+                }
+                voter_ids.insert(id);
+            }
+            return Ok(voter_ids);
+        }
 
-        match state.working_block {
-            Some(ref working_block) => {
-                let block_num_matches = block.block_num == working_block.get_block_num() + 1;
-                let block_id_matches = block.previous_id == working_block.get_block_id();
+        // The votes are split into one chunk per thread; the threads are scoped, so they can
+        // borrow the seal and the verifier instead of each getting a copy
+        let chunk_size = (votes.len() + threads - 1) / threads;
+        thread::scope(|scope| {
+            let workers: Vec<_> = votes
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        let _permit = limiter.map(VerificationLimiter::acquire);
+                        chunk
+                            .iter()
+                            .map(|v| {
+                                Self::verify_consensus_vote(v, seal, seq_num, verifier, algorithm)
+                            })
+                            .collect::<Result<Vec<_>, PbftError>>()
+                    })
+                })
+                .collect();
 
-                if !block_num_matches || !block_id_matches {
-                    error!(
-                        "Block didn't match for catchup: {:?} {:?}",
-                        block, working_block
-                    );
-                    return Err(PbftError::BlockMismatch(
-                        pbft_block_from_block(block.clone()),
-                        working_block.clone(),
-                    ));
+            let mut voter_ids = HashSet::new();
+            for worker in workers {
+                let ids = worker.join().map_err(|_| {
+                    PbftError::InternalError("Vote verification thread panicked".into())
+                })??;
+                for id in ids {
+                    if voter_ids.contains(&id) {
+                        return Err(PbftError::DuplicateVote(id));
+                    }
+                    voter_ids.insert(id);
                 }
             }
-            None => {
-                error!(
-                    "Trying to catch up, but node does not have block #{} yet",
-                    state.seq_num
-                );
-                return Err(PbftError::NoWorkingBlock);
-            }
+
+            Ok(voter_ids)
+        })
+    }
+
+    /// Make sure a seal has some votes, but not more than there are peers to cast them, before
+    /// spending time verifying them
+    fn check_seal_size(seal: &PbftSeal, peers: usize) -> Result<(), PbftError> {
+        let votes = seal.get_previous_commit_votes().len();
+        if votes == 0 {
+            return Err(PbftError::EmptySeal(seal.previous_id.clone()));
+        }
+        if votes > peers {
+            return Err(PbftError::WrongNumMessages(
+                PbftMessageType::Commit,
+                peers,
+                votes,
+            ));
         }
+        Ok(())
+    }
 
-        // Parse messages from the seal
-        let seal: PbftSeal =
-            protobuf::parse_from_bytes(&block.payload).map_err(PbftError::SerializationError)?;
+    /// Make sure that the votes in a consensus seal were all cast in the same view; a quorum is
+    /// only ever reached within a single view
+    fn check_seal_single_view(seal: &PbftSeal) -> Result<(), PbftError> {
+        let views = seal
+            .get_previous_commit_votes()
+            .iter()
+            .map(|v| {
+                protobuf::parse_from_bytes::<PbftMessage>(v.get_message_bytes())
+                    .map(|msg| msg.get_info().get_view())
+                    .map_err(|err| PbftError::SerializationError("parsing vote message", err))
+            })
+            .collect::<Result<HashSet<_>, PbftError>>()?;
+        if views.len() > 1 {
+            return Err(PbftError::InvalidMessage(format!(
+                "Seal has votes from more than one view: {:?}",
+                views.into_iter().collect::<BTreeSet<_>>()
+            )));
+        }
+        Ok(())
+    }
 
-        let messages =
-            seal.get_previous_commit_votes()
-                .iter()
-                .try_fold(Vec::new(), |mut msgs, v| {
-                    msgs.push(ParsedMessage::from_pbft_message(
-                        protobuf::parse_from_bytes(&v.get_message_bytes())
-                            .map_err(PbftError::SerializationError)?,
-                    ));
-                    Ok(msgs)
-                })?;
+    /// Get the highest view that the votes in a consensus seal were cast in
+    fn seal_view(seal: &PbftSeal) -> Result<Option<u64>, PbftError> {
+        seal.get_previous_commit_votes()
+            .iter()
+            .map(|v| {
+                protobuf::parse_from_bytes::<PbftMessage>(v.get_message_bytes())
+                    .map(|msg| msg.get_info().get_view())
+                    .map_err(|err| PbftError::SerializationError("parsing vote message", err))
+            })
+            .collect::<Result<Vec<_>, PbftError>>()
+            .map(|views| views.into_iter().max())
+    }
 
-        // Update our view if necessary
-        let view = messages[0].info().get_view();
-        if view > state.view {
-            info!("Updating view from {} to {}.", state.view, view);
-            state.view = view;
+    /// Verifies the consensus seal from the current block, for the previous block
+    fn verify_consensus_seal(
+        &mut self,
+        block: &Block,
+        state: &mut PbftState,
+    ) -> Result<Option<PbftSeal>, PbftError> {
+        self.check_consensus_seal(block, state, true)
+    }
+
+    /// Like `verify_consensus_seal`, but always checks every vote, even if the seal was already
+    /// verified for the block
+    fn audit_consensus_seal(
+        &mut self,
+        block: &Block,
+        state: &mut PbftState,
+    ) -> Result<Option<PbftSeal>, PbftError> {
+        self.check_consensus_seal(block, state, false)
+    }
+
+    /// Verify a block's consensus seal against the network's membership as of the block it seals;
+    /// with `use_cache`, a seal that was already verified for the block isn't checked again
+    fn check_consensus_seal(
+        &mut self,
+        block: &Block,
+        state: &mut PbftState,
+        use_cache: bool,
+    ) -> Result<Option<PbftSeal>, PbftError> {
+        let seal = match self.get_block_seal(block)? {
+            Some(seal) => seal,
+            None => return Ok(None),
+        };
+
+        // The same block can be verified more than once (for instance, when it's delivered again
+        // while catching up); if this exact seal was already verified for it, don't pay for
+        // checking all of the signatures again
+        if use_cache && self.msg_log.get_consensus_seal_for_block(&block.block_id) == Some(&seal) {
+            trace!(
+                "{}: Seal for block {} was already verified",
+                state,
+                hex::encode(&block.block_id)
+            );
+            self.seal_timing.cache_hits += 1;
+            return Ok(Some(seal));
         }
 
-        // Add messages to the log
-        for message in &messages {
-            self.msg_log.add_message(message.clone(), state)?;
+        // The membership may have changed since, so the seal is checked against the one as of
+        // the block it's for
+        let membership = self.get_membership_at(&block.previous_id, state)?;
+        Self::check_seal_size(&seal, membership.voters.len())?;
+
+        // Without weights, a seal with fewer than 2f votes can't be a quorum however they check
+        // out
+        let votes = seal.get_previous_commit_votes().len();
+        if !self.msg_log.is_weighted() && votes < 2 * membership.f as usize {
+            return Err(PbftError::InsufficientVotes(
+                2 * membership.f as usize,
+                votes,
+            ));
         }
 
-        // Skip straight to the Committing phase and Commit the new block using one of the parsed
-        // messages to simulate having received a regular commit message
-        state.phase = PbftPhase::Committing;
-        handlers::commit(
-            state,
-            &mut *self.service,
-            &messages[0].as_msg_type(PbftMessageType::Commit),
+        Self::check_seal_network(&seal, &state.network_id)?;
+
+        // Verify each individual vote, and extract the signer ID from each PbftMessage that
+        // it contains, so that we can do some sanity checks on those IDs.
+        let voter_ids = seal.check_internal_with(
+            block,
+            self.vote_verifier(),
+            self.hash_algorithm,
+            self.vote_verification_threads,
+            Some(&self.verification_limiter),
         )?;
 
-        // Call on_block_commit right away so we're ready to catch up again if necessary
-        self.on_block_commit(BlockId::from(messages[0].get_block().get_block_id()), state);
+        // The primary can't explicitly vote itself, since publishing a block is an implicit vote
+        self.check_seal_voters(&voter_ids, &membership, Some(&block.signer_id))?;
 
-        Ok(())
+        // Keep track of who didn't vote for new blocks; observers never vote, so they don't count
+        if block.block_num >= state.seq_num {
+            let non_voters = membership
+                .voters
+                .into_iter()
+                .filter(|peer| !voter_ids.contains(peer) && *peer != block.signer_id)
+                .collect();
+            self.metrics
+                .record_non_voters(block.block_num - 1, non_voters);
+        }
+
+        Ok(Some(seal))
     }
 
-    /// Handle a `BlockNew` update from the Validator
-    ///
-    /// The validator has received a new block; verify the block's consensus seal and add the
-    /// BlockNew to the message log. If this is the block we are waiting for: set it as the working
-    /// block, update the idle & commit timers, and broadcast a PrePrepare if this node is the
-    /// primary. If this is the block after the one this node is working on, use it to catch up.
-    pub fn on_block_new(&mut self, block: Block, state: &mut PbftState) -> Result<(), PbftError> {
-        info!(
-            "{}: Got BlockNew: {} / {}",
-            state,
-            block.block_num,
-            hex::encode(&block.block_id[..3]),
-        );
+    /// Get the consensus seal from a block's payload, or `None` if the block is from before the
+    /// first sealed block
+    fn get_block_seal(&self, block: &Block) -> Result<Option<PbftSeal>, PbftError> {
+        // Blocks before the first sealed block (by default, block 1, since nobody votes on the
+        // genesis block) are published without a consensus seal, so there's nothing to verify.
+        // Block 1 never has a seal to carry, so a payload in it is bogus; later unsealed blocks
+        // may be from before the first sealed block was raised, when they did carry seals.
+        if block.block_num < self.first_sealed_block {
+            if block.block_num == 1 && !block.payload.is_empty() {
+                return Err(PbftError::UnexpectedPayload(block.block_num));
+            }
+            return Ok(None);
+        }
 
-        if block.block_num < state.seq_num {
-            info!(
-                "Ignoring block ({}) that's older than current sequence number ({}).",
-                block.block_num, state.seq_num
-            );
-            return Ok(());
+        parse_seal(block).map(Some)
+    }
+
+    /// Get the list of peers from the on-chain settings as of the given block
+    ///
+    /// The validator may be briefly unable to answer (for instance, while it restarts). Waiting
+    /// for it here would hold up the whole event loop, so a failed read returns a
+    /// `SettingsUnavailable` error right away, and the validator isn't asked again until
+    /// `settings_retry_delay` has passed; the delay doubles with each failure in a row, up to
+    /// `settings_retries` times. Callers should try again later.
+    fn get_peers_settings(
+        &mut self,
+        block_id: &BlockId,
+    ) -> Result<HashMap<String, String>, PbftError> {
+        if let Some(retry_at) = self.settings_retry_at {
+            if Instant::now() < retry_at {
+                return Err(PbftError::SettingsUnavailable(
+                    self.settings_failures,
+                    "waiting to retry".into(),
+                ));
+            }
         }
 
-        match self.verify_consensus_seal(&block, state) {
-            Ok(Some(seal)) => {
-                self.msg_log
-                    .add_consensus_seal(block.block_id.clone(), state.seq_num, seal);
+        match self.service.get_settings(
+            block_id.clone(),
+            vec![
+                self.peers_setting_key.clone(),
+                PEER_WEIGHTS_SETTING_KEY.into(),
+                OBSERVERS_SETTING_KEY.into(),
+            ],
+        ) {
+            Ok(settings) => {
+                self.settings_failures = 0;
+                self.settings_retry_at = None;
+                Ok(settings)
             }
-            Ok(None) => {}
             Err(err) => {
+                self.settings_failures += 1;
+                let doublings = (self.settings_failures - 1)
+                    .min(self.settings_retries)
+                    .min(16);
+                let delay = self.settings_retry_delay * 2u32.pow(doublings);
                 warn!(
-                    "Failing block due to failed consensus seal verification and \
-                     proposing view change! Error was {}",
-                    err
+                    "Failed to get settings ({} failures in a row), retrying in {:?}: {}",
+                    self.settings_failures, delay, err
                 );
-                self.service.fail_block(block.block_id).map_err(|err| {
-                    PbftError::InternalError(format!("Couldn't fail block: {}", err))
-                })?;
-                self.propose_view_change(state)?;
-                return Err(err);
+                self.settings_retry_at = Some(Instant::now() + delay);
+                Err(PbftError::SettingsUnavailable(
+                    self.settings_failures,
+                    err.to_string(),
+                ))
             }
         }
+    }
 
-        // Create PBFT message for BlockNew and add it to the log
-        let mut msg = PbftMessage::new();
-        msg.set_info(handlers::make_msg_info(
-            &PbftMessageType::BlockNew,
-            state.view,
-            block.block_num,
-            state.id.clone(),
-        ));
+    /// Get the network's membership as of the given block, from the on-chain settings at that
+    /// block
+    ///
+    /// The observers are those of the observers setting; without one, this node's observers that
+    /// are still members are used, as in `update_membership`. A membership that can't tolerate a
+    /// fault is an `InsufficientPeers` error, unless single-node networks are allowed.
+    fn get_membership_at(
+        &mut self,
+        block_id: &BlockId,
+        state: &PbftState,
+    ) -> Result<Membership, PbftError> {
+        let settings = self.get_peers_settings(block_id)?;
+        let peers = dedup_peers(&get_peers_from_settings(
+            &settings,
+            &self.peers_setting_key,
+        )?);
+        let observers =
+            get_observers_from_settings(&settings)?.unwrap_or_else(|| state.observers.clone());
+        let voters: Vec<PeerId> = peers
+            .into_iter()
+            .filter(|peer| !observers.contains(peer))
+            .collect();
 
-        let pbft_block = pbft_block_from_block(block.clone());
-        msg.set_block(pbft_block.clone());
+        let f = match compute_f(voters.len()) {
+            Ok(f) => f,
+            Err(_) if state.allow_single_node && voters.len() == 1 => 0,
+            Err(err) => return Err(err),
+        };
 
-        self.msg_log
-            .add_message(ParsedMessage::from_pbft_message(msg.clone()), state)?;
+        Ok(Membership { voters, f })
+    }
 
-        // We can use this block's seal to commit the next block (i.e. catch-up) if it's the block
-        // after the one we're waiting for and we haven't already told the validator to commit the
-        // block we're waiting for
-        if block.block_num == state.seq_num + 1 && state.phase != PbftPhase::Finished {
-            self.catchup(state, &block)?;
-        } else if block.block_num == state.seq_num {
-            // This is the block we're waiting for, so we update state
-            state.working_block = Some(msg.get_block().clone());
+    /// Check that the voters of a seal are a quorum of the given membership, which must be the
+    /// network's as of the block the seal is for
+    ///
+    /// All of the votes must come from voting members other than `implicit_voter`, if given.
+    /// Without an implicit voter, the seal's own votes have to make up the whole `2f + 1`.
+    fn check_seal_voters(
+        &self,
+        voter_ids: &HashSet<Vec<u8>>,
+        membership: &Membership,
+        implicit_voter: Option<&PeerId>,
+    ) -> Result<(), PbftError> {
+        check_voters_are_peers(voter_ids, &membership.voters, implicit_voter)?;
 
-            // Send PrePrepare messages if we're the primary
-            if state.is_primary() {
-                let s = state.seq_num;
-                self._broadcast_pbft_message(s, &PbftMessageType::PrePrepare, pbft_block, state)?;
+        // With weighted quorums, the implicit vote counts with its own weight towards the 2f + 1
+        if self.msg_log.is_weighted() {
+            let signers: Vec<&[u8]> = voter_ids
+                .iter()
+                .map(Vec::as_slice)
+                .chain(implicit_voter.map(Vec::as_slice))
+                .collect();
+            let required = 2 * membership.f + 1;
+            if !self.msg_log.has_quorum(signers.iter().cloned(), required) {
+                return Err(PbftError::InsufficientVotes(
+                    self.msg_log.quorum_weight(required) as usize,
+                    self.msg_log.signer_weight(signers) as usize,
+                ));
             }
+            return Ok(());
         }
 
-        Ok(())
+        let required = 2 * membership.f as usize + 1;
+        if implicit_voter.is_none() && voter_ids.len() < required {
+            return Err(PbftError::InsufficientVotes(required, voter_ids.len()));
+        }
+        check_vote_count(voter_ids, membership.f)
     }
 
-    /// Handle a `BlockCommit` update from the Validator
+    /// Work out exactly why a block's consensus seal fails verification
     ///
-    /// A block was sucessfully committed; update state to be ready for the next block, make any
-    /// necessary view and membership changes, garbage collect the logs, update the commit & idle
-    /// timers, and start a new block if this node is the primary.
-    #[allow(clippy::needless_pass_by_value)]
-    pub fn on_block_commit(&mut self, block_id: BlockId, state: &mut PbftState) {
-        debug!("{}: <<<<<< BlockCommit: {:?}", state, block_id);
+    /// This runs the same checks as seal verification during normal operation, but reports which
+    /// one failed in a form that's useful for triage. Every vote is checked on its own, so this is
+    /// slower; it's meant to be called on demand, after verification has already failed. Returns
+    /// `None` if the seal is valid.
+    pub fn explain_seal_failure(
+        &mut self,
+        block: &Block,
+        state: &PbftState,
+    ) -> Option<SealFailure> {
+        let seal = match self.get_block_seal(block) {
+            Ok(Some(seal)) => seal,
+            Ok(None) => return None,
+            Err(err) => return Some(err.into()),
+        };
 
-        let is_working_block = match state.working_block {
-            Some(ref block) => BlockId::from(block.get_block_id()) == block_id,
-            None => false,
+        let membership = match self.get_membership_at(&block.previous_id, state) {
+            Ok(membership) => membership,
+            Err(err) => {
+                error!("{}: Couldn't get peers to check the seal: {}", state, err);
+                return None;
+            }
         };
 
-        if state.phase != PbftPhase::Finished || !is_working_block {
-            info!(
-                "{}: Got BlockCommit for a block that isn't the working block",
-                state
-            );
-            return;
+        if let Err(err) = Self::check_seal_size(&seal, membership.voters.len())
+            .and_then(|_| check_seal_matches_block(&seal, block))
+        {
+            return Some(err.into());
         }
 
-        // Update state to be ready for next block
-        state.switch_phase(PbftPhase::PrePreparing);
-        state.seq_num += 1;
-
-        // If we already have a BlockNew for the next block, we can make it the working block;
-        // otherwise just set the working block to None
-        state.working_block = self
-            .msg_log
-            .get_messages_of_type_seq(&PbftMessageType::BlockNew, state.seq_num)
-            .first()
-            .map(|msg| msg.get_block().clone());
-
-        // Start a view change if we need to force one for fairness or if membership changed
-        if state.at_forced_view_change() || self.update_membership(block_id.clone(), state) {
-            self.force_view_change(state);
+        // Check the votes one at a time, so the first bad one can be pointed out
+        let seq_num = block.block_num.saturating_sub(1);
+        let mut voter_ids = HashSet::new();
+        for (i, vote) in seal.get_previous_commit_votes().iter().enumerate() {
+            match Self::check_vote_network(vote, &state.network_id).and_then(|_| {
+                Self::verify_consensus_vote(
+                    vote,
+                    &seal,
+                    seq_num,
+                    self.vote_verifier(),
+                    self.hash_algorithm,
+                )
+            }) {
+                Ok(id) if voter_ids.contains(&id) => {
+                    return Some(SealFailure::BadVote(
+                        i,
+                        PbftError::DuplicateVote(id).to_string(),
+                    ));
+                }
+                Ok(id) => {
+                    voter_ids.insert(id);
+                }
+                Err(err) => return Some(SealFailure::BadVote(i, err.to_string())),
+            }
         }
-
-        // Tell the log to garbage collect if it needs to
-        self.msg_log.garbage_collect(state.seq_num, &block_id);
-
-        // Restart the faulty primary timeout for the next block
-        state.faulty_primary_timeout.start();
-
-        if state.is_primary() && state.working_block.is_none() {
-            info!(
-                "{}: Initializing block with previous ID {:?}",
-                state, block_id
-            );
-            self.service
-                .initialize_block(Some(block_id.clone()))
-                .unwrap_or_else(|err| error!("Couldn't initialize block: {}", err));
+        if let Err(err) = Self::check_seal_single_view(&seal) {
+            return Some(err.into());
         }
+
+        self.check_seal_voters(&voter_ids, &membership, Some(&block.signer_id))
+            .err()
+            .map(SealFailure::from)
     }
 
-    /// Handle a `BlockValid` update
-    /// This message arrives after `check_blocks` is called, signifying that the validator has
-    /// successfully checked a block with this `BlockId`.
-    /// Once a `BlockValid` is received, transition to committing blocks.
-    #[allow(clippy::ptr_arg)]
-    pub fn on_block_valid(
+    /// Verify the consensus seals and previous-ID linkage of the committed blocks `from..=to`
+    ///
+    /// Each block's seal is checked against the membership at that height, and every vote is
+    /// checked, even for seals that were already verified. Returns a `ChainVerificationFailed`
+    /// error for the first block that doesn't pass.
+    pub fn verify_chain_seals(
         &mut self,
-        block_id: &BlockId,
+        from: u64,
+        to: u64,
         state: &mut PbftState,
     ) -> Result<(), PbftError> {
-        debug!("{}: <<<<<< BlockValid: {:?}", state, block_id);
-        let block = match state.working_block {
-            Some(ref block) => {
-                if &BlockId::from(block.get_block_id()) == block_id {
-                    Ok(block.clone())
-                } else {
-                    warn!("Got BlockValid that doesn't match the working block");
-                    Err(PbftError::NotReadyForMessage)
-                }
-            }
-            None => {
-                warn!("Got BlockValid with no working block");
-                Err(PbftError::NoWorkingBlock)
+        let blocks = self.get_committed_blocks(from, to)?;
+
+        for (i, block) in blocks.iter().enumerate() {
+            if i > 0 && block.previous_id != blocks[i - 1].block_id {
+                return Err(PbftError::ChainVerificationFailed(
+                    block.block_num,
+                    Box::new(PbftError::InternalError(
+                        "Block's previous ID doesn't match the preceding block".into(),
+                    )),
+                ));
             }
-        }?;
 
-        state.switch_phase(PbftPhase::Committing);
-        self._broadcast_pbft_message(
-            state.seq_num,
-            &PbftMessageType::Commit,
-            block.clone(),
-            state,
-        )?;
+            self.audit_consensus_seal(block, state).map_err(|err| {
+                PbftError::ChainVerificationFailed(block.block_num, Box::new(err))
+            })?;
+        }
+
         Ok(())
     }
 
-    // ---------- Methods for periodically checking on and updating the state, called by the engine ----------
+    /// Restore the consensus seals of the committed blocks `from..=to` to the log
+    ///
+    /// Each block's embedded seal is fetched from the validator, verified, and stored again under
+    /// that block. This is used when a peer asks for seals that have already been garbage
+    /// collected from the log. Returns the number of seals that were restored.
+    pub fn replay_missing_seals_for(
+        &mut self,
+        from: u64,
+        to: u64,
+        state: &mut PbftState,
+    ) -> Result<usize, PbftError> {
+        let blocks = self.get_committed_blocks(from, to)?;
 
-    fn build_seal(&mut self, state: &PbftState, summary: Vec<u8>) -> Result<Vec<u8>, PbftError> {
-        info!("{}: Building seal for block {}", state, state.seq_num - 1);
+        let mut restored = 0;
+        for block in blocks {
+            if self.msg_log.get_consensus_seal(block.block_num).is_ok() {
+                continue;
+            }
 
-        let min_votes = 2 * state.f;
-        let messages = self
-            .msg_log
-            .get_enough_messages(&PbftMessageType::Commit, state.seq_num - 1, min_votes)
-            .ok_or_else(|| {
-                debug!("{}: {}", state, self.msg_log);
-                PbftError::InternalError(format!(
-                    "Couldn't find {} commit messages in the message log for building a seal!",
-                    min_votes
-                ))
+            let seal = self.verify_consensus_seal(&block, state).map_err(|err| {
+                PbftError::ChainVerificationFailed(block.block_num, Box::new(err))
             })?;
 
-        let mut seal = PbftSeal::new();
+            if let Some(seal) = seal {
+                self.msg_log
+                    .add_consensus_seal(block.block_id.clone(), block.block_num, seal)?;
+                restored += 1;
+            }
+        }
 
-        seal.set_summary(summary);
-        seal.set_previous_id(BlockId::from(messages[0].get_block().get_block_id()));
-        seal.set_previous_commit_votes(RepeatedField::from(
-            messages
-                .iter()
-                .map(|m| {
-                    let mut vote = PbftSignedCommitVote::new();
+        debug!(
+            "{}: Restored {} consensus seals for blocks {} to {}",
+            state, restored, from, to
+        );
 
-                    vote.set_header_bytes(m.header_bytes.clone());
-                    vote.set_header_signature(m.header_signature.clone());
-                    vote.set_message_bytes(m.message_bytes.clone());
+        Ok(restored)
+    }
 
-                    vote
-                })
-                .collect::<Vec<_>>(),
-        ));
-
-        seal.write_to_bytes().map_err(PbftError::SerializationError)
-    }
+    /// Get the committed blocks `from..=to`, in order, by walking back from the chain head
+    fn get_committed_blocks(&mut self, from: u64, to: u64) -> Result<Vec<Block>, PbftError> {
+        let head = self
+            .service
+            .get_chain_head()
+            .map_err(|err| PbftError::InternalError(format!("Couldn't get chain head: {}", err)))?;
 
-    /// The primary tries to finalize a block every so often
-    /// # Panics
-    /// Panics if `finalize_block` fails. This is necessary because it means the validator wasn't
-    /// able to publish the new block.
-    pub fn try_publish(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
-        // Only the primary takes care of this, and we try publishing a block
-        // on every engine loop, even if it's not yet ready. This isn't an error,
-        // so just return Ok(()).
-        if !state.is_primary() || state.phase != PbftPhase::PrePreparing {
-            return Ok(());
+        if from > to || to > head.block_num {
+            return Err(PbftError::InternalError(format!(
+                "Can't verify blocks {} to {}; chain head is block {}",
+                from, to, head.block_num
+            )));
         }
 
-        info!("{}: Summarizing block", state);
+        // Walk back from the chain head, collecting the blocks in the requested range
+        let mut blocks = vec![];
+        let mut block = head;
+        loop {
+            let block_num = block.block_num;
+            let previous_id = block.previous_id.clone();
 
-        let summary = match self.service.summarize_block() {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                debug!(
-                    "{}: Couldn't summarize, so not finalizing: {}",
-                    state,
-                    e.description().to_string()
-                );
-                return Ok(());
+            if block_num <= to {
+                blocks.push(block);
+            }
+            if block_num <= from {
+                break;
             }
-        };
 
-        // We don't publish a consensus seal at block 1, since we never receive any
-        // votes on the genesis block. Leave payload blank for the first block.
-        let data = if state.seq_num <= 1 {
-            vec![]
-        } else {
-            self.build_seal(state, summary)?
-        };
+            block = self
+                .service
+                .get_blocks(vec![previous_id.clone()])
+                .map_err(|err| PbftError::InternalError(format!("Couldn't get block: {}", err)))?
+                .remove(&previous_id)
+                .ok_or_else(|| {
+                    PbftError::ChainVerificationFailed(
+                        block_num - 1,
+                        Box::new(PbftError::InternalError(format!(
+                            "Couldn't find block {}",
+                            hex::encode(&previous_id)
+                        ))),
+                    )
+                })?;
+        }
+        blocks.reverse();
 
-        match self.service.finalize_block(data) {
-            Ok(block_id) => {
-                info!("{}: Publishing block {:?}", state, block_id);
-                Ok(())
-            }
-            Err(EngineError::BlockNotReady) => {
-                debug!("{}: Block not ready", state);
-                Ok(())
-            }
-            Err(err) => {
-                error!("Couldn't finalize block: {}", err);
-                Err(PbftError::InternalError("Couldn't finalize block!".into()))
+        Ok(blocks)
+    }
+
+    /// Rebuild this node's state from the chain after it has been found to be inconsistent
+    ///
+    /// Verifies the chain head's consensus seal, clears the message log and replays the seal's
+    /// votes into it, then aligns the sequence number with the chain and returns to `Normal`
+    /// mode. The view is moved up to the seal's view if it's behind, but never back, since a
+    /// lower view could be one this node has already given up on.
+    pub fn force_resync(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
+        warn!("{}: Resyncing state from the chain", state);
+
+        let head = self
+            .service
+            .get_chain_head()
+            .map_err(|err| PbftError::InternalError(format!("Couldn't get chain head: {}", err)))?;
+
+        // The chain head's seal holds the votes for the block before it; it's also the seal
+        // needed for proposing a view change at the next sequence number
+        let seal = self.verify_consensus_seal(&head, state)?;
+
+        self.msg_log.clear();
+
+        state.seq_num = head.block_num + 1;
+        state.advance_low_watermark(head.block_num);
+        self.prune_deferred_blocks(state);
+        state.set_phase(PbftPhase::PrePreparing);
+        state.mode = PbftMode::Normal;
+        state.working_block = None;
+        state.last_committed_block = Some(head.block_id.clone());
+        state.commit_timeout.stop();
+        state.view_change_timeout.stop();
+
+        if let Some(seal) = seal {
+            let votes = seal
+                .get_previous_commit_votes()
+                .iter()
+                .map(|v| {
+                    let mut msg = ParsedMessage::from_pbft_message(
+                        protobuf::parse_from_bytes(v.get_message_bytes()).map_err(|err| {
+                            PbftError::SerializationError("parsing vote message", err)
+                        })?,
+                    );
+                    msg.header_bytes = v.get_header_bytes().to_vec();
+                    msg.header_signature = v.get_header_signature().to_vec();
+                    Ok(msg)
+                })
+                .collect::<Result<Vec<_>, PbftError>>()?;
+
+            let seal_view = votes.iter().map(|v| v.info().get_view()).max();
+            if let Some(view) = seal_view {
+                if view > state.view {
+                    state.set_view(view);
+                }
             }
+
+            let votes = votes
+                .into_iter()
+                .filter(|vote| Some(vote.info().get_view()) == seal_view)
+                .collect();
+            self.msg_log.add_messages(votes, state)?;
+
+            self.msg_log
+                .add_consensus_seal(head.block_id.clone(), head.block_num, seal)?;
         }
-    }
 
-    /// Check to see if the faulty primary timeout has expired
-    pub fn check_faulty_primary_timeout_expired(&mut self, state: &mut PbftState) -> bool {
-        state.faulty_primary_timeout.check_expired()
-    }
+        if state.id == state.get_primary_id() {
+            state.upgrade_role();
+            self.initialize_block(Some(head.block_id.clone()));
+        } else {
+            state.downgrade_role();
+        }
 
-    pub fn start_faulty_primary_timeout(&self, state: &mut PbftState) {
         state.faulty_primary_timeout.start();
-    }
 
-    /// Retry messages from the backlog queue
-    pub fn retry_backlog(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
-        let mut peer_res = Ok(());
-        if let Some(msg) = self.msg_log.pop_backlog() {
-            debug!("{}: Popping message from backlog", state);
-            peer_res = self.on_peer_message(msg, state);
-        }
-        peer_res
-    }
+        info!("{}: Resynced to chain head {}", state, head.block_num);
 
-    pub fn force_view_change(&mut self, state: &mut PbftState) {
-        info!("{}: Forcing view change", state);
-        handlers::force_view_change(state, &mut *self.service)
+        Ok(())
     }
 
-    /// Initiate a view change (this node suspects that the primary is faulty)
-    /// Nodes drop everything when they're doing a view change - will not process any peer messages
-    /// other than `ViewChanges` until the view change is complete.
-    pub fn propose_view_change(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
-        if state.mode == PbftMode::ViewChanging {
-            return Ok(());
+    /// Use the given block's consensus seal to verify and commit the block this node is working on
+    ///
+    /// If this node doesn't have a working block (for instance, it restarted partway through the
+    /// chain), the block the seal is for is fetched from the validator instead, as long as it's
+    /// the block at the current sequence number and builds on the chain head.
+    fn catchup(&mut self, state: &mut PbftState, block: &Block) -> Result<(), PbftError> {
+        info!(
+            "{}: Trying catchup to #{} from BlockNew message #{}",
+            state, state.seq_num, block.block_num,
+        );
+
+        match state.working_block {
+            Some(ref working_block) => {
+                let block_num_matches = block.block_num == working_block.get_block_num() + 1;
+                let block_id_matches = block.previous_id == working_block.get_block_id();
+
+                if !block_num_matches || !block_id_matches {
+                    error!(
+                        "Block didn't match for catchup: {:?} {:?}",
+                        block, working_block
+                    );
+                    return Err(PbftError::BlockMismatch(
+                        pbft_block_from_block(block.clone()),
+                        working_block.clone(),
+                    ));
+                }
+            }
+            None => {
+                let working_block = self.get_catchup_block(block, state)?;
+                state.working_block = Some(working_block);
+            }
         }
-        warn!("{}: Starting view change", state);
-        state.mode = PbftMode::ViewChanging;
 
-        let info = handlers::make_msg_info(
-            &PbftMessageType::ViewChange,
-            state.view + 1,
-            state.seq_num - 1,
-            state.id.clone(),
+        // Parse messages from the seal; it was verified and stored when the block arrived
+        let seal: PbftSeal = match self.msg_log.get_consensus_seal_for_block(&block.block_id) {
+            Some(seal) => seal.clone(),
+            None => parse_seal(block)?,
+        };
+
+        self.commit_from_seal(&seal, state)
+    }
+
+    /// Catch up through every block from the one at the current sequence number up to the one
+    /// before `block`, which is more than one block ahead
+    ///
+    /// The blocks in between are found by walking back from `block`, the first time it's seen.
+    /// They're committed in order, each with the seal carried by the block after it; since committing a
+    /// block applies any membership change it makes, each seal is checked against the peers as of
+    /// the block it's for.
+    ///
+    /// At most `max_catchup_blocks_per_loop` blocks are committed at once, so that a node that is
+    /// far behind keeps handling messages, and only those blocks are fetched; `block` is then saved
+    /// as the state's catch-up target, and `continue_catchup` picks up from there.
+    fn catchup_range(&mut self, state: &mut PbftState, block: &Block) -> Result<(), PbftError> {
+        info!(
+            "{}: Trying catchup from #{} to #{} from BlockNew message #{}",
+            state,
+            state.seq_num,
+            block.block_num - 1,
+            block.block_num,
         );
 
-        let mut vc_msg = PbftViewChange::new();
-        vc_msg.set_info(info);
-        vc_msg.set_seal(self.msg_log.get_consensus_seal(state.seq_num - 1)?);
-        let msg_bytes = vc_msg
-            .write_to_bytes()
-            .map_err(PbftError::SerializationError)?;
+        // The first time this target is seen, walk back from it to the block at the current
+        // sequence number to find the blocks in between; after that, only the next batch of
+        // blocks is fetched
+        let known_path = match self.catchup_path {
+            Some((ref target_id, _)) => *target_id == block.block_id,
+            None => false,
+        };
+        if !known_path {
+            let mut path = VecDeque::with_capacity((block.block_num - state.seq_num) as usize);
+            let mut previous_id = block.previous_id.clone();
+            for block_num in (state.seq_num..block.block_num).rev() {
+                let prev = self.get_catchup_range_block(&previous_id, block_num)?;
+                path.push_front((block_num, previous_id));
+                previous_id = prev.previous_id;
+            }
+            self.catchup_path = Some((block.block_id.clone(), path));
+        }
 
-        self._broadcast_message(&PbftMessageType::ViewChange, msg_bytes, state)
-    }
+        // Committing a block can release deferred blocks that catch this node up further
+        let path: Vec<(u64, BlockId)> = match self.catchup_path {
+            Some((_, ref mut path)) => {
+                path.retain(|(block_num, _)| *block_num >= state.seq_num);
+                path.iter().cloned().collect()
+            }
+            None => vec![],
+        };
+        let batch_len = if self.max_catchup_blocks_per_loop > 0 {
+            path.len().min(self.max_catchup_blocks_per_loop as usize)
+        } else {
+            path.len()
+        };
 
-    /// Check the on-chain list of peers; if it has changed, update peers list and return true.
-    fn update_membership(&mut self, block_id: BlockId, state: &mut PbftState) -> bool {
-        // Get list of peers from settings
-        let settings = self
+        // Fetch the blocks to commit along with the one after them, which carries the last one's
+        // seal; that's the new block itself if this is the last batch
+        let mut blocks = Vec::with_capacity(batch_len + 1);
+        let batch = &path[..path.len().min(batch_len + 1)];
+        let mut fetched = self
             .service
-            .get_settings(
-                block_id,
-                vec![String::from("sawtooth.consensus.pbft.peers")],
-            )
-            .expect("Failed to get settings");
-        let peers = get_peers_from_settings(&settings);
-        let new_peers_set: HashSet<PeerId> = peers.iter().cloned().collect();
+            .get_blocks(batch.iter().map(|(_, id)| id.clone()).collect())
+            .map_err(|err| PbftError::InternalError(format!("Couldn't get block: {}", err)))?;
+        for (block_num, block_id) in batch {
+            let fetched_block = fetched
+                .remove(block_id)
+                .filter(|b| b.block_id == *block_id && b.block_num == *block_num)
+                .ok_or_else(|| {
+                    error!(
+                        "Trying to catch up, but node does not have block #{} yet",
+                        block_num
+                    );
+                    PbftError::NoWorkingBlock
+                })?;
+            blocks.push(fetched_block);
+        }
+        if blocks.len() == batch_len {
+            blocks.push(block.clone());
+        }
+
+        // The first block has to be the one this node is working on or, without a working block,
+        // the one that builds on the chain head
+        if batch_len > 0 {
+            match state.working_block {
+                Some(ref working_block) => {
+                    if blocks[0].block_id != working_block.get_block_id() {
+                        error!(
+                            "Block didn't match for catchup: {:?} {:?}",
+                            blocks[0], working_block
+                        );
+                        return Err(PbftError::BlockMismatch(
+                            pbft_block_from_block(blocks[0].clone()),
+                            working_block.clone(),
+                        ));
+                    }
+                }
+                None => {
+                    let head = self.service.get_chain_head().map_err(|err| {
+                        PbftError::InternalError(format!("Couldn't get chain head: {}", err))
+                    })?;
+                    if blocks[0].previous_id != head.block_id {
+                        error!(
+                            "Trying to catch up, but block #{} doesn't build on the chain head",
+                            blocks[0].block_num
+                        );
+                        return Err(PbftError::NoWorkingBlock);
+                    }
+                }
+            }
+        }
 
-        // Check if membership has changed
-        let old_peers_set: HashSet<PeerId> = state.peer_ids.iter().cloned().collect();
+        for (sealed_block, sealing_block) in blocks[..batch_len].iter().zip(&blocks[1..]) {
+            // Committing a block can release deferred blocks that catch this node up further
+            if state.seq_num > sealed_block.block_num {
+                continue;
+            }
 
-        if new_peers_set != old_peers_set {
-            state.peer_ids = peers;
-            let f = ((state.peer_ids.len() - 1) / 3) as u64;
-            if f == 0 {
-                panic!("This network no longer contains enough nodes to be fault tolerant");
+            // The new block's seal was verified and stored when it arrived
+            let seal = match self.verify_consensus_seal(sealing_block, state)? {
+                Some(seal) => seal,
+                None => return Err(PbftError::NoWorkingBlock),
+            };
+            if sealing_block.block_id != block.block_id {
+                self.msg_log.add_consensus_seal(
+                    sealing_block.block_id.clone(),
+                    sealing_block.block_num,
+                    seal.clone(),
+                )?;
             }
-            state.f = f;
-            return true;
+
+            state.working_block = Some(pbft_block_from_block(sealed_block.clone()));
+            self.commit_from_seal(&seal, state)?;
         }
 
-        false
+        if path
+            .iter()
+            .any(|(block_num, _)| *block_num >= state.seq_num)
+        {
+            info!(
+                "{}: Committed {} blocks catching up to #{}; continuing later",
+                state, batch_len, block.block_num
+            );
+            state.catchup_target = Some(block.block_id.clone());
+        } else {
+            state.catchup_target = None;
+            self.catchup_path = None;
+        }
+        Ok(())
     }
 
-    // ---------- Methods for communication between nodes ----------
-
-    // Broadcast a message to this node's peers, and itself
-    fn _broadcast_pbft_message(
+    /// Get a block between the current sequence number and a catch-up target from the validator,
+    /// checking that it's the one expected
+    fn get_catchup_range_block(
         &mut self,
-        seq_num: u64,
-        msg_type: &PbftMessageType,
-        block: PbftBlock,
-        state: &mut PbftState,
-    ) -> Result<(), PbftError> {
-        let expected_type = state.check_msg_type();
-        // Make sure that we should be sending messages of this type
-        if msg_type.is_multicast() && msg_type != &expected_type {
+        block_id: &BlockId,
+        block_num: u64,
+    ) -> Result<Block, PbftError> {
+        self.service
+            .get_blocks(vec![block_id.clone()])
+            .map_err(|err| PbftError::InternalError(format!("Couldn't get block: {}", err)))?
+            .remove(block_id)
+            .filter(|block| block.block_id == *block_id && block.block_num == block_num)
+            .ok_or_else(|| {
+                error!(
+                    "Trying to catch up, but node does not have block #{} yet",
+                    block_num
+                );
+                PbftError::NoWorkingBlock
+            })
+    }
+
+    /// Commit the next batch of blocks of a catch-up that `catchup_range` didn't finish
+    ///
+    /// Does nothing unless the state has a catch-up target. The target is forgotten once the node
+    /// has caught up to it, or gone past it some other way.
+    pub fn continue_catchup(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
+        let target_id = match state.catchup_target {
+            Some(ref target_id) => target_id.clone(),
+            None => return Ok(()),
+        };
+        if state.phase == PbftPhase::Finished {
             return Ok(());
         }
 
-        let msg_bytes = make_msg_bytes(
-            handlers::make_msg_info(&msg_type, state.view, seq_num, state.id.clone()),
-            block,
-        )
-        .unwrap_or_default();
+        let target = self
+            .service
+            .get_blocks(vec![target_id.clone()])
+            .map_err(|err| PbftError::InternalError(format!("Couldn't get block: {}", err)))?
+            .remove(&target_id)
+            .ok_or(PbftError::NoWorkingBlock)?;
 
-        self._broadcast_message(&msg_type, msg_bytes, state)
+        if target.block_num <= state.seq_num {
+            state.catchup_target = None;
+            Ok(())
+        } else if target.block_num == state.seq_num + 1 {
+            state.catchup_target = None;
+            self.catchup(state, &target)
+        } else {
+            self.catchup_range(state, &target)
+        }
     }
 
-    #[cfg(not(test))]
-    fn _broadcast_message(
+    /// Get the block that `block`'s seal is for from the validator, checking that it's the next
+    /// block to commit on top of the chain head
+    fn get_catchup_block(
         &mut self,
-        msg_type: &PbftMessageType,
-        msg: Vec<u8>,
-        state: &mut PbftState,
-    ) -> Result<(), PbftError> {
-        // Broadcast to peers
-        debug!("{}: Broadcasting {:?}", state, msg_type);
-        self.service
-            .broadcast(String::from(msg_type).as_str(), msg.clone())
-            .unwrap_or_else(|err| error!("Couldn't broadcast: {}", err));
-
-        // Send to self
-        let parsed_message = ParsedMessage::from_bytes(msg)?;
+        block: &Block,
+        state: &PbftState,
+    ) -> Result<PbftBlock, PbftError> {
+        let head = self
+            .service
+            .get_chain_head()
+            .map_err(|err| PbftError::InternalError(format!("Couldn't get chain head: {}", err)))?;
 
-        self.on_peer_message(parsed_message, state)
+        let sealed_block = self
+            .service
+            .get_blocks(vec![block.previous_id.clone()])
+            .map_err(|err| PbftError::InternalError(format!("Couldn't get block: {}", err)))?
+            .remove(&block.previous_id);
+
+        match sealed_block {
+            Some(ref sealed_block)
+                if sealed_block.block_id == block.previous_id
+                    && sealed_block.block_num == state.seq_num
+                    && sealed_block.previous_id == head.block_id =>
+            {
+                info!(
+                    "{}: Catching up without a working block, using block {}",
+                    state,
+                    hex::encode(&sealed_block.block_id)
+                );
+                Ok(pbft_block_from_block(sealed_block.clone()))
+            }
+            _ => {
+                error!(
+                    "Trying to catch up, but node does not have block #{} yet",
+                    state.seq_num
+                );
+                Err(PbftError::NoWorkingBlock)
+            }
+        }
     }
 
-    /// NOTE: Disabling self-sending for testing purposes
-    #[cfg(test)]
-    fn _broadcast_message(
+    /// Commit the block this node is waiting for using a seal obtained out-of-band, such as one
+    /// exported from another node's log
+    ///
+    /// This is a recovery tool for when the votes for a block are no longer available from the
+    /// network. Every vote in the seal must be a valid commit for the block, and since whoever
+    /// built the seal is unknown, they must come from `2f + 1` distinct members of the network as
+    /// of that block; otherwise nothing changes.
+    pub fn commit_with_external_seal(
         &mut self,
-        _msg_type: &PbftMessageType,
-        _msg: Vec<u8>,
-        _state: &mut PbftState,
+        block_id: BlockId,
+        seal_bytes: &[u8],
+        state: &mut PbftState,
     ) -> Result<(), PbftError> {
-        return Ok(());
-    }
-}
+        let block = self
+            .service
+            .get_blocks(vec![block_id.clone()])
+            .map_err(|err| PbftError::InternalError(format!("Couldn't get block: {}", err)))?
+            .remove(&block_id)
+            .ok_or_else(|| {
+                PbftError::InternalError(format!("Couldn't find block {}", hex::encode(&block_id)))
+            })?;
 
-/// Create a Protobuf binary representation of a PbftMessage from its info and corresponding Block
-fn make_msg_bytes(info: PbftMessageInfo, block: PbftBlock) -> Result<Vec<u8>, ProtobufError> {
-    let mut msg = PbftMessage::new();
-    msg.set_info(info);
-    msg.set_block(block);
-    msg.write_to_bytes()
-}
+        if block.block_num != state.seq_num || state.phase == PbftPhase::Finished {
+            return Err(PbftError::InternalError(format!(
+                "Can't commit block {} with an external seal; node is at sequence number {} ({:?})",
+                block.block_num, state.seq_num, state.phase
+            )));
+        }
+        if let Some(ref working_block) = state.working_block {
+            if working_block.get_block_id() != block_id.as_slice() {
+                return Err(PbftError::BlockMismatch(
+                    pbft_block_from_block(block),
+                    working_block.clone(),
+                ));
+            }
+        }
 
-// Make a PbftBlock out of a consensus Block (PBFT doesn't need to use all the information about
-// the block - this keeps blocks lighter weight)
-fn pbft_block_from_block(block: Block) -> PbftBlock {
-    let mut pbft_block = PbftBlock::new();
-    pbft_block.set_block_id(block.block_id);
-    pbft_block.set_signer_id(block.signer_id);
-    pbft_block.set_block_num(block.block_num);
-    pbft_block.set_summary(block.summary);
-    pbft_block
-}
+        let seal: PbftSeal = protobuf::parse_from_bytes(seal_bytes)
+            .map_err(|err| PbftError::SerializationError("parsing external seal", err))?;
+        if seal.previous_id != block_id.as_slice() {
+            return Err(PbftError::InternalError(format!(
+                "External seal is for block {}, not {}",
+                hex::encode(&seal.previous_id),
+                hex::encode(&block_id)
+            )));
+        }
 
-/// NOTE: Testing the PbftNode is a bit strange. Due to missing functionality in the Service,
-/// a node calling `broadcast()` doesn't include sending a message to itself. In order to get around
-/// this, `on_peer_message()` is called, which sometimes causes unintended side effects when
-/// testing. Self-sending has been disabled (see `broadcast()` method) for testing purposes.
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::mock_config;
-    use crate::handlers::make_msg_info;
-    use crate::hash::{hash_sha256, hash_sha512};
-    use sawtooth_sdk::consensus::engine::{Error, PeerId};
-    use sawtooth_sdk::messages::consensus::ConsensusPeerMessageHeader;
-    use serde_json;
-    use std::collections::HashMap;
-    use std::default::Default;
-    use std::fs::{remove_file, File};
-    use std::io::prelude::*;
+        // Whoever built the seal is unknown, so there's no implicit vote to leave out
+        let membership = self.get_membership_at(&block_id, state)?;
+        Self::check_seal_size(&seal, membership.voters.len())?;
+        Self::check_seal_network(&seal, &state.network_id)?;
+        let voter_ids = Self::verify_consensus_votes(
+            &seal,
+            block.block_num,
+            self.vote_verifier(),
+            self.hash_algorithm,
+            self.vote_verification_threads,
+            Some(&self.verification_limiter),
+        )?;
+        self.check_seal_voters(&voter_ids, &membership, None)?;
 
-    const BLOCK_FILE: &str = "target/blocks.txt";
+        warn!(
+            "{}: Committing block {} with an external seal",
+            state,
+            hex::encode(&block_id)
+        );
+        state.working_block = Some(pbft_block_from_block(block));
 
-    /// Mock service to roughly keep track of the blockchain
-    pub struct MockService {
-        pub chain: Vec<BlockId>,
+        self.commit_from_seal(&seal, state)
     }
 
-    impl MockService {
-        /// Serialize the chain into JSON, and write to a file
-        fn write_chain(&self) {
-            let mut block_file = File::create(BLOCK_FILE).unwrap();
-            let block_bytes: Vec<Vec<u8>> = self
-                .chain
+    /// Commit the working block with the votes from a verified seal for it, as if they had
+    /// arrived as regular `Commit` messages
+    fn commit_from_seal(
+        &mut self,
+        seal: &PbftSeal,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
+        // The view and block are taken from the votes, so there must be at least one
+        if seal.get_previous_commit_votes().is_empty() {
+            return Err(PbftError::EmptySeal(seal.previous_id.clone()));
+        }
+
+        let messages =
+            seal.get_previous_commit_votes()
                 .iter()
-                .map(|block: &BlockId| -> Vec<u8> { Vec::<u8>::from(block.clone()) })
-                .collect();
+                .try_fold(Vec::new(), |mut msgs, v| {
+                    msgs.push(ParsedMessage::from_pbft_message(
+                        protobuf::parse_from_bytes(&v.get_message_bytes()).map_err(|err| {
+                            PbftError::SerializationError("parsing vote message", err)
+                        })?,
+                    ));
+                    Ok(msgs)
+                })?;
 
-            let ser_blocks = serde_json::to_string(&block_bytes).unwrap();
-            block_file.write_all(&ser_blocks.into_bytes()).unwrap();
+        // Every vote was checked against the seal when it was verified, but the block committed
+        // is taken from the first vote, so make sure they really all agree on it
+        let voted_block = messages[0].get_block();
+        if let Some(msg) = messages
+            .iter()
+            .find(|msg| msg.get_block().get_block_id() != voted_block.get_block_id())
+        {
+            return Err(PbftError::BlockMismatch(
+                voted_block.clone(),
+                msg.get_block().clone(),
+            ));
+        }
+
+        // Update our view if necessary
+        let view = messages[0].info().get_view();
+        if view > state.view {
+            info!("Updating view from {} to {}.", state.view, view);
+            self.catch_up_to_view(state, view);
+        }
+
+        // Add messages to the log; they may be from an earlier view than this node's. The seal
+        // has been verified, so the block is committed even if the log won't take its votes.
+        if let Err(err) = self.msg_log.add_messages(messages.clone(), state) {
+            warn!("Not adding the votes from the seal to the log: {}", err);
+        }
+
+        // Skip straight to the Committing phase and Commit the new block using one of the parsed
+        // messages to simulate having received a regular commit message
+        state.set_phase(PbftPhase::Committing);
+        handlers::commit(
+            state,
+            &mut *self.service,
+            &messages[0].as_msg_type(PbftMessageType::Commit),
+        )?;
+
+        // The block was committed out-of-band, so it's no longer waiting on Commit messages
+        state.commit_timeout.stop();
+
+        // Call on_block_commit right away so we're ready to catch up again if necessary; the
+        // validator's BlockCommit for this block will then be ignored
+        let block_id = BlockId::from(messages[0].get_block().get_block_id());
+        if self.catchup_commits.len() >= CATCHUP_COMMIT_WINDOW {
+            self.catchup_commits.pop_front();
         }
+        self.catchup_commits.push_back(block_id.clone());
+        self.on_block_commit(block_id, state);
+
+        Ok(())
     }
 
-    impl Service for MockService {
-        fn send_to(
-            &mut self,
-            _peer: &PeerId,
-            _message_type: &str,
-            _payload: Vec<u8>,
-        ) -> Result<(), Error> {
-            Ok(())
+    /// Handle a `BlockNew` update from the Validator
+    ///
+    /// The validator has received a new block; verify the block's consensus seal and add the
+    /// BlockNew to the message log. If this is the block we are waiting for: set it as the working
+    /// block, update the idle & commit timers, and broadcast a PrePrepare if this node is the
+    /// primary. If this is the block after the one this node is working on, use it to catch up.
+    pub fn on_block_new(&mut self, block: Block, state: &mut PbftState) -> Result<(), PbftError> {
+        info!(
+            "{}: Got BlockNew: {} / {} {}",
+            state,
+            block.block_num,
+            hex::encode(&block.block_id[..block.block_id.len().min(3)]),
+            LogFields::for_state(state).block_id(&block.block_id),
+        );
+
+        if block.block_num < state.seq_num {
+            info!(
+                "Ignoring block ({}) that's older than current sequence number ({}).",
+                block.block_num, state.seq_num
+            );
+            return Ok(());
         }
-        fn broadcast(&mut self, _message_type: &str, _payload: Vec<u8>) -> Result<(), Error> {
-            Ok(())
+
+        // The block at the current sequence number has already been committed once the node is
+        // in the Finished phase, so there's no need to verify it again
+        if block.block_num == state.seq_num && state.phase == PbftPhase::Finished {
+            debug!(
+                "{}: Ignoring block {}; block {} was already committed",
+                state,
+                hex::encode(&block.block_id),
+                block.block_num
+            );
+            return Ok(());
         }
-        fn initialize_block(&mut self, _previous_id: Option<BlockId>) -> Result<(), Error> {
-            Ok(())
+
+        // The validator can announce the same block more than once (for instance, after
+        // reconnecting). A block only gets into the log once its seal has been verified, so there's
+        // nothing left to do for one that's already there.
+        let already_verified = self
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::BlockNew, block.block_num)
+            .iter()
+            .any(|msg| msg.get_block().get_block_id() == block.block_id.as_slice());
+        if already_verified {
+            debug!(
+                "{}: Ignoring repeated block {}",
+                state,
+                hex::encode(&block.block_id)
+            );
+            self.seal_timing.cache_hits += 1;
+            return Ok(());
         }
-        fn summarize_block(&mut self) -> Result<Vec<u8>, Error> {
-            Ok(Default::default())
+
+        // With no other nodes, there are no votes to collect or verify; the block is committed as
+        // soon as it's the one this node is waiting for
+        if state.is_single_node() {
+            if block.block_num == state.seq_num && state.phase == PbftPhase::PrePreparing {
+                return self.commit_single_node_block(block, state);
+            }
+            return Ok(());
         }
-        fn finalize_block(&mut self, _data: Vec<u8>) -> Result<BlockId, Error> {
-            Ok(Default::default())
+
+        // Blocks past the high watermark wait until a stable checkpoint moves the watermark past
+        // them
+        if let Err(err) = state.check_watermark(block.block_num) {
+            debug!("{}: Deferring block: {}", state, err);
+            self.defer_block(block, state);
+            return Ok(());
         }
-        fn cancel_block(&mut self) -> Result<(), Error> {
-            Ok(())
+
+        // Verifying the seals of blocks that won't be needed for a while only takes time away from
+        // the ones that will, so they're held until the node gets closer to them
+        if self.seal_prefetch_depth > 0
+            && block.block_num > state.seq_num + self.seal_prefetch_depth
+        {
+            debug!(
+                "{}: Deferring block {} until sequence number {}",
+                state,
+                block.block_num,
+                block.block_num - self.seal_prefetch_depth
+            );
+            self.defer_block(block, state);
+            return Ok(());
         }
-        fn check_blocks(&mut self, _priority: Vec<BlockId>) -> Result<(), Error> {
-            Ok(())
+
+        let verification_start = Instant::now();
+        let seal = match self.verify_consensus_seal(&block, state) {
+            Ok(seal) => seal,
+            // The seal couldn't be checked for now, through no fault of the block's (for instance,
+            // the validator couldn't read the settings), so it's tried again later
+            Err(err) if err.retry_after().is_some() => {
+                warn!(
+                    "{}: Deferring block {} until its seal can be verified: {}",
+                    state, block.block_num, err
+                );
+                self.defer_block(block, state);
+                return Err(err);
+            }
+            // A corrupt on-chain peers list is a problem with the settings, not with the block or
+            // whoever published it, so the block is left alone
+            Err(err @ PbftError::InvalidPeersSetting(_, _)) => {
+                error!(
+                    "{}: Can't verify the seal of block {}: {}",
+                    state, block.block_num, err
+                );
+                return Err(err);
+            }
+            Err(err) => {
+                warn!(
+                    "Failing block due to failed consensus seal verification and \
+                     proposing view change! Error was {}",
+                    err
+                );
+                self.record_fault(block.signer_id.clone(), block.block_id.clone(), &err);
+                self.service.fail_block(block.block_id).map_err(|err| {
+                    PbftError::InternalError(format!("Couldn't fail block: {}", err))
+                })?;
+                self.propose_view_change(state, ViewChangeReason::InvalidSeal)?;
+                return Err(err);
+            }
+        };
+        if let Some(ref seal) = seal {
+            self.seal_timing.record(
+                verification_start.elapsed(),
+                seal.get_previous_commit_votes().len(),
+            );
         }
-        fn commit_block(&mut self, block_id: BlockId) -> Result<(), Error> {
-            self.chain.push(block_id);
-            self.write_chain();
-            Ok(())
+
+        // A block for the current sequence number that forks off an older ancestor may still have
+        // an internally consistent seal, so its parent is checked against the committed head. Only
+        // a verified fork from the primary shows that the primary is faulty; any node could have
+        // published one that wasn't checked or that came from someone else, so those are dropped
+        // without a view change.
+        if block.block_num == state.seq_num && !self.extends_chain_head(&block, state)? {
+            let err = PbftError::ForkedBlock(block.block_id.clone(), block.previous_id.clone());
+            let from_primary = seal.is_some() && block.signer_id == state.get_primary_id();
+            self.service.ignore_block(block.block_id).map_err(|err| {
+                PbftError::InternalError(format!("Couldn't ignore block: {}", err))
+            })?;
+            if from_primary {
+                warn!(
+                    "{}: Ignoring block and proposing view change: {}",
+                    state, err
+                );
+                self.propose_view_change(state, ViewChangeReason::ForkedBlock)?;
+            } else {
+                warn!("{}: Ignoring block: {}", state, err);
+            }
+            return Err(err);
         }
-        fn ignore_block(&mut self, _block_id: BlockId) -> Result<(), Error> {
-            Ok(())
+
+        if let Some(seal) = seal {
+            // The votes in a valid seal were cast at the view the network is in, so if they're
+            // ahead of this node, it missed a view change and must align with the network before
+            // treating the block as coming from the current view's primary
+            if let Some(view) = Self::seal_view(&seal)? {
+                if view > state.view {
+                    warn!(
+                        "{}: Block {} was proposed in a later view ({}); catching up",
+                        state, block.block_num, view
+                    );
+                    self.catch_up_to_view(state, view);
+                }
+            }
+
+            // The seal is stored under the number of the block that carries it, which is ahead of
+            // the current sequence number when this block is used for catching up
+            self.msg_log
+                .add_consensus_seal(block.block_id.clone(), block.block_num, seal)?;
         }
-        fn fail_block(&mut self, _block_id: BlockId) -> Result<(), Error> {
-            Ok(())
+
+        // Create PBFT message for BlockNew and add it to the log
+        let mut msg = PbftMessage::new();
+        msg.set_info(handlers::make_msg_info(
+            &PbftMessageType::BlockNew,
+            state.view,
+            block.block_num,
+            state.id.clone(),
+        ));
+
+        let pbft_block = pbft_block_from_block(block.clone());
+        msg.set_block(pbft_block.clone());
+
+        self.msg_log
+            .add_message(ParsedMessage::from_pbft_message(msg.clone()), state)?;
+
+        // We can use this block's seal to commit the next block (i.e. catch-up) if it's the block
+        // after the one we're waiting for and we haven't already told the validator to commit the
+        // block we're waiting for; if it's further ahead, the blocks in between are committed too
+        if block.block_num == state.seq_num + 1 && state.phase != PbftPhase::Finished {
+            self.catchup(state, &block)?;
+        } else if block.block_num > state.seq_num + 1 && state.phase != PbftPhase::Finished {
+            self.catchup_range(state, &block)?;
+        } else if block.block_num == state.seq_num {
+            // This is the block we're waiting for, so we update state
+            state.working_block = Some(msg.get_block().clone());
+            self.working_block_since = Some(Instant::now());
+            state.commit_timeout.start();
+
+            // Send PrePrepare messages if we're the primary
+            if state.is_primary() {
+                let s = state.seq_num;
+                self._broadcast_pbft_message(s, &PbftMessageType::PrePrepare, pbft_block, state)?;
+            }
         }
-        fn get_blocks(
-            &mut self,
-            block_ids: Vec<BlockId>,
-        ) -> Result<HashMap<BlockId, Block>, Error> {
-            let mut res = HashMap::new();
-            for id in &block_ids {
-                let index = self
-                    .chain
-                    .iter()
-                    .position(|val| val == id)
-                    .unwrap_or(self.chain.len());
-                res.insert(id.clone(), mock_block(index as u64));
+
+        debug_assert!(
+            state.check_invariants().is_ok(),
+            "{:?}",
+            state.check_invariants()
+        );
+        Ok(())
+    }
+
+    /// Ask the validator for blocks this node has fallen behind on
+    ///
+    /// Catching up normally happens when the validator delivers the block after the one this node
+    /// is working on, but a node whose consensus has stalled may never be sent it. When peers have
+    /// committed later sequence numbers, the blocks their `Commit`s are for (and the blocks between
+    /// those and the current sequence number) are requested and handled as if they had just been
+    /// delivered, oldest first. The `Commit`s that were too far ahead for the log to keep are known
+    /// from the latest one each peer sent. Blocks this node already has, such as its working block,
+    /// aren't requested, and ones the validator doesn't have are skipped. At most
+    /// `max_catchup_blocks_per_loop` blocks are requested at once; the rest are remembered for the
+    /// next call, and the blocks are only handled once they've all been fetched. Returns the IDs of
+    /// the blocks that were requested.
+    pub fn request_missing_blocks(
+        &mut self,
+        state: &mut PbftState,
+    ) -> Result<Vec<BlockId>, PbftError> {
+        if state.highest_seen_seq_num <= state.seq_num {
+            self.missing_blocks.clear();
+            self.fetched_blocks.clear();
+            return Ok(vec![]);
+        }
+
+        let in_range =
+            |seq_num: u64| seq_num >= state.seq_num && seq_num <= state.highest_seen_seq_num;
+        let mut known = self.known_blocks(state);
+        known.extend(
+            self.fetched_blocks
+                .iter()
+                .map(|block| block.block_id.clone()),
+        );
+
+        // The blocks left over from last time come first, since they're the furthest along
+        let mut queue: Vec<BlockId> = self.missing_blocks.split_off(0);
+        queue.extend(
+            self.msg_log
+                .iter_matching(|msg| {
+                    msg.info().get_msg_type() == PbftMessageType::Commit.as_str()
+                        && in_range(msg.info().get_seq_num())
+                })
+                .map(|msg| BlockId::from(msg.get_block().get_block_id())),
+        );
+        queue.extend(
+            self.latest_commits
+                .values()
+                .filter(|&&(seq_num, _)| in_range(seq_num))
+                .map(|(_, block_id)| block_id.clone()),
+        );
+        let mut wanted: HashSet<BlockId> = HashSet::new();
+        queue.retain(|block_id| !known.contains(block_id) && wanted.insert(block_id.clone()));
+
+        // Each block's predecessor is requested too, until the blocks reach the current sequence
+        // number. Blocks are requested one at a time, since the validator fails a request for
+        // several blocks if it doesn't have any one of them; the ones it does have are still used.
+        let limit = match self.max_catchup_blocks_per_loop {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+        debug!("{}: Requesting {} missing blocks", state, queue.len());
+        let mut blocks = self.fetched_blocks.split_off(0);
+        let mut next = 0;
+        while next < queue.len() && next < limit {
+            let block_id = queue[next].clone();
+            next += 1;
+
+            let block = match self.service.get_blocks(vec![block_id.clone()]) {
+                Ok(mut found) => match found.remove(&block_id) {
+                    Some(block) => block,
+                    None => continue,
+                },
+                Err(err) => {
+                    warn!(
+                        "{}: Couldn't get block {}: {}",
+                        state,
+                        hex::encode(&block_id),
+                        err
+                    );
+                    continue;
+                }
+            };
+
+            if block.block_num > state.seq_num
+                && block.block_num <= state.highest_seen_seq_num
+                && !known.contains(&block.previous_id)
+                && wanted.insert(block.previous_id.clone())
+            {
+                queue.push(block.previous_id.clone());
             }
-            Ok(res)
+            known.insert(block.block_id.clone());
+            blocks.push(block);
         }
-        fn get_chain_head(&mut self) -> Result<Block, Error> {
-            let prev_num = self.chain.len().checked_sub(2).unwrap_or(0);
-            Ok(Block {
-                block_id: self.chain.last().unwrap().clone(),
-                previous_id: self.chain.get(prev_num).unwrap().clone(),
-                signer_id: PeerId::from(vec![]),
-                block_num: self.chain.len().checked_sub(1).unwrap_or(0) as u64,
-                payload: vec![],
-                summary: vec![],
-            })
+        self.missing_blocks = queue.split_off(next);
+
+        // A block can only be handled once the ones before it are in
+        if !self.missing_blocks.is_empty() {
+            self.fetched_blocks = blocks;
+            return Ok(queue);
         }
-        fn get_settings(
-            &mut self,
-            _block_id: BlockId,
-            _settings: Vec<String>,
-        ) -> Result<HashMap<String, String>, Error> {
-            let mut settings: HashMap<String, String> = Default::default();
-            settings.insert(
-                "sawtooth.consensus.pbft.peers".to_string(),
-                "[\"00\", \"01\", \"02\", \"03\"]".to_string(),
-            );
-            Ok(settings)
+
+        blocks.sort_by_key(|block| block.block_num);
+        for block in blocks {
+            if block.block_num >= state.seq_num {
+                self.on_block_new(block, state)?;
+            }
+        }
+
+        Ok(queue)
+    }
+
+    /// Get the IDs of the blocks from the current sequence number on that the validator has
+    /// already sent this node, including its working block
+    fn known_blocks(&self, state: &PbftState) -> HashSet<BlockId> {
+        self.msg_log
+            .iter_matching(|msg| {
+                msg.info().get_msg_type() == PbftMessageType::BlockNew.as_str()
+                    && msg.info().get_seq_num() >= state.seq_num
+                    && msg.info().get_seq_num() <= state.highest_seen_seq_num
+            })
+            .map(|msg| BlockId::from(msg.get_block().get_block_id()))
+            .chain(
+                state
+                    .working_block
+                    .as_ref()
+                    .map(|block| BlockId::from(block.get_block_id())),
+            )
+            .collect()
+    }
+
+    /// Check whether the given block builds on the block this node committed last
+    ///
+    /// The last block committed by this node is used when there is one; otherwise (for instance,
+    /// right after startup) the validator's chain head is asked for, and the check is skipped if
+    /// it isn't the block right before the current sequence number.
+    fn extends_chain_head(&mut self, block: &Block, state: &PbftState) -> Result<bool, PbftError> {
+        let head_id = match state.last_committed_block {
+            Some(ref id) => id.clone(),
+            None => {
+                let head = self.service.get_chain_head().map_err(|err| {
+                    PbftError::InternalError(format!("Couldn't get chain head: {}", err))
+                })?;
+                if head.block_num + 1 != state.seq_num {
+                    return Ok(true);
+                }
+                head.block_id
+            }
+        };
+        Ok(block.previous_id == head_id)
+    }
+
+    /// Commit a block right away, without going through consensus; only used when this node is
+    /// the only one in the network
+    fn commit_single_node_block(
+        &mut self,
+        block: Block,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
+        info!(
+            "{}: Committing block {} as a single node",
+            state, block.block_num
+        );
+
+        state.working_block = Some(pbft_block_from_block(block.clone()));
+        self.service
+            .commit_block(block.block_id)
+            .map_err(|e| PbftError::InternalError(format!("Failed to commit block: {:?}", e)))?;
+        state.set_phase(PbftPhase::Finished);
+
+        Ok(())
+    }
+
+    /// Handle a `BlockCommit` update from the Validator
+    ///
+    /// A block was sucessfully committed; update state to be ready for the next block, make any
+    /// necessary view and membership changes, garbage collect the logs, update the commit & idle
+    /// timers, and start a new block if this node is the primary.
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn on_block_commit(&mut self, block_id: BlockId, state: &mut PbftState) {
+        debug!(
+            "{}: <<<<<< BlockCommit: {:?} {}",
+            state,
+            block_id,
+            LogFields::for_state(state).block_id(&block_id)
+        );
+
+        // The validator can send the same BlockCommit more than once (for instance, after
+        // reconnecting); only the first one counts
+        if state.last_committed_block.as_ref() == Some(&block_id) {
+            if let Some(pos) = self.catchup_commits.iter().position(|id| *id == block_id) {
+                self.catchup_commits.remove(pos);
+            }
+            debug!(
+                "{}: Block {} was already committed",
+                state,
+                hex::encode(&block_id)
+            );
+            return;
+        }
+
+        let is_working_block = match state.working_block {
+            Some(ref block) => BlockId::from(block.get_block_id()) == block_id,
+            None => false,
+        };
+
+        if state.phase != PbftPhase::Finished || !is_working_block {
+            // Catchup handles the commit itself, so the validator's notification comes late
+            if let Some(pos) = self.catchup_commits.iter().position(|id| *id == block_id) {
+                self.catchup_commits.remove(pos);
+                debug!(
+                    "{}: Block {} was already committed through catchup",
+                    state,
+                    hex::encode(&block_id)
+                );
+            } else {
+                warn!(
+                    "{}: Got BlockCommit for block {}, which isn't the working block",
+                    state,
+                    hex::encode(&block_id)
+                );
+            }
+            return;
+        }
+
+        if let Some(since) = self.working_block_since.take() {
+            self.metrics.record_commit(since.elapsed());
+        }
+        state.commit_timeout.stop();
+        state.last_committed_block = Some(block_id.clone());
+
+        // Remember which view the block was committed in, as shown by its Commit votes
+        let commit_view = self
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::Commit, state.seq_num)
+            .iter()
+            .filter(|msg| msg.get_block().get_block_id() == block_id.as_slice())
+            .map(|msg| msg.info().get_view())
+            .max()
+            .unwrap_or(state.view);
+        self.msg_log
+            .record_commit_view(block_id.clone(), state.seq_num, commit_view);
+
+        // Let the other nodes know this block was committed, if it's time for a checkpoint
+        if self.checkpoint_period > 0 && state.seq_num % self.checkpoint_period == 0 {
+            if let Some(block) = state.working_block.clone() {
+                let seq_num = state.seq_num;
+                self._broadcast_pbft_message(seq_num, &PbftMessageType::Checkpoint, block, state)
+                    .unwrap_or_else(|err| error!("Couldn't broadcast checkpoint: {}", err));
+            }
+        }
+
+        // Update state to be ready for next block; the phase was checked above, so this can't
+        // fail
+        if let Err(err) = state.switch_phase(PbftPhase::PrePreparing) {
+            error!("{}: {}", state, err);
+        }
+        state.seq_num += 1;
+        // A block this node has committed is as settled for it as a stable checkpoint
+        state.advance_low_watermark(state.seq_num - 1);
+
+        // If we already have a BlockNew for the next block, we can make it the working block;
+        // otherwise just set the working block to None
+        state.working_block = self
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::BlockNew, state.seq_num)
+            .first()
+            .map(|msg| msg.get_block().clone());
+        if state.working_block.is_some() {
+            self.working_block_since = Some(Instant::now());
+            state.commit_timeout.start();
+        }
+
+        // Start a view change if we need to force one for fairness or if membership changed
+        let reason = if state.at_forced_view_change() {
+            Some(ViewChangeReason::ForcedRotation)
+        } else {
+            match self.update_membership(block_id.clone(), state) {
+                Ok(true) => Some(ViewChangeReason::MembershipChange),
+                Ok(false) => None,
+                Err(err) => {
+                    warn!(
+                        "{}: Not applying membership change; staying on the current peers: {}",
+                        state, err
+                    );
+                    None
+                }
+            }
+        };
+        if let Some(reason) = reason {
+            self.force_view_change(state, reason);
+        }
+
+        // Tell the log to garbage collect if it needs to
+        self.msg_log.garbage_collect(state.seq_num, &block_id);
+
+        // Restart the faulty primary timeout for the next block; the network is making progress,
+        // so any view change backoff no longer applies
+        state.reset_view_change_backoff();
+        state.faulty_primary_timeout.start();
+
+        if state.is_primary() && state.working_block.is_none() {
+            info!(
+                "{}: Initializing block with previous ID {:?}",
+                state, block_id
+            );
+            self.initialize_block(Some(block_id.clone()));
+        }
+
+        self.release_deferred_blocks(state);
+
+        debug_assert!(
+            state.check_invariants().is_ok(),
+            "{:?}",
+            state.check_invariants()
+        );
+    }
+
+    /// Handle the deferred blocks that are now within `seal_prefetch_depth` of the current
+    /// sequence number and not past the high watermark, verifying their seals ahead of when
+    /// they're needed
+    ///
+    /// Blocks whose seals couldn't be verified for the time being are deferred again, so this is
+    /// also called every so often to retry them.
+    pub fn release_deferred_blocks(&mut self, state: &mut PbftState) {
+        self.prune_deferred_blocks(state);
+
+        let depth = self.seal_prefetch_depth;
+        let limit = state.seq_num + depth;
+        let (mut ready, deferred): (Vec<_>, Vec<_>) =
+            self.deferred_blocks.drain(..).partition(|block| {
+                (depth == 0 || block.block_num <= limit)
+                    && state.check_watermark(block.block_num).is_ok()
+            });
+        self.deferred_blocks = deferred;
+
+        ready.sort_by_key(|block| block.block_num);
+        for block in ready {
+            let block_num = block.block_num;
+            match self.on_block_new(block, state) {
+                Err(ref err) if err.retry_after().is_some() => debug!(
+                    "{}: Deferred block {} still can't be handled: {}",
+                    state, block_num, err
+                ),
+                Err(err) => error!(
+                    "{}: Couldn't handle deferred block {}: {}",
+                    state, block_num, err
+                ),
+                Ok(()) => {}
+            }
+        }
+    }
+
+    /// Hold a block back to be handled by `release_deferred_blocks` later
+    ///
+    /// At most `MAX_DEFERRED_BLOCKS` blocks are held; past that, the one farthest ahead is dropped,
+    /// since it's needed last. The node can still catch up through it later, from a block that
+    /// builds on it.
+    fn defer_block(&mut self, block: Block, state: &PbftState) {
+        self.prune_deferred_blocks(state);
+        if self
+            .deferred_blocks
+            .iter()
+            .any(|deferred| deferred.block_id == block.block_id)
+        {
+            return;
+        }
+
+        self.deferred_blocks.push(block);
+        if self.deferred_blocks.len() > MAX_DEFERRED_BLOCKS {
+            if let Some(index) = self
+                .deferred_blocks
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, deferred)| deferred.block_num)
+                .map(|(index, _)| index)
+            {
+                let dropped = self.deferred_blocks.swap_remove(index);
+                warn!(
+                    "{}: Too many deferred blocks; dropping block {}",
+                    state, dropped.block_num
+                );
+            }
+        }
+    }
+
+    /// Forget the deferred blocks for sequence numbers this node has already moved past
+    fn prune_deferred_blocks(&mut self, state: &PbftState) {
+        let seq_num = state.seq_num;
+        self.deferred_blocks
+            .retain(|block| block.block_num >= seq_num);
+    }
+
+    /// Handle a `BlockValid` update
+    /// This message arrives after `check_blocks` is called, signifying that the validator has
+    /// successfully checked a block with this `BlockId`.
+    /// Once a `BlockValid` is received, transition to committing blocks. If enough `Commit`
+    /// messages already arrived while the block was being checked, the block is committed right
+    /// away.
+    ///
+    /// A node only commits a block it's prepared for, so if the log doesn't hold the working
+    /// block's `PrePrepare` and `2f + 1` matching `Prepare`s, a `NotPrepared` error is returned
+    /// and no `Commit` is sent.
+    ///
+    /// The summary of the block in the primary's `PrePrepare` must also match the one the validator
+    /// has for the block; if it doesn't, the primary sent inconsistent block metadata, so the block
+    /// is discarded, the primary is recorded as faulty, and a view change is started.
+    #[allow(clippy::ptr_arg)]
+    pub fn on_block_valid(
+        &mut self,
+        block_id: &BlockId,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
+        debug!("{}: <<<<<< BlockValid: {:?}", state, block_id);
+        let block = match state.working_block {
+            Some(ref block) => {
+                if &BlockId::from(block.get_block_id()) == block_id {
+                    Ok(block.clone())
+                } else {
+                    warn!("Got BlockValid that doesn't match the working block");
+                    Err(PbftError::NotReadyForMessage)
+                }
+            }
+            None => {
+                warn!("Got BlockValid with no working block");
+                Err(PbftError::NoWorkingBlock)
+            }
+        }?;
+
+        if let Err(err) = self.check_validated_summary(&block, state) {
+            warn!(
+                "{}: Discarding working block and starting view change: {}",
+                state, err
+            );
+            self.record_fault(state.get_primary_id(), block_id.clone(), &err);
+            state.working_block = None;
+            self.working_block_since = None;
+            state.commit_timeout.stop();
+            state.set_phase(PbftPhase::PrePreparing);
+            self.propose_view_change(state, ViewChangeReason::SummaryMismatch)?;
+            return Err(err);
+        }
+
+        if !self.is_prepared_for(&block, state) {
+            warn!(
+                "{}: Got BlockValid for block {}, which this node isn't prepared for",
+                state,
+                hex::encode(block_id)
+            );
+            return Err(PbftError::NotPrepared(block_id.clone()));
+        }
+
+        state.switch_phase(PbftPhase::Committing)?;
+        self._broadcast_pbft_message(state.seq_num, &PbftMessageType::Commit, block, state)?;
+
+        // Commits that arrived while the block was being checked couldn't be acted on yet
+        self.replay_buffered_votes(state)?;
+
+        debug_assert!(
+            state.check_invariants().is_ok(),
+            "{:?}",
+            state.check_invariants()
+        );
+        Ok(())
+    }
+
+    /// Check that the summary the primary proposed for the working block in its `PrePrepare`
+    /// matches the one the validator has for the block
+    ///
+    /// If the validator can't be asked for the block, the working block it delivered is compared
+    /// against instead. Without a `PrePrepare` for the block there's nothing to check; the node
+    /// isn't prepared for the block either.
+    fn check_validated_summary(
+        &mut self,
+        block: &PbftBlock,
+        state: &PbftState,
+    ) -> Result<(), PbftError> {
+        let mut info = PbftMessageInfo::new();
+        info.set_view(state.view);
+        info.set_seq_num(state.seq_num);
+        let proposed = match self
+            .msg_log
+            .get_one_msg(&info, &PbftMessageType::PrePrepare)
+        {
+            Some(msg) if msg.get_block().get_block_id() == block.get_block_id() => {
+                msg.get_block().get_summary().to_vec()
+            }
+            _ => return Ok(()),
+        };
+
+        let block_id = block.get_block_id().to_vec();
+        let validated = match self.service.get_blocks(vec![block_id.clone()]) {
+            Ok(mut blocks) => blocks.remove(&block_id).map(|validated| validated.summary),
+            Err(err) => {
+                warn!("Couldn't get validated block to check its summary: {}", err);
+                None
+            }
+        }
+        .unwrap_or_else(|| block.get_summary().to_vec());
+
+        if proposed != validated {
+            return Err(PbftError::BlockSummaryMismatch(proposed, validated));
+        }
+        Ok(())
+    }
+
+    /// Whether the log shows this node is prepared for the block in the current view and sequence
+    /// number: it has the primary's `PrePrepare` for the block, and `2f + 1` matching `Prepare`s
+    fn is_prepared_for(&self, block: &PbftBlock, state: &PbftState) -> bool {
+        let mut info = PbftMessageInfo::new();
+        info.set_view(state.view);
+        info.set_seq_num(state.seq_num);
+
+        let pre_prepare_matches = self
+            .msg_log
+            .get_one_msg(&info, &PbftMessageType::PrePrepare)
+            .map_or(false, |msg| msg.get_block() == block);
+        pre_prepare_matches && self.msg_log.check_prepared(&info, state)
+    }
+
+    /// Handle a `BlockInvalid` update
+    /// The validator found that a block it was asked to check is invalid. If it's the working
+    /// block, the primary proposed a block that can't be committed, so the block is discarded and
+    /// a view change is started. Any other block isn't holding up consensus, so it's ignored.
+    #[allow(clippy::ptr_arg)]
+    pub fn on_block_invalid(
+        &mut self,
+        block_id: &BlockId,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
+        debug!("{}: <<<<<< BlockInvalid: {:?}", state, block_id);
+        let is_working_block = match state.working_block {
+            Some(ref block) => &BlockId::from(block.get_block_id()) == block_id,
+            None => false,
+        };
+        if !is_working_block {
+            debug!(
+                "{}: Ignoring BlockInvalid for block {}, which isn't the working block",
+                state,
+                hex::encode(block_id)
+            );
+            return Ok(());
+        }
+
+        warn!(
+            "{}: Working block {} is invalid; starting view change",
+            state,
+            hex::encode(block_id)
+        );
+        state.working_block = None;
+        self.working_block_since = None;
+        state.commit_timeout.stop();
+        state.set_phase(PbftPhase::PrePreparing);
+
+        self.propose_view_change(state, ViewChangeReason::InvalidBlock)
+    }
+
+    /// Handle a `PeerConnected` update
+    /// Every connection is recorded, since the validator also connects to peers that aren't PBFT
+    /// members (yet); only members are counted toward a live quorum.
+    pub fn on_peer_connected(&mut self, peer_id: PeerId, state: &mut PbftState) {
+        if !Self::is_member(&peer_id, state) {
+            debug!(
+                "{}: Peer {}, which isn't a member, connected",
+                state,
+                hex::encode(&peer_id)
+            );
+        } else {
+            debug!("{}: Peer {} connected", state, hex::encode(&peer_id));
+        }
+        state.live_peers.insert(peer_id);
+        self.initialize_block_if_ready(state);
+    }
+
+    /// Handle a `PeerDisconnected` update
+    pub fn on_peer_disconnected(&mut self, peer_id: PeerId, state: &mut PbftState) {
+        debug!("{}: Peer {} disconnected", state, hex::encode(&peer_id));
+        state.live_peers.remove(&peer_id);
+    }
+
+    /// Whether the peer is in this node's configured set of peers, voting or not
+    fn is_member(peer_id: &[u8], state: &PbftState) -> bool {
+        state
+            .peer_ids
+            .iter()
+            .chain(state.observers.iter())
+            .any(|peer| peer.as_slice() == peer_id)
+    }
+
+    // ---------- Methods for periodically checking on and updating the state, called by the engine ----------
+
+    fn build_seal(&mut self, state: &PbftState, summary: Vec<u8>) -> Result<Vec<u8>, PbftError> {
+        let previous_seq_num = state.previous_seq_num()?;
+        info!("{}: Building seal for block {}", state, previous_seq_num);
+
+        // The seal is for the block this one builds on, so only votes for that block belong in it;
+        // this node's vote is implicit, but counts towards the quorum
+        let previous_id = state.last_committed_block.as_deref();
+        let required = 2 * state.f + 1;
+        let mut messages = self
+            .msg_log
+            .get_enough_messages(
+                &PbftMessageType::Commit,
+                previous_seq_num,
+                previous_id,
+                required,
+                state,
+            )
+            .ok_or_else(|| {
+                debug!("{}: {}", state, self.msg_log);
+                PbftError::InternalError(format!(
+                    "Couldn't find commit messages for a quorum of {} (weight {}) in the message \
+                     log for building a seal!",
+                    required,
+                    self.msg_log.quorum_weight(required)
+                ))
+            })?;
+
+        // The log doesn't keep messages in any particular order; sort the votes so the same set
+        // always makes the same seal
+        messages.sort_by(|a, b| a.info().get_signer_id().cmp(b.info().get_signer_id()));
+
+        let mut seal = PbftSeal::new();
+
+        seal.set_version(SEAL_VERSION);
+        seal.set_summary(summary);
+        seal.set_previous_id(BlockId::from(messages[0].get_block().get_block_id()));
+        seal.set_previous_commit_votes(RepeatedField::from(
+            messages
+                .iter()
+                .map(|m| {
+                    let mut vote = PbftSignedCommitVote::new();
+
+                    vote.set_header_bytes(m.header_bytes.clone());
+                    vote.set_header_signature(m.header_signature.clone());
+                    vote.set_message_bytes(m.message_bytes.clone());
+
+                    vote
+                })
+                .collect::<Vec<_>>(),
+        ));
+
+        seal.write_to_bytes()
+            .map_err(|err| PbftError::SerializationError("serializing consensus seal", err))
+    }
+
+    /// Check that `build_seal` will be able to build a seal for the block at the current sequence
+    /// number once it's committed
+    ///
+    /// The seal is built from the commits for `block_id` in the highest view whose signers make a
+    /// quorum along with this node (by weight, if quorums are weighted), so there must be such a
+    /// set; commits for any other block are left out of it.
+    fn check_seal_buildable(&self, block_id: &[u8], state: &PbftState) -> Result<(), PbftError> {
+        let required = 2 * state.f + 1;
+        self.msg_log
+            .get_enough_messages(
+                &PbftMessageType::Commit,
+                state.seq_num,
+                Some(block_id),
+                required,
+                state,
+            )
+            .map(|_| ())
+            .ok_or_else(|| {
+                PbftError::InternalError(format!(
+                    "Can't commit block {}: the commits available for its seal aren't a quorum \
+                     of {} (weight {})",
+                    hex::encode(block_id),
+                    required,
+                    self.msg_log.quorum_weight(required)
+                ))
+            })
+    }
+
+    /// The primary tries to finalize a block every so often
+    ///
+    /// Returns the ID of the block if one was published, or `None` if this node isn't primary or
+    /// the block isn't ready yet.
+    /// # Panics
+    /// Panics if `finalize_block` fails. This is necessary because it means the validator wasn't
+    /// able to publish the new block.
+    pub fn try_publish(&mut self, state: &mut PbftState) -> Result<Option<BlockId>, PbftError> {
+        // Only the primary takes care of this, and we try publishing a block
+        // on every engine loop, even if it's not yet ready. This isn't an error,
+        // so just return Ok(None).
+        if !state.is_primary() || state.phase != PbftPhase::PrePreparing {
+            return Ok(None);
+        }
+
+        // A block published before enough peers are connected to vote on it would only time out
+        if !state.has_live_quorum() {
+            return Ok(None);
+        }
+
+        // Hold off if the last block was published too recently
+        if state.block_publish_timeout.is_running() && !state.block_publish_timeout.check_expired()
+        {
+            return Ok(None);
+        }
+
+        info!("{}: Summarizing block", state);
+
+        let summary = match self.service.summarize_block() {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                debug!(
+                    "{}: Couldn't summarize, so not finalizing: {}",
+                    state,
+                    e.description().to_string()
+                );
+                return Ok(None);
+            }
+        };
+
+        // We don't publish a consensus seal before the first sealed block (by default, at block 1,
+        // since we never receive any votes on the genesis block). Leave payload blank for those
+        // blocks, and for every block if this node is the only one in the network.
+        let data = if state.seq_num < self.first_sealed_block || state.is_single_node() {
+            vec![]
+        } else {
+            self.build_seal(state, summary)?
+        };
+
+        match self.service.finalize_block(data) {
+            Ok(block_id) => {
+                info!("{}: Publishing block {:?}", state, block_id);
+                self.block_initialized = false;
+                if state.block_publish_timeout.duration() > Duration::from_millis(0) {
+                    state.block_publish_timeout.start();
+                }
+                Ok(Some(block_id))
+            }
+            Err(EngineError::BlockNotReady) => {
+                debug!("{}: Block not ready", state);
+                Ok(None)
+            }
+            Err(err) => {
+                error!("Couldn't finalize block: {}", err);
+                Err(PbftError::InternalError("Couldn't finalize block!".into()))
+            }
+        }
+    }
+
+    /// Check to see if the faulty primary timeout has expired
+    pub fn check_faulty_primary_timeout_expired(&mut self, state: &mut PbftState) -> bool {
+        state.faulty_primary_timeout.check_expired()
+    }
+
+    pub fn start_faulty_primary_timeout(&self, state: &mut PbftState) {
+        state.faulty_primary_timeout.start();
+    }
+
+    /// Check to see if the working block has been waiting too long to be committed
+    ///
+    /// Only applies in `Normal` mode; a view change already in progress will discard the block. If
+    /// the working block was dropped without the timer being stopped (for instance, when syncing
+    /// to the chain head), the timer is stopped here instead of starting a view change.
+    pub fn check_commit_timeout_expired(&mut self, state: &mut PbftState) -> bool {
+        if state.working_block.is_none() {
+            state.commit_timeout.stop();
+            return false;
+        }
+        state.mode == PbftMode::Normal && state.commit_timeout.check_expired()
+    }
+
+    /// Check to see if the current view change has been waiting too long for the other nodes
+    pub fn check_view_change_timeout_expired(&mut self, state: &mut PbftState) -> bool {
+        state.mode == PbftMode::ViewChanging && state.view_change_timeout.check_expired()
+    }
+
+    /// Retry a message from the backlog queue
+    ///
+    /// Messages for sequence numbers this node has already moved past are discarded, and those
+    /// for sequence numbers it hasn't reached yet are put back for later. The backlog is looked
+    /// through at most once. Returns `true` if a message was retried, in which case more may be
+    /// ready, or `false` if none were.
+    pub fn retry_backlog(&mut self, state: &mut PbftState) -> Result<bool, PbftError> {
+        self.metrics
+            .record_backlog_depth(self.msg_log.backlog_len());
+
+        match self.next_ready_backlog_msg(state, &HashSet::new()) {
+            Some(msg) => {
+                debug!("{}: Popping message from backlog", state);
+                self.on_peer_message(msg, state)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Retry every message in the backlog that's ready to be handled
+    ///
+    /// Handling one message may make others ready, so this keeps going until none are left. Each
+    /// message is retried at most once per call, even if it ends up back in the backlog, and one
+    /// that can't be handled doesn't hold up the rest. Returns the number of messages that were
+    /// handled: not the ones that failed, or that went back into the backlog.
+    pub fn drain_backlog(&mut self, state: &mut PbftState) -> usize {
+        self.metrics
+            .record_backlog_depth(self.msg_log.backlog_len());
+
+        let mut retried = HashSet::new();
+        let mut handled = 0;
+        while let Some(msg) = self.next_ready_backlog_msg(state, &retried) {
+            debug!("{}: Popping message from backlog", state);
+            let msg_bytes = msg.message_bytes.clone();
+            match self.on_peer_message(msg, state) {
+                Ok(()) if !self.msg_log.is_backlogged(&msg_bytes) => handled += 1,
+                Ok(()) => {}
+                Err(err) => handle_pbft_result(Err(err)),
+            }
+            retried.insert(msg_bytes);
+        }
+        handled
+    }
+
+    /// Take the first message from the backlog that's ready to be handled and hasn't been retried
+    /// yet, looking through the backlog at most once
+    ///
+    /// Messages for sequence numbers this node has already moved past are discarded; all others
+    /// are kept in the backlog.
+    fn next_ready_backlog_msg(
+        &mut self,
+        state: &PbftState,
+        retried: &HashSet<Vec<u8>>,
+    ) -> Option<ParsedMessage> {
+        for _ in 0..self.msg_log.backlog_len() {
+            let msg = match self.msg_log.pop_backlog() {
+                Some(msg) => msg,
+                None => break,
+            };
+
+            let seq_num = msg.info().get_seq_num();
+            if seq_num < state.seq_num {
+                debug!(
+                    "{}: Dropping stale message from backlog: {}",
+                    state,
+                    msg.info()
+                );
+                continue;
+            }
+            let later_view = msg.info().get_view() > state.view;
+            if seq_num > state.seq_num || (later_view && state.mode == PbftMode::ViewChanging) {
+                trace!(
+                    "{}: Not ready for backlogged message: {}",
+                    state,
+                    msg.info()
+                );
+                self.msg_log.push_backlog(msg);
+                continue;
+            }
+            if retried.contains(&msg.message_bytes) {
+                self.msg_log.push_backlog(msg);
+                continue;
+            }
+
+            return Some(msg);
+        }
+        None
+    }
+
+    pub fn force_view_change(&mut self, state: &mut PbftState, reason: ViewChangeReason) {
+        info!("{}: Forcing view change ({:?})", state, reason);
+        self.last_view_change_reason = Some(reason);
+        self.cancel_initialized_block(state);
+        handlers::force_view_change(state, &mut *self.service);
+        if state.is_primary() {
+            self.block_initialized = true;
+        }
+    }
+
+    /// Initiate a view change (this node suspects that the primary is faulty)
+    /// Nodes drop everything when they're doing a view change - will not process any peer messages
+    /// other than `ViewChanges` until the view change is complete.
+    pub fn propose_view_change(
+        &mut self,
+        state: &mut PbftState,
+        reason: ViewChangeReason,
+    ) -> Result<(), PbftError> {
+        // A verify-only node has no say in who the primary is
+        if state.mode == PbftMode::ViewChanging || self.verify_only {
+            return Ok(());
+        }
+        let previous_seq_num = state.previous_seq_num()?;
+        let mut fields = LogFields::for_state(state);
+        if let Some(ref block) = state.working_block {
+            fields = fields.block_id(block.get_block_id());
+        }
+        warn!("{}: Starting view change ({:?}) {}", state, reason, fields);
+        self.cancel_initialized_block(state);
+        state.begin_view_change();
+        // The faulty primary timeout can't fire again while the view change is in progress; once
+        // it's done, the new primary gets the whole timeout
+        state.faulty_primary_timeout.pause();
+        state.commit_timeout.stop();
+        state.record_view_change_attempt();
+        self.metrics.record_view_change();
+        self.last_view_change_reason = Some(reason);
+        state.view_change_seal = Some(self.msg_log.get_consensus_seal(previous_seq_num)?);
+        self.check_view_jump(state.view + 1, state.id.clone(), state);
+
+        self.broadcast_view_change(state)
+    }
+
+    /// Pick a view change back up after a restart
+    ///
+    /// A node that was restarted from a state saved in the middle of a view change comes back in
+    /// `ViewChanging` mode for the same target view, but the messages it had sent and received were
+    /// lost with its log. Its own `ViewChange` is sent again (with the seal saved in the state) so
+    /// that the other nodes can still count it; `ViewChange`s from the other nodes are collected
+    /// again as they arrive. Does nothing if the node isn't view changing.
+    pub fn resume_view_change(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
+        if state.mode != PbftMode::ViewChanging {
+            return Ok(());
+        }
+        warn!(
+            "{}: Resuming view change to view {}",
+            state,
+            state.view_change_target().unwrap_or(state.view + 1)
+        );
+        state.restart_view_change_timeout();
+        self.broadcast_view_change(state)
+    }
+
+    /// Move a view change that has stalled on to the next view
+    ///
+    /// If not enough nodes join this node's view change before the view change timeout expires
+    /// (for instance, because the primary of the target view is down too), the node aims one view
+    /// further and sends a `ViewChange` for that view instead. Does nothing if the node isn't view
+    /// changing.
+    pub fn retry_view_change(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
+        if state.mode != PbftMode::ViewChanging {
+            return Ok(());
+        }
+        let target = state.skip_view_change_target();
+        warn!(
+            "{}: View change timed out; trying view {} (waiting {:?})",
+            state,
+            target,
+            state.view_change_timeout.duration()
+        );
+        state.record_view_change_attempt();
+        self.metrics.record_view_change();
+        self.check_view_jump(target, state.id.clone(), state);
+        self.broadcast_view_change(state)
+    }
+
+    /// Send this node's `ViewChange` for the next view, with the seal saved when the view change
+    /// was started
+    fn broadcast_view_change(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
+        let seal = state
+            .view_change_seal
+            .clone()
+            .ok_or_else(|| PbftError::InternalError("No seal saved for view change".into()))?;
+
+        let mut info = handlers::make_msg_info(
+            &PbftMessageType::ViewChange,
+            state.view_change_target().unwrap_or(state.view + 1),
+            state.previous_seq_num()?,
+            state.id.clone(),
+        );
+        info.set_network_id(state.network_id.clone());
+        info.set_timestamp(timing::unix_millis());
+
+        let mut vc_msg = PbftViewChange::new();
+        vc_msg.set_info(info);
+        vc_msg.set_seal(seal);
+        let msg_bytes = vc_msg
+            .write_to_bytes()
+            .map_err(|err| PbftError::SerializationError("serializing ViewChange", err))?;
+
+        self._broadcast_message(
+            &PbftMessageType::ViewChange,
+            PbftMessageWrapper::ViewChange(vc_msg),
+            msg_bytes,
+            state,
+        )
+    }
+
+    /// Announce that this node is the primary of the view it just changed to
+    ///
+    /// The `NewView` carries the `ViewChange` messages from `2f` other nodes for the view, so
+    /// that nodes that didn't see the quorum themselves can check that the view change happened.
+    fn broadcast_new_view(
+        &mut self,
+        vc_message: &ParsedMessage,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
+        let view = vc_message.info().get_view();
+        let seq_num = vc_message.info().get_seq_num();
+        let required = 2 * state.f as usize;
+
+        let mut signers = HashSet::new();
+        let view_changes = self
+            .msg_log
+            .get_messages_of_type_seq_view(&PbftMessageType::ViewChange, seq_num, view)
+            .into_iter()
+            .filter(|msg| !msg.from_self && msg.info().get_signer_id() != state.id.as_slice())
+            .filter(|msg| signers.insert(msg.info().get_signer_id().to_vec()))
+            .take(required)
+            .map(|msg| {
+                let mut vote = PbftSignedCommitVote::new();
+
+                vote.set_header_bytes(msg.header_bytes.clone());
+                vote.set_header_signature(msg.header_signature.clone());
+                vote.set_message_bytes(msg.message_bytes.clone());
+
+                vote
+            })
+            .collect::<Vec<_>>();
+
+        if view_changes.len() < required {
+            warn!(
+                "{}: Only have {} ViewChange messages from other nodes for view {}; can't \
+                 broadcast NewView",
+                state,
+                view_changes.len(),
+                view
+            );
+            return Ok(());
+        }
+
+        let mut info =
+            handlers::make_msg_info(&PbftMessageType::NewView, view, seq_num, state.id.clone());
+        info.set_network_id(state.network_id.clone());
+        info.set_timestamp(timing::unix_millis());
+
+        let mut nv_msg = PbftNewView::new();
+        nv_msg.set_info(info);
+        nv_msg.set_view_changes(RepeatedField::from(view_changes));
+        let msg_bytes = nv_msg
+            .write_to_bytes()
+            .map_err(|err| PbftError::SerializationError("serializing NewView", err))?;
+
+        self._broadcast_message(
+            &PbftMessageType::NewView,
+            PbftMessageWrapper::NewView(nv_msg),
+            msg_bytes,
+            state,
+        )
+    }
+
+    /// Step down as primary, for instance ahead of planned maintenance
+    ///
+    /// Cancels the block this node is building, stops it from proposing any more blocks, and
+    /// starts a view change so the next primary can take over without waiting for the faulty
+    /// primary timeout. Does nothing if this node isn't the primary.
+    pub fn resign_primary(&mut self, state: &mut PbftState) -> Result<(), PbftError> {
+        if !state.is_primary() {
+            debug!(
+                "{}: Not resigning, since this node isn't the primary",
+                state
+            );
+            return Ok(());
+        }
+
+        warn!("{}: Resigning as primary", state);
+        self.cancel_initialized_block(state);
+        state.downgrade_role();
+
+        self.propose_view_change(state, ViewChangeReason::Resigned)
+    }
+
+    /// List the peers that haven't yet sent the message this node is waiting on for the current
+    /// sequence number and view
+    ///
+    /// In `PrePreparing` only the primary is expected to send a message; in the `Preparing`,
+    /// `Checking`, and `Committing` phases every peer (including this node) is.
+    pub fn missing_voters(&self, state: &PbftState) -> Vec<PeerId> {
+        let msg_type = state.check_msg_type();
+        let expected = match msg_type {
+            PbftMessageType::PrePrepare => vec![state.get_primary_id()],
+            PbftMessageType::Prepare | PbftMessageType::Commit => state.peer_ids.clone(),
+            _ => return vec![],
+        };
+
+        let voters: HashSet<&[u8]> = self
+            .msg_log
+            .get_messages_of_type_seq_view(&msg_type, state.seq_num, state.view)
+            .iter()
+            .map(|msg| msg.info().get_signer_id())
+            .collect();
+
+        expected
+            .into_iter()
+            .filter(|id| !voters.contains(id.as_slice()))
+            .collect()
+    }
+
+    /// Check whether this node is making progress, for liveness probes
+    ///
+    /// A node is stuck if it's past `PrePreparing` on its working block, the commit timer for the
+    /// block has run out, and fewer than `2f + 1` nodes have sent the votes for its current phase.
+    pub fn is_healthy(&self, state: &PbftState) -> HealthStatus {
+        if state.mode == PbftMode::ViewChanging {
+            return HealthStatus::ViewChanging;
+        }
+
+        let voting = match state.phase {
+            PbftPhase::Preparing | PbftPhase::Checking | PbftPhase::Committing => true,
+            PbftPhase::PrePreparing | PbftPhase::Finished => false,
+        };
+        let timed_out = state.commit_timeout.remaining() == Duration::from_secs(0);
+        let signers = self.msg_log.count_distinct_signers(
+            &state.check_msg_type(),
+            state.view,
+            state.seq_num,
+            state,
+        );
+
+        if voting && timed_out && signers < (2 * state.f + 1) as usize {
+            HealthStatus::Stuck
+        } else {
+            HealthStatus::Normal
+        }
+    }
+
+    /// Get the node's current phase, mode, view, and sequence number, for external tooling
+    pub fn status(&self, state: &PbftState) -> ConsensusStatus {
+        ConsensusStatus {
+            phase: state.phase.clone(),
+            mode: state.mode,
+            view: state.view,
+            seq_num: state.seq_num,
+            primary_id: state.get_primary_id(),
+            is_primary: state.is_primary(),
+        }
+    }
+
+    /// Send messages to peers with the given transport instead of the validator service
+    pub fn set_transport(&mut self, transport: Box<MessageTransport>) {
+        self.transport = Some(transport);
+    }
+
+    /// Record every update this node handles with the given recorder, so the run can be replayed
+    pub fn set_update_recorder(&mut self, recorder: Box<UpdateRecorder>) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Choose whether messages this node broadcasts are also delivered to itself right away, as
+    /// if they had come from a peer
+    ///
+    /// Self-delivery is on by default. It can be turned off when something else, like a test
+    /// harness routing messages between nodes, takes care of handing a node its own messages.
+    pub fn set_self_send(&mut self, enabled: bool) {
+        self.self_send = enabled;
+    }
+
+    /// Get the transport that messages to peers are sent with
+    fn transport(&mut self) -> &mut MessageTransport {
+        match self.transport {
+            Some(ref mut transport) => &mut **transport,
+            None => &mut self.service,
+        }
+    }
+
+    /// Check vote signatures with the given verifier instead of the one for the configured
+    /// signing algorithm
+    pub fn set_signature_verifier(&mut self, verifier: Arc<SignatureVerifier>) {
+        self.signature_verifier = verifier;
+    }
+
+    /// The verifier for vote signatures, unless signature verification is turned off
+    fn vote_verifier(&self) -> Option<&Arc<SignatureVerifier>> {
+        if self.verify_signatures {
+            Some(&self.signature_verifier)
+        } else {
+            None
+        }
+    }
+
+    /// Get how long verifying recent consensus seals took
+    pub fn seal_verification_timing(&self) -> &SealVerificationTiming {
+        &self.seal_timing
+    }
+
+    /// Check whether this node's wall clock has drifted from its monotonic clock since the last
+    /// check; meant to be called periodically from the engine loop
+    pub fn check_clock_drift(&mut self) {
+        self.clock_drift.check();
+    }
+
+    /// Get the most recent clock drift beyond the threshold, in ms, if there has been one
+    pub fn last_clock_drift(&self) -> Option<i64> {
+        self.clock_drift.last_drift()
+    }
+
+    /// Get the time by which this node has to publish the block for the current sequence number
+    ///
+    /// The deadline is only set while this node is the primary and is waiting to propose a block
+    /// (`PrePreparing` in `Normal` mode). It is measured from when the node entered that phase,
+    /// and allows as long as the secondaries will wait before suspecting the primary is faulty.
+    pub fn primary_proposal_deadline(&self, state: &PbftState) -> Option<Instant> {
+        if !state.is_primary()
+            || state.mode != PbftMode::Normal
+            || state.phase != PbftPhase::PrePreparing
+        {
+            return None;
+        }
+
+        state
+            .phase_timeout
+            .started_at()
+            .map(|started| started + state.faulty_primary_timeout.duration())
+    }
+
+    /// Get the consensus seal stored for the chain head (sequence number `seq_num - 1`), for
+    /// instance to hand to a new node as proof of where the chain is
+    ///
+    /// The genesis block, and any other block before the first sealed block, has no seal; for
+    /// those a `NoConsensusSeal` error is returned.
+    pub fn head_seal(&self, state: &PbftState) -> Result<PbftSeal, PbftError> {
+        let head = state.seq_num.saturating_sub(1);
+        if head < self.first_sealed_block {
+            return Err(PbftError::NoConsensusSeal(head));
+        }
+        self.msg_log.get_consensus_seal(head)
+    }
+
+    /// Get the reason the most recent view change was started, if there has been one
+    pub fn last_view_change_reason(&self) -> Option<ViewChangeReason> {
+        self.last_view_change_reason
+    }
+
+    /// Re-read the on-chain list of peers as of the given block, without waiting for the next
+    /// commit, and return whether the peers changed
+    ///
+    /// Meant for correcting membership by hand, for instance when the network is stalled because
+    /// of it. As with a change picked up on commit, a change that would leave the network unable to
+    /// tolerate a fault is an `InsufficientPeers` error and isn't applied. No view change is
+    /// started, since the other nodes may not refresh at the same time; this node just takes
+    /// whichever role it has in the current view under the new peers.
+    pub fn refresh_membership(
+        &mut self,
+        block_id: BlockId,
+        state: &mut PbftState,
+    ) -> Result<bool, PbftError> {
+        if !self.update_membership(block_id, state)? {
+            return Ok(false);
+        }
+
+        info!(
+            "{}: Refreshed membership; now {} voting peers with f = {}",
+            state,
+            state.peer_ids.len(),
+            state.f
+        );
+        if state.get_primary_id() == state.id {
+            state.upgrade_role();
+        } else {
+            self.cancel_initialized_block(state);
+            state.downgrade_role();
+        }
+        Ok(true)
+    }
+
+    /// Check the on-chain list of peers; if it has changed, update peers list and return true.
+    /// The peers are always left sorted, even if the list itself hasn't changed.
+    ///
+    /// A change that would leave the network unable to tolerate a fault isn't applied; the node
+    /// keeps running on its current peers and an `InsufficientPeers` error is returned.
+    fn update_membership(
+        &mut self,
+        block_id: BlockId,
+        state: &mut PbftState,
+    ) -> Result<bool, PbftError> {
+        // Get list of peers from settings
+        let settings = self.get_peers_settings(&block_id)?;
+        let peers = dedup_peers(&get_peers_from_settings(
+            &settings,
+            &self.peers_setting_key,
+        )?);
+        let new_peers_set: HashSet<PeerId> = peers.iter().cloned().collect();
+
+        // The weights may change along with the peers, or on their own
+        let weights = get_peer_weights_from_settings(&settings)?;
+        check_peer_weights(&weights, &peers).map_err(|problem| {
+            PbftError::InvalidPeersSetting(PEER_WEIGHTS_SETTING_KEY.into(), problem)
+        })?;
+        self.msg_log.set_peer_weights(weights);
+
+        // Check if membership has changed; observers are still members, they just don't vote
+        let old_peers_set: HashSet<PeerId> = state
+            .peer_ids
+            .iter()
+            .chain(state.observers.iter())
+            .cloned()
+            .collect();
+        // A state stored by an older version may not have its peers in order, which every node
+        // needs to agree on the primary
+        let unsorted = state.peer_ids.windows(2).any(|pair| pair[0] > pair[1]);
+
+        if new_peers_set != old_peers_set || unsorted {
+            let observers: Vec<PeerId> = state
+                .observers
+                .iter()
+                .filter(|observer| new_peers_set.contains(*observer))
+                .cloned()
+                .collect();
+            let mut peer_ids: Vec<PeerId> = peers
+                .into_iter()
+                .filter(|peer| !observers.contains(peer))
+                .collect();
+            peer_ids.sort();
+
+            let f = match compute_f(peer_ids.len()) {
+                Ok(f) => f,
+                Err(_) if state.allow_single_node && peer_ids.len() == 1 => 0,
+                Err(err) => return Err(err),
+            };
+
+            state.observers = observers;
+            state.peer_ids = peer_ids;
+            state.f = f;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    // ---------- Methods for communication between nodes ----------
+
+    // Broadcast a message to this node's peers, and itself
+    fn _broadcast_pbft_message(
+        &mut self,
+        seq_num: u64,
+        msg_type: &PbftMessageType,
+        block: PbftBlock,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
+        let expected_type = state.check_msg_type();
+        // Make sure that we should be sending messages of this type; observers don't vote, and
+        // verify-only nodes don't send anything
+        if self.verify_only
+            || (msg_type.is_multicast() && (msg_type != &expected_type || state.is_observer()))
+        {
+            return Ok(());
+        }
+
+        // The same message can be broadcast again through different paths; peers only need it once
+        let key = (
+            String::from(msg_type),
+            state.view,
+            seq_num,
+            BlockId::from(block.get_block_id()),
+        );
+        while self
+            .recent_broadcasts
+            .front()
+            .map_or(false, |(_, sent)| sent.elapsed() >= BROADCAST_DEDUP_WINDOW)
+        {
+            self.recent_broadcasts.pop_front();
+        }
+        if self
+            .recent_broadcasts
+            .iter()
+            .any(|(recent, _)| *recent == key)
+        {
+            debug!(
+                "{}: Not broadcasting {:?} for seq {} again",
+                state, msg_type, seq_num
+            );
+            return Ok(());
+        }
+        self.recent_broadcasts.push_back((key, Instant::now()));
+
+        let mut info = handlers::make_msg_info(&msg_type, state.view, seq_num, state.id.clone());
+        info.set_network_id(state.network_id.clone());
+        info.set_timestamp(timing::unix_millis());
+
+        let mut pbft_msg = PbftMessage::new();
+        pbft_msg.set_info(info);
+        pbft_msg.set_block(block);
+        let msg_bytes = pbft_msg
+            .write_to_bytes()
+            .map_err(|err| PbftError::SerializationError("serializing PbftMessage", err))?;
+
+        self._broadcast_message(
+            &msg_type,
+            PbftMessageWrapper::Message(pbft_msg),
+            msg_bytes,
+            state,
+        )
+    }
+
+    /// NOTE: Messages sent to a single peer are also recorded when testing
+    fn _send_to(&mut self, peer: &PeerId, msg_type: &str, msg: Vec<u8>) {
+        if self.verify_only {
+            return;
+        }
+
+        #[cfg(test)]
+        {
+            let mut parsed_message = ParsedMessage::from_bytes(msg.clone()).unwrap();
+            parsed_message.from_self = false;
+            self.sent_direct.push((peer.clone(), parsed_message));
+        }
+
+        self.transport()
+            .send_to(peer, msg_type, msg)
+            .unwrap_or_else(|err| error!("Couldn't send to {}: {}", hex::encode(peer), err));
+    }
+
+    /// Broadcast a message to this node's peers, then deliver it to this node too if self-delivery
+    /// is on
+    ///
+    /// `msg` is `message` serialized. The node built `message` itself, so it's delivered as is
+    /// rather than parsed back out of the bytes.
+    ///
+    /// NOTE: Broadcast messages are also recorded when testing
+    fn _broadcast_message(
+        &mut self,
+        msg_type: &PbftMessageType,
+        message: PbftMessageWrapper,
+        msg: Vec<u8>,
+        state: &mut PbftState,
+    ) -> Result<(), PbftError> {
+        if self.verify_only {
+            return Ok(());
+        }
+
+        // Broadcast to peers
+        debug!("{}: Broadcasting {:?}", state, msg_type);
+        self.transport()
+            .broadcast(String::from(msg_type).as_str(), msg.clone())
+            .unwrap_or_else(|err| error!("Couldn't broadcast: {}", err));
+
+        let parsed_message = ParsedMessage {
+            header_bytes: vec![],
+            header_signature: vec![],
+            message,
+            message_bytes: msg,
+            from_self: true,
+        };
+        #[cfg(test)]
+        self.sent_messages.push(parsed_message.clone());
+
+        // Send to self
+        if self.self_send {
+            self.on_peer_message(parsed_message, state)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Log the error from handling an update or a timer, if there is one; the errors that are
+/// expected in normal operation are only traced
+pub fn handle_pbft_result(res: Result<(), PbftError>) {
+    if let Err(e) = res {
+        match e {
+            PbftError::Timeout => (),
+            PbftError::WrongNumMessages(_, _, _) | PbftError::NotReadyForMessage => trace!("{}", e),
+            _ => error!("{}", e),
+        }
+    }
+}
+
+/// Verify a block's consensus seal against the given peers, without a running node
+///
+/// `peers` and `f` must be those of the network as of the block the seal is for (the block's
+/// predecessor). Every vote's signature is checked, and the seal must have votes from `2f` of the
+/// peers other than the block's publisher, whose vote is implicit. Meant for tools, like block
+/// explorers, that have the chain but no validator to ask for settings.
+pub fn verify_seal(block: &Block, peers: &[PeerId], f: u64) -> Result<(), PbftError> {
+    let seal = parse_seal(block)?;
+    PbftNode::check_seal_size(&seal, peers.len())?;
+    let voter_ids = seal.check_internal(block)?;
+    check_voters_are_peers(&voter_ids, peers, Some(&block.signer_id))?;
+    check_vote_count(&voter_ids, f)
+}
+
+impl PbftSeal {
+    /// Check the parts of this seal that don't depend on who the peers are: that it's for the
+    /// block's predecessor with the block's summary, and that every vote is a `Commit` for that
+    /// block from the same view, correctly signed with the default signing and hash algorithms
+    ///
+    /// Returns the IDs of the voters; whether they're peers, and enough of them, is up to the
+    /// caller.
+    pub fn check_internal(&self, block: &Block) -> Result<HashSet<Vec<u8>>, PbftError> {
+        let verifier = signing::verifier_for(signing::DEFAULT_SIGNING_ALGORITHM)?;
+        self.check_internal_with(block, Some(&verifier), HashAlgorithm::default(), 1, None)
+    }
+
+    /// Like `check_internal`, but with the given verifier (or none, to skip checking signatures)
+    /// and hash algorithm, spreading the votes across up to `threads` threads that each take a
+    /// slot from the limiter, if there is one
+    fn check_internal_with(
+        &self,
+        block: &Block,
+        verifier: Option<&Arc<SignatureVerifier>>,
+        algorithm: HashAlgorithm,
+        threads: usize,
+        limiter: Option<&VerificationLimiter>,
+    ) -> Result<HashSet<Vec<u8>>, PbftError> {
+        check_seal_matches_block(self, block)?;
+        PbftNode::verify_consensus_votes(
+            self,
+            block.block_num.saturating_sub(1),
+            verifier,
+            algorithm,
+            threads,
+            limiter,
+        )
+    }
+}
+
+/// Get the consensus seal stored in a block's payload
+///
+/// Seals in a newer format than `SEAL_VERSION` are rejected, since they can't be verified
+/// correctly.
+fn parse_seal(block: &Block) -> Result<PbftSeal, PbftError> {
+    if block.payload.is_empty() {
+        return Err(PbftError::InternalError(
+            "Got empty payload for non-genesis block!".into(),
+        ));
+    }
+
+    let seal: PbftSeal = protobuf::parse_from_bytes(&block.payload)
+        .map_err(|err| PbftError::SerializationError("parsing consensus seal", err))?;
+    if seal.get_version() > SEAL_VERSION {
+        return Err(PbftError::UnsupportedSealVersion(seal.get_version()));
+    }
+    Ok(seal)
+}
+
+/// Check that a seal taken from a block is for the block's predecessor
+fn check_seal_matches_block(seal: &PbftSeal, block: &Block) -> Result<(), PbftError> {
+    if seal.previous_id != &block.previous_id[..] {
+        return Err(PbftError::SealPreviousIdMismatch(
+            seal.previous_id.clone(),
+            block.previous_id.clone(),
+        ));
+    }
+
+    if seal.summary != &block.summary[..] {
+        return Err(PbftError::SealSummaryMismatch(
+            seal.summary.clone(),
+            block.summary.clone(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check that all of a seal's votes come from the given peers, other than `implicit_voter`
+fn check_voters_are_peers(
+    voter_ids: &HashSet<Vec<u8>>,
+    peers: &[PeerId],
+    implicit_voter: Option<&PeerId>,
+) -> Result<(), PbftError> {
+    let peer_ids: HashSet<_> = peers
+        .iter()
+        .cloned()
+        .filter(|pid| Some(pid) != implicit_voter)
+        .collect();
+
+    if !voter_ids.is_subset(&peer_ids) {
+        let mut unknown: Vec<_> = voter_ids.difference(&peer_ids).cloned().collect();
+        unknown.sort();
+        return Err(PbftError::UnknownVoters(unknown));
+    }
+
+    Ok(())
+}
+
+/// Check that a seal has `2f` votes, since the primary vote is implicit
+fn check_vote_count(voter_ids: &HashSet<Vec<u8>>, f: u64) -> Result<(), PbftError> {
+    if voter_ids.len() < 2 * f as usize {
+        return Err(PbftError::InsufficientVotes(
+            2 * f as usize,
+            voter_ids.len(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Create a Protobuf binary representation of a PbftMessage from its info and corresponding Block
+// Make a PbftBlock out of a consensus Block (PBFT doesn't need to use all the information about
+// the block - this keeps blocks lighter weight)
+fn pbft_block_from_block(block: Block) -> PbftBlock {
+    let mut pbft_block = PbftBlock::new();
+    pbft_block.set_block_id(block.block_id);
+    pbft_block.set_signer_id(block.signer_id);
+    pbft_block.set_block_num(block.block_num);
+    pbft_block.set_summary(block.summary);
+    pbft_block
+}
+
+/// NOTE: Testing the PbftNode is a bit strange. Due to missing functionality in the Service,
+/// a node calling `broadcast()` doesn't include sending a message to itself. In order to get around
+/// this, `on_peer_message()` is called, which sometimes causes unintended side effects when
+/// testing. Most tests turn self-delivery off (see `mock_node()`) so that the node's handling of
+/// each message can be checked on its own; tests of the full flow turn it back on.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{mock_config, DEFAULT_PEERS_SETTING_KEY};
+    use crate::handlers::make_msg_info;
+    use crate::hash::{hash_sha256, hash_sha512};
+    use crate::message_type::PbftMessageWrapper;
+    use crate::recorder::{read_recording, FileRecorder};
+    use crate::timing::MockClock;
+    use sawtooth_sdk::consensus::engine::{Error, PeerId, PeerMessage, PeerMessageHeader};
+    use sawtooth_sdk::messages::consensus::ConsensusPeerMessageHeader;
+    use sawtooth_sdk::signing::create_context;
+    use serde_json;
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use std::default::Default;
+    use std::fs::{remove_file, File};
+    use std::io::prelude::*;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    const BLOCK_FILE: &str = "target/blocks.txt";
+
+    /// Mock service to roughly keep track of the blockchain
+    pub struct MockService {
+        pub chain: Vec<BlockId>,
+
+        /// Full blocks to hand out instead of plain mock blocks, if present
+        pub blocks: HashMap<BlockId, Block>,
+
+        /// Peers to report in the settings
+        pub peers: Vec<PeerId>,
+
+        /// Setting to report the peers under
+        pub peers_setting_key: String,
+
+        /// Number of blocks finalized through this service
+        pub finalized: Arc<AtomicUsize>,
+
+        /// Number of blocks cancelled through this service
+        pub cancelled: Arc<AtomicUsize>,
+
+        /// Number of upcoming settings reads that fail
+        pub settings_failures: Arc<AtomicUsize>,
+
+        /// Number of blocks asked for through `get_blocks`
+        pub blocks_fetched: Arc<AtomicUsize>,
+
+        /// Other settings to report, by key
+        pub settings: HashMap<String, String>,
+
+        /// Blocks the validator doesn't have; asking for any of them fails the whole request
+        pub unknown_blocks: HashSet<BlockId>,
+
+        /// Settings to report as of specific blocks, over the ones above
+        pub settings_at: HashMap<BlockId, HashMap<String, String>>,
+    }
+
+    impl MockService {
+        /// Create a service whose chain only holds the genesis block, and whose settings list the
+        /// given peers under the default peers setting
+        pub fn new(peers: Vec<PeerId>) -> Self {
+            MockService {
+                // Create genesis block (but with actual ID)
+                chain: vec![mock_block_id(0)],
+                blocks: HashMap::new(),
+                peers,
+                peers_setting_key: DEFAULT_PEERS_SETTING_KEY.into(),
+                finalized: Default::default(),
+                cancelled: Default::default(),
+                settings_failures: Default::default(),
+                blocks_fetched: Default::default(),
+                settings: HashMap::new(),
+                unknown_blocks: HashSet::new(),
+                settings_at: HashMap::new(),
+            }
+        }
+
+        /// Serialize the chain into JSON, and write to a file
+        fn write_chain(&self) {
+            let mut block_file = File::create(BLOCK_FILE).unwrap();
+            let block_bytes: Vec<Vec<u8>> = self
+                .chain
+                .iter()
+                .map(|block: &BlockId| -> Vec<u8> { Vec::<u8>::from(block.clone()) })
+                .collect();
+
+            let ser_blocks = serde_json::to_string(&block_bytes).unwrap();
+            block_file.write_all(&ser_blocks.into_bytes()).unwrap();
+        }
+    }
+
+    impl Service for MockService {
+        fn send_to(
+            &mut self,
+            _peer: &PeerId,
+            _message_type: &str,
+            _payload: Vec<u8>,
+        ) -> Result<(), Error> {
+            Ok(())
+        }
+        fn broadcast(&mut self, _message_type: &str, _payload: Vec<u8>) -> Result<(), Error> {
+            Ok(())
+        }
+        fn initialize_block(&mut self, _previous_id: Option<BlockId>) -> Result<(), Error> {
+            Ok(())
+        }
+        fn summarize_block(&mut self) -> Result<Vec<u8>, Error> {
+            Ok(Default::default())
+        }
+        fn finalize_block(&mut self, _data: Vec<u8>) -> Result<BlockId, Error> {
+            self.finalized.fetch_add(1, Ordering::SeqCst);
+            Ok(Default::default())
+        }
+        fn cancel_block(&mut self) -> Result<(), Error> {
+            self.cancelled.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+        fn check_blocks(&mut self, _priority: Vec<BlockId>) -> Result<(), Error> {
+            Ok(())
+        }
+        fn commit_block(&mut self, block_id: BlockId) -> Result<(), Error> {
+            self.chain.push(block_id);
+            self.write_chain();
+            Ok(())
+        }
+        fn ignore_block(&mut self, _block_id: BlockId) -> Result<(), Error> {
+            Ok(())
+        }
+        fn fail_block(&mut self, _block_id: BlockId) -> Result<(), Error> {
+            Ok(())
+        }
+        fn get_blocks(
+            &mut self,
+            block_ids: Vec<BlockId>,
+        ) -> Result<HashMap<BlockId, Block>, Error> {
+            self.blocks_fetched
+                .fetch_add(block_ids.len(), Ordering::SeqCst);
+            if let Some(id) = block_ids
+                .iter()
+                .find(|id| self.unknown_blocks.contains(*id))
+            {
+                return Err(Error::UnknownBlock(hex::encode(id)));
+            }
+            let mut res = HashMap::new();
+            for id in &block_ids {
+                if let Some(block) = self.blocks.get(id) {
+                    res.insert(id.clone(), block.clone());
+                    continue;
+                }
+                let index = self
+                    .chain
+                    .iter()
+                    .position(|val| val == id)
+                    .unwrap_or(self.chain.len());
+                res.insert(id.clone(), mock_block(index as u64));
+            }
+            Ok(res)
+        }
+        fn get_chain_head(&mut self) -> Result<Block, Error> {
+            if let Some(block) = self.blocks.get(self.chain.last().unwrap()) {
+                return Ok(block.clone());
+            }
+            let prev_num = self.chain.len().checked_sub(2).unwrap_or(0);
+            Ok(Block {
+                block_id: self.chain.last().unwrap().clone(),
+                previous_id: self.chain.get(prev_num).unwrap().clone(),
+                signer_id: PeerId::from(vec![]),
+                block_num: self.chain.len().checked_sub(1).unwrap_or(0) as u64,
+                payload: vec![],
+                summary: vec![],
+            })
+        }
+        fn get_settings(
+            &mut self,
+            block_id: BlockId,
+            _settings: Vec<String>,
+        ) -> Result<HashMap<String, String>, Error> {
+            if self.settings_failures.load(Ordering::SeqCst) > 0 {
+                self.settings_failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(Error::ReceiveError("Validator unavailable".into()));
+            }
+            let mut settings = self.settings.clone();
+            let peers: Vec<String> = self.peers.iter().map(hex::encode).collect();
+            settings.insert(
+                self.peers_setting_key.clone(),
+                serde_json::to_string(&peers).unwrap(),
+            );
+            if let Some(overrides) = self.settings_at.get(&block_id) {
+                settings.extend(overrides.clone());
+            }
+            Ok(settings)
+        }
+        fn get_state(
+            &mut self,
+            _block_id: BlockId,
+            _addresses: Vec<String>,
+        ) -> Result<HashMap<String, Vec<u8>>, Error> {
+            Ok(Default::default())
+        }
+    }
+
+    /// Create a node for a 4-node network
+    fn mock_node() -> PbftNode {
+        let service: Box<MockService> = Box::new(MockService::new(mock_config(4).peers));
+        let cfg = mock_config(4);
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        node.set_self_send(false);
+        node
+    }
+
+    /// Have every other voting peer connect to the node, so that a primary can publish blocks
+    fn connect_peers(node: &mut PbftNode, state: &mut PbftState) {
+        for peer in state.peer_ids.clone() {
+            if peer != state.id {
+                node.on_peer_connected(peer, state);
+            }
+        }
+    }
+
+    /// Create a node whose service hands out the given chain of full blocks, on top of a genesis
+    /// block
+    fn mock_chain_node(cfg: &PbftConfig, blocks: &[Block]) -> PbftNode {
+        let service = Box::new(MockService {
+            chain: std::iter::once(mock_block_id(0))
+                .chain(blocks.iter().map(|b| b.block_id.clone()))
+                .collect(),
+            blocks: blocks
+                .iter()
+                .map(|b| (b.block_id.clone(), b.clone()))
+                .collect(),
+            ..MockService::new(mock_config(4).peers)
+        });
+        let mut node = PbftNode::new(cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        node.set_self_send(false);
+        node
+    }
+
+    /// Create a chain of blocks `1..=len`, where each block after the first carries a valid seal
+    fn mock_sealed_chain(len: u64) -> Vec<Block> {
+        let cfg = mock_config(4);
+        let mut builder = mock_node();
+        let mut builder_state = PbftState::new(vec![1], 0, &cfg);
+        let mut blocks = vec![mock_block(1)];
+        for i in 2..=len {
+            builder_state.seq_num = i;
+            blocks.push(mock_block_with_seal(i, &mut builder, &mut builder_state));
+        }
+        blocks
+    }
+
+    /// Checks the stand-in signatures made by `mock_sign`
+    ///
+    /// The peers of a mock network have IDs like `[1]`, which aren't public keys that anything
+    /// could be signed with, so tests sign votes with a digest of the signer's ID instead.
+    struct MockVerifier;
+
+    impl SignatureVerifier for MockVerifier {
+        fn verify(
+            &self,
+            signature: &[u8],
+            message: &[u8],
+            public_key: &[u8],
+        ) -> Result<bool, PbftError> {
+            Ok(signature == mock_signature(message, public_key).as_slice())
+        }
+    }
+
+    /// The stand-in signature of `message` by `signer`, as checked by `MockVerifier`
+    fn mock_signature(message: &[u8], signer: &[u8]) -> Vec<u8> {
+        hash_sha256(&[signer, message].concat())
+    }
+
+    /// Give a message the signed header the validator would give it, as if it came from `signer`
+    fn mock_sign(message: &mut ParsedMessage, signer: &[u8]) {
+        let mut header = ConsensusPeerMessageHeader::new();
+        header.set_signer_id(signer.to_vec());
+        header.set_content_sha512(hash_sha512(&message.message_bytes));
+        message.header_bytes = header.write_to_bytes().unwrap();
+        message.header_signature = mock_signature(&message.header_bytes, signer);
+        message.from_self = false;
+    }
+
+    /// Create a signed vote for a consensus seal, of any type, as if it came from `signer`
+    fn mock_seal_vote(
+        msg_type: &PbftMessageType,
+        view: u64,
+        seq_num: u64,
+        block_id: &[u8],
+        signer: u8,
+    ) -> PbftSignedCommitVote {
+        let mut block = PbftBlock::new();
+        block.set_block_id(block_id.to_vec());
+        let mut msg = PbftMessage::new();
+        msg.set_info(make_msg_info(msg_type, view, seq_num, vec![signer]));
+        msg.set_block(block);
+
+        let mut message = ParsedMessage::from_pbft_message(msg);
+        mock_sign(&mut message, &[signer]);
+
+        let mut vote = PbftSignedCommitVote::new();
+        vote.set_header_bytes(message.header_bytes);
+        vote.set_header_signature(message.header_signature);
+        vote.set_message_bytes(message.message_bytes);
+        vote
+    }
+
+    /// Create a deterministic BlockId hash based on a block number
+    fn mock_block_id(num: u64) -> BlockId {
+        BlockId::from(hash_sha256(
+            format!("I'm a block with block num {}", num).as_bytes(),
+        ))
+    }
+
+    /// Create a mock Block, including only the BlockId, the BlockId of the previous block, and the
+    /// block number
+    fn mock_block(num: u64) -> Block {
+        Block {
+            block_id: mock_block_id(num),
+            previous_id: mock_block_id(num.saturating_sub(1)),
+            signer_id: PeerId::from(vec![]),
+            block_num: num,
+            payload: vec![],
+            summary: vec![],
+        }
+    }
+
+    /// Creates a block with a valid consensus seal for the previous block
+    fn mock_block_with_seal(num: u64, node: &mut PbftNode, state: &mut PbftState) -> Block {
+        mock_block_with_seal_from(num, node, state, &[0, 1, 2])
+    }
+
+    /// Creates a block with a valid consensus seal for the previous block, with votes from the
+    /// given peers
+    fn mock_block_with_seal_from(
+        num: u64,
+        node: &mut PbftNode,
+        state: &mut PbftState,
+        voters: &[u8],
+    ) -> Block {
+        let head = mock_block(num - 1);
+        let mut block = mock_block(num);
+        block.summary = vec![1, 2, 3];
+
+        for &i in voters {
+            let mut info = PbftMessageInfo::new();
+            info.set_msg_type("Commit".into());
+            info.set_view(state.view);
+            info.set_seq_num(num - 1);
+            info.set_signer_id(vec![i]);
+
+            let mut block = PbftBlock::new();
+            block.set_block_id(head.block_id.clone());
+
+            let mut msg = PbftMessage::new();
+            msg.set_info(info);
+            msg.set_block(block);
+
+            let mut message = ParsedMessage::from_pbft_message(msg);
+            mock_sign(&mut message, &[i]);
+
+            node.msg_log.add_message(message, state).unwrap();
+        }
+
+        block.payload = node.build_seal(state, vec![1, 2, 3]).unwrap();
+
+        block
+    }
+
+    /// Creates a block with a consensus seal for the previous block, with votes signed by the
+    /// given number of freshly generated keys, for checks that use real signatures; returns the
+    /// block and the voters' public keys
+    fn mock_block_with_keyed_seal(num: u64, voters: usize) -> (Block, Vec<PeerId>) {
+        mock_block_with_keyed_votes(num, voters, &PbftMessageType::Commit, num - 1)
+    }
+
+    /// Like `mock_block_with_keyed_seal`, but with votes of the given type and sequence number
+    fn mock_block_with_keyed_votes(
+        num: u64,
+        voters: usize,
+        msg_type: &PbftMessageType,
+        seq_num: u64,
+    ) -> (Block, Vec<PeerId>) {
+        let context = create_context("secp256k1").unwrap();
+        let head = mock_block(num - 1);
+        let mut block = mock_block(num);
+        block.summary = vec![1, 2, 3];
+
+        let mut keys = vec![];
+        let votes: Vec<_> = (0..voters)
+            .map(|_| {
+                let key = context.new_random_private_key().unwrap();
+                let pub_key = context.get_public_key(&*key).unwrap().as_slice().to_vec();
+
+                let mut info = PbftMessageInfo::new();
+                info.set_msg_type(String::from(msg_type));
+                info.set_seq_num(seq_num);
+                info.set_signer_id(pub_key.clone());
+                let mut pbft_block = PbftBlock::new();
+                pbft_block.set_block_id(head.block_id.clone());
+                let mut msg = PbftMessage::new();
+                msg.set_info(info);
+                msg.set_block(pbft_block);
+                let message_bytes = msg.write_to_bytes().unwrap();
+
+                let mut header = ConsensusPeerMessageHeader::new();
+                header.set_signer_id(pub_key.clone());
+                header.set_content_sha512(hash_sha512(&message_bytes));
+                let header_bytes = header.write_to_bytes().unwrap();
+
+                let mut vote = PbftSignedCommitVote::new();
+                vote.set_header_signature(
+                    hex::decode(context.sign(&header_bytes, &*key).unwrap()).unwrap(),
+                );
+                vote.set_header_bytes(header_bytes);
+                vote.set_message_bytes(message_bytes);
+                keys.push(pub_key);
+                vote
+            })
+            .collect();
+
+        let mut seal = PbftSeal::new();
+        seal.set_version(SEAL_VERSION);
+        seal.set_summary(block.summary.clone());
+        seal.set_previous_id(head.block_id.clone());
+        seal.set_previous_commit_votes(RepeatedField::from(votes));
+        block.payload = seal.write_to_bytes().unwrap();
+
+        (block, keys)
+    }
+
+    /// Create a mock serialized PbftMessage
+    fn mock_msg(
+        msg_type: &PbftMessageType,
+        view: u64,
+        seq_num: u64,
+        block: Block,
+        from: PeerId,
+    ) -> ParsedMessage {
+        let info = make_msg_info(&msg_type, view, seq_num, from);
+
+        let mut pbft_msg = PbftMessage::new();
+        pbft_msg.set_info(info);
+        pbft_msg.set_block(pbft_block_from_block(block.clone()));
+
+        ParsedMessage::from_pbft_message(pbft_msg)
+    }
+
+    /// Wrap a message in the `PeerMessage` update the validator would deliver it in
+    fn peer_update(msg: &ParsedMessage) -> Update {
+        let signer_id = msg.info().get_signer_id().to_vec();
+        let message = PeerMessage {
+            header: PeerMessageHeader {
+                signer_id: signer_id.clone(),
+                content_sha512: hash_sha512(&msg.message_bytes),
+                message_type: msg.info().get_msg_type().into(),
+                ..Default::default()
+            },
+            content: msg.message_bytes.clone(),
+            ..Default::default()
+        };
+        Update::PeerMessage(message, signer_id)
+    }
+
+    /// Create a node with the given configuration, as it would be started by the engine
+    fn node_from_config(cfg: &PbftConfig, node_id: PeerId) -> (PbftNode, PbftState) {
+        let service = Box::new(MockService {
+            peers_setting_key: cfg.peers_setting_key.clone(),
+            ..MockService::new(cfg.peers.clone())
+        });
+        let state = PbftState::new(node_id, 0, cfg);
+        let mut node = PbftNode::new(cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        (node, state)
+    }
+
+    /// Reconstruct a node from its configuration, and feed it the updates that were recorded at
+    /// the given path, in order
+    fn replay(path: &Path, cfg: &PbftConfig, node_id: PeerId) -> (PbftNode, PbftState) {
+        let (mut node, mut state) = node_from_config(cfg, node_id);
+        for recorded in read_recording(path).unwrap() {
+            if recorded.update == UpdateRecord::Tick {
+                node.on_tick(&mut state);
+                continue;
+            }
+            node.apply_update(Update::try_from(recorded.update).unwrap(), &mut state)
+                .unwrap_or_else(|err| {
+                    handle_pbft_err(err);
+                    true
+                });
+        }
+        (node, state)
+    }
+
+    fn handle_pbft_err(e: PbftError) {
+        match e {
+            PbftError::Timeout => (),
+            PbftError::WrongNumMessages(_, _, _) | PbftError::NotReadyForMessage => {
+                println!("{}", e)
+            }
+            _ => panic!("{}", e),
+        }
+    }
+
+    /// Make sure that a block for the current sequence number that doesn't build on the chain
+    /// head is rejected and starts a view change, both right after startup (when the head comes
+    /// from the validator) and after a commit (when it's the last block this node committed)
+    #[test]
+    fn block_new_forked() {
+        let cfg = mock_config(4);
+
+        // Block 1 isn't sealed, so a fork of it could have come from anyone; it's dropped, but
+        // doesn't start a view change
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+        let mut forked = mock_block(1);
+        forked.previous_id = mock_block_id(7);
+        forked.signer_id = state.get_primary_id();
+        match node.on_block_new(forked, &mut state) {
+            Err(PbftError::ForkedBlock(_, ref previous_id)) => {
+                assert_eq!(previous_id, &mock_block_id(7))
+            }
+            res => panic!("Expected ForkedBlock error, got {:?}", res),
+        }
+        assert_eq!(state.working_block, None);
+        assert_eq!(state.mode, PbftMode::Normal);
+
+        // Block 1 was committed, so block 2 must build on it rather than on the validator's head.
+        // These forks have valid seals (for a block 1 this node didn't commit).
+        let sealed_fork = |voters: &[u8], signer: u8| {
+            let mut builder_state = PbftState::new(vec![], 1, &cfg);
+            builder_state.seq_num = 2;
+            let mut block =
+                mock_block_with_seal_from(2, &mut mock_node(), &mut builder_state, voters);
+            block.signer_id = vec![signer];
+            block
+        };
+        let forked_node = || {
+            let mut node = mock_node();
+            let mut state = PbftState::new(vec![1], 1, &cfg);
+            state.last_committed_block = Some(mock_block_id(9));
+            node.msg_log
+                .add_consensus_seal(mock_block_id(9), 1, PbftSeal::new())
+                .unwrap();
+            (node, state)
+        };
+
+        // A fork from a node other than the primary is dropped without a view change
+        let (mut node, mut state) = forked_node();
+        match node.on_block_new(sealed_fork(&[0, 1, 3], 2), &mut state) {
+            Err(PbftError::ForkedBlock(_, _)) => {}
+            res => panic!("Expected ForkedBlock error, got {:?}", res),
+        }
+        assert_eq!(state.working_block, None);
+        assert_eq!(state.mode, PbftMode::Normal);
+
+        // A fork from the primary starts a view change
+        let (mut node, mut state) = forked_node();
+        match node.on_block_new(sealed_fork(&[1, 2, 3], 0), &mut state) {
+            Err(PbftError::ForkedBlock(_, _)) => {}
+            res => panic!("Expected ForkedBlock error, got {:?}", res),
+        }
+        assert_eq!(state.working_block, None);
+        assert_eq!(state.mode, PbftMode::ViewChanging);
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::ForkedBlock)
+        );
+
+        state.last_committed_block = Some(mock_block_id(1));
+        assert!(node.extends_chain_head(&mock_block(2), &state).unwrap());
+    }
+
+    /// Make sure that receiving a `BlockNew` update works as expected for block #1
+    #[test]
+    fn block_new_initial() {
+        // NOTE: Special case for primary node
+        let mut node0 = mock_node();
+        let cfg = mock_config(4);
+        let mut state0 = PbftState::new(vec![0], 0, &cfg);
+        node0.on_block_new(mock_block(1), &mut state0).unwrap();
+        assert_eq!(state0.phase, PbftPhase::PrePreparing);
+        assert_eq!(state0.seq_num, 1);
+        assert_eq!(
+            state0.working_block,
+            Some(pbft_block_from_block(mock_block(1)))
+        );
+
+        // Try the next block
+        let mut node1 = mock_node();
+        let mut state1 = PbftState::new(vec![], 0, &cfg);
+        node1
+            .on_block_new(mock_block(1), &mut state1)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state1.phase, PbftPhase::PrePreparing);
+        assert_eq!(
+            state1.working_block,
+            Some(pbft_block_from_block(mock_block(1)))
+        );
+    }
+
+    #[test]
+    fn block_new_first_10_blocks() {
+        let mut node = mock_node();
+        let cfg = mock_config(4);
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+
+        let block_0_id = mock_block_id(0);
+
+        // Assert starting state
+        let head = node.service.get_chain_head().unwrap();
+        assert_eq!(head.block_num, 0);
+        assert_eq!(head.block_id, block_0_id);
+        assert_eq!(head.previous_id, block_0_id);
+
+        assert_eq!(state.id, vec![0]);
+        assert_eq!(state.view, 0);
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+        assert_eq!(state.mode, PbftMode::Normal);
+        assert_eq!(state.peer_ids, (0..4).map(|i| vec![i]).collect::<Vec<_>>());
+        assert_eq!(state.f, 1);
+        assert_eq!(state.forced_view_change_period, 30);
+        assert_eq!(state.working_block, None);
+        assert!(state.is_primary());
+
+        // Handle the first block and assert resulting state
+        node.on_block_new(mock_block(1), &mut state).unwrap();
+
+        let head = node.service.get_chain_head().unwrap();
+        assert_eq!(head.block_num, 0);
+        assert_eq!(head.block_id, block_0_id);
+        assert_eq!(head.previous_id, block_0_id);
+
+        assert_eq!(state.id, vec![0]);
+        assert_eq!(state.seq_num, 1);
+        assert_eq!(state.view, 0);
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+        assert_eq!(state.mode, PbftMode::Normal);
+        assert_eq!(state.peer_ids, (0..4).map(|i| vec![i]).collect::<Vec<_>>());
+        assert_eq!(state.f, 1);
+        assert_eq!(state.forced_view_change_period, 30);
+        assert_eq!(
+            state.working_block,
+            Some(pbft_block_from_block(mock_block(1)))
+        );
+        assert!(state.is_primary());
+
+        state.seq_num += 1;
+
+        // Handle the rest of the blocks
+        for i in 2..10 {
+            assert_eq!(state.seq_num, i);
+            let block = mock_block_with_seal(i, &mut node, &mut state);
+            node.on_block_new(block.clone(), &mut state).unwrap();
+
+            assert_eq!(state.id, vec![0]);
+            assert_eq!(state.view, 0);
+            assert_eq!(state.phase, PbftPhase::PrePreparing);
+            assert_eq!(state.mode, PbftMode::Normal);
+            assert_eq!(state.peer_ids, (0..4).map(|i| vec![i]).collect::<Vec<_>>());
+            assert_eq!(state.f, 1);
+            assert_eq!(state.forced_view_change_period, 30);
+            assert_eq!(state.working_block, Some(pbft_block_from_block(block)));
+            assert!(state.is_primary());
+
+            state.seq_num += 1;
+        }
+    }
+
+    /// Make sure that `BlockNew` properly checks the consensus seal.
+    #[test]
+    fn block_new_consensus() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        state.seq_num = 7;
+        let head = mock_block(6);
+        let mut block = mock_block(7);
+        block.summary = vec![1, 2, 3];
+
+        for i in 0..3 {
+            let mut info = PbftMessageInfo::new();
+            info.set_msg_type("Commit".into());
+            info.set_view(0);
+            info.set_seq_num(6);
+            info.set_signer_id(vec![i]);
+
+            let mut block = PbftBlock::new();
+            block.set_block_id(head.block_id.clone());
+
+            let mut msg = PbftMessage::new();
+            msg.set_info(info);
+            msg.set_block(block);
+
+            let mut message = ParsedMessage::from_pbft_message(msg);
+            mock_sign(&mut message, &[i]);
+
+            node.msg_log.add_message(message, &state).unwrap();
+        }
+
+        let seal = node.build_seal(&state, vec![1, 2, 3]).unwrap();
+        block.payload = seal;
+
+        node.on_block_new(block, &mut state).unwrap();
+    }
+
+    /// Verify a seal with 30 votes serially, across threads, and across more threads than the
+    /// limiter has slots for, and make sure the results agree and that a single bad vote fails the
+    /// whole seal either way
+    #[test]
+    fn verify_consensus_votes_parallel() {
+        let verifier = signing::verifier_for("secp256k1").unwrap();
+        let (block, _) = mock_block_with_keyed_seal(2, 30);
+        let mut seal = parse_seal(&block).unwrap();
+
+        let serial = PbftNode::verify_consensus_votes(
+            &seal,
+            1,
+            Some(&verifier),
+            HashAlgorithm::default(),
+            1,
+            None,
+        )
+        .unwrap();
+        let parallel = PbftNode::verify_consensus_votes(
+            &seal,
+            1,
+            Some(&verifier),
+            HashAlgorithm::default(),
+            4,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(serial.len(), 30);
+        assert_eq!(serial, parallel);
+
+        // With fewer slots than threads, the extra threads wait their turn instead of failing
+        let limiter = VerificationLimiter::new(2);
+        let limited = PbftNode::verify_consensus_votes(
+            &seal,
+            1,
+            Some(&verifier),
+            HashAlgorithm::default(),
+            4,
+            Some(&limiter),
+        )
+        .unwrap();
+        assert_eq!(serial, limited);
+        assert_eq!(limiter.active(), 0);
+
+        seal.mut_previous_commit_votes()[17].set_header_signature(vec![0; 64]);
+        assert!(PbftNode::verify_consensus_votes(
+            &seal,
+            1,
+            Some(&verifier),
+            HashAlgorithm::default(),
+            1,
+            None
+        )
+        .is_err());
+        assert!(PbftNode::verify_consensus_votes(
+            &seal,
+            1,
+            Some(&verifier),
+            HashAlgorithm::default(),
+            4,
+            None
+        )
+        .is_err());
+    }
+
+    /// Benchmark verifying a seal with 30 votes across threads against verifying it serially;
+    /// on a machine with more than one core, the threads have to come out ahead
+    ///
+    /// Timing depends on the machine and how busy it is, so this only runs when asked for (with
+    /// `cargo test -- --ignored`).
+    #[test]
+    #[ignore]
+    fn bench_verify_consensus_votes() {
+        const ROUNDS: u32 = 20;
+        let verifier = signing::verifier_for("secp256k1").unwrap();
+        let (block, _) = mock_block_with_keyed_seal(2, 30);
+        let seal = parse_seal(&block).unwrap();
+        let time = |threads| {
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                PbftNode::verify_consensus_votes(
+                    &seal,
+                    1,
+                    Some(&verifier),
+                    HashAlgorithm::default(),
+                    threads,
+                    None,
+                )
+                .unwrap();
+            }
+            start.elapsed() / ROUNDS
+        };
+
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        let threads = cores.min(4);
+        let serial = time(1);
+        let parallel = time(threads);
+        if threads > 1 {
+            assert!(
+                parallel < serial,
+                "Verifying 30 votes took {:?} with {} threads, but only {:?} serially",
+                parallel,
+                threads,
+                serial
+            );
+        }
+    }
+
+    /// Make sure that each way a seal can be invalid is reported as the right kind of failure
+    #[test]
+    fn explain_seal_failure() {
+        let cfg = mock_config(4);
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        state.seq_num = 7;
+        let mut builder = mock_node();
+        let block = mock_block_with_seal(7, &mut builder, &mut state);
+        let seal: PbftSeal = protobuf::parse_from_bytes(&block.payload).unwrap();
+        let with_seal = |seal: &PbftSeal| {
+            let mut block = block.clone();
+            block.payload = seal.write_to_bytes().unwrap();
+            block
+        };
+
+        let mut node = mock_node();
+        assert_eq!(node.explain_seal_failure(&block, &state), None);
+
+        let mut malformed = block.clone();
+        malformed.payload = vec![0xff, 0xff];
+        match node.explain_seal_failure(&malformed, &state) {
+            Some(SealFailure::Malformed(_)) => {}
+            res => panic!("Expected Malformed, got {:?}", res),
+        }
+
+        let mut unlinked = block.clone();
+        unlinked.previous_id = mock_block_id(42);
+        assert_eq!(
+            node.explain_seal_failure(&unlinked, &state),
+            Some(SealFailure::PreviousIdMismatch(
+                mock_block_id(6),
+                mock_block_id(42)
+            ))
+        );
+
+        let mut resummarized = block.clone();
+        resummarized.summary = vec![4, 5, 6];
+        assert_eq!(
+            node.explain_seal_failure(&resummarized, &state),
+            Some(SealFailure::SummaryMismatch(vec![1, 2, 3], vec![4, 5, 6]))
+        );
+
+        let mut bad_signature = seal.clone();
+        bad_signature.mut_previous_commit_votes()[1].set_header_signature(vec![0; 64]);
+        match node.explain_seal_failure(&with_seal(&bad_signature), &state) {
+            Some(SealFailure::BadVote(1, _)) => {}
+            res => panic!("Expected BadVote(1, _), got {:?}", res),
+        }
+
+        // Node 0 published the block, so its vote isn't expected
+        let mut own_vote = block.clone();
+        own_vote.signer_id = vec![0];
+        assert_eq!(
+            node.explain_seal_failure(&own_vote, &state),
+            Some(SealFailure::UnknownVoters(vec![vec![0]]))
+        );
+
+        let mut too_few = seal.clone();
+        too_few.set_previous_commit_votes(RepeatedField::from(
+            seal.get_previous_commit_votes()[..1].to_vec(),
+        ));
+        assert_eq!(
+            node.explain_seal_failure(&with_seal(&too_few), &state),
+            Some(SealFailure::InsufficientVotes(2, 1))
+        );
+    }
+
+    /// Make sure that each way a seal can fail verification is reported with its own error, so
+    /// that a faulty primary can be told apart from a problem on this node
+    #[test]
+    fn seal_verification_errors() {
+        let cfg = mock_config(4);
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        state.seq_num = 7;
+        let mut builder = mock_node();
+        let block = mock_block_with_seal(7, &mut builder, &mut state);
+        let seal: PbftSeal = protobuf::parse_from_bytes(&block.payload).unwrap();
+        let mut node = mock_node();
+
+        let mut unlinked = block.clone();
+        unlinked.previous_id = mock_block_id(42);
+        match node.verify_consensus_seal(&unlinked, &mut state) {
+            Err(PbftError::SealPreviousIdMismatch(seal_id, block_id)) => {
+                assert_eq!(seal_id, mock_block_id(6));
+                assert_eq!(block_id, mock_block_id(42));
+            }
+            res => panic!("Expected SealPreviousIdMismatch, got {:?}", res),
+        }
+
+        let mut resummarized = block.clone();
+        resummarized.summary = vec![4, 5, 6];
+        match node.verify_consensus_seal(&resummarized, &mut state) {
+            Err(PbftError::SealSummaryMismatch(seal_summary, block_summary)) => {
+                assert_eq!(seal_summary, vec![1, 2, 3]);
+                assert_eq!(block_summary, vec![4, 5, 6]);
+            }
+            res => panic!("Expected SealSummaryMismatch, got {:?}", res),
+        }
+
+        // Node 0 published the block, so its vote isn't expected
+        let mut own_vote = block.clone();
+        own_vote.signer_id = vec![0];
+        match node.verify_consensus_seal(&own_vote, &mut state) {
+            Err(PbftError::UnknownVoters(ids)) => assert_eq!(ids, vec![vec![0]]),
+            res => panic!("Expected UnknownVoters, got {:?}", res),
+        }
+
+        let mut too_few = seal.clone();
+        too_few.set_previous_commit_votes(RepeatedField::from(
+            seal.get_previous_commit_votes()[..1].to_vec(),
+        ));
+        let mut too_few_block = block.clone();
+        too_few_block.payload = too_few.write_to_bytes().unwrap();
+        match node.verify_consensus_seal(&too_few_block, &mut state) {
+            Err(PbftError::InsufficientVotes(2, 1)) => {}
+            res => panic!("Expected InsufficientVotes(2, 1), got {:?}", res),
+        }
+
+        assert!(node.verify_consensus_seal(&block, &mut state).is_ok());
+    }
+
+    /// Make sure that turning off `verify_signatures` lets a seal with invalid vote signatures
+    /// through, while its structure is still checked
+    #[test]
+    fn verify_signatures_disabled() {
+        let mut cfg = mock_config(4);
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        state.seq_num = 7;
+
+        let mut builder = mock_node();
+        let mut block = mock_block_with_seal(7, &mut builder, &mut state);
+
+        // Replace every vote's signature with garbage
+        let mut seal: PbftSeal = protobuf::parse_from_bytes(&block.payload).unwrap();
+        for vote in seal.mut_previous_commit_votes().iter_mut() {
+            vote.set_header_signature(vec![0; 64]);
+        }
+        block.payload = seal.write_to_bytes().unwrap();
+
+        let mut node = mock_node();
+        assert!(node.verify_consensus_seal(&block, &mut state).is_err());
+
+        cfg.verify_signatures = false;
+        let mut node = mock_chain_node(&cfg, &[]);
+        assert!(node.verify_consensus_seal(&block, &mut state).is_ok());
+
+        // Structural problems are still caught
+        let mut unlinked = block.clone();
+        unlinked.previous_id = mock_block_id(42);
+        assert!(node.verify_consensus_seal(&unlinked, &mut state).is_err());
+    }
+
+    /// Make sure that a vote whose header has a malformed public key fails verification with an
+    /// error, instead of a panic
+    #[test]
+    fn malformed_vote_public_key() {
+        let cfg = mock_config(4);
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        state.seq_num = 7;
+        let block = mock_block_with_seal(7, &mut mock_node(), &mut state);
+
+        // The IDs of a mock network's peers aren't valid public keys for the real verifier
+        let service = Box::new(MockService::new(cfg.peers.clone()));
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        match node.verify_consensus_seal(&block, &mut state) {
+            Err(PbftError::InvalidPublicKey(ref key, _)) if *key == [0] => {}
+            res => panic!("Expected InvalidPublicKey, got {:?}", res),
+        }
+    }
+
+    /// Make sure that a seal vote whose header was signed by a different node than the one its
+    /// message names is rejected, even though the signature itself is valid
+    #[test]
+    fn seal_vote_signer_mismatch() {
+        let cfg = mock_config(4);
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        state.seq_num = 7;
+        let mut block = mock_block_with_seal(7, &mut mock_node(), &mut state);
+
+        // Node 3 re-signs node 0's vote as its own
+        let mut seal: PbftSeal = protobuf::parse_from_bytes(&block.payload).unwrap();
+        let mut header: ConsensusPeerMessageHeader =
+            protobuf::parse_from_bytes(seal.get_previous_commit_votes()[0].get_header_bytes())
+                .unwrap();
+        header.set_signer_id(vec![3]);
+        let header_bytes = header.write_to_bytes().unwrap();
+        seal.mut_previous_commit_votes()[0]
+            .set_header_signature(mock_signature(&header_bytes, &[3]));
+        seal.mut_previous_commit_votes()[0].set_header_bytes(header_bytes);
+        block.payload = seal.write_to_bytes().unwrap();
+
+        let mut node = mock_node();
+        match node.verify_consensus_seal(&block, &mut state) {
+            Err(PbftError::SignerMismatch(ref header_signer, ref signer))
+                if *signer == [0] && *header_signer == [3] => {}
+            res => panic!("Expected SignerMismatch, got {:?}", res),
+        }
+    }
+
+    /// Make sure that votes from observers don't count toward a seal's quorum
+    #[test]
+    fn seal_observer_votes() {
+        let mut cfg = mock_config(5);
+        cfg.observers = vec![vec![4]];
+        let service = Box::new(MockService::new(cfg.peers.clone()));
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![2], 6, &cfg);
+        assert_eq!(state.f, 1);
+
+        let sealed_by = |voters: &[u8]| {
+            let mut builder_state = PbftState::new(vec![], 6, &mock_config(4));
+            mock_block_with_seal_from(7, &mut mock_node(), &mut builder_state, voters)
+        };
+
+        match node.verify_consensus_seal(&sealed_by(&[0, 4]), &mut state) {
+            Err(PbftError::UnknownVoters(ref voters)) if *voters == [vec![4]] => {}
+            res => panic!("Expected UnknownVoters, got {:?}", res),
+        }
+        match node.explain_seal_failure(&sealed_by(&[0, 4]), &state) {
+            Some(SealFailure::UnknownVoters(ref voters)) if *voters == [vec![4]] => {}
+            res => panic!("Expected UnknownVoters, got {:?}", res),
+        }
+        assert!(node
+            .verify_consensus_seal(&sealed_by(&[0, 1]), &mut state)
+            .unwrap()
+            .is_some());
+    }
+
+    /// Make sure that a node that missed a view change aligns its view with the one a new block
+    /// was proposed in before accepting the block
+    #[test]
+    fn block_new_future_view() {
+        let cfg = mock_config(4);
+
+        let mut builder = mock_node();
+        let mut builder_state = PbftState::new(vec![], 6, &cfg);
+        builder_state.view = 2;
+        let block = mock_block_with_seal(7, &mut builder, &mut builder_state);
+
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![2], 6, &cfg);
+        assert_eq!(state.view, 0);
+        assert!(!state.is_primary());
+
+        node.on_block_new(block, &mut state).unwrap();
+
+        assert_eq!(state.view, 2);
+        assert!(state.is_primary());
+        assert_eq!(
+            state.working_block.as_ref().map(|b| b.get_block_num()),
+            Some(7)
+        );
+        let block_news = node
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::BlockNew, 7);
+        assert_eq!(block_news.len(), 1);
+        assert_eq!(block_news[0].info().get_view(), 2);
+    }
+
+    /// Make sure that messages with timestamps that are too old or too far in the future are
+    /// rejected, and that ones within the allowed age and clock skew are accepted
+    #[test]
+    fn message_age() {
+        let mut cfg = mock_config(4);
+        cfg.max_message_age = Duration::from_secs(60);
+        cfg.max_clock_skew = Duration::from_secs(5);
+        let mut node = mock_chain_node(&cfg, &[]);
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        let now = timing::unix_millis();
+
+        for (peer, timestamp, accepted) in &[
+            (0, now - 3_600_000, false),
+            (1, now + 3_600_000, false),
+            (2, now - 62_000, true),
+            (3, now + 2_000, true),
+        ] {
+            let mut msg = mock_msg(&PbftMessageType::Prepare, 0, 1, mock_block(1), vec![*peer]);
+            msg.info_mut().set_timestamp(*timestamp);
+
+            match node.on_peer_message(msg, &mut state) {
+                Err(PbftError::InvalidTimestamp(_, _)) => assert!(!accepted),
+                Ok(()) => assert!(accepted),
+                Err(err) => panic!("Unexpected error: {}", err),
+            }
+        }
+
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::Prepare, 1)
+                .len(),
+            2
+        );
+
+        // A vote cast longer ago than the maximum age is stamped again when it's re-sent on
+        // request, so it's still accepted
+        let mut old_vote = mock_msg(&PbftMessageType::Commit, 0, 1, mock_block(1), vec![0]);
+        old_vote.info_mut().set_timestamp(now - 3_600_000);
+        let mut voter = mock_node();
+        let mut voter_state = PbftState::new(vec![0], 0, &cfg);
+        voter.msg_log.add_message(old_vote, &voter_state).unwrap();
+
+        let mut info = make_msg_info(&PbftMessageType::VoteRequest, 0, 1, vec![1]);
+        info.set_timestamp(now);
+        let mut request = PbftVoteRequest::new();
+        request.set_info(info);
+        request.set_vote_type("Commit".into());
+        voter
+            .on_peer_message(
+                ParsedMessage::from_vote_request_message(request),
+                &mut voter_state,
+            )
+            .unwrap();
+
+        let (_, resent) = voter.sent_direct[0].clone();
+        assert!(resent.info().get_timestamp() >= now);
+        node.on_peer_message(resent, &mut state).unwrap();
+    }
+
+    /// Make sure that a seal's internal checks return its voters without looking at who the peers
+    /// are, and catch a seal for the wrong block or with a tampered vote
+    #[test]
+    fn seal_check_internal() {
+        let cfg = mock_config(4);
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        state.seq_num = 7;
+        let (block, keys) = mock_block_with_keyed_seal(7, 3);
+        let seal: PbftSeal = protobuf::parse_from_bytes(&block.payload).unwrap();
+
+        let expected: HashSet<Vec<u8>> = keys.iter().cloned().collect();
+        assert_eq!(seal.check_internal(&block).unwrap(), expected);
+
+        // Whether the publisher voted, or the voters are peers at all, is for the caller to check
+        let mut own_vote = block.clone();
+        own_vote.signer_id = keys[0].clone();
+        assert_eq!(seal.check_internal(&own_vote).unwrap(), expected);
+
+        let mut unlinked = block.clone();
+        unlinked.previous_id = mock_block_id(42);
+        match seal.check_internal(&unlinked) {
+            Err(PbftError::SealPreviousIdMismatch(_, _)) => {}
+            res => panic!("Expected SealPreviousIdMismatch, got {:?}", res),
+        }
+
+        let mut tampered = seal.clone();
+        let mut votes = seal.get_previous_commit_votes().to_vec();
+        let mut message: PbftMessage =
+            protobuf::parse_from_bytes(votes[0].get_message_bytes()).unwrap();
+        message.mut_info().set_signer_id(keys[1].clone());
+        votes[0].set_message_bytes(message.write_to_bytes().unwrap());
+        tampered.set_previous_commit_votes(RepeatedField::from(votes));
+        match tampered.check_internal(&block) {
+            Err(PbftError::SignerMismatch(ref header_signer, ref signer))
+                if *signer == keys[1] && *header_signer == keys[0] => {}
+            res => panic!("Expected SignerMismatch, got {:?}", res),
+        }
+
+        // Only Commits for the sealed block's sequence number can seal it
+        let (prepared, _) = mock_block_with_keyed_votes(7, 3, &PbftMessageType::Prepare, 6);
+        let (wrong_seq, _) = mock_block_with_keyed_votes(7, 3, &PbftMessageType::Commit, 7);
+        for block in &[prepared, wrong_seq] {
+            let seal: PbftSeal = protobuf::parse_from_bytes(&block.payload).unwrap();
+            match seal.check_internal(block) {
+                Err(PbftError::InvalidMessage(_)) => {}
+                res => panic!("Expected InvalidMessage, got {:?}", res),
+            }
+        }
+    }
+
+    /// Make sure that verifying a block's seal records how long it took and how many votes it had
+    #[test]
+    fn seal_verification_timing() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        state.seq_num = 7;
+        assert_eq!(node.seal_verification_timing().last(), None);
+
+        let block = mock_block_with_seal(7, &mut node, &mut state);
+        node.on_block_new(block, &mut state).unwrap();
+
+        let timing = node.seal_verification_timing();
+        assert_eq!(timing.count, 1);
+        let (duration, votes) = timing.last().unwrap();
+        assert!(duration > Duration::from_millis(0));
+        assert_eq!(votes, 3);
+        assert_eq!(timing.average(), Some(duration));
+        assert_eq!(timing.average_votes(), Some(3.0));
+    }
+
+    /// Make sure that a seal that was already verified and stored isn't verified again
+    #[test]
+    fn seal_verification_cache() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        state.seq_num = 7;
+
+        let block = mock_block_with_seal(7, &mut node, &mut state);
+        node.on_block_new(block.clone(), &mut state).unwrap();
+        assert_eq!(node.seal_verification_timing().cache_hits, 0);
+        let stored: PbftSeal = node
+            .msg_log
+            .get_consensus_seal_for_block(&block.block_id)
+            .cloned()
+            .unwrap();
+
+        // Verifying the same block again uses the stored seal
+        let seal = node.verify_consensus_seal(&block, &mut state).unwrap();
+        assert_eq!(seal.as_ref(), Some(&stored));
+        assert_eq!(node.seal_verification_timing().cache_hits, 1);
+
+        // A block with a different seal under the same ID is still fully verified
+        let mut other = block.clone();
+        let mut tampered = stored.clone();
+        tampered.set_summary(vec![0xff]);
+        other.payload = tampered.write_to_bytes().unwrap();
+        assert!(node.verify_consensus_seal(&other, &mut state).is_err());
+        assert_eq!(node.seal_verification_timing().cache_hits, 1);
+    }
+
+    /// Make sure that a block that's delivered again after its seal was verified isn't verified or
+    /// handled a second time
+    #[test]
+    fn repeated_block_new() {
+        struct CountingVerifier(AtomicUsize);
+        impl SignatureVerifier for CountingVerifier {
+            fn verify(
+                &self,
+                signature: &[u8],
+                message: &[u8],
+                public_key: &[u8],
+            ) -> Result<bool, PbftError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                MockVerifier.verify(signature, message, public_key)
+            }
+        }
+
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let verifier = Arc::new(CountingVerifier(AtomicUsize::new(0)));
+        node.set_signature_verifier(verifier.clone());
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        state.seq_num = 7;
+
+        let block = mock_block_with_seal(7, &mut node, &mut state);
+        node.on_block_new(block.clone(), &mut state).unwrap();
+        let verified = verifier.0.load(Ordering::SeqCst);
+        assert!(verified > 0);
+        assert_eq!(
+            state.working_block,
+            Some(pbft_block_from_block(block.clone()))
+        );
+
+        state.commit_timeout.stop();
+        node.on_block_new(block, &mut state).unwrap();
+        assert_eq!(verifier.0.load(Ordering::SeqCst), verified);
+        assert_eq!(node.seal_verification_timing().count, 1);
+        assert!(!state.commit_timeout.is_running());
+    }
+
+    /// Make sure that a verify-only node still verifies the seal of each block it gets, but never
+    /// sends a message, builds a block, or becomes primary
+    #[test]
+    fn verify_only() {
+        let mut cfg = mock_config(4);
+        let mut builder_state = PbftState::new(vec![1], 0, &cfg);
+        builder_state.seq_num = 7;
+        let block = mock_block_with_seal(7, &mut mock_node(), &mut builder_state);
+
+        cfg.verify_only = true;
+        let service = Box::new(MockService::new(cfg.peers.clone()));
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+
+        // Node 0 would be the primary of view 0
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        assert!(!state.is_primary());
+        connect_peers(&mut node, &mut state);
+        assert!(!node.block_initialized);
+
+        state.seq_num = 7;
+        node.on_block_new(block.clone(), &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(node.seal_verification_timing().count, 1);
+        assert_eq!(state.working_block, Some(pbft_block_from_block(block)));
+
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state.mode, PbftMode::Normal);
+        state.upgrade_role();
+        assert!(!state.is_primary());
+        assert!(state.check_invariants().is_ok());
+
+        assert!(node.sent_messages.is_empty());
+        assert!(node.sent_direct.is_empty());
+    }
+
+    /// Make sure that a seal with the same signer's vote repeated is rejected as such, and that a
+    /// seal with more votes than there are peers is rejected before its votes are verified
+    #[test]
+    fn duplicate_seal_vote() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![], 0, &cfg);
+        state.seq_num = 7;
+        let mut block = mock_block_with_seal(7, &mut node, &mut state);
+        let mut seal: PbftSeal = protobuf::parse_from_bytes(&block.payload).unwrap();
+        let vote = seal.get_previous_commit_votes()[0].clone();
+        let verifier: Arc<SignatureVerifier> = Arc::new(MockVerifier);
+        let signer = protobuf::parse_from_bytes::<PbftMessage>(vote.get_message_bytes())
+            .unwrap()
+            .get_info()
+            .get_signer_id()
+            .to_vec();
+
+        let mut votes = seal.get_previous_commit_votes().to_vec();
+        votes[1] = vote.clone();
+        seal.set_previous_commit_votes(RepeatedField::from(votes.clone()));
+        block.payload = seal.write_to_bytes().unwrap();
+        node.msg_log.clear();
+        match node.verify_consensus_seal(&block, &mut state) {
+            Err(PbftError::DuplicateVote(ref id)) if *id == signer => {}
+            res => panic!("Expected DuplicateVote, got {:?}", res),
+        }
+        for threads in &[1, 4] {
+            match PbftNode::verify_consensus_votes(
+                &seal,
+                block.block_num - 1,
+                Some(&verifier),
+                HashAlgorithm::default(),
+                *threads,
+                None,
+            ) {
+                Err(PbftError::DuplicateVote(ref id)) if *id == signer => {}
+                res => panic!("Expected DuplicateVote, got {:?}", res),
+            }
+        }
+
+        votes.extend(vec![vote; 2]);
+        seal.set_previous_commit_votes(RepeatedField::from(votes));
+        block.payload = seal.write_to_bytes().unwrap();
+        match node.verify_consensus_seal(&block, &mut state) {
+            Err(PbftError::WrongNumMessages(PbftMessageType::Commit, 4, 5)) => {}
+            res => panic!("Expected WrongNumMessages, got {:?}", res),
+        }
+    }
+
+    /// Make sure that the validator's `BlockCommit` for a block that was committed through catchup
+    /// is a no-op
+    #[test]
+    fn block_commit_after_catchup() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        // Block 7 carries the seal for block 6, which this node is working on
+        state.seq_num = 7;
+        let block = mock_block_with_seal(7, &mut node, &mut state);
+        state.seq_num = 6;
+        state.working_block = Some(pbft_block_from_block(mock_block(6)));
+
+        node.on_block_new(block, &mut state).unwrap();
+        assert_eq!(state.seq_num, 7);
+        assert_eq!(
+            node.catchup_commits.iter().collect::<Vec<_>>(),
+            vec![&mock_block_id(6)]
+        );
+
+        let phase = state.phase.clone();
+        node.on_block_commit(mock_block_id(6), &mut state);
+        assert_eq!(state.seq_num, 7);
+        assert_eq!(state.phase, phase);
+        assert!(node.catchup_commits.is_empty());
+
+        // A commit for a block that isn't expected at all is ignored too
+        node.on_block_commit(mock_block_id(9), &mut state);
+        assert_eq!(state.seq_num, 7);
+        assert_eq!(state.phase, phase);
+    }
+
+    /// Make sure that a node without a working block can catch up using a sealed block, as long as
+    /// the block the seal is for builds on its chain head
+    #[test]
+    fn catchup_without_working_block() {
+        let cfg = mock_config(4);
+        let chain: Vec<_> = (1..6).map(mock_block).collect();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        // Block 7 carries the seal for block 6, which this node doesn't have yet
+        state.seq_num = 7;
+        let block = mock_block_with_seal(7, &mut mock_node(), &mut state);
+        state.seq_num = 6;
+
+        // The chain head is block 4, so block 6 can't be the next block
+        let mut node = mock_chain_node(&cfg, &chain[..4]);
+        match node.on_block_new(block.clone(), &mut state) {
+            Err(PbftError::NoWorkingBlock) => {}
+            res => panic!("Expected NoWorkingBlock, got {:?}", res),
+        }
+        assert_eq!(state.seq_num, 6);
+        assert!(state.working_block.is_none());
+
+        let mut node = mock_chain_node(&cfg, &chain);
+        node.on_block_new(block, &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state.seq_num, 7);
+        assert_eq!(
+            node.catchup_commits.iter().collect::<Vec<_>>(),
+            vec![&mock_block_id(6)]
+        );
+    }
+
+    /// Make sure that catching up with a seal whose votes aren't all for the same block is
+    /// rejected, instead of committing whichever block the first vote is for
+    #[test]
+    fn catchup_mismatched_votes() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+
+        let mut seal = PbftSeal::new();
+        seal.set_previous_id(mock_block_id(1));
+        let votes: Vec<_> = [(0u8, 1), (2, 1), (3, 3)]
+            .iter()
+            .map(|(peer, num)| {
+                let msg = mock_msg(
+                    &PbftMessageType::Commit,
+                    1,
+                    1,
+                    mock_block(*num),
+                    vec![*peer],
+                );
+                let mut vote = PbftSignedCommitVote::new();
+                vote.set_message_bytes(msg.message_bytes);
+                vote
+            })
+            .collect();
+        seal.set_previous_commit_votes(RepeatedField::from_vec(votes));
+
+        let mut block = mock_block(2);
+        block.payload = seal.write_to_bytes().unwrap();
+
+        match node.catchup(&mut state, &block) {
+            Err(PbftError::BlockMismatch(_, _)) => {}
+            res => panic!("Expected BlockMismatch, got {:?}", res),
+        }
+        assert_eq!(state.seq_num, 1);
+        assert_eq!(state.view, 0);
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+        assert!(node.catchup_commits.is_empty());
+    }
+
+    /// Make sure that the same set of commits always makes the same seal, no matter what order they
+    /// were added to the log in
+    #[test]
+    fn deterministic_seal() {
+        let cfg = mock_config(4);
+        let commits: Vec<_> = [0u8, 2, 3]
+            .iter()
+            .map(|i| mock_msg(&PbftMessageType::Commit, 0, 1, mock_block(1), vec![*i]))
+            .collect();
+
+        let mut seals = vec![];
+        for order in &[[0, 1, 2], [2, 0, 1], [1, 2, 0]] {
+            let mut node = mock_node();
+            let mut state = PbftState::new(vec![1], 0, &cfg);
+            state.seq_num = 1;
+            for i in order {
+                node.msg_log
+                    .add_message(commits[*i].clone(), &state)
+                    .unwrap();
+            }
+            state.seq_num = 2;
+            seals.push(node.build_seal(&state, vec![1, 2, 3]).unwrap());
+        }
+
+        assert_eq!(seals[0], seals[1]);
+        assert_eq!(seals[0], seals[2]);
+    }
+
+    /// Make sure that a node that fell several blocks behind catches up to the head in one go when
+    /// it gets a sealed block further ahead
+    #[test]
+    fn catchup_multiple_blocks() {
+        let cfg = mock_config(4);
+        let chain = mock_sealed_chain(6);
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        // The validator has committed blocks 1 and 2, but this node missed blocks 3 to 5
+        let service = Box::new(MockService {
+            chain: std::iter::once(mock_block_id(0))
+                .chain(chain[..2].iter().map(|b| b.block_id.clone()))
+                .collect(),
+            blocks: chain
+                .iter()
+                .map(|b| (b.block_id.clone(), b.clone()))
+                .collect(),
+            ..MockService::new(mock_config(4).peers)
+        });
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        node.set_self_send(false);
+        state.seq_num = 3;
+
+        node.on_block_new(chain[5].clone(), &mut state).unwrap();
+        assert_eq!(state.seq_num, 6);
+        assert_eq!(
+            state.working_block.as_ref().map(|b| b.get_block_num()),
+            Some(6)
+        );
+        assert_eq!(
+            node.catchup_commits.iter().collect::<Vec<_>>(),
+            vec![&mock_block_id(3), &mock_block_id(4), &mock_block_id(5)]
+        );
+    }
+
+    /// Make sure that a node that is far behind catches up a limited number of blocks at a time,
+    /// picking up where it left off each time
+    #[test]
+    fn catchup_in_batches() {
+        let mut cfg = mock_config(4);
+        cfg.max_catchup_blocks_per_loop = 2;
+        let chain = mock_sealed_chain(6);
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        // Blocks 1 to 5 were missed
+        let blocks_fetched = Arc::new(AtomicUsize::new(0));
+        let service = Box::new(MockService {
+            blocks: chain
+                .iter()
+                .map(|b| (b.block_id.clone(), b.clone()))
+                .collect(),
+            blocks_fetched: Arc::clone(&blocks_fetched),
+            ..MockService::new(mock_config(4).peers)
+        });
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        node.set_self_send(false);
+
+        node.on_block_new(chain[5].clone(), &mut state).unwrap();
+        assert_eq!(state.seq_num, 3);
+        assert_eq!(state.catchup_target, Some(mock_block_id(6)));
+
+        // Later batches don't walk back from the target again; they only fetch the target, the
+        // blocks they commit, and the block after them
+        let before = blocks_fetched.load(Ordering::SeqCst);
+        node.continue_catchup(&mut state).unwrap();
+        assert_eq!(state.seq_num, 5);
+        assert_eq!(state.catchup_target, Some(mock_block_id(6)));
+        assert_eq!(blocks_fetched.load(Ordering::SeqCst) - before, 4);
+
+        node.continue_catchup(&mut state).unwrap();
+        assert_eq!(state.seq_num, 6);
+        assert_eq!(state.catchup_target, None);
+        assert_eq!(
+            state.working_block.as_ref().map(|b| b.get_block_num()),
+            Some(6)
+        );
+        let caught_up: Vec<_> = node.catchup_commits.iter().cloned().collect();
+        assert_eq!(caught_up, (1..6).map(mock_block_id).collect::<Vec<_>>());
+
+        // Nothing left to do
+        node.continue_catchup(&mut state).unwrap();
+        assert_eq!(state.seq_num, 6);
+    }
+
+    /// Make sure that a node that sees enough `Commit`s for a later sequence number asks the
+    /// validator for the blocks it's missing, and catches up with them even if some of them are
+    /// unknown to the validator
+    #[test]
+    fn request_missing_blocks() {
+        let cfg = mock_config(4);
+        let chain = mock_sealed_chain(5);
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        // The validator has committed blocks 1 and 2, but never sent this node blocks 3 and 4
+        let service = Box::new(MockService {
+            chain: std::iter::once(mock_block_id(0))
+                .chain(chain[..2].iter().map(|b| b.block_id.clone()))
+                .collect(),
+            blocks: chain
+                .iter()
+                .map(|b| (b.block_id.clone(), b.clone()))
+                .collect(),
+            unknown_blocks: std::iter::once(mock_block_id(99)).collect(),
+            ..MockService::new(mock_config(4).peers)
+        });
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        node.set_self_send(false);
+        state.seq_num = 3;
+        assert!(node.request_missing_blocks(&mut state).unwrap().is_empty());
+
+        // A single peer isn't enough to show that the network has moved on
+        let commit = |from| mock_msg(&PbftMessageType::Commit, 0, 4, chain[3].clone(), from);
+        node.on_peer_message(commit(vec![0]), &mut state).unwrap();
+        assert_eq!(state.highest_seen_seq_num, 0);
+        node.on_peer_message(commit(vec![2]), &mut state).unwrap();
+        assert_eq!(state.highest_seen_seq_num, 4);
+
+        // Another peer's latest Commit is for a block the validator doesn't know about
+        node.latest_commits.insert(vec![3], (4, mock_block_id(99)));
+
+        assert_eq!(
+            node.request_missing_blocks(&mut state).unwrap(),
+            vec![mock_block_id(4), mock_block_id(99), mock_block_id(3)]
+        );
+        assert_eq!(state.seq_num, 4);
+        assert_eq!(
+            state.working_block.as_ref().map(|b| b.get_block_num()),
+            Some(4)
+        );
+        assert!(node.request_missing_blocks(&mut state).unwrap().is_empty());
+    }
+
+    /// Make sure that a node that has fallen further behind than the log keeps messages for still
+    /// finds out from its peers' `Commit`s, and catches up all the way
+    #[test]
+    fn catchup_far_behind() {
+        let cfg = mock_config(4);
+        let chain = mock_sealed_chain(22);
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        let mut node = mock_chain_node(&cfg, &chain);
+        assert_eq!(state.seq_num, 1);
+
+        // The log drops Commits this far ahead, but they still show the network has moved on
+        let commit = |from| mock_msg(&PbftMessageType::Commit, 0, 21, chain[20].clone(), from);
+        for peer in &[0, 2] {
+            match node.on_peer_message(commit(vec![*peer]), &mut state) {
+                Err(PbftError::SeqNumTooFarAhead(21, 1)) => {}
+                res => panic!("Expected SeqNumTooFarAhead, got {:?}", res),
+            }
+        }
+        assert_eq!(state.highest_seen_seq_num, 21);
+
+        assert_eq!(node.request_missing_blocks(&mut state).unwrap().len(), 21);
+        assert_eq!(state.seq_num, 21);
+        assert_eq!(
+            state.working_block.as_ref().map(|b| b.get_block_num()),
+            Some(21)
+        );
+    }
+
+    /// Make sure that no more than `max_catchup_blocks_per_loop` missing blocks are requested at
+    /// once, and that the next call picks up where the last one left off
+    #[test]
+    fn request_missing_blocks_limit() {
+        let cfg = mock_config(4);
+        let chain = mock_sealed_chain(22);
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        let mut node = mock_chain_node(&cfg, &chain);
+        node.max_catchup_blocks_per_loop = 5;
+        node.latest_commits.insert(vec![0], (21, mock_block_id(21)));
+        node.latest_commits.insert(vec![2], (21, mock_block_id(21)));
+        state.highest_seen_seq_num = 21;
+
+        let mut requested = vec![];
+        for _ in 0..4 {
+            let batch = node.request_missing_blocks(&mut state).unwrap();
+            assert_eq!(batch.len(), 5);
+            requested.extend(batch);
+        }
+        assert_eq!(state.seq_num, 1);
+        assert_eq!(node.missing_blocks, vec![mock_block_id(1)]);
+        assert_eq!(node.fetched_blocks.len(), 20);
+
+        requested.extend(node.request_missing_blocks(&mut state).unwrap());
+        assert_eq!(
+            requested,
+            (1..=21).rev().map(mock_block_id).collect::<Vec<_>>()
+        );
+        assert_eq!(state.seq_num, 21);
+        assert!(node.missing_blocks.is_empty());
+        assert!(node.fetched_blocks.is_empty());
+    }
+
+    /// Make sure that votes from an earlier view are ignored and votes from a later view are only
+    /// kept while changing views, but that votes from an earlier view in a seal can still be used
+    /// to catch up
+    #[test]
+    fn vote_view_validation() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        state.seq_num = 6;
+        state.view = 1;
+        state.upgrade_role();
+        let block = mock_block(6);
+
+        for msg_type in &[PbftMessageType::Prepare, PbftMessageType::Commit] {
+            let stale = mock_msg(msg_type, 0, 6, block.clone(), vec![2]);
+            node.on_peer_message(stale, &mut state)
+                .unwrap_or_else(handle_pbft_err);
+            assert!(node
+                .msg_log
+                .get_messages_of_type_seq(msg_type, 6)
+                .is_empty());
+
+            let future = mock_msg(msg_type, 2, 6, block.clone(), vec![2]);
+            match node.on_peer_message(future, &mut state) {
+                Err(PbftError::ViewMismatch(2, 1)) => {}
+                res => panic!("Expected ViewMismatch, got {:?}", res),
+            }
+            assert!(node
+                .msg_log
+                .get_messages_of_type_seq(msg_type, 6)
+                .is_empty());
+        }
+
+        // Stale prepares in a batch are ignored as well
+        let batch = (0..4)
+            .map(|i| mock_msg(&PbftMessageType::Prepare, 0, 6, block.clone(), vec![i]))
+            .collect();
+        node.process_batch(batch, &mut state).unwrap();
+        assert!(node
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::Prepare, 6)
+            .is_empty());
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+
+        // While changing views, votes for the later view wait in the backlog
+        state.mode = PbftMode::ViewChanging;
+        let future = mock_msg(&PbftMessageType::Prepare, 2, 6, block.clone(), vec![2]);
+        node.on_peer_message(future, &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(node.msg_log.backlog_len(), 1);
+        assert!(!node.retry_backlog(&mut state).unwrap());
+        assert_eq!(node.msg_log.backlog_len(), 1);
+        state.mode = PbftMode::Normal;
+
+        // Block 7 carries a seal for block 6 with votes from view 0
+        let mut builder_state = PbftState::new(vec![1], 0, &cfg);
+        builder_state.seq_num = 7;
+        let sealed = mock_block_with_seal(7, &mut mock_node(), &mut builder_state);
+        state.working_block = Some(pbft_block_from_block(block));
+        node.on_block_new(sealed, &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state.seq_num, 7);
+        assert_eq!(state.view, 1);
+    }
+
+    /// Export a valid seal for a block, clear the log, and make sure the block can still be
+    /// committed with the exported seal, while a seal for another block is refused
+    #[test]
+    fn commit_with_external_seal() {
+        let cfg = mock_config(4);
+        let mut node = mock_chain_node(&cfg, &[mock_block(6)]);
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        // Block 7's payload is the seal for block 6
+        state.seq_num = 7;
+        let seal_bytes = mock_block_with_seal(7, &mut node, &mut state).payload;
+        node.msg_log.clear();
+        state.seq_num = 6;
+
+        let wrong_seal = mock_block_with_seal(6, &mut mock_node(), &mut state).payload;
+        assert!(node
+            .commit_with_external_seal(mock_block_id(6), &wrong_seal, &mut state)
+            .is_err());
+        assert!(node
+            .commit_with_external_seal(mock_block_id(6), &[1, 2, 3], &mut state)
+            .is_err());
+
+        // Only the builder's vote is implicit in a block's seal, so 2f votes aren't enough here
+        let mut short_seal: PbftSeal = protobuf::parse_from_bytes(&seal_bytes).unwrap();
+        let votes = short_seal.get_previous_commit_votes()[..2].to_vec();
+        short_seal.set_previous_commit_votes(RepeatedField::from(votes));
+        match node.commit_with_external_seal(
+            mock_block_id(6),
+            &short_seal.write_to_bytes().unwrap(),
+            &mut state,
+        ) {
+            Err(PbftError::InsufficientVotes(3, 2)) => {}
+            res => panic!("Expected InsufficientVotes, got {:?}", res),
+        }
+        assert_eq!(state.seq_num, 6);
+        assert!(state.working_block.is_none());
+
+        node.commit_with_external_seal(mock_block_id(6), &seal_bytes, &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state.seq_num, 7);
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::Commit, 6)
+                .len(),
+            3
+        );
+        assert!(node.catchup_commits.contains(&mock_block_id(6)));
+    }
+
+    /// Make sure that an external seal is refused unless its votes are all `Commit`s for the
+    /// block's sequence number, cast in the same view
+    #[test]
+    fn external_seal_vote_checks() {
+        let cfg = mock_config(4);
+        let mut node = mock_chain_node(&cfg, &[mock_block(6)]);
+        let mut state = PbftState::new(vec![1], 5, &cfg);
+        let seal_of = |votes: &[(PbftMessageType, u64, u64)]| {
+            let mut seal = PbftSeal::new();
+            seal.set_previous_id(mock_block_id(6));
+            seal.set_previous_commit_votes(RepeatedField::from(
+                votes
+                    .iter()
+                    .zip(0..)
+                    .map(|((msg_type, view, seq_num), signer)| {
+                        mock_seal_vote(msg_type, *view, *seq_num, &mock_block_id(6), signer)
+                    })
+                    .collect::<Vec<_>>(),
+            ));
+            seal.write_to_bytes().unwrap()
+        };
+
+        let prepares = seal_of(&[
+            (PbftMessageType::Prepare, 0, 6),
+            (PbftMessageType::Prepare, 0, 6),
+            (PbftMessageType::Prepare, 0, 6),
+        ]);
+        let wrong_seq = seal_of(&[
+            (PbftMessageType::Commit, 0, 5),
+            (PbftMessageType::Commit, 0, 5),
+            (PbftMessageType::Commit, 0, 5),
+        ]);
+        let mixed_views = seal_of(&[
+            (PbftMessageType::Commit, 0, 6),
+            (PbftMessageType::Commit, 0, 6),
+            (PbftMessageType::Commit, 1, 6),
+        ]);
+        for seal_bytes in &[prepares, wrong_seq, mixed_views] {
+            match node.commit_with_external_seal(mock_block_id(6), seal_bytes, &mut state) {
+                Err(PbftError::InvalidMessage(_)) => {}
+                res => panic!("Expected InvalidMessage, got {:?}", res),
+            }
+            assert_eq!(state.seq_num, 6);
+            assert!(state.working_block.is_none());
+        }
+
+        let commits = seal_of(&[
+            (PbftMessageType::Commit, 0, 6),
+            (PbftMessageType::Commit, 0, 6),
+            (PbftMessageType::Commit, 0, 6),
+        ]);
+        node.commit_with_external_seal(mock_block_id(6), &commits, &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state.seq_num, 7);
+    }
+
+    /// Make sure that seals are verified ahead of time only for blocks within
+    /// `seal_prefetch_depth`, and that the results are kept until the chain diverges from them
+    #[test]
+    fn seal_prefetch() {
+        let mut cfg = mock_config(4);
+        cfg.seal_prefetch_depth = 2;
+        let mut node = mock_chain_node(&cfg, &[]);
+        let mut state = PbftState::new(vec![], 0, &cfg);
+
+        let blocks: Vec<Block> = (6..10)
+            .map(|num| {
+                state.seq_num = num;
+                mock_block_with_seal(num, &mut node, &mut state)
+            })
+            .collect();
+        let is_cached = |node: &PbftNode, block: &Block| {
+            node.msg_log
+                .get_consensus_seal_for_block(&block.block_id)
+                .is_some()
+        };
+
+        // Block 5 is already being committed, so none of the new blocks are used for catchup
+        state.seq_num = 5;
+        state.working_block = Some(pbft_block_from_block(mock_block(5)));
+        state.phase = PbftPhase::Finished;
+        for block in &blocks {
+            node.on_block_new(block.clone(), &mut state).unwrap();
+        }
+        assert_eq!(node.seal_verification_timing().count, 2);
+        assert!(is_cached(&node, &blocks[0]));
+        assert!(is_cached(&node, &blocks[1]));
+        assert!(!is_cached(&node, &blocks[2]));
+        assert!(!is_cached(&node, &blocks[3]));
+
+        // A pre-verified seal that doesn't extend the committed chain
+        let mut fork_seal = PbftSeal::new();
+        fork_seal.set_previous_id(mock_block_id(42));
+        node.msg_log
+            .add_consensus_seal(vec![0xf0], 10, fork_seal)
+            .unwrap();
+
+        // Committing block 5 brings block 8 into range, and drops the seal on the other fork
+        node.on_block_commit(mock_block_id(5), &mut state);
+        assert_eq!(state.seq_num, 6);
+        assert_eq!(node.seal_verification_timing().count, 3);
+        assert!(is_cached(&node, &blocks[0]));
+        assert!(is_cached(&node, &blocks[1]));
+        assert!(is_cached(&node, &blocks[2]));
+        assert!(!is_cached(&node, &blocks[3]));
+        assert!(node.msg_log.get_consensus_seal_for_block(&[0xf0]).is_none());
+    }
+
+    /// Make sure that a single node with `allow_single_node` set commits its blocks without any
+    /// peer messages
+    #[test]
+    fn single_node() {
+        let mut cfg = mock_config(1);
+        cfg.allow_single_node = true;
+        let mut node = mock_chain_node(&cfg, &[]);
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        assert!(state.is_primary());
+        assert_eq!(state.f, 0);
+
+        let block = mock_block(1);
+        node.on_block_new(block.clone(), &mut state).unwrap();
+
+        assert_eq!(state.phase, PbftPhase::Finished);
+        assert_eq!(
+            state.working_block.as_ref().map(|b| b.get_block_num()),
+            Some(1)
+        );
+        assert!(node.sent_messages.is_empty());
+        assert!(node.msg_log.is_empty());
+
+        // Without the setting, a single node can't run, and doesn't skip consensus
+        cfg.allow_single_node = false;
+        state.allow_single_node = false;
+        assert!(!state.is_single_node());
+        assert!(::std::panic::catch_unwind(|| PbftState::new(vec![0], 0, &cfg)).is_err());
+    }
+
+    /// Make sure that receiving a `BlockValid` update works as expected
+    #[test]
+    fn block_valid() {
+        let mut node = mock_node();
+        let cfg = mock_config(4);
+        let mut state0 = PbftState::new(vec![0], 0, &cfg);
+        state0.phase = PbftPhase::Checking;
+        state0.working_block = Some(pbft_block_from_block(mock_block(1)));
+        let pre_prepare = mock_msg(&PbftMessageType::PrePrepare, 0, 1, mock_block(1), vec![0]);
+        node.msg_log.add_message(pre_prepare, &state0).unwrap();
+        for peer in 0..3 {
+            let prepare = mock_msg(&PbftMessageType::Prepare, 0, 1, mock_block(1), vec![peer]);
+            node.msg_log.add_message(prepare, &state0).unwrap();
+        }
+        node.on_block_valid(&mock_block_id(1), &mut state0)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state0.phase, PbftPhase::Committing);
+    }
+
+    /// Make sure that a `BlockValid` for a block this node isn't prepared for doesn't make it send
+    /// a `Commit`
+    #[test]
+    fn block_valid_not_prepared() {
+        let mut node = mock_node();
+        let cfg = mock_config(4);
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        node.set_self_send(false);
+        state.phase = PbftPhase::Checking;
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+
+        let not_prepared = |node: &mut PbftNode, state: &mut PbftState| {
+            match node.on_block_valid(&mock_block_id(1), state) {
+                Err(PbftError::NotPrepared(block_id)) => assert_eq!(block_id, mock_block_id(1)),
+                res => panic!("Expected NotPrepared, got {:?}", res),
+            }
+            assert_eq!(state.phase, PbftPhase::Checking);
+            assert!(node.sent_messages.is_empty());
+        };
+
+        // No votes at all
+        not_prepared(&mut node, &mut state);
+
+        // Only 2f Prepares
+        let pre_prepare = mock_msg(&PbftMessageType::PrePrepare, 0, 1, mock_block(1), vec![0]);
+        node.msg_log.add_message(pre_prepare, &state).unwrap();
+        for peer in 0..2 {
+            let prepare = mock_msg(&PbftMessageType::Prepare, 0, 1, mock_block(1), vec![peer]);
+            node.msg_log.add_message(prepare, &state).unwrap();
+        }
+        not_prepared(&mut node, &mut state);
+
+        // A Prepare for another block doesn't count
+        let mut other_block = mock_block(1);
+        other_block.block_id = mock_block_id(9);
+        let prepare = mock_msg(&PbftMessageType::Prepare, 0, 1, other_block, vec![2]);
+        node.msg_log.add_message(prepare, &state).unwrap();
+        not_prepared(&mut node, &mut state);
+    }
+
+    /// Make sure that the peers who haven't voted in the current phase are reported
+    #[test]
+    fn missing_voters() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+
+        assert_eq!(node.missing_voters(&state), vec![vec![0]]);
+
+        state.phase = PbftPhase::Preparing;
+        for peer in &[0, 2] {
+            let msg = mock_msg(&PbftMessageType::Prepare, 0, 1, mock_block(1), vec![*peer]);
+            node.msg_log.add_message(msg, &state).unwrap();
+        }
+        assert_eq!(node.missing_voters(&state), vec![vec![1], vec![3]]);
+
+        // Votes for other sequence numbers don't count
+        let msg = mock_msg(&PbftMessageType::Prepare, 0, 2, mock_block(2), vec![1]);
+        node.msg_log.add_message(msg, &state).unwrap();
+        assert_eq!(node.missing_voters(&state), vec![vec![1], vec![3]]);
+
+        state.phase = PbftPhase::Committing;
+        assert_eq!(node.missing_voters(&state).len(), 4);
+    }
+
+    /// Make sure that the reported status follows the node through the multicast protocol
+    #[test]
+    fn consensus_status() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        let status = node.status(&state);
+        assert_eq!(status.phase, PbftPhase::PrePreparing);
+        assert_eq!(status.mode, PbftMode::Normal);
+        assert_eq!((status.view, status.seq_num), (0, 1));
+        assert_eq!(status.primary_id, vec![0]);
+        assert!(!status.is_primary);
+
+        let block = mock_block(1);
+        node.on_block_new(block.clone(), &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        let msg = mock_msg(&PbftMessageType::PrePrepare, 0, 1, block, vec![0]);
+        node.on_peer_message(msg, &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(node.status(&state).phase, PbftPhase::Preparing);
+    }
+
+    /// Make sure that a node whose commit timer ran out without enough votes reports that it's
+    /// stuck, and that it's healthy otherwise
+    #[test]
+    fn health_status() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        assert_eq!(node.is_healthy(&state), HealthStatus::Normal);
+
+        // Wedged in Committing with only one Commit and an expired commit timer
+        state.phase = PbftPhase::Committing;
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+        node.msg_log
+            .add_message(
+                mock_msg(&PbftMessageType::Commit, 0, 1, mock_block(1), vec![0]),
+                &state,
+            )
+            .unwrap();
+        assert_eq!(node.is_healthy(&state), HealthStatus::Normal);
+        state.commit_timeout.set_duration(Duration::from_millis(0));
+        state.commit_timeout.start();
+        assert_eq!(node.is_healthy(&state), HealthStatus::Stuck);
+
+        // Enough Commits to move on
+        for peer in 2..4 {
+            node.msg_log
+                .add_message(
+                    mock_msg(&PbftMessageType::Commit, 0, 1, mock_block(1), vec![peer]),
+                    &state,
+                )
+                .unwrap();
+        }
+        assert_eq!(node.is_healthy(&state), HealthStatus::Normal);
+
+        state.mode = PbftMode::ViewChanging;
+        assert_eq!(node.is_healthy(&state), HealthStatus::ViewChanging);
+    }
+
+    /// Make sure that a view change is proposed if the working block never gets enough commits,
+    /// and that the commit timeout doesn't run once the block is committed some other way
+    #[test]
+    fn commit_timeout() {
+        let mut cfg = mock_config(4);
+        cfg.commit_timeout = Duration::from_millis(20);
+        let mut node = mock_node();
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        let clock = MockClock::new();
+        state.set_clock(clock.clone());
+        assert!(!state.commit_timeout.is_running());
+
+        let block = mock_block(1);
+        node.on_block_new(block.clone(), &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert!(state.commit_timeout.is_running());
+
+        let msg = mock_msg(&PbftMessageType::PrePrepare, 0, 1, block.clone(), vec![0]);
+        node.on_peer_message(msg, &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        for peer in 0..3 {
+            let msg = mock_msg(&PbftMessageType::Prepare, 0, 1, block.clone(), vec![peer]);
+            node.on_peer_message(msg, &mut state)
+                .unwrap_or_else(handle_pbft_err);
+        }
+        node.on_block_valid(&mock_block_id(1), &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state.phase, PbftPhase::Committing);
+
+        // Only one Commit arrives
+        let msg = mock_msg(&PbftMessageType::Commit, 0, 1, block.clone(), vec![0]);
+        node.on_peer_message(msg, &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert!(!node.check_commit_timeout_expired(&mut state));
+
+        clock.advance(Duration::from_millis(30));
+        assert!(node.check_commit_timeout_expired(&mut state));
+        node.propose_view_change(&mut state, ViewChangeReason::CommitTimeout)
+            .unwrap();
+        assert_eq!(state.mode, PbftMode::ViewChanging);
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::CommitTimeout)
+        );
+        assert!(!state.commit_timeout.is_running());
+
+        // A block committed out-of-band with a seal stops the timer
+        let mut node = mock_chain_node(&cfg, &[mock_block(6)]);
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        state.seq_num = 7;
+        let seal_bytes = mock_block_with_seal(7, &mut node, &mut state).payload;
+        node.msg_log.clear();
+        state.seq_num = 6;
+        state.working_block = Some(pbft_block_from_block(mock_block(6)));
+        state.commit_timeout.start();
+
+        node.commit_with_external_seal(mock_block_id(6), &seal_bytes, &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state.seq_num, 7);
+        assert!(!state.commit_timeout.is_running());
+
+        // A timer left running after the working block was dropped doesn't start a view change
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        state.set_clock(clock.clone());
+        state.commit_timeout.start();
+        clock.advance(Duration::from_millis(30));
+        assert!(!node.check_commit_timeout_expired(&mut state));
+        assert!(!state.commit_timeout.is_running());
+    }
+
+    /// Delivers messages straight to other nodes' inboxes, without going through a validator
+    struct InMemoryTransport {
+        id: PeerId,
+        inboxes: Arc<Mutex<HashMap<PeerId, Vec<Vec<u8>>>>>,
+    }
+
+    impl MessageTransport for InMemoryTransport {
+        fn broadcast(&mut self, _message_type: &str, payload: Vec<u8>) -> Result<(), Error> {
+            for (peer, inbox) in self.inboxes.lock().unwrap().iter_mut() {
+                if *peer != self.id {
+                    inbox.push(payload.clone());
+                }
+            }
+            Ok(())
+        }
+
+        fn send_to(
+            &mut self,
+            peer: &PeerId,
+            _message_type: &str,
+            payload: Vec<u8>,
+        ) -> Result<(), Error> {
+            self.inboxes
+                .lock()
+                .unwrap()
+                .entry(peer.clone())
+                .or_default()
+                .push(payload);
+            Ok(())
+        }
+    }
+
+    /// Make sure that a node sends its messages through the transport it's given, and that they
+    /// can be routed to another node without a validator in between
+    #[test]
+    fn in_memory_transport() {
+        let cfg = mock_config(4);
+        let inboxes = Arc::new(Mutex::new(HashMap::new()));
+        let mut nodes = Vec::new();
+        for id in 0..2 {
+            inboxes.lock().unwrap().insert(vec![id], Vec::new());
+            let mut node = mock_node();
+            node.set_transport(Box::new(InMemoryTransport {
+                id: vec![id],
+                inboxes: inboxes.clone(),
+            }));
+            nodes.push((node, PbftState::new(vec![id], 0, &cfg)));
+        }
+
+        let (ref mut node0, ref mut state0) = nodes[0];
+        state0.phase = PbftPhase::Preparing;
+        node0
+            ._broadcast_pbft_message(
+                1,
+                &PbftMessageType::Prepare,
+                pbft_block_from_block(mock_block(1)),
+                state0,
+            )
+            .unwrap_or_else(handle_pbft_err);
+        assert!(inboxes.lock().unwrap()[&vec![0]].is_empty());
+
+        let delivered = inboxes
+            .lock()
+            .unwrap()
+            .get_mut(&vec![1])
+            .unwrap()
+            .split_off(0);
+        assert_eq!(delivered.len(), 1);
+
+        let (ref mut node1, ref mut state1) = nodes[1];
+        for bytes in delivered {
+            let mut msg = ParsedMessage::from_bytes(bytes).unwrap();
+            msg.from_self = false;
+            node1
+                .on_peer_message(msg, state1)
+                .unwrap_or_else(handle_pbft_err);
+        }
+
+        let prepares = node1
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::Prepare, 1);
+        assert_eq!(prepares.len(), 1);
+        assert_eq!(prepares[0].info().get_signer_id(), &[0]);
+    }
+
+    /// Make sure that built seals carry the current version, that seals without one are still
+    /// accepted, and that seals from a future version are rejected
+    #[test]
+    fn seal_version() {
+        let cfg = mock_config(4);
+        let mut builder = mock_node();
+        let mut builder_state = PbftState::new(vec![0], 1, &cfg);
+        let built = mock_block_with_seal(2, &mut builder, &mut builder_state);
+        assert_eq!(parse_seal(&built).unwrap().get_version(), SEAL_VERSION);
+
+        let (mut block, mut peers) = mock_block_with_keyed_seal(2, 3);
+        peers.push(vec![9]);
+        let mut seal = parse_seal(&block).unwrap();
+        assert!(verify_seal(&block, &peers, 1).is_ok());
+
+        seal.set_version(0);
+        block.payload = seal.write_to_bytes().unwrap();
+        assert!(verify_seal(&block, &peers, 1).is_ok());
+
+        seal.set_version(SEAL_VERSION + 1);
+        block.payload = seal.write_to_bytes().unwrap();
+        match verify_seal(&block, &peers, 1) {
+            Err(PbftError::UnsupportedSealVersion(version)) => {
+                assert_eq!(version, SEAL_VERSION + 1)
+            }
+            res => panic!("Expected UnsupportedSealVersion, got {:?}", res),
+        }
+
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 1, &cfg);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(1), 1, PbftSeal::new())
+            .unwrap();
+        match node.explain_seal_failure(&block, &state) {
+            Some(SealFailure::Malformed(_)) => {}
+            res => panic!("Expected Malformed, got {:?}", res),
+        }
+        match node.on_block_new(block, &mut state) {
+            Err(PbftError::UnsupportedSealVersion(_)) => {}
+            res => panic!("Expected UnsupportedSealVersion, got {:?}", res),
+        }
+    }
+
+    /// Make sure that a seal can be verified against an explicit list of peers, without a node
+    #[test]
+    fn verify_seal_offline() {
+        let (block, mut peers) = mock_block_with_keyed_seal(2, 3);
+        peers.push(vec![9]);
+
+        assert!(verify_seal(&block, &peers, 1).is_ok());
+
+        // Not enough votes for a larger network
+        assert!(verify_seal(&block, &peers, 2).is_err());
+
+        // Votes from nodes that aren't peers
+        assert!(verify_seal(&block, &peers[1..], 1).is_err());
+
+        // The publisher's vote is implicit, so it can't also be in the seal
+        let mut published_by_voter = block.clone();
+        published_by_voter.signer_id = peers[0].clone();
+        assert!(verify_seal(&published_by_voter, &peers, 1).is_err());
+
+        let mut unsealed = block;
+        unsealed.payload = vec![];
+        assert!(verify_seal(&unsealed, &peers, 1).is_err());
+
+        // Votes that aren't Commits for the sealed block
+        let (prepared, mut peers) = mock_block_with_keyed_votes(2, 3, &PbftMessageType::Prepare, 1);
+        peers.push(vec![9]);
+        assert!(verify_seal(&prepared, &peers, 1).is_err());
+        let (wrong_seq, mut peers) = mock_block_with_keyed_votes(2, 3, &PbftMessageType::Commit, 2);
+        peers.push(vec![9]);
+        assert!(verify_seal(&wrong_seq, &peers, 1).is_err());
+    }
+
+    /// Make sure that a `BlockNew` for a block that was already committed is dropped without
+    /// verifying its seal
+    #[test]
+    fn block_new_already_committed() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        state.seq_num = 7;
+        let block = mock_block_with_seal(7, &mut mock_node(), &mut state);
+        state.working_block = Some(pbft_block_from_block(block.clone()));
+        state.phase = PbftPhase::Finished;
+
+        node.on_block_new(block, &mut state).unwrap();
+        assert_eq!(node.seal_verification_timing().last(), None);
+        assert_eq!(node.seal_verification_timing().cache_hits, 0);
+        assert!(node
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::BlockNew, 7)
+            .is_empty());
+        assert_eq!(state.seq_num, 7);
+    }
+
+    /// Make sure that the peers missing from a new block's seal are recorded, without counting the
+    /// block's publisher, whose vote is implicit
+    #[test]
+    fn non_voters() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        // Node 0 publishes the block, so its vote is implicit
+        let mut publisher_state = PbftState::new(vec![0], 6, &cfg);
+        let mut block =
+            mock_block_with_seal_from(7, &mut mock_node(), &mut publisher_state, &[1, 2]);
+        block.signer_id = vec![0];
+        state.seq_num = 7;
+
+        node.on_block_new(block, &mut state).unwrap();
+        let snapshot = node.metrics.snapshot();
+        assert_eq!(snapshot.non_voters, vec![(6, vec![vec![3]])]);
+        assert_eq!(snapshot.missed_votes(&[3]), 1);
+        assert_eq!(snapshot.missed_votes(&[0]), 0);
+    }
+
+    /// Make sure that malformed messages are dropped before they're handled
+    #[test]
+    fn malformed_messages() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        let block = mock_block(1);
+
+        let no_signer = mock_msg(&PbftMessageType::Prepare, 0, 1, block.clone(), vec![]);
+        let long_signer = mock_msg(&PbftMessageType::Prepare, 0, 1, block.clone(), vec![0; 300]);
+        let mut unknown_type = mock_msg(&PbftMessageType::Prepare, 0, 1, block.clone(), vec![0]);
+        unknown_type.info_mut().set_msg_type("Prepear".into());
+        let mut no_block = mock_msg(&PbftMessageType::Commit, 0, 1, block.clone(), vec![0]);
+        if let PbftMessageWrapper::Message(ref mut msg) = no_block.message {
+            msg.clear_block();
+        }
+        let mut misparsed = PbftViewChange::new();
+        misparsed.set_info(make_msg_info(&PbftMessageType::Commit, 0, 1, vec![0]));
+
+        let cases = vec![
+            no_signer,
+            long_signer,
+            unknown_type,
+            no_block,
+            ParsedMessage::from_view_change_message(misparsed),
+        ];
+        let count = cases.len() as u64;
+        for msg in cases {
+            match node.on_peer_message(msg, &mut state) {
+                Err(PbftError::InvalidMessage(_)) => {}
+                res => panic!("Expected InvalidMessage, got {:?}", res),
+            }
+        }
+
+        // Unknown types are counted separately, to make unexpected traffic easier to spot
+        assert!(node.msg_log.is_empty());
+        assert_eq!(
+            node.metrics
+                .snapshot()
+                .rejections(RejectionReason::Malformed),
+            count - 1
+        );
+        assert_eq!(
+            node.metrics
+                .snapshot()
+                .rejections(RejectionReason::UnknownType),
+            1
+        );
+        assert!(mock_msg(&PbftMessageType::Commit, 0, 1, block, vec![0])
+            .validate()
+            .is_ok());
+    }
+
+    /// Make sure that a duplicate `BlockCommit` for the same block is ignored, even if the node
+    /// happens to be in a state where it would otherwise be accepted
+    #[test]
+    fn duplicate_block_commit() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        for _ in 0..2 {
+            state.working_block = Some(pbft_block_from_block(mock_block(1)));
+            state.phase = PbftPhase::Finished;
+            node.on_block_commit(mock_block_id(1), &mut state);
+        }
+
+        assert_eq!(state.seq_num, 2);
+        assert_eq!(state.last_committed_block, Some(mock_block_id(1)));
+    }
+
+    /// Make sure that messages from a different PBFT network are rejected
+    #[test]
+    fn wrong_network() {
+        let mut cfg = mock_config(4);
+        cfg.network_id = "network-a".into();
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        let mut msg = mock_msg(&PbftMessageType::Prepare, 0, 1, mock_block(1), vec![0]);
+        msg.info_mut().set_network_id("network-b".into());
+        match node.on_peer_message(msg, &mut state) {
+            Err(PbftError::WrongNetwork(ref exp, ref got))
+                if exp == "network-a" && got == "network-b" => {}
+            res => panic!("Expected WrongNetwork, got {:?}", res),
+        }
+        assert!(node
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::Prepare, 1)
+            .is_empty());
+
+        let mut msg = mock_msg(&PbftMessageType::Prepare, 0, 1, mock_block(1), vec![0]);
+        msg.info_mut().set_network_id("network-a".into());
+        node.on_peer_message(msg, &mut state).unwrap();
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::Prepare, 1)
+                .len(),
+            1
+        );
+
+        // Neither are seals made of votes from a different network
+        let mut builder = mock_node();
+        let mut builder_state = PbftState::new(vec![1], 1, &mock_config(4));
+        let block = mock_block_with_seal(2, &mut builder, &mut builder_state);
+        match node.verify_consensus_seal(&block, &mut state) {
+            Err(PbftError::WrongNetwork(ref exp, ref got))
+                if exp == "network-a" && got.is_empty() => {}
+            res => panic!("Expected WrongNetwork, got {:?}", res),
+        }
+        match node.explain_seal_failure(&block, &state) {
+            Some(SealFailure::BadVote(0, _)) => {}
+            res => panic!("Expected BadVote(0, _), got {:?}", res),
+        }
+    }
+
+    /// Make sure that a `PrePrepare` for an already-committed sequence number is dropped
+    #[test]
+    fn old_pre_prepare() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 4, &cfg);
+        assert_eq!(state.seq_num, 5);
+
+        let msg = mock_msg(&PbftMessageType::PrePrepare, 0, 1, mock_block(1), vec![0]);
+        node.on_peer_message(msg, &mut state).unwrap();
+
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+        assert!(node
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::PrePrepare, 1)
+            .is_empty());
+        assert!(node.msg_log.pop_backlog().is_none());
+    }
+
+    /// Make sure that the backlog doesn't grow past `max_backlog_size`, and that backlogged messages
+    /// for sequence numbers that are already past are dropped
+    #[test]
+    fn bounded_backlog() {
+        let mut cfg = mock_config(4);
+        cfg.max_backlog_size = 3;
+        let mut node = mock_chain_node(&cfg, &[]);
+        let mut state = PbftState::new(vec![1], 4, &cfg);
+
+        // None of these blocks have arrived, so their PrePrepares are all backlogged
+        for seq_num in 5..10 {
+            let msg = mock_msg(
+                &PbftMessageType::PrePrepare,
+                0,
+                seq_num,
+                mock_block(seq_num),
+                vec![0],
+            );
+            node.on_peer_message(msg, &mut state).unwrap();
+            assert!(node.msg_log.backlog_len() <= 3);
+        }
+        assert_eq!(node.msg_log.backlog_len(), 3);
+
+        // The PrePrepare for 7 is stale by now; the one for 8 is retried, and still has to wait
+        state.seq_num = 8;
+        node.retry_backlog(&mut state).unwrap();
+        let mut backlogged = vec![];
+        while let Some(msg) = node.msg_log.pop_backlog() {
+            backlogged.push(msg.info().get_seq_num());
+        }
+        assert_eq!(backlogged, vec![9, 8]);
+    }
+
+    /// Make sure that `retry_backlog` reports whether it retried a message, so that the backlog
+    /// can be drained until nothing more is ready
+    #[test]
+    fn retry_backlog_drain() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 4, &cfg);
+
+        for peer in &[0, 2, 3] {
+            node.msg_log.push_backlog(mock_msg(
+                &PbftMessageType::Prepare,
+                0,
+                5,
+                mock_block(5),
+                vec![*peer],
+            ));
+        }
+        // A message for a later block is kept for later, and one for an earlier block is dropped
+        node.msg_log.push_backlog(mock_msg(
+            &PbftMessageType::Prepare,
+            0,
+            7,
+            mock_block(7),
+            vec![0],
+        ));
+        node.msg_log.push_backlog(mock_msg(
+            &PbftMessageType::Prepare,
+            0,
+            2,
+            mock_block(2),
+            vec![0],
+        ));
+
+        for _ in 0..3 {
+            assert!(node.retry_backlog(&mut state).unwrap());
+        }
+        assert!(!node.retry_backlog(&mut state).unwrap());
+
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::Prepare, 5)
+                .len(),
+            3
+        );
+        assert_eq!(node.msg_log.backlog_len(), 1);
+        assert_eq!(node.msg_log.pop_backlog().unwrap().info().get_seq_num(), 7);
+    }
+
+    /// Make sure that `drain_backlog` handles every ready message in one call, and stops even when
+    /// messages go straight back into the backlog
+    #[test]
+    fn drain_backlog() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 4, &cfg);
+
+        for peer in &[0, 2, 3] {
+            node.msg_log.push_backlog(mock_msg(
+                &PbftMessageType::Prepare,
+                0,
+                5,
+                mock_block(5),
+                vec![*peer],
+            ));
+        }
+        node.msg_log.push_backlog(mock_msg(
+            &PbftMessageType::Prepare,
+            0,
+            7,
+            mock_block(7),
+            vec![0],
+        ));
+
+        assert_eq!(node.drain_backlog(&mut state), 3);
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::Prepare, 5)
+                .len(),
+            3
+        );
+        assert_eq!(node.msg_log.backlog_len(), 1);
+        assert_eq!(node.drain_backlog(&mut state), 0);
+
+        // A message that fails (here, a conflicting vote) doesn't stop the ones after it, and
+        // isn't counted
+        for (block, peer) in &[(5, 0), (6, 0), (5, 2)] {
+            node.msg_log.push_backlog(mock_msg(
+                &PbftMessageType::Commit,
+                0,
+                5,
+                mock_block(*block),
+                vec![*peer],
+            ));
+        }
+        assert_eq!(node.drain_backlog(&mut state), 2);
+        assert_eq!(node.msg_log.backlog_len(), 1);
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::Commit, 5)
+                .len(),
+            2
+        );
+
+        // A PrePrepare for a block that hasn't arrived yet is backlogged again when retried
+        let mut state = PbftState::new(vec![1], 4, &cfg);
+        node.msg_log.clear();
+        node.msg_log.push_backlog(mock_msg(
+            &PbftMessageType::PrePrepare,
+            0,
+            5,
+            mock_block(5),
+            vec![0],
+        ));
+        assert_eq!(node.drain_backlog(&mut state), 0);
+        assert_eq!(node.msg_log.backlog_len(), 1);
+    }
+
+    /// Make sure that broadcasting the same message twice in a row only sends it once
+    #[test]
+    fn broadcast_dedup() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 4, &cfg);
+        let block = pbft_block_from_block(mock_block(5));
+
+        for _ in 0..2 {
+            node._broadcast_pbft_message(
+                5,
+                &PbftMessageType::Checkpoint,
+                block.clone(),
+                &mut state,
+            )
+            .unwrap();
+        }
+        assert_eq!(node.sent_messages.len(), 1);
+
+        // A message that differs in any way is still sent
+        node._broadcast_pbft_message(6, &PbftMessageType::Checkpoint, block.clone(), &mut state)
+            .unwrap();
+        state.view = 1;
+        node._broadcast_pbft_message(5, &PbftMessageType::Checkpoint, block, &mut state)
+            .unwrap();
+        assert_eq!(node.sent_messages.len(), 3);
+    }
+
+    /// Make sure that `Commit`s received while the block is still being checked are acted on as
+    /// soon as the `BlockValid` arrives
+    #[test]
+    fn commits_during_checking() {
+        let cfg = mock_config(4);
+        let mut node1 = mock_node();
+        let mut state1 = PbftState::new(vec![1], 0, &cfg);
+        let block = mock_block(1);
+        node1
+            .on_block_new(block.clone(), &mut state1)
+            .unwrap_or_else(handle_pbft_err);
+
+        let msg = mock_msg(&PbftMessageType::PrePrepare, 0, 1, block.clone(), vec![0]);
+        node1
+            .on_peer_message(msg, &mut state1)
+            .unwrap_or_else(handle_pbft_err);
+        for peer in 0..3 {
+            let msg = mock_msg(&PbftMessageType::Prepare, 0, 1, block.clone(), vec![peer]);
+            node1
+                .on_peer_message(msg, &mut state1)
+                .unwrap_or_else(handle_pbft_err);
+        }
+        assert_eq!(state1.phase, PbftPhase::Checking);
+
+        // All of the commits arrive before the block has been validated
+        for peer in 0..4 {
+            let msg = mock_msg(&PbftMessageType::Commit, 0, 1, block.clone(), vec![peer]);
+            node1
+                .on_peer_message(msg, &mut state1)
+                .unwrap_or_else(handle_pbft_err);
+            assert_eq!(state1.phase, PbftPhase::Checking);
+        }
+
+        node1
+            .on_block_valid(&mock_block_id(1), &mut state1)
+            .unwrap();
+        assert_eq!(state1.phase, PbftPhase::Finished);
+    }
+
+    /// Make sure that when every vote arrives before the `PrePrepare`, the node moves through each
+    /// phase as soon as it enters the one before, without waiting for another vote
+    #[test]
+    fn votes_before_pre_prepare() {
+        let cfg = mock_config(4);
+        let mut node1 = mock_node();
+        let mut state1 = PbftState::new(vec![1], 0, &cfg);
+        let block = mock_block(1);
+        node1
+            .on_block_new(block.clone(), &mut state1)
+            .unwrap_or_else(handle_pbft_err);
+
+        for peer in &[0, 2, 3] {
+            for msg_type in &[PbftMessageType::Prepare, PbftMessageType::Commit] {
+                let msg = mock_msg(msg_type, 0, 1, block.clone(), vec![*peer]);
+                node1
+                    .on_peer_message(msg, &mut state1)
+                    .unwrap_or_else(handle_pbft_err);
+            }
+        }
+        assert_eq!(state1.phase, PbftPhase::PrePreparing);
+
+        let msg = mock_msg(&PbftMessageType::PrePrepare, 0, 1, block.clone(), vec![0]);
+        node1
+            .on_peer_message(msg, &mut state1)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state1.phase, PbftPhase::Checking);
+
+        node1
+            .on_block_valid(&mock_block_id(1), &mut state1)
+            .unwrap();
+        assert_eq!(state1.phase, PbftPhase::Finished);
+    }
+
+    /// Make sure that a vote for a different block doesn't keep a block from being committed, and
+    /// is left out of the seal built for it
+    #[test]
+    fn commit_with_conflicting_vote() {
+        let cfg = mock_config(4);
+        let mut node1 = mock_node();
+        node1.set_self_send(true);
+        let mut state1 = PbftState::new(vec![1], 0, &cfg);
+        let block = mock_block(1);
+        node1
+            .on_block_new(block.clone(), &mut state1)
+            .unwrap_or_else(handle_pbft_err);
+
+        let msg = mock_msg(&PbftMessageType::PrePrepare, 0, 1, block.clone(), vec![0]);
+        node1
+            .on_peer_message(msg, &mut state1)
+            .unwrap_or_else(handle_pbft_err);
+        for peer in 0..3 {
+            let msg = mock_msg(&PbftMessageType::Prepare, 0, 1, block.clone(), vec![peer]);
+            node1
+                .on_peer_message(msg, &mut state1)
+                .unwrap_or_else(handle_pbft_err);
+        }
+        node1
+            .on_block_valid(&mock_block_id(1), &mut state1)
+            .unwrap();
+        assert_eq!(state1.phase, PbftPhase::Committing);
+
+        // Node 3 also votes for a different block
+        let mut other_block = mock_block(1);
+        other_block.block_id = mock_block_id(2);
+        let msg = mock_msg(&PbftMessageType::Commit, 0, 1, other_block, vec![3]);
+        node1.on_peer_message(msg, &mut state1).unwrap();
+
+        let msg = mock_msg(&PbftMessageType::Commit, 0, 1, block.clone(), vec![0]);
+        node1.on_peer_message(msg, &mut state1).unwrap();
+        assert_eq!(state1.phase, PbftPhase::Committing);
+
+        // Node 2's commit completes the quorum along with this node's own
+        let msg = mock_msg(&PbftMessageType::Commit, 0, 1, block.clone(), vec![2]);
+        node1.on_peer_message(msg, &mut state1).unwrap();
+        assert_eq!(state1.phase, PbftPhase::Finished);
+
+        node1.on_block_commit(mock_block_id(1), &mut state1);
+        let mut next_block = mock_block(2);
+        next_block.payload = node1.build_seal(&state1, vec![1, 2, 3]).unwrap();
+        let seal = parse_seal(&next_block).unwrap();
+        assert_eq!(seal.get_previous_id(), mock_block_id(1).as_slice());
+        assert_eq!(seal.get_previous_commit_votes().len(), 2);
+    }
+
+    /// Make sure that receiving a `BlockCommit` update works as expected
+    #[test]
+    fn block_commit() {
+        let mut node = mock_node();
+        let cfg = mock_config(4);
+        let mut state0 = PbftState::new(vec![0], 0, &cfg);
+        state0.phase = PbftPhase::Finished;
+        state0.working_block = Some(pbft_block_from_block(mock_block(1)));
+        assert_eq!(state0.seq_num, 1);
+        node.on_block_commit(mock_block_id(1), &mut state0);
+        assert_eq!(state0.phase, PbftPhase::PrePreparing);
+        assert_eq!(state0.working_block, None);
+        assert_eq!(state0.seq_num, 2);
+    }
+
+    /// Make sure that seals without enough votes are rejected with an error, both when they're
+    /// verified and when they're used to catch up, instead of causing a panic
+    #[test]
+    fn empty_seal() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        let mut seal = PbftSeal::new();
+        seal.set_previous_id(mock_block_id(1));
+        let mut block = mock_block(2);
+        block.payload = seal.write_to_bytes().unwrap();
+        match node.verify_consensus_seal(&block, &mut state) {
+            Err(PbftError::EmptySeal(previous_id)) => assert_eq!(previous_id, mock_block_id(1)),
+            res => panic!("Expected EmptySeal, got {:?}", res),
+        }
+
+        // One vote isn't enough for f = 1, and isn't even looked at
+        let mut vote = PbftSignedCommitVote::new();
+        vote.set_message_bytes(vec![1, 2, 3]);
+        let mut single_vote_seal = seal.clone();
+        single_vote_seal.set_previous_commit_votes(RepeatedField::from(vec![vote]));
+        let mut single_vote_block = mock_block(2);
+        single_vote_block.payload = single_vote_seal.write_to_bytes().unwrap();
+        match node.verify_consensus_seal(&single_vote_block, &mut state) {
+            Err(PbftError::InsufficientVotes(2, 1)) => {}
+            res => panic!("Expected InsufficientVotes, got {:?}", res),
+        }
+
+        // Catching up with an empty seal fails without touching the working block
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+        match node.catchup(&mut state, &block) {
+            Err(PbftError::EmptySeal(previous_id)) => assert_eq!(previous_id, mock_block_id(1)),
+            res => panic!("Expected EmptySeal, got {:?}", res),
+        }
+        assert_eq!(state.seq_num, 1);
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+    }
+
+    /// Make sure that a seal that can't be checked because the on-chain peers setting is invalid
+    /// doesn't fail the block, count against its publisher, or start a view change
+    #[test]
+    fn block_new_invalid_peers_setting() {
+        let cfg = mock_config(4);
+        let mut builder_state = PbftState::new(vec![1], 0, &cfg);
+        builder_state.seq_num = 2;
+        let block = mock_block_with_seal(2, &mut mock_node(), &mut builder_state);
+
+        let service = Box::new(MockService {
+            peers_setting_key: "not.the.peers.setting".into(),
+            ..MockService::new(cfg.peers.clone())
+        });
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![1], 1, &cfg);
+
+        match node.on_block_new(block, &mut state) {
+            Err(PbftError::InvalidPeersSetting(_, _)) => {}
+            res => panic!("Expected InvalidPeersSetting, got {:?}", res),
+        }
+        assert_eq!(state.mode, PbftMode::Normal);
+        assert_eq!(node.last_view_change_reason(), None);
+        assert!(node.recent_faults().is_empty());
+        assert!(node.deferred_blocks.is_empty());
+    }
+
+    /// Make sure that block 1, which comes before the first sealed block, is rejected if its
+    /// payload isn't empty
+    #[test]
+    fn unsealed_block_with_payload() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+
+        let mut bogus = mock_block(1);
+        bogus.payload = vec![1, 2, 3];
+        match node.explain_seal_failure(&bogus, &state) {
+            Some(SealFailure::Malformed(_)) => {}
+            res => panic!("Expected Malformed, got {:?}", res),
+        }
+        match node.on_block_new(bogus, &mut state) {
+            Err(PbftError::UnexpectedPayload(1)) => {}
+            res => panic!("Expected UnexpectedPayload, got {:?}", res),
+        }
+        assert!(node
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::BlockNew, 1)
+            .is_empty());
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::InvalidSeal)
+        );
+
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        assert_eq!(node.explain_seal_failure(&mock_block(1), &state), None);
+        node.on_block_new(mock_block(1), &mut state).unwrap();
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::BlockNew, 1)
+                .len(),
+            1
+        );
+
+        // Later unsealed blocks may still carry seals from before the first sealed block was
+        // raised
+        let mut raised_cfg = mock_config(4);
+        raised_cfg.first_sealed_block = 4;
+        let mut node = mock_chain_node(&raised_cfg, &[]);
+        let mut state = PbftState::new(vec![1], 2, &raised_cfg);
+        let mut old = mock_block(3);
+        old.payload = vec![1, 2, 3];
+        assert_eq!(node.explain_seal_failure(&old, &state), None);
+        assert!(node
+            .verify_consensus_seal(&old, &mut state)
+            .unwrap()
+            .is_none());
+    }
+
+    /// Make sure that an invalid seal on a block and conflicting votes from a peer are both
+    /// recorded as faults, against the block's publisher and the voter respectively
+    #[test]
+    fn recent_faults() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 1, &cfg);
+        assert!(node.recent_faults().is_empty());
+
+        let mut seal = PbftSeal::new();
+        seal.set_previous_id(mock_block_id(1));
+        let mut block = mock_block(2);
+        block.signer_id = vec![0];
+        block.payload = seal.write_to_bytes().unwrap();
+        assert!(node.on_block_new(block, &mut state).is_err());
+
+        assert_eq!(node.recent_faults().len(), 1);
+        let fault = &node.recent_faults()[0];
+        assert_eq!(fault.peer_id, vec![0]);
+        assert_eq!(fault.block_id, mock_block_id(2));
+        assert_eq!(
+            fault.reason,
+            PbftError::EmptySeal(mock_block_id(1)).to_string()
+        );
+
+        let state = PbftState::new(vec![1], 0, &cfg);
+        let mut other_block = mock_block(1);
+        other_block.block_id = mock_block_id(9);
+        node.add_vote(
+            mock_msg(&PbftMessageType::Prepare, 0, 1, mock_block(1), vec![2]),
+            &state,
+        )
+        .unwrap();
+        assert!(node
+            .add_vote(
+                mock_msg(&PbftMessageType::Prepare, 0, 1, other_block, vec![2]),
+                &state,
+            )
+            .is_err());
+
+        assert_eq!(node.recent_faults().len(), 2);
+        let fault = &node.recent_faults()[1];
+        assert_eq!(fault.peer_id, vec![2]);
+        assert_eq!(fault.block_id, mock_block_id(9));
+        assert_eq!(
+            fault.reason,
+            PbftError::Equivocation(PbftMessageType::Prepare, vec![2]).to_string()
+        );
+    }
+
+    /// Make sure that the view a block was committed in is recorded, so it's known after a view
+    /// change
+    #[test]
+    fn view_for_block() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+
+        // Change to view 1, which this node is the primary of
+        for peer in &[0, 2, 3] {
+            node.on_peer_message(mock_signed_view_change(1, *peer), &mut state)
+                .unwrap();
+        }
+        assert_eq!(state.view, 1);
+        assert_eq!(node.msg_log.view_for_block(&mock_block_id(1)), None);
+
+        for peer in 0..4 {
+            node.msg_log
+                .add_message(
+                    mock_msg(&PbftMessageType::Commit, 1, 1, mock_block(1), vec![peer]),
+                    &state,
+                )
+                .unwrap();
+        }
+        state.phase = PbftPhase::Finished;
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+        node.on_block_commit(mock_block_id(1), &mut state);
+        assert_eq!(node.msg_log.view_for_block(&mock_block_id(1)), Some(1));
+    }
+
+    /// Make sure that repeated view changes back off the faulty primary timeout, and that
+    /// committing a block or catching up to the network's view resets it
+    #[test]
+    fn view_change_backoff() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+
+        // Two view changes that don't lead to a committed block
+        for _ in 0..2 {
+            node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+                .unwrap();
+            state.discard_current_block();
+        }
+        assert_eq!(state.view_change_attempts, 2);
+        assert_eq!(
+            state.faulty_primary_timeout.duration(),
+            cfg.faulty_primary_timeout * 2
+        );
+
+        state.phase = PbftPhase::Finished;
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+        node.on_block_commit(mock_block_id(1), &mut state);
+        assert_eq!(state.view_change_attempts, 0);
+        assert_eq!(
+            state.faulty_primary_timeout.duration(),
+            cfg.faulty_primary_timeout
+        );
+        assert!(state.faulty_primary_timeout.is_running());
+
+        // A node that fell behind and catches up doesn't keep its backed-off timeout
+        node.msg_log
+            .add_consensus_seal(mock_block_id(1), 1, PbftSeal::new())
+            .unwrap();
+        for _ in 0..2 {
+            node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+                .unwrap();
+            state.discard_current_block();
+        }
+        handlers::catch_up_to_view(&mut state, 5);
+        assert_eq!(state.view_change_attempts, 0);
+        assert_eq!(
+            state.faulty_primary_timeout.duration(),
+            cfg.faulty_primary_timeout
+        );
+    }
+
+    /// Make sure that nodes broadcast a checkpoint for every `checkpoint_period`th block, and that
+    /// peers' checkpoints make it stable
+    #[test]
+    fn checkpoint_on_commit() {
+        let mut cfg = mock_config(4);
+        cfg.checkpoint_period = 2;
+        let mut node = mock_chain_node(&cfg, &[]);
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+
+        for num in 1..=2 {
+            state.phase = PbftPhase::Finished;
+            state.working_block = Some(pbft_block_from_block(mock_block(num)));
+            node.on_block_commit(mock_block_id(num), &mut state);
+        }
+
+        let checkpoints: Vec<_> = node
+            .sent_messages
+            .iter()
+            .filter(|msg| msg.info().get_msg_type() == "Checkpoint")
+            .collect();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(checkpoints[0].info().get_seq_num(), 2);
+        assert_eq!(
+            checkpoints[0].get_block().get_block_id(),
+            &mock_block_id(2)[..]
+        );
+
+        for peer in 0..3 {
+            let msg = mock_msg(
+                &PbftMessageType::Checkpoint,
+                0,
+                2,
+                mock_block(2),
+                vec![peer],
+            );
+            node.on_peer_message(msg, &mut state).unwrap();
+        }
+        assert_eq!(node.msg_log.stable_checkpoint(), 2);
+    }
+
+    /// Make sure that messages past the high watermark are rejected (and blocks held back) until
+    /// a stable checkpoint moves the watermark past them
+    #[test]
+    fn watermarks() {
+        let mut cfg = mock_config(4);
+        cfg.watermark_window = 2;
+        assert!(cfg.validate().is_err());
+        cfg.checkpoint_period = 2;
+        assert!(cfg.validate().is_ok());
+
+        let mut node = mock_chain_node(&cfg, &[]);
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        assert_eq!(state.high_watermark(), Some(2));
+
+        let prepare = mock_msg(&PbftMessageType::Prepare, 0, 3, mock_block(3), vec![1]);
+        match node.on_peer_message(prepare.clone(), &mut state) {
+            Err(PbftError::AboveHighWatermark(3, 2)) => {}
+            res => panic!("Expected AboveHighWatermark, got {:?}", res),
+        }
+        node.on_block_new(mock_block(3), &mut state).unwrap();
+        assert_eq!(node.deferred_blocks.len(), 1);
+
+        for peer in 0..3 {
+            let msg = mock_msg(
+                &PbftMessageType::Checkpoint,
+                0,
+                2,
+                mock_block(2),
+                vec![peer],
+            );
+            node.on_peer_message(msg, &mut state).unwrap();
+        }
+        assert_eq!(state.low_watermark, 2);
+        assert_eq!(state.high_watermark(), Some(4));
+        assert!(node.deferred_blocks.is_empty());
+
+        node.on_peer_message(prepare, &mut state).unwrap();
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::Prepare, 3)
+                .len(),
+            1
+        );
+
+        // Committing a block moves the watermarks too, without waiting for a checkpoint
+        state.seq_num = 3;
+        state.phase = PbftPhase::Finished;
+        state.working_block = Some(pbft_block_from_block(mock_block(3)));
+        node.on_block_commit(mock_block_id(3), &mut state);
+        assert_eq!(state.low_watermark, 3);
+        assert_eq!(state.high_watermark(), Some(5));
+    }
+
+    /// Test the multicast protocol (`PrePrepare` => `Prepare` => `Commit`)
+    #[test]
+    fn multicast_protocol() {
+        let cfg = mock_config(4);
+
+        // Make sure BlockNew is in the log
+        let mut node1 = mock_node();
+        node1.set_self_send(true);
+        let mut state1 = PbftState::new(vec![1], 0, &cfg);
+        let block = mock_block(1);
+        node1
+            .on_block_new(block.clone(), &mut state1)
+            .unwrap_or_else(handle_pbft_err);
+
+        // Receive a PrePrepare
+        let msg = mock_msg(&PbftMessageType::PrePrepare, 0, 1, block.clone(), vec![0]);
+        node1
+            .on_peer_message(msg, &mut state1)
+            .unwrap_or_else(handle_pbft_err);
+
+        assert_eq!(state1.phase, PbftPhase::Preparing);
+        assert_eq!(state1.seq_num, 1);
+        if let Some(ref blk) = state1.working_block {
+            assert_eq!(BlockId::from(blk.clone().block_id), mock_block_id(1));
+        } else {
+            panic!("Wrong WorkingBlockOption");
+        }
+
+        // The node's own `Prepare` was delivered back to it
+        let own_votes = |node: &PbftNode, msg_type| {
+            node.msg_log
+                .get_messages_of_type_seq_view(&msg_type, 1, 0)
+                .iter()
+                .filter(|msg| msg.from_self && msg.info().get_signer_id() == [1])
+                .count()
+        };
+        assert_eq!(own_votes(&node1, PbftMessageType::Prepare), 1);
+
+        // Receive 2 more `Prepare` messages
+        for peer in &[0, 2] {
+            assert_eq!(state1.phase, PbftPhase::Preparing);
+            let msg = mock_msg(&PbftMessageType::Prepare, 0, 1, block.clone(), vec![*peer]);
+            node1
+                .on_peer_message(msg, &mut state1)
+                .unwrap_or_else(handle_pbft_err);
+        }
+        assert_eq!(state1.phase, PbftPhase::Checking);
+
+        // Spoof the `check_blocks()` call
+        assert!(node1.on_block_valid(&mock_block_id(1), &mut state1).is_ok());
+        assert_eq!(own_votes(&node1, PbftMessageType::Commit), 1);
+
+        // Receive 2 more `Commit` messages
+        for peer in &[0, 2] {
+            assert_eq!(state1.phase, PbftPhase::Committing);
+            let msg = mock_msg(&PbftMessageType::Commit, 0, 1, block.clone(), vec![*peer]);
+            node1
+                .on_peer_message(msg, &mut state1)
+                .unwrap_or_else(handle_pbft_err);
+        }
+        assert_eq!(state1.phase, PbftPhase::Finished);
+
+        // Spoof the `commit_blocks()` call
+        node1.on_block_commit(mock_block_id(1), &mut state1);
+        assert_eq!(state1.phase, PbftPhase::PrePreparing);
+
+        // Make sure the block was actually committed
+        let mut f = File::open(BLOCK_FILE).unwrap();
+        let mut buffer = String::new();
+        f.read_to_string(&mut buffer).unwrap();
+        let deser: Vec<Vec<u8>> = serde_json::from_str(&buffer).unwrap();
+        let blocks: Vec<BlockId> = deser
+            .iter()
+            .filter(|&block| !block.is_empty())
+            .map(|ref block| BlockId::from(block.clone().clone()))
+            .collect();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1], mock_block_id(1));
+
+        remove_file(BLOCK_FILE).unwrap();
+    }
+
+    /// Make sure that replaying the updates and ticks recorded while going through the multicast
+    /// protocol leaves a new node in the same state as the original
+    #[test]
+    fn record_and_replay() {
+        let cfg = mock_config(4);
+        let block = mock_block(1);
+        let path = ::std::env::temp_dir().join(format!("pbft_replay_{}", ::std::process::id()));
+
+        let mut updates = vec![
+            Update::BlockNew(block.clone()),
+            peer_update(&mock_msg(
+                &PbftMessageType::PrePrepare,
+                0,
+                1,
+                block.clone(),
+                vec![0],
+            )),
+        ];
+        for peer in &[0, 2] {
+            updates.push(peer_update(&mock_msg(
+                &PbftMessageType::Prepare,
+                0,
+                1,
+                block.clone(),
+                vec![*peer],
+            )));
+        }
+        updates.push(Update::BlockValid(mock_block_id(1)));
+        for peer in &[0, 2] {
+            updates.push(peer_update(&mock_msg(
+                &PbftMessageType::Commit,
+                0,
+                1,
+                block.clone(),
+                vec![*peer],
+            )));
+        }
+        updates.push(Update::BlockCommit(mock_block_id(1)));
+
+        let (mut node, mut state) = node_from_config(&cfg, vec![1]);
+        node.set_update_recorder(Box::new(FileRecorder::create(&path).unwrap()));
+        for update in updates {
+            assert!(node.apply_update(update, &mut state).unwrap_or_else(|err| {
+                handle_pbft_err(err);
+                true
+            }));
+        }
+        node.on_tick(&mut state);
+        assert_eq!(state.seq_num, 2);
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+
+        let (replayed_node, replayed_state) = replay(&path, &cfg, vec![1]);
+        assert_eq!(read_recording(&path).unwrap().len(), 9);
+        remove_file(&path).unwrap();
+
+        assert_eq!(replayed_state.seq_num, state.seq_num);
+        assert_eq!(replayed_state.view, state.view);
+        assert_eq!(replayed_state.phase, state.phase);
+        assert_eq!(replayed_state.mode, state.mode);
+        assert_eq!(replayed_state.working_block, state.working_block);
+        assert_eq!(
+            replayed_state.last_committed_block,
+            state.last_committed_block
+        );
+        assert_eq!(
+            replayed_node.msg_log.message_type_counts(),
+            node.msg_log.message_type_counts()
+        );
+    }
+
+    /// Make sure that processing a batch of `Prepare` and `Commit` messages has the same result as
+    /// processing them one at a time
+    #[test]
+    fn process_batch() {
+        let cfg = mock_config(4);
+        let block = mock_block(1);
+
+        let mut votes = vec![];
+        for msg_type in &[PbftMessageType::Prepare, PbftMessageType::Commit] {
+            for peer in 0..3 {
+                votes.push(mock_msg(msg_type, 0, 1, block.clone(), vec![peer]));
+            }
+        }
+
+        let mut results = vec![];
+        for batch in &[false, true] {
+            let mut node = mock_node();
+            let mut state = PbftState::new(vec![1], 0, &cfg);
+            node.on_block_new(block.clone(), &mut state)
+                .unwrap_or_else(handle_pbft_err);
+            let msg = mock_msg(&PbftMessageType::PrePrepare, 0, 1, block.clone(), vec![0]);
+            node.on_peer_message(msg, &mut state)
+                .unwrap_or_else(handle_pbft_err);
+
+            if *batch {
+                node.process_batch(votes.clone(), &mut state)
+                    .unwrap_or_else(handle_pbft_err);
+            } else {
+                for msg in votes.clone() {
+                    node.on_peer_message(msg, &mut state)
+                        .unwrap_or_else(handle_pbft_err);
+                }
+            }
+            assert_eq!(state.phase, PbftPhase::Checking);
+
+            // Spoof the `check_blocks()` call; the buffered commits complete the block right away
+            node.on_block_valid(&mock_block_id(1), &mut state)
+                .unwrap_or_else(handle_pbft_err);
+            assert_eq!(state.phase, PbftPhase::Finished);
+
+            results.push((
+                state.seq_num,
+                state.working_block.clone(),
+                node.msg_log.message_type_counts(),
+            ));
+        }
+
+        assert_eq!(results[0], results[1]);
+    }
+
+    /// Make sure that votes in a batch go through the same checks as votes handled one at a time
+    #[test]
+    fn process_batch_checks_votes() {
+        let mut cfg = mock_config(4);
+        cfg.network_id = "network-a".into();
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        let commit = |from: u8, network: &str| {
+            let mut msg = mock_msg(&PbftMessageType::Commit, 0, 4, mock_block(4), vec![from]);
+            msg.info_mut().set_network_id(network.into());
+            msg
+        };
+
+        let batch = vec![commit(0, "network-b"), commit(2, "network-a")];
+        match node.process_batch(batch, &mut state) {
+            Err(PbftError::WrongNetwork(_, ref got)) if got == "network-b" => {}
+            res => panic!("Expected WrongNetwork, got {:?}", res),
+        }
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::Commit, 4)
+                .len(),
+            1
+        );
+        assert_eq!(state.highest_seen_seq_num, 0);
+
+        // Commits in a batch count toward noticing that the network has moved on
+        node.process_batch(vec![commit(3, "network-a")], &mut state)
+            .unwrap();
+        assert_eq!(state.highest_seen_seq_num, 4);
+    }
+
+    /// Make sure that the primary moves to `Preparing` when it receives its own `PrePrepare`, and
+    /// that it only broadcasts one `Prepare` even if the `PrePrepare` is delivered again
+    #[test]
+    fn primary_own_pre_prepare() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        node.set_self_send(true);
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        assert!(state.is_primary());
+
+        node.on_block_new(mock_block(1), &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state.phase, PbftPhase::Preparing);
+
+        let pre_prepare = node
+            .sent_messages
+            .iter()
+            .find(|msg| msg.info().get_msg_type() == "PrePrepare")
+            .cloned()
+            .expect("PrePrepare not broadcast");
+        node.on_peer_message(pre_prepare, &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state.phase, PbftPhase::Preparing);
+
+        let prepares = node
+            .sent_messages
+            .iter()
+            .filter(|msg| msg.info().get_msg_type() == "Prepare")
+            .count();
+        assert_eq!(prepares, 1);
+    }
+
+    /// Make sure that a node's own broadcasts are handled without being parsed or checked again,
+    /// or having any signatures verified, while the same message from a peer is still checked
+    #[test]
+    fn self_message_fast_path() {
+        struct PanickingVerifier;
+        impl SignatureVerifier for PanickingVerifier {
+            fn verify(&self, _: &[u8], _: &[u8], _: &[u8]) -> Result<bool, PbftError> {
+                panic!("Signature verified for a self-delivered message");
+            }
+        }
+
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        node.set_self_send(true);
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        state.seq_num = 2;
+
+        // The block's seal is verified when it arrives, but nothing the node sends about it is
+        let block = mock_block_with_seal(2, &mut node, &mut state);
+        node.on_block_new(block.clone(), &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(state.phase, PbftPhase::Preparing);
+        node.set_signature_verifier(Arc::new(PanickingVerifier));
+
+        // The node's own ViewChange carries a seal, signed votes and all
+        node.msg_log
+            .add_consensus_seal(mock_block_id(1), 1, parse_seal(&block).unwrap())
+            .unwrap();
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap_or_else(handle_pbft_err);
+        let view_change = node
+            .sent_messages
+            .iter()
+            .find(|msg| msg.info().get_msg_type() == "ViewChange")
+            .cloned()
+            .expect("ViewChange not broadcast");
+        assert!(view_change.from_self);
+        assert_eq!(
+            view_change
+                .get_view_change_message()
+                .get_seal()
+                .get_previous_commit_votes()
+                .len(),
+            3
+        );
+        assert!(node.msg_log.view_change_from(&[0], 1).is_some());
+
+        // Own messages skip the network check, so switching networks doesn't affect them
+        let mut pre_prepare = node
+            .sent_messages
+            .iter()
+            .find(|msg| msg.info().get_msg_type() == "PrePrepare")
+            .cloned()
+            .expect("PrePrepare not broadcast");
+        state.network_id = "other".into();
+        node.on_peer_message(pre_prepare.clone(), &mut state)
+            .unwrap_or_else(handle_pbft_err);
+        assert_eq!(
+            ParsedMessage::from_bytes(pre_prepare.message_bytes.clone()).unwrap(),
+            pre_prepare
+        );
+
+        pre_prepare.from_self = false;
+        match node.on_peer_message(pre_prepare, &mut state) {
+            Err(PbftError::WrongNetwork(..)) => {}
+            res => panic!("Expected WrongNetwork, got {:?}", res),
+        }
+    }
+
+    /// Test that view changes work as expected, and that nodes take the proper roles after a view
+    /// change
+    #[test]
+    fn view_change() {
+        let mut node1 = mock_node();
+        let cfg = mock_config(4);
+        let mut state1 = PbftState::new(vec![1], 0, &cfg);
+
+        assert!(!state1.is_primary());
+
+        node1
+            .msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+
+        // Receive 3 `ViewChange` messages
+        for peer in 0..3 {
+            // It takes f + 1 `ViewChange` messages to trigger a view change, if it wasn't started
+            // by `propose_view_change()`
+            if peer < 2 {
+                assert_eq!(state1.mode, PbftMode::Normal);
+            } else {
+                assert_eq!(state1.mode, PbftMode::ViewChanging);
+            }
+            let info = make_msg_info(&PbftMessageType::ViewChange, 1, 0, vec![peer]);
+            let mut vc_msg = PbftViewChange::new();
+            vc_msg.set_info(info);
+            vc_msg.set_seal(PbftSeal::new());
+
+            node1
+                .on_peer_message(ParsedMessage::from_view_change_message(vc_msg), &mut state1)
+                .unwrap_or_else(handle_pbft_err);
+        }
+
+        assert!(state1.is_primary());
+        assert_eq!(state1.view, 1);
+    }
+
+    /// Make sure that nodes which receive the quorums for two competing views in different orders
+    /// all end up in the higher view
+    #[test]
+    fn view_change_competing_quorums() {
+        let cfg = mock_config(4);
+        let vc = |view: u64, peer: u8| {
+            let info = make_msg_info(&PbftMessageType::ViewChange, view, 0, vec![peer]);
+            let mut vc_msg = PbftViewChange::new();
+            vc_msg.set_info(info);
+            vc_msg.set_seal(PbftSeal::new());
+            ParsedMessage::from_view_change_message(vc_msg)
+        };
+
+        // `None` is this node's own timeout firing, which puts it back into view changing mode
+        let orders = vec![
+            // The quorum for view 1 is reached first
+            vec![
+                Some((1, 0)),
+                Some((1, 2)),
+                Some((2, 0)),
+                Some((1, 3)),
+                Some((2, 2)),
+                Some((2, 3)),
+            ],
+            // The quorum for view 2 is reached first, then the node starts another view change
+            // before the last message for view 1 arrives
+            vec![
+                Some((1, 0)),
+                Some((2, 0)),
+                Some((2, 2)),
+                Some((1, 2)),
+                Some((2, 3)),
+                None,
+                Some((1, 3)),
+            ],
+            // Interleaved
+            vec![
+                Some((2, 0)),
+                Some((1, 0)),
+                Some((1, 2)),
+                Some((2, 2)),
+                Some((2, 3)),
+                None,
+                Some((1, 3)),
+            ],
+        ];
+
+        for order in orders {
+            let mut node = mock_node();
+            let mut state = PbftState::new(vec![1], 0, &cfg);
+            node.msg_log
+                .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+                .unwrap();
+            node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+                .unwrap();
+
+            for event in order {
+                match event {
+                    Some((view, peer)) => node
+                        .on_peer_message(vc(view, peer), &mut state)
+                        .unwrap_or_else(handle_pbft_err),
+                    None => node
+                        .propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+                        .unwrap(),
+                }
+            }
+
+            assert_eq!(state.view, 2);
+            assert!(!state.is_primary());
+        }
+    }
+
+    /// Create a signed `ViewChange` message, as it would be received from a peer
+    fn mock_signed_view_change(view: u64, peer: u8) -> ParsedMessage {
+        let mut info = make_msg_info(&PbftMessageType::ViewChange, view, 0, vec![peer]);
+        info.set_network_id(mock_config(4).network_id);
+        let mut vc_msg = PbftViewChange::new();
+        vc_msg.set_info(info);
+        vc_msg.set_seal(PbftSeal::new());
+        let mut message = ParsedMessage::from_view_change_message(vc_msg);
+        mock_sign(&mut message, &[peer]);
+
+        message
+    }
+
+    /// Make sure that the new primary justifies a view change with a `NewView` message, and that
+    /// other nodes only accept a `NewView` that carries a quorum of `ViewChange` messages
+    #[test]
+    fn new_view() {
+        let cfg = mock_config(4);
+
+        // Node 1 becomes the primary of view 1 once it has enough ViewChange messages, and
+        // includes 2f of them in its NewView
+        let mut primary = mock_node();
+        let mut primary_state = PbftState::new(vec![1], 0, &cfg);
+        primary
+            .msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+        for peer in &[0, 2, 3] {
+            primary
+                .on_peer_message(mock_signed_view_change(1, *peer), &mut primary_state)
+                .unwrap();
+        }
+        assert_eq!(primary_state.view, 1);
+        assert!(primary_state.is_primary());
+        let new_view = primary.sent_messages.last().unwrap().clone();
+        assert_eq!(new_view.info().get_msg_type(), "NewView");
+        assert_eq!(new_view.info().get_signer_id(), &[1][..]);
+        assert_eq!(new_view.get_new_view_message().get_view_changes().len(), 2);
+
+        // Node 2 missed the ViewChange messages and is still waiting on its own view change
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![2], 0, &cfg);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap();
+
+        let make_new_view = |signer: u8, peers: &[u8]| {
+            let mut info = make_msg_info(&PbftMessageType::NewView, 1, 0, vec![signer]);
+            info.set_network_id(cfg.network_id.clone());
+            let mut nv_msg = PbftNewView::new();
+            nv_msg.set_info(info);
+            nv_msg.set_view_changes(RepeatedField::from(
+                peers
+                    .iter()
+                    .map(|peer| {
+                        let vc = mock_signed_view_change(1, *peer);
+                        let mut vote = PbftSignedCommitVote::new();
+                        vote.set_header_bytes(vc.header_bytes);
+                        vote.set_header_signature(vc.header_signature);
+                        vote.set_message_bytes(vc.message_bytes);
+                        vote
+                    })
+                    .collect::<Vec<_>>(),
+            ));
+            ParsedMessage::from_new_view_message(nv_msg)
+        };
+
+        // Not enough ViewChanges, duplicated ViewChanges, a ViewChange from the new primary
+        // itself, or a NewView from a node that isn't the primary of the view are all rejected
+        let invalid = vec![
+            make_new_view(1, &[0]),
+            make_new_view(1, &[0, 0]),
+            make_new_view(1, &[0, 1]),
+            make_new_view(3, &[0, 2]),
+        ];
+        for msg in invalid {
+            assert!(node.on_peer_message(msg, &mut state).is_err());
+            assert_eq!(state.view, 0);
+            assert_eq!(state.mode, PbftMode::ViewChanging);
+        }
+
+        // A tampered ViewChange fails verification
+        let mut tampered = make_new_view(1, &[0, 3]);
+        if let PbftMessageWrapper::NewView(ref mut nv_msg) = tampered.message {
+            nv_msg.mut_view_changes()[1].set_header_signature(vec![0; 64]);
+        }
+        assert!(node.on_peer_message(tampered, &mut state).is_err());
+        assert_eq!(state.view, 0);
+
+        // So does a ViewChange that another node signed as its own
+        let mut resigned = make_new_view(1, &[0, 3]);
+        if let PbftMessageWrapper::NewView(ref mut nv_msg) = resigned.message {
+            let mut header: ConsensusPeerMessageHeader =
+                protobuf::parse_from_bytes(nv_msg.get_view_changes()[1].get_header_bytes())
+                    .unwrap();
+            header.set_signer_id(vec![2]);
+            let header_bytes = header.write_to_bytes().unwrap();
+            nv_msg.mut_view_changes()[1].set_header_signature(mock_signature(&header_bytes, &[2]));
+            nv_msg.mut_view_changes()[1].set_header_bytes(header_bytes);
+        }
+        match node.on_peer_message(resigned, &mut state) {
+            Err(PbftError::SignerMismatch(ref header_signer, ref signer))
+                if *header_signer == [2] && *signer == [3] => {}
+            res => panic!("Expected SignerMismatch, got {:?}", res),
+        }
+        assert_eq!(state.view, 0);
+
+        // A NewView too far past this node's view is rejected before anything else is checked
+        let mut far = make_new_view(1, &[0, 3]);
+        if let PbftMessageWrapper::NewView(ref mut nv_msg) = far.message {
+            nv_msg.mut_info().set_view(MAX_NEW_VIEW_DISTANCE + 1);
+        }
+        match node.on_peer_message(far, &mut state) {
+            Err(PbftError::InvalidMessage(_)) => {}
+            res => panic!("Expected InvalidMessage, got {:?}", res),
+        }
+        assert_eq!(state.view, 0);
+
+        // The primary's NewView is accepted
+        node.on_peer_message(new_view.clone(), &mut state).unwrap();
+        assert_eq!(state.view, 1);
+        assert_eq!(state.mode, PbftMode::Normal);
+        assert!(!state.is_primary());
+        assert_eq!(state.get_primary_id(), vec![1]);
+
+        // Once it's in the view, the NewView is stale
+        node.on_peer_message(new_view, &mut state).unwrap();
+        assert_eq!(state.view, 1);
+    }
+
+    /// Make sure that a membership change that would leave the network without fault tolerance
+    /// is refused, without panicking, and the node keeps its current peers
+    #[test]
+    fn membership_too_small() {
+        let cfg = mock_config(4);
+        let service = Box::new(MockService::new(mock_config(3).peers));
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        match node.update_membership(mock_block_id(0), &mut state) {
+            Err(PbftError::InsufficientPeers(3)) => {}
+            res => panic!("Expected InsufficientPeers, got {:?}", res),
+        }
+        assert_eq!(state.peer_ids, cfg.peers);
+        assert_eq!(state.f, 1);
+
+        // Committing a block carries on as normal, on the old peers
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+        state.set_phase(PbftPhase::Finished);
+        node.on_block_commit(mock_block_id(1), &mut state);
+        assert_eq!(state.seq_num, 2);
+        assert_eq!(state.peer_ids, cfg.peers);
+        assert_eq!(state.mode, PbftMode::Normal);
+    }
+
+    /// Make sure that the validator rejecting the working block discards it and starts a view
+    /// change, and that rejecting any other block is ignored
+    #[test]
+    fn block_invalid() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+        node.on_block_new(mock_block(1), &mut state).unwrap();
+        state.set_phase(PbftPhase::Checking);
+
+        node.on_block_invalid(&mock_block_id(2), &mut state)
+            .unwrap();
+        assert_eq!(state.mode, PbftMode::Normal);
+        assert!(state.working_block.is_some());
+
+        node.on_block_invalid(&mock_block_id(1), &mut state)
+            .unwrap();
+        assert_eq!(state.mode, PbftMode::ViewChanging);
+        assert_eq!(state.working_block, None);
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+        assert!(!state.commit_timeout.is_running());
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::InvalidBlock)
+        );
+    }
+
+    /// Make sure that a block whose summary in the primary's `PrePrepare` doesn't match the
+    /// validated block's isn't committed, and that the primary that proposed it is replaced
+    #[test]
+    fn block_summary_mismatch() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+        let mut delivered = mock_block(1);
+        delivered.summary = vec![2];
+        node.on_block_new(delivered.clone(), &mut state).unwrap();
+
+        // The primary's PrePrepare is for the same block, but with a different summary
+        let mut proposed = delivered.clone();
+        proposed.summary = vec![1];
+        let pre_prepare = mock_msg(&PbftMessageType::PrePrepare, 0, 1, proposed, vec![0]);
+        node.msg_log.add_message(pre_prepare, &state).unwrap();
+        state.set_phase(PbftPhase::Checking);
+
+        let validated = delivered;
+        let mut blocks = HashMap::new();
+        blocks.insert(mock_block_id(1), validated);
+        node.service = Box::new(MockService {
+            blocks,
+            ..MockService::new(mock_config(4).peers)
+        });
+
+        match node.on_block_valid(&mock_block_id(1), &mut state) {
+            Err(PbftError::BlockSummaryMismatch(ref proposed, ref validated))
+                if proposed == &vec![1] && validated == &vec![2] => {}
+            res => panic!("Expected BlockSummaryMismatch, got {:?}", res),
+        }
+        assert_eq!(state.mode, PbftMode::ViewChanging);
+        assert_eq!(state.working_block, None);
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::SummaryMismatch)
+        );
+        assert!(!node
+            .sent_messages
+            .iter()
+            .any(|msg| msg.info().get_msg_type() == "Commit"));
+        assert_eq!(node.recent_faults().len(), 1);
+        assert_eq!(node.recent_faults()[0].peer_id, vec![0]);
+    }
+
+    /// Make sure that peers read from the on-chain setting are sorted, whatever order they're
+    /// listed in
+    #[test]
+    fn update_membership_sorts_peers() {
+        let cfg = mock_config(4);
+        let service = Box::new(MockService::new(vec![
+            vec![4],
+            vec![1],
+            vec![3],
+            vec![0],
+            vec![2],
+        ]));
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        assert!(node
+            .update_membership(mock_block_id(0), &mut state)
+            .unwrap());
+        assert_eq!(state.peer_ids, mock_config(5).peers);
+        assert_eq!(state.get_primary_id_for_view(4), vec![4]);
+
+        // The same peers, stored out of order, are still put back in order
+        state.peer_ids.reverse();
+        assert!(node
+            .update_membership(mock_block_id(0), &mut state)
+            .unwrap());
+        assert_eq!(state.peer_ids, mock_config(5).peers);
+        assert!(!node
+            .update_membership(mock_block_id(0), &mut state)
+            .unwrap());
+    }
+
+    /// Make sure that the peer weights are reloaded from the settings along with the peers, and
+    /// that seals are explained against them
+    #[test]
+    fn update_membership_weights() {
+        let cfg = mock_config(4);
+        let weights = |weights: &str| MockService {
+            settings: std::iter::once((PEER_WEIGHTS_SETTING_KEY.to_string(), weights.to_string()))
+                .collect(),
+            ..MockService::new(cfg.peers.clone())
+        };
+        let mut node = mock_node();
+        node.service = Box::new(weights(r#"{"00": 5, "01": 1, "02": 1, "03": 1}"#));
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        assert!(!node
+            .update_membership(mock_block_id(0), &mut state)
+            .unwrap());
+        assert!(node.msg_log.is_weighted());
+
+        // Three votes would be enough without weights, but these three don't weigh enough
+        state.seq_num = 7;
+        let light = mock_block_with_seal_from(7, &mut mock_node(), &mut state, &[1, 2, 3]);
+        match node.explain_seal_failure(&light, &state) {
+            Some(SealFailure::InsufficientVotes(6, 3)) => {}
+            res => panic!("Expected InsufficientVotes, got {:?}", res),
+        }
+        let mut builder_state = PbftState::new(vec![3], 6, &cfg);
+        let heavy = mock_block_with_seal_from(7, &mut mock_node(), &mut builder_state, &[0, 1]);
+        assert_eq!(node.explain_seal_failure(&heavy, &state), None);
+
+        // Weights that leave a peer out aren't applied
+        node.service = Box::new(weights(r#"{"00": 5, "01": 1}"#));
+        match node.update_membership(mock_block_id(0), &mut state) {
+            Err(PbftError::InvalidPeersSetting(ref key, _)) if key == PEER_WEIGHTS_SETTING_KEY => {}
+            res => panic!("Expected InvalidPeersSetting, got {:?}", res),
+        }
+        assert!(node.msg_log.is_weighted());
+
+        // Once the setting is gone, quorums are counted in nodes again
+        node.service = Box::new(MockService::new(cfg.peers.clone()));
+        node.update_membership(mock_block_id(0), &mut state)
+            .unwrap();
+        assert!(!node.msg_log.is_weighted());
+    }
+
+    /// Make sure that with weighted quorums, a node can build a seal from commits that weigh
+    /// enough along with its own, even if there are fewer of them than `2f`
+    #[test]
+    fn weighted_seal() {
+        let mut cfg = mock_config(4);
+        cfg.peer_weights = vec![(vec![0], 5), (vec![1], 1), (vec![2], 1), (vec![3], 1)]
+            .into_iter()
+            .collect();
+        let weighted_node = || {
+            let service = Box::new(MockService::new(cfg.peers.clone()));
+            let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+            node.set_signature_verifier(Arc::new(MockVerifier));
+            node
+        };
+        let commit = |signer: u8| {
+            let mut message = mock_msg(&PbftMessageType::Commit, 0, 6, mock_block(6), vec![signer]);
+            mock_sign(&mut message, &[signer]);
+            message.from_self = signer == 1;
+            message
+        };
+
+        // Nodes 0 and 1 commit block 6; node 1's commit is its own, so it's left out of the seal
+        let mut node = weighted_node();
+        let mut state = PbftState::new(vec![1], 5, &cfg);
+        for signer in 0..2 {
+            node.msg_log.add_message(commit(signer), &state).unwrap();
+        }
+        node.check_seal_buildable(&mock_block_id(6), &state)
+            .unwrap_or_else(handle_pbft_err);
+
+        state.seq_num = 7;
+        state.last_committed_block = Some(mock_block_id(6));
+        let block = Block {
+            signer_id: vec![1],
+            summary: vec![1, 2, 3],
+            payload: node.build_seal(&state, vec![1, 2, 3]).unwrap(),
+            ..mock_block(7)
+        };
+        assert_eq!(
+            parse_seal(&block)
+                .unwrap()
+                .get_previous_commit_votes()
+                .len(),
+            1
+        );
+        let verifier_state = PbftState::new(vec![2], 6, &cfg);
+        assert_eq!(
+            weighted_node().explain_seal_failure(&block, &verifier_state),
+            None
+        );
+
+        // Nodes 1, 2, and 3 would be a quorum by count, but not by weight
+        let mut node = weighted_node();
+        let mut state = PbftState::new(vec![1], 5, &cfg);
+        for signer in 1..4 {
+            node.msg_log.add_message(commit(signer), &state).unwrap();
+        }
+        assert!(node
+            .check_seal_buildable(&mock_block_id(6), &state)
+            .is_err());
+        state.seq_num = 7;
+        state.last_committed_block = Some(mock_block_id(6));
+        assert!(node.build_seal(&state, vec![1, 2, 3]).is_err());
+    }
+
+    /// Make sure that refreshing membership on demand picks up a changed peers setting, recomputes
+    /// `f`, and updates the node's role for the new peers
+    #[test]
+    fn refresh_membership() {
+        let cfg = mock_config(4);
+        let service_with_peers = |peers: Vec<PeerId>| Box::new(MockService::new(peers));
+        let mut node = PbftNode::new(
+            &cfg,
+            service_with_peers(cfg.peers.clone()),
+            Arc::new(PbftMetrics::new()),
+        );
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        assert!(!node
+            .refresh_membership(mock_block_id(0), &mut state)
+            .unwrap());
+
+        // Node 0 is removed, so node 1 is the primary of view 0
+        let peers: Vec<PeerId> = (1..8).map(|i| vec![i]).collect();
+        node.service = service_with_peers(peers.clone());
+        assert!(node
+            .refresh_membership(mock_block_id(0), &mut state)
+            .unwrap());
+        assert_eq!(state.peer_ids, peers);
+        assert_eq!(state.f, 2);
+        assert!(state.is_primary());
+        assert!(state.check_invariants().is_ok());
+
+        // Too few peers is an error, and the current peers stay
+        node.service = service_with_peers(vec![vec![1], vec![2]]);
+        match node.refresh_membership(mock_block_id(0), &mut state) {
+            Err(PbftError::InsufficientPeers(2)) => {}
+            res => panic!("Expected InsufficientPeers, got {:?}", res),
+        }
+        assert_eq!(state.peer_ids, peers);
+        assert_eq!(state.f, 2);
+    }
+
+    /// Make sure that a failed settings read returns an error right away instead of waiting, that
+    /// the validator isn't asked again until the retry delay has passed, and that a successful
+    /// read clears the failures
+    #[test]
+    fn settings_retry() {
+        let mut cfg = mock_config(4);
+        cfg.settings_retry_delay = Duration::from_secs(60);
+        let failures = Arc::new(AtomicUsize::new(1));
+        let service = Box::new(MockService {
+            settings_failures: failures.clone(),
+            ..MockService::new(mock_config(5).peers)
+        });
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        match node.update_membership(mock_block_id(0), &mut state) {
+            Err(PbftError::SettingsUnavailable(1, _)) => {}
+            res => panic!("Expected SettingsUnavailable, got {:?}", res),
+        }
+        assert_eq!(failures.load(Ordering::SeqCst), 0);
+
+        // The validator would answer now, but it isn't asked until the delay has passed
+        match node.update_membership(mock_block_id(0), &mut state) {
+            Err(PbftError::SettingsUnavailable(1, _)) => {}
+            res => panic!("Expected SettingsUnavailable, got {:?}", res),
+        }
+        assert_eq!(state.peer_ids, mock_config(4).peers);
+
+        node.settings_retry_at = Some(Instant::now());
+        assert!(node
+            .update_membership(mock_block_id(0), &mut state)
+            .unwrap());
+        assert_eq!(state.peer_ids, mock_config(5).peers);
+        assert_eq!(node.settings_failures, 0);
+        assert_eq!(node.settings_retry_at, None);
+    }
+
+    /// Make sure that only so many blocks are deferred, keeping the nearest ones, and that the
+    /// deferred blocks a resync moves past are forgotten
+    #[test]
+    fn deferred_blocks_bounded() {
+        let mut cfg = mock_config(4);
+        cfg.seal_prefetch_depth = 1;
+        let blocks = mock_sealed_chain(5);
+        let mut node = mock_chain_node(&cfg, &blocks);
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        let last = 2 + MAX_DEFERRED_BLOCKS as u64 + 3;
+        for block_num in 3..=last {
+            node.on_block_new(mock_block(block_num), &mut state)
+                .unwrap();
+        }
+        assert_eq!(node.deferred_blocks.len(), MAX_DEFERRED_BLOCKS);
+        assert_eq!(
+            node.deferred_blocks.iter().map(|b| b.block_num).max(),
+            Some(2 + MAX_DEFERRED_BLOCKS as u64)
+        );
+
+        node.force_resync(&mut state).unwrap();
+        assert_eq!(state.seq_num, 6);
+        assert_eq!(
+            node.deferred_blocks.iter().map(|b| b.block_num).min(),
+            Some(6)
+        );
+        assert_eq!(node.deferred_blocks.len(), MAX_DEFERRED_BLOCKS - 3);
+    }
+
+    /// Make sure that a block whose seal can't be verified because the settings can't be read is
+    /// deferred until they can, without a fault against its publisher or a view change
+    #[test]
+    fn seal_verification_waits_for_settings() {
+        let cfg = mock_config(4);
+        let mut builder = mock_node();
+        let mut builder_state = PbftState::new(vec![1], 1, &cfg);
+        let block = mock_block_with_seal(2, &mut builder, &mut builder_state);
+
+        let service = Box::new(MockService {
+            settings_failures: Arc::new(AtomicUsize::new(1)),
+            ..MockService::new(cfg.peers.clone())
+        });
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![1], 1, &cfg);
+
+        match node.on_block_new(block, &mut state) {
+            Err(PbftError::SettingsUnavailable(1, _)) => {}
+            res => panic!("Expected SettingsUnavailable, got {:?}", res),
+        }
+        assert!(node.recent_faults().is_empty());
+        assert_eq!(node.last_view_change_reason(), None);
+        assert_eq!(state.mode, PbftMode::Normal);
+        assert_eq!(node.deferred_blocks.len(), 1);
+
+        // Retrying before the delay has passed leaves the block deferred
+        node.release_deferred_blocks(&mut state);
+        assert_eq!(node.deferred_blocks.len(), 1);
+
+        node.settings_retry_at = Some(Instant::now());
+        node.release_deferred_blocks(&mut state);
+        assert!(node.deferred_blocks.is_empty());
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::BlockNew, 2)
+                .len(),
+            1
+        );
+    }
+
+    /// Make sure that a node configured to read its peers from another setting reads membership
+    /// changes from that setting
+    #[test]
+    fn custom_peers_setting_key() {
+        let mut cfg = mock_config(4);
+        cfg.peers_setting_key = "example.pbft.members".into();
+        let service = Box::new(MockService {
+            peers_setting_key: "example.pbft.members".into(),
+            ..MockService::new(mock_config(5).peers)
+        });
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+
+        assert!(node
+            .update_membership(mock_block_id(0), &mut state)
+            .unwrap());
+        assert_eq!(state.peer_ids, mock_config(5).peers);
+    }
+
+    /// Make sure that the live peer set follows connects and disconnects, and that only members
+    /// count toward a live quorum, including a peer that connected before it joined the network
+    #[test]
+    fn live_peers() {
+        let cfg = mock_config(4);
+        let mut joined = mock_config(4).peers;
+        joined.push(vec![9]);
+        let service = Box::new(MockService::new(joined));
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        assert!(state.live_peers.is_empty());
+
+        node.on_peer_connected(vec![1], &mut state);
+        node.on_peer_connected(vec![2], &mut state);
+        node.on_peer_connected(vec![9], &mut state);
+        let expected: HashSet<PeerId> = vec![vec![1], vec![2], vec![9]].into_iter().collect();
+        assert_eq!(state.live_peers, expected);
+        assert!(state.has_live_quorum());
+
+        // A disconnect of a peer that wasn't connected changes nothing
+        node.on_peer_disconnected(vec![3], &mut state);
+        assert_eq!(state.live_peers, expected);
+
+        // Peer 9 isn't a member yet, so it doesn't count
+        node.on_peer_disconnected(vec![2], &mut state);
+        node.on_peer_connected(vec![1], &mut state);
+        let expected: HashSet<PeerId> = vec![vec![1], vec![9]].into_iter().collect();
+        assert_eq!(state.live_peers, expected);
+        assert!(!state.has_live_quorum());
+
+        // Once it joins, its earlier connection counts
+        assert!(node
+            .update_membership(mock_block_id(0), &mut state)
+            .unwrap());
+        assert_eq!(state.live_peers, expected);
+        assert!(state.has_live_quorum());
+    }
+
+    /// Feed the node a message for each reason it rejects messages, and make sure each one is
+    /// counted under its reason
+    #[test]
+    fn rejection_metrics() {
+        let mut cfg = mock_config(4);
+        cfg.max_message_age = Duration::from_secs(60);
+        let metrics = Arc::new(PbftMetrics::new());
+        let service = Box::new(MockService::new(mock_config(4).peers));
+        let mut node = PbftNode::new(&cfg, service, Arc::clone(&metrics));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+
+        // Node 1 is the primary of view 1
+        let mut state = PbftState::new(vec![2], 0, &cfg);
+        state.view = 1;
+        let block = mock_block(1);
+        let mut other_block = mock_block(1);
+        other_block.block_id = mock_block_id(10);
+        node.on_block_new(other_block.clone(), &mut state).unwrap();
+        node.on_block_new(block.clone(), &mut state).unwrap();
+        node.on_peer_message(
+            mock_msg(&PbftMessageType::PrePrepare, 1, 1, block.clone(), vec![1]),
+            &mut state,
+        )
+        .unwrap();
+
+        let mut wrong_network = mock_msg(&PbftMessageType::Prepare, 1, 1, block.clone(), vec![0]);
+        wrong_network.info_mut().set_network_id("other".into());
+        let mut old = mock_msg(&PbftMessageType::Prepare, 1, 1, block.clone(), vec![0]);
+        old.info_mut()
+            .set_timestamp(timing::unix_millis() - 3_600_000);
+        let mut new_view = PbftNewView::new();
+        new_view.set_info(make_msg_info(&PbftMessageType::NewView, 2, 1, vec![3]));
+
+        let mut unknown_type = mock_msg(&PbftMessageType::Commit, 1, 1, block.clone(), vec![0]);
+        unknown_type.info_mut().set_msg_type("Comit".into());
+
+        let cases = vec![
+            (unknown_type, RejectionReason::UnknownType),
+            (wrong_network, RejectionReason::WrongNetwork),
+            (old, RejectionReason::InvalidTimestamp),
+            (
+                mock_msg(&PbftMessageType::Commit, 0, 1, block.clone(), vec![0]),
+                RejectionReason::StaleView,
+            ),
+            (
+                mock_msg(&PbftMessageType::Commit, 2, 1, block.clone(), vec![0]),
+                RejectionReason::FutureView,
+            ),
+            (
+                mock_msg(&PbftMessageType::PrePrepare, 1, 1, block.clone(), vec![3]),
+                RejectionReason::NotFromPrimary,
+            ),
+            (
+                mock_msg(&PbftMessageType::PrePrepare, 1, 1, other_block, vec![1]),
+                RejectionReason::Equivocation,
+            ),
+            (
+                ParsedMessage::from_new_view_message(new_view),
+                RejectionReason::InvalidNewView,
+            ),
+            (
+                mock_msg(&PbftMessageType::Commit, 1, 100, block.clone(), vec![0]),
+                RejectionReason::TooFarAhead,
+            ),
+        ];
+
+        for (msg, reason) in cases {
+            assert!(
+                node.on_peer_message(msg, &mut state).is_err()
+                    || reason == RejectionReason::StaleView
+            );
+            assert_eq!(metrics.snapshot().rejections(reason), 1, "{:?}", reason);
+        }
+        assert_eq!(metrics.snapshot().rejections.len(), 9);
+
+        // Messages past the high watermark count as too far ahead as well
+        state.watermark_window = 2;
+        assert!(node
+            .on_peer_message(
+                mock_msg(&PbftMessageType::Prepare, 1, 5, block.clone(), vec![0]),
+                &mut state,
+            )
+            .is_err());
+        assert_eq!(
+            metrics.snapshot().rejections(RejectionReason::TooFarAhead),
+            2
+        );
+    }
+
+    /// Make sure that the metrics passed to the node count a view change exactly once, no matter
+    /// how many `ViewChange` messages it takes to complete
+    #[test]
+    fn view_change_metrics() {
+        let cfg = mock_config(4);
+        let metrics = Arc::new(PbftMetrics::new());
+        let service = Box::new(MockService::new(mock_config(4).peers));
+        let mut node = PbftNode::new(&cfg, service, Arc::clone(&metrics));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap();
+        for peer in &[0, 2, 3] {
+            node.on_peer_message(mock_signed_view_change(1, *peer), &mut state)
+                .unwrap();
+        }
+
+        assert_eq!(state.view, 1);
+        assert_eq!(metrics.snapshot().view_changes, 1);
+    }
+
+    /// Make sure that a node that is behind the chain head syncs up with it when a view change
+    /// makes it the primary, and gets the head's Commits from the other nodes to publish a block
+    #[test]
+    fn view_change_unsynced_primary() {
+        let cfg = mock_config(4);
+        let mut node = mock_chain_node(&cfg, &mock_sealed_chain(5));
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        connect_peers(&mut node, &mut state);
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+        state.phase = PbftPhase::Preparing;
+        state.commit_timeout.start();
+
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+
+        for peer in 0..3 {
+            let info = make_msg_info(&PbftMessageType::ViewChange, 1, 0, vec![peer]);
+            let mut vc_msg = PbftViewChange::new();
+            vc_msg.set_info(info);
+            vc_msg.set_seal(PbftSeal::new());
+
+            node.on_peer_message(ParsedMessage::from_view_change_message(vc_msg), &mut state)
+                .unwrap_or_else(handle_pbft_err);
         }
-        fn get_state(
-            &mut self,
-            _block_id: BlockId,
-            _addresses: Vec<String>,
-        ) -> Result<HashMap<String, Vec<u8>>, Error> {
-            Ok(Default::default())
+
+        assert!(state.is_primary());
+        assert_eq!(state.view, 1);
+        assert_eq!(state.seq_num, 6);
+        assert_eq!(state.working_block, None);
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+        assert_eq!(state.last_committed_block, Some(mock_block_id(5)));
+        assert!(!state.commit_timeout.is_running());
+
+        // It has none of the Commits for the head to seal its block with, so it asks for them
+        let requested: Vec<PeerId> = node
+            .sent_direct
+            .iter()
+            .filter(|(_, msg)| msg.info().get_msg_type() == "VoteRequest")
+            .map(|(peer, msg)| {
+                assert_eq!(msg.info().get_seq_num(), 5);
+                assert_eq!(msg.get_vote_request_message().get_vote_type(), "Commit");
+                peer.clone()
+            })
+            .collect();
+        assert_eq!(requested, vec![vec![0], vec![2], vec![3]]);
+        assert!(node.try_publish(&mut state).is_err());
+
+        // The head was committed in the view before; those Commits are what the seal is built from
+        for peer in &[0, 2] {
+            let mut commit = mock_msg(&PbftMessageType::Commit, 0, 5, mock_block(5), vec![*peer]);
+            mock_sign(&mut commit, &[*peer]);
+            node.on_peer_message(commit, &mut state)
+                .unwrap_or_else(handle_pbft_err);
         }
+        assert!(node.try_publish(&mut state).unwrap().is_some());
     }
 
-    /// Create a node, based on a given ID
-    fn mock_node(node_id: PeerId) -> PbftNode {
-        let service: Box<MockService> = Box::new(MockService {
-            // Create genesis block (but with actual ID)
-            chain: vec![mock_block_id(0)],
-        });
+    /// Make sure that the proposal deadline is only set for a primary that has yet to propose
+    #[test]
+    fn primary_proposal_deadline() {
         let cfg = mock_config(4);
-        PbftNode::new(&cfg, service, node_id == vec![0])
-    }
+        let node = mock_node();
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        assert!(state.is_primary());
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
 
-    /// Create a deterministic BlockId hash based on a block number
-    fn mock_block_id(num: u64) -> BlockId {
-        BlockId::from(hash_sha256(
-            format!("I'm a block with block num {}", num).as_bytes(),
-        ))
-    }
+        let deadline = node.primary_proposal_deadline(&state).unwrap();
+        assert!(deadline > Instant::now());
+        assert!(deadline <= Instant::now() + cfg.faulty_primary_timeout);
 
-    /// Create a mock Block, including only the BlockId, the BlockId of the previous block, and the
-    /// block number
-    fn mock_block(num: u64) -> Block {
-        Block {
-            block_id: mock_block_id(num),
-            previous_id: mock_block_id(num - 1),
-            signer_id: PeerId::from(vec![]),
-            block_num: num,
-            payload: vec![],
-            summary: vec![],
-        }
+        state.switch_phase(PbftPhase::Preparing).unwrap();
+        assert_eq!(node.primary_proposal_deadline(&state), None);
+
+        let secondary_state = PbftState::new(vec![1], 0, &cfg);
+        assert_eq!(
+            mock_node().primary_proposal_deadline(&secondary_state),
+            None
+        );
     }
 
-    /// Creates a block with a valid consensus seal for the previous block
-    fn mock_block_with_seal(num: u64, node: &mut PbftNode, state: &mut PbftState) -> Block {
-        let head = mock_block(num - 1);
-        let mut block = mock_block(num);
-        block.summary = vec![1, 2, 3];
-        let context = create_context("secp256k1").unwrap();
+    /// Make sure that a resigning primary stops proposing and starts a view change
+    #[test]
+    fn resign_primary() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+        assert!(state.is_primary());
 
-        for i in 0..3 {
-            let mut info = PbftMessageInfo::new();
-            info.set_msg_type("Commit".into());
-            info.set_view(0);
-            info.set_seq_num(num - 1);
-            info.set_signer_id(vec![i]);
+        node.resign_primary(&mut state).unwrap();
 
-            let mut block = PbftBlock::new();
-            block.set_block_id(head.block_id.clone());
+        assert!(!state.is_primary());
+        assert_eq!(state.mode, PbftMode::ViewChanging);
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::Resigned)
+        );
+        let view_changes: Vec<_> = node
+            .sent_messages
+            .iter()
+            .filter(|msg| msg.info().get_msg_type() == "ViewChange")
+            .collect();
+        assert_eq!(view_changes.len(), 1);
+        assert_eq!(view_changes[0].info().get_view(), 1);
 
-            let mut msg = PbftMessage::new();
-            msg.set_info(info);
-            msg.set_block(block);
+        // A new block doesn't get proposed
+        node.on_block_new(mock_block(1), &mut state).unwrap();
+        assert!(node
+            .sent_messages
+            .iter()
+            .all(|msg| msg.info().get_msg_type() != "PrePrepare"));
+    }
 
-            let mut message = ParsedMessage::from_pbft_message(msg);
+    /// Make sure that a node can ask a peer for a vote it didn't receive, and that the peer sends
+    /// its vote back, stamped again
+    #[test]
+    fn vote_request() {
+        let cfg = mock_config(4);
+        let block = mock_block(1);
+        let pre_prepare = mock_msg(&PbftMessageType::PrePrepare, 0, 1, block.clone(), vec![0]);
 
-            let key = context.new_random_private_key().unwrap();
-            let pub_key = context.get_public_key(&*key).unwrap();
-            let mut header = ConsensusPeerMessageHeader::new();
+        // Node 1 broadcasts its Prepare (and logs it, since self-sending is on)
+        let mut node1 = mock_node();
+        node1.set_self_send(true);
+        let mut state1 = PbftState::new(vec![1], 0, &cfg);
+        node1.on_block_new(block.clone(), &mut state1).unwrap();
+        node1
+            .on_peer_message(pre_prepare.clone(), &mut state1)
+            .unwrap();
+
+        // Node 2 never got it, so it asks everyone it hasn't heard from
+        let mut node2 = mock_node();
+        let mut state2 = PbftState::new(vec![2], 0, &cfg);
+        node2.on_block_new(block.clone(), &mut state2).unwrap();
+        node2.on_peer_message(pre_prepare, &mut state2).unwrap();
+        node2.request_missing_votes(&mut state2).unwrap();
+
+        let (_, request) = node2
+            .sent_direct
+            .iter()
+            .find(|(peer, _)| peer == &vec![1])
+            .cloned()
+            .expect("No vote request sent to node 1");
+        assert_eq!(request.info().get_msg_type(), "VoteRequest");
+        assert_eq!(
+            request.get_vote_request_message().get_vote_type(),
+            "Prepare"
+        );
+        assert!(node2.sent_direct.iter().all(|(peer, _)| peer != &vec![2]));
 
-            header.set_signer_id(pub_key.as_slice().to_vec());
-            header.set_content_sha512(hash_sha512(&message.message_bytes));
+        let logged = node1
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::Prepare, 1)
+            .into_iter()
+            .find(|vote| vote.info().get_signer_id() == [1])
+            .cloned()
+            .expect("Node 1 didn't log its Prepare");
+        node1.on_peer_message(request, &mut state1).unwrap();
+        let (peer, vote) = node1.sent_direct[0].clone();
+        assert_eq!(peer, vec![2]);
+        assert_eq!(vote.info().get_msg_type(), "Prepare");
+        assert_eq!(vote.info().get_signer_id(), &[1]);
+        assert_eq!(vote.get_block(), logged.get_block());
+        assert!(vote.info().get_timestamp() >= logged.info().get_timestamp());
+        assert!(vote.info().get_timestamp() > 0);
+
+        node2.on_peer_message(vote, &mut state2).unwrap();
+        assert_eq!(
+            node2
+                .msg_log
+                .get_messages_of_type_seq(&PbftMessageType::Prepare, 1)
+                .len(),
+            1
+        );
+    }
 
-            let header_bytes = header.write_to_bytes().unwrap();
-            let header_signature =
-                hex::decode(context.sign(&header_bytes, &*key).unwrap()).unwrap();
+    /// Make sure that a request for a `Commit` for a block that was already committed gets the
+    /// one cast in an earlier view, but that other votes are only re-sent for the view asked for
+    #[test]
+    fn vote_request_committed_block() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        for msg_type in &[PbftMessageType::Prepare, PbftMessageType::Commit] {
+            let mut vote = mock_msg(msg_type, 0, 1, mock_block(1), vec![1]);
+            vote.from_self = true;
+            node.msg_log.add_message(vote, &state).unwrap();
+        }
+        state.seq_num = 2;
+        state.view = 2;
+
+        let request = |vote_type: &str, seq_num: u64| {
+            let mut request = PbftVoteRequest::new();
+            request.set_info(make_msg_info(
+                &PbftMessageType::VoteRequest,
+                2,
+                seq_num,
+                vec![2],
+            ));
+            request.set_vote_type(vote_type.into());
+            ParsedMessage::from_vote_request_message(request)
+        };
 
-            message.from_self = false;
-            message.header_bytes = header_bytes;
-            message.header_signature = header_signature;
+        node.on_peer_message(request("Prepare", 1), &mut state)
+            .unwrap();
+        assert!(node.sent_direct.is_empty());
 
-            node.msg_log.add_message(message, state).unwrap();
-        }
+        node.on_peer_message(request("Commit", 1), &mut state)
+            .unwrap();
+        let (peer, vote) = node.sent_direct[0].clone();
+        assert_eq!(peer, vec![2]);
+        assert_eq!(vote.info().get_msg_type(), "Commit");
+        assert_eq!(vote.info().get_view(), 0);
+    }
 
-        block.payload = node.build_seal(state, vec![1, 2, 3]).unwrap();
+    /// Make sure that view changes start correctly
+    #[test]
+    fn propose_view_change() {
+        let mut node1 = mock_node();
+        let cfg = mock_config(4);
+        let mut state1 = PbftState::new(vec![1], 0, &cfg);
+        assert_eq!(state1.mode, PbftMode::Normal);
 
-        block
+        node1
+            .msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+
+        node1
+            .propose_view_change(&mut state1, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap_or_else(handle_pbft_err);
+
+        assert_eq!(state1.mode, PbftMode::ViewChanging);
     }
 
-    /// Create a mock serialized PbftMessage
-    fn mock_msg(
-        msg_type: &PbftMessageType,
-        view: u64,
-        seq_num: u64,
-        block: Block,
-        from: PeerId,
-    ) -> ParsedMessage {
-        let info = make_msg_info(&msg_type, view, seq_num, from);
+    /// Make sure that a view change that doesn't get enough support moves on to the view after
+    /// its target, waiting longer each time, and that its timeout stops once it's done
+    #[test]
+    fn view_change_timeout() {
+        let mut cfg = mock_config(4);
+        cfg.view_change_duration = Duration::from_millis(20);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        let clock = MockClock::new();
+        state.set_clock(clock.clone());
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap();
+        assert_eq!(state.view_change_target(), Some(1));
+        assert!(!node.check_view_change_timeout_expired(&mut state));
+
+        // No other node responds before the timeout expires
+        clock.advance(Duration::from_millis(30));
+        assert!(node.check_view_change_timeout_expired(&mut state));
+        node.retry_view_change(&mut state).unwrap();
+        assert_eq!(state.mode, PbftMode::ViewChanging);
+        assert_eq!(state.view, 0);
+        assert_eq!(state.view_change_target(), Some(2));
+        assert_eq!(
+            state.view_change_timeout.duration(),
+            cfg.view_change_duration * 2
+        );
+        assert_eq!(state.view_change_attempts, 2);
 
-        let mut pbft_msg = PbftMessage::new();
-        pbft_msg.set_info(info);
-        pbft_msg.set_block(pbft_block_from_block(block.clone()));
+        let vc = node.sent_messages.last().unwrap();
+        assert_eq!(vc.info().get_msg_type(), "ViewChange");
+        assert_eq!(vc.info().get_view(), 2);
 
-        ParsedMessage::from_pbft_message(pbft_msg)
+        // Once the view change is done there's nothing to time out
+        state.discard_current_block();
+        assert!(!state.view_change_timeout.is_running());
+        assert!(!node.check_view_change_timeout_expired(&mut state));
+        assert_eq!(state.view_change_target(), None);
     }
 
-    fn handle_pbft_err(e: PbftError) {
-        match e {
-            PbftError::Timeout => (),
-            PbftError::WrongNumMessages(_, _, _) | PbftError::NotReadyForMessage => {
-                println!("{}", e)
-            }
-            _ => panic!("{}", e),
+    /// Make sure that a node restarted in the middle of a view change goes back to view changing
+    /// toward the same view, and sends its `ViewChange` again even though its log was lost
+    #[test]
+    fn resume_view_change() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        let mut seal = PbftSeal::new();
+        seal.set_previous_id(mock_block_id(0));
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, seal.clone())
+            .unwrap();
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap();
+        assert_eq!(state.view_change_target(), Some(1));
+
+        // "Restart" from the persisted state, with a new node that has an empty log
+        let bytes = state.serialize_compact();
+        let mut restored = PbftState::deserialize_compact(&bytes, &cfg).unwrap();
+        let mut restarted = mock_node();
+        assert_eq!(restored.mode, PbftMode::ViewChanging);
+        assert_eq!(restored.view_change_target(), Some(1));
+
+        restarted.resume_view_change(&mut restored).unwrap();
+        assert_eq!(restarted.sent_messages.len(), 1);
+        let vc = &restarted.sent_messages[0];
+        assert_eq!(vc.info().get_msg_type(), "ViewChange");
+        assert_eq!(vc.info().get_view(), 1);
+        assert_eq!(vc.info().get_seq_num(), 0);
+        match vc.message {
+            PbftMessageWrapper::ViewChange(ref msg) => assert_eq!(msg.get_seal(), &seal),
+            _ => panic!("Expected a ViewChange"),
         }
+
+        // Nothing to resume once the view change is done
+        restored.discard_current_block();
+        restarted.resume_view_change(&mut restored).unwrap();
+        assert_eq!(restarted.sent_messages.len(), 1);
+        assert_eq!(restored.view_change_target(), None);
     }
 
-    /// Make sure that receiving a `BlockNew` update works as expected for block #1
+    /// Make sure that the reason for the most recent view change is recorded for each of the ways
+    /// a view change can be started
     #[test]
-    fn block_new_initial() {
-        // NOTE: Special case for primary node
-        let mut node0 = mock_node(vec![0]);
+    fn view_change_reasons() {
         let cfg = mock_config(4);
-        let mut state0 = PbftState::new(vec![0], 0, &cfg);
-        node0.on_block_new(mock_block(1), &mut state0).unwrap();
-        assert_eq!(state0.phase, PbftPhase::PrePreparing);
-        assert_eq!(state0.seq_num, 1);
+        let new_node = || {
+            let mut node = mock_node();
+            node.msg_log
+                .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+                .unwrap();
+            (node, PbftState::new(vec![1], 0, &cfg))
+        };
+
+        // Faulty primary timeout (started by the engine)
+        let (mut node, mut state) = new_node();
+        assert_eq!(node.last_view_change_reason(), None);
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap();
         assert_eq!(
-            state0.working_block,
-            Some(pbft_block_from_block(mock_block(1)))
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::FaultyPrimaryTimeout)
         );
 
-        // Try the next block
-        let mut node1 = mock_node(vec![1]);
-        let mut state1 = PbftState::new(vec![], 0, &cfg);
-        node1
-            .on_block_new(mock_block(1), &mut state1)
-            .unwrap_or_else(handle_pbft_err);
-        assert_eq!(state1.phase, PbftPhase::PrePreparing);
+        // f + 1 ViewChange messages
+        let (mut node, mut state) = new_node();
+        for peer in 2..4 {
+            let mut vc_msg = PbftViewChange::new();
+            vc_msg.set_info(make_msg_info(
+                &PbftMessageType::ViewChange,
+                1,
+                0,
+                vec![peer],
+            ));
+            vc_msg.set_seal(PbftSeal::new());
+            node.on_peer_message(ParsedMessage::from_view_change_message(vc_msg), &mut state)
+                .unwrap_or_else(handle_pbft_err);
+        }
         assert_eq!(
-            state1.working_block,
-            Some(pbft_block_from_block(mock_block(1)))
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::ViewChangeMessages)
         );
-    }
 
-    #[test]
-    fn block_new_first_10_blocks() {
-        let mut node = mock_node(vec![0]);
-        let cfg = mock_config(4);
-        let mut state = PbftState::new(vec![0], 0, &cfg);
+        // Invalid consensus seal
+        let (mut node, mut state) = new_node();
+        state.seq_num = 2;
+        let mut block = mock_block(2);
+        block.payload = vec![1, 2, 3];
+        assert!(node.on_block_new(block, &mut state).is_err());
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::InvalidSeal)
+        );
 
-        let block_0_id = mock_block_id(0);
+        // Forced rotation of the primary
+        let (mut node, mut state) = new_node();
+        state.forced_view_change_period = 2;
+        state.phase = PbftPhase::Finished;
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+        node.on_block_commit(mock_block_id(1), &mut state);
+        assert_eq!(state.view, 1);
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::ForcedRotation)
+        );
 
-        // Assert starting state
-        let head = node.service.get_chain_head().unwrap();
-        assert_eq!(head.block_num, 0);
-        assert_eq!(head.block_id, block_0_id);
-        assert_eq!(head.previous_id, block_0_id);
+        // Change to the on-chain peers
+        let (mut node, mut state) = new_node();
+        node.service = Box::new(MockService::new(mock_config(5).peers));
+        state.phase = PbftPhase::Finished;
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+        node.on_block_commit(mock_block_id(1), &mut state);
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::MembershipChange)
+        );
 
-        assert_eq!(state.id, vec![0]);
-        assert_eq!(state.view, 0);
-        assert_eq!(state.phase, PbftPhase::PrePreparing);
-        assert_eq!(state.mode, PbftMode::Normal);
-        assert_eq!(state.peer_ids, (0..4).map(|i| vec![i]).collect::<Vec<_>>());
-        assert_eq!(state.f, 1);
-        assert_eq!(state.forced_view_change_period, 30);
-        assert_eq!(state.working_block, None);
-        assert!(state.is_primary());
+        // The validator rejecting the working block
+        let (mut node, mut state) = new_node();
+        node.on_block_new(mock_block(1), &mut state).unwrap();
+        state.set_phase(PbftPhase::Checking);
+        node.on_block_invalid(&mock_block_id(1), &mut state)
+            .unwrap();
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::InvalidBlock)
+        );
 
-        // Handle the first block and assert resulting state
+        // The primary stepping down
+        let (mut node, _) = new_node();
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        node.resign_primary(&mut state).unwrap();
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::Resigned)
+        );
+
+        // Commit timeout (checked on each tick)
+        let mut timeout_cfg = mock_config(4);
+        timeout_cfg.commit_timeout = Duration::from_millis(20);
+        let (mut node, _) = new_node();
+        let mut state = PbftState::new(vec![1], 0, &timeout_cfg);
+        let clock = MockClock::new();
+        state.set_clock(clock.clone());
         node.on_block_new(mock_block(1), &mut state).unwrap();
+        clock.advance(Duration::from_millis(30));
+        node.on_tick(&mut state);
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::CommitTimeout)
+        );
 
-        let head = node.service.get_chain_head().unwrap();
-        assert_eq!(head.block_num, 0);
-        assert_eq!(head.block_id, block_0_id);
-        assert_eq!(head.previous_id, block_0_id);
+        // A block from the primary that doesn't build on the last committed block
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 1, &cfg);
+        state.last_committed_block = Some(mock_block_id(9));
+        node.msg_log
+            .add_consensus_seal(mock_block_id(9), 1, PbftSeal::new())
+            .unwrap();
+        let mut builder_state = PbftState::new(vec![], 1, &cfg);
+        let mut forked =
+            mock_block_with_seal_from(2, &mut mock_node(), &mut builder_state, &[1, 2, 3]);
+        forked.signer_id = vec![0];
+        assert!(node.on_block_new(forked, &mut state).is_err());
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::ForkedBlock)
+        );
 
-        assert_eq!(state.id, vec![0]);
-        assert_eq!(state.seq_num, 1);
-        assert_eq!(state.view, 0);
-        assert_eq!(state.phase, PbftPhase::PrePreparing);
-        assert_eq!(state.mode, PbftMode::Normal);
-        assert_eq!(state.peer_ids, (0..4).map(|i| vec![i]).collect::<Vec<_>>());
-        assert_eq!(state.f, 1);
-        assert_eq!(state.forced_view_change_period, 30);
+        // The primary's summary of the working block not matching the validator's
+        let (mut node, mut state) = new_node();
+        let mut delivered = mock_block(1);
+        delivered.summary = vec![2];
+        node.on_block_new(delivered.clone(), &mut state).unwrap();
+        let mut proposed = delivered.clone();
+        proposed.summary = vec![1];
+        let pre_prepare = mock_msg(&PbftMessageType::PrePrepare, 0, 1, proposed, vec![0]);
+        node.msg_log.add_message(pre_prepare, &state).unwrap();
+        state.set_phase(PbftPhase::Checking);
+        node.service = Box::new(MockService {
+            blocks: std::iter::once((mock_block_id(1), delivered)).collect(),
+            ..MockService::new(mock_config(4).peers)
+        });
+        assert!(node.on_block_valid(&mock_block_id(1), &mut state).is_err());
         assert_eq!(
-            state.working_block,
-            Some(pbft_block_from_block(mock_block(1)))
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::SummaryMismatch)
         );
-        assert!(state.is_primary());
+    }
 
-        state.seq_num += 1;
+    /// Make sure that a node configured to need more than f + 1 `ViewChange`s to join a view change
+    /// early waits for that many
+    #[test]
+    fn view_change_join_threshold() {
+        let mut cfg = mock_config(4);
+        cfg.view_change_join_threshold = 3;
+        let mut node = mock_node();
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        assert_eq!(state.view_change_join_threshold(), 3);
+
+        let send_vc = |node: &mut PbftNode, state: &mut PbftState, peer| {
+            let mut vc_msg = PbftViewChange::new();
+            vc_msg.set_info(make_msg_info(
+                &PbftMessageType::ViewChange,
+                1,
+                0,
+                vec![peer],
+            ));
+            vc_msg.set_seal(PbftSeal::new());
+            node.on_peer_message(ParsedMessage::from_view_change_message(vc_msg), state)
+                .unwrap_or_else(handle_pbft_err);
+        };
 
-        // Handle the rest of the blocks
-        for i in 2..10 {
-            assert_eq!(state.seq_num, i);
-            let block = mock_block_with_seal(i, &mut node, &mut state);
-            node.on_block_new(block.clone(), &mut state).unwrap();
+        // f + 1 ViewChanges aren't enough anymore
+        send_vc(&mut node, &mut state, 2);
+        send_vc(&mut node, &mut state, 3);
+        assert_eq!(node.last_view_change_reason(), None);
+        assert_eq!(state.view_change_target(), None);
 
-            assert_eq!(state.id, vec![0]);
-            assert_eq!(state.view, 0);
-            assert_eq!(state.phase, PbftPhase::PrePreparing);
-            assert_eq!(state.mode, PbftMode::Normal);
-            assert_eq!(state.peer_ids, (0..4).map(|i| vec![i]).collect::<Vec<_>>());
-            assert_eq!(state.f, 1);
-            assert_eq!(state.forced_view_change_period, 30);
-            assert_eq!(state.working_block, Some(pbft_block_from_block(block)));
-            assert!(state.is_primary());
+        // f + 2 are
+        send_vc(&mut node, &mut state, 0);
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::ViewChangeMessages)
+        );
 
-            state.seq_num += 1;
-        }
+        // A threshold below f + 1 is never used
+        state.view_change_join_threshold = 1;
+        assert_eq!(state.view_change_join_threshold(), 2);
     }
 
-    /// Make sure that `BlockNew` properly checks the consensus seal.
-    #[test]
-    fn block_new_consensus() {
-        let cfg = mock_config(4);
-        let mut node = mock_node(vec![1]);
-        let mut state = PbftState::new(vec![], 0, &cfg);
-        state.seq_num = 7;
-        let head = mock_block(6);
-        let mut block = mock_block(7);
-        block.summary = vec![1, 2, 3];
-        let context = create_context("secp256k1").unwrap();
+    /// Test that try_publish adds in the consensus seal, and returns the published block's ID only
+    /// if this node is the primary
+    #[test]
+    fn try_publish() {
+        let mut node0 = mock_node();
+        let cfg = mock_config(4);
+        let mut state0 = PbftState::new(vec![0], 0, &cfg);
+        let block0 = mock_block(1);
+        let pbft_block0 = pbft_block_from_block(block0);
 
         for i in 0..3 {
             let mut info = PbftMessageInfo::new();
             info.set_msg_type("Commit".into());
             info.set_view(0);
-            info.set_seq_num(6);
+            info.set_seq_num(0);
             info.set_signer_id(vec![i]);
 
-            let mut block = PbftBlock::new();
-            block.set_block_id(head.block_id.clone());
-
             let mut msg = PbftMessage::new();
             msg.set_info(info);
-            msg.set_block(block);
+            node0
+                .msg_log
+                .add_message(ParsedMessage::from_pbft_message(msg), &state0)
+                .unwrap();
+        }
 
-            let mut message = ParsedMessage::from_pbft_message(msg);
+        state0.phase = PbftPhase::PrePreparing;
+        state0.working_block = Some(pbft_block0.clone());
+        connect_peers(&mut node0, &mut state0);
 
-            let key = context.new_random_private_key().unwrap();
-            let pub_key = context.get_public_key(&*key).unwrap();
-            let mut header = ConsensusPeerMessageHeader::new();
+        assert_eq!(
+            node0.try_publish(&mut state0).unwrap(),
+            Some(BlockId::default())
+        );
 
-            header.set_signer_id(pub_key.as_slice().to_vec());
-            header.set_content_sha512(hash_sha512(&message.message_bytes));
+        let mut node1 = mock_node();
+        let mut state1 = PbftState::new(vec![1], 0, &cfg);
+        state1.working_block = Some(pbft_block0);
+        assert_eq!(node1.try_publish(&mut state1).unwrap(), None);
+    }
 
-            let header_bytes = header.write_to_bytes().unwrap();
-            let header_signature =
-                hex::decode(context.sign(&header_bytes, &*key).unwrap()).unwrap();
+    /// Make sure that replayed ViewChanges, either for an old sequence number or repeating a peer's
+    /// ViewChange for a view it already sent one for, don't make the node join a view change
+    #[test]
+    fn replayed_view_changes() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        state.seq_num = 5;
+        node.msg_log
+            .add_consensus_seal(mock_block_id(4), 4, PbftSeal::new())
+            .unwrap();
+        let send_vc = |node: &mut PbftNode, state: &mut PbftState, peer, seq_num| {
+            let mut vc_msg = PbftViewChange::new();
+            vc_msg.set_info(make_msg_info(
+                &PbftMessageType::ViewChange,
+                1,
+                seq_num,
+                vec![peer],
+            ));
+            vc_msg.set_seal(PbftSeal::new());
+            node.on_peer_message(ParsedMessage::from_view_change_message(vc_msg), state)
+                .unwrap_or_else(handle_pbft_err);
+        };
 
-            message.from_self = false;
-            message.header_bytes = header_bytes;
-            message.header_signature = header_signature;
+        // A full set of ViewChanges from long ago is ignored
+        send_vc(&mut node, &mut state, 0, 2);
+        send_vc(&mut node, &mut state, 2, 2);
+        send_vc(&mut node, &mut state, 3, 2);
+        assert_eq!(state.mode, PbftMode::Normal);
+        assert!(node.msg_log.view_change_from(&[0], 1).is_none());
 
-            node.msg_log.add_message(message, &state).unwrap();
-        }
+        // Node 2's second ViewChange for view 1 doesn't count alongside node 0's
+        send_vc(&mut node, &mut state, 2, 4);
+        send_vc(&mut node, &mut state, 2, 5);
+        send_vc(&mut node, &mut state, 0, 5);
+        assert_eq!(state.mode, PbftMode::Normal);
+        assert_eq!(node.last_view_change_reason(), None);
 
-        let seal = node.build_seal(&state, vec![1, 2, 3]).unwrap();
-        block.payload = seal;
+        // A second peer for the same sequence number is enough
+        send_vc(&mut node, &mut state, 3, 5);
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::ViewChangeMessages)
+        );
 
-        node.on_block_new(block, &mut state).unwrap();
+        // A sequence number at the top of the range is handled without overflowing
+        send_vc(&mut node, &mut state, 0, u64::MAX);
     }
 
-    /// Make sure that receiving a `BlockValid` update works as expected
+    /// Make sure that a state left at sequence number 0 makes proposing a view change fail, rather
+    /// than panic or wrap around looking for the previous block's seal
     #[test]
-    fn block_valid() {
-        let mut node = mock_node(vec![0]);
+    fn view_change_at_seq_num_zero() {
         let cfg = mock_config(4);
-        let mut state0 = PbftState::new(vec![0], 0, &cfg);
-        state0.phase = PbftPhase::Checking;
-        state0.working_block = Some(pbft_block_from_block(mock_block(1)));
-        node.on_block_valid(&mock_block_id(1), &mut state0)
-            .unwrap_or_else(handle_pbft_err);
-        assert_eq!(state0.phase, PbftPhase::Committing);
-    }
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        state.seq_num = 0;
 
-    /// Make sure that receiving a `BlockCommit` update works as expected
-    #[test]
-    fn block_commit() {
-        let mut node = mock_node(vec![0]);
-        let cfg = mock_config(4);
-        let mut state0 = PbftState::new(vec![0], 0, &cfg);
-        state0.phase = PbftPhase::Finished;
-        state0.working_block = Some(pbft_block_from_block(mock_block(1)));
-        assert_eq!(state0.seq_num, 1);
-        node.on_block_commit(mock_block_id(1), &mut state0);
-        assert_eq!(state0.phase, PbftPhase::PrePreparing);
-        assert_eq!(state0.working_block, None);
-        assert_eq!(state0.seq_num, 2);
+        match node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout) {
+            Err(PbftError::InternalError(_)) => {}
+            res => panic!("Expected InternalError, got {:?}", res),
+        }
+        assert_eq!(state.mode, PbftMode::Normal);
+        assert!(node.sent_messages.is_empty());
+        assert!(state.previous_seq_num().is_err());
     }
 
-    /// Test the multicast protocol (`PrePrepare` => `Prepare` => `Commit`)
+    /// Make sure that peers' view changes to views more than `max_view_jump` past the last
+    /// committed block's view are recorded as faults, and that this node's own aren't
     #[test]
-    fn multicast_protocol() {
-        let cfg = mock_config(4);
-
-        // Make sure BlockNew is in the log
-        let mut node1 = mock_node(vec![1]);
-        let mut state1 = PbftState::new(vec![], 0, &cfg);
-        let block = mock_block(1);
-        node1
-            .on_block_new(block.clone(), &mut state1)
-            .unwrap_or_else(handle_pbft_err);
+    fn max_view_jump() {
+        let mut cfg = mock_config(4);
+        cfg.max_view_jump = 3;
+        let mut node = mock_chain_node(&cfg, &[]);
+        let mut state = PbftState::new(vec![1], 1, &cfg);
+        state.last_committed_block = Some(mock_block_id(1));
+        node.msg_log.record_commit_view(mock_block_id(1), 1, 0);
+        node.msg_log
+            .add_consensus_seal(mock_block_id(1), 1, PbftSeal::new())
+            .unwrap();
+        let vc_for = |view, peer| {
+            let mut vc_msg = PbftViewChange::new();
+            vc_msg.set_info(make_msg_info(
+                &PbftMessageType::ViewChange,
+                view,
+                1,
+                vec![peer],
+            ));
+            vc_msg.set_seal(PbftSeal::new());
+            ParsedMessage::from_view_change_message(vc_msg)
+        };
 
-        // Receive a PrePrepare
-        let msg = mock_msg(&PbftMessageType::PrePrepare, 0, 1, block.clone(), vec![0]);
-        node1
-            .on_peer_message(msg, &mut state1)
-            .unwrap_or_else(handle_pbft_err);
+        // Node 0 jumps far ahead; sending the same ViewChange again isn't another fault
+        node.on_peer_message(vc_for(10, 0), &mut state).unwrap();
+        node.on_peer_message(vc_for(10, 0), &mut state).unwrap();
+        assert_eq!(node.recent_faults().len(), 1);
+        assert_eq!(node.recent_faults()[0].peer_id, vec![0]);
+        assert_eq!(node.recent_faults()[0].block_id, mock_block_id(1));
+        assert_eq!(state.mode, PbftMode::Normal);
 
-        assert_eq!(state1.phase, PbftPhase::Preparing);
-        assert_eq!(state1.seq_num, 1);
-        if let Some(ref blk) = state1.working_block {
-            assert_eq!(BlockId::from(blk.clone().block_id), mock_block_id(1));
-        } else {
-            panic!("Wrong WorkingBlockOption");
+        // This node's own view changes are only logged as they go on
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap();
+        for _ in 0..3 {
+            node.retry_view_change(&mut state).unwrap();
         }
+        assert_eq!(state.view_change_target(), Some(4));
+        assert_eq!(node.recent_faults().len(), 1);
+    }
 
-        // Receive 3 `Prepare` messages
-        for peer in 0..3 {
-            assert_eq!(state1.phase, PbftPhase::Preparing);
-            let msg = mock_msg(&PbftMessageType::Prepare, 0, 1, block.clone(), vec![peer]);
-            node1
-                .on_peer_message(msg, &mut state1)
-                .unwrap_or_else(handle_pbft_err);
+    /// Make sure that a node that catches up to a view it's the primary of starts building a block
+    #[test]
+    fn catch_up_to_view_as_primary() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        for peer in &[0, 2] {
+            node.on_peer_connected(vec![*peer], &mut state);
         }
-        assert_eq!(state1.phase, PbftPhase::Checking);
+        assert!(!state.is_primary());
 
-        // Spoof the `check_blocks()` call
-        assert!(node1.on_block_valid(&mock_block_id(1), &mut state1).is_ok());
+        node.catch_up_to_view(&mut state, 2);
+        assert!(!state.is_primary());
+        assert!(!node.block_initialized);
 
-        // Receive 3 `Commit` messages
-        for peer in 0..3 {
-            assert_eq!(state1.phase, PbftPhase::Committing);
-            let msg = mock_msg(&PbftMessageType::Commit, 0, 1, block.clone(), vec![peer]);
-            node1
-                .on_peer_message(msg, &mut state1)
-                .unwrap_or_else(handle_pbft_err);
-        }
-        assert_eq!(state1.phase, PbftPhase::Finished);
+        node.catch_up_to_view(&mut state, 5);
+        assert!(state.is_primary());
+        assert!(node.block_initialized);
+    }
 
-        // Spoof the `commit_blocks()` call
-        node1.on_block_commit(mock_block_id(1), &mut state1);
-        assert_eq!(state1.phase, PbftPhase::PrePreparing);
+    /// Make sure that a primary doesn't start or publish a block until `2f` other voting peers are
+    /// connected
+    #[test]
+    fn live_quorum_before_publishing() {
+        let cfg = mock_config(4);
+        let mut node = mock_node();
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        assert!(state.is_primary());
 
-        // Make sure the block was actually committed
-        let mut f = File::open(BLOCK_FILE).unwrap();
-        let mut buffer = String::new();
-        f.read_to_string(&mut buffer).unwrap();
-        let deser: Vec<Vec<u8>> = serde_json::from_str(&buffer).unwrap();
-        let blocks: Vec<BlockId> = deser
-            .iter()
-            .filter(|&block| !block.is_empty())
-            .map(|ref block| BlockId::from(block.clone().clone()))
-            .collect();
-        assert_eq!(blocks.len(), 2);
-        assert_eq!(blocks[1], mock_block_id(1));
+        node.initialize_block_if_ready(&state);
+        assert!(!node.block_initialized);
+        assert_eq!(node.try_publish(&mut state).unwrap(), None);
 
-        remove_file(BLOCK_FILE).unwrap();
+        node.on_peer_connected(vec![1], &mut state);
+        assert!(!node.block_initialized);
+        assert_eq!(node.try_publish(&mut state).unwrap(), None);
+
+        node.on_peer_connected(vec![2], &mut state);
+        assert!(node.block_initialized);
+        assert!(node.try_publish(&mut state).unwrap().is_some());
     }
 
-    /// Test that view changes work as expected, and that nodes take the proper roles after a view
-    /// change
+    /// Make sure that a primary cancels the block it started when it starts a view change, and
+    /// only does so once
     #[test]
-    fn view_change() {
-        let mut node1 = mock_node(vec![1]);
+    fn cancel_block_on_view_change() {
         let cfg = mock_config(4);
-        let mut state1 = PbftState::new(vec![1], 0, &cfg);
+        let new_node = |id: u8| {
+            let cancelled = Arc::new(AtomicUsize::new(0));
+            let service = Box::new(MockService {
+                cancelled: Arc::clone(&cancelled),
+                ..MockService::new(mock_config(4).peers)
+            });
+            let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+            node.set_signature_verifier(Arc::new(MockVerifier));
+            node.set_self_send(false);
+            node.msg_log
+                .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+                .unwrap();
+            let mut state = PbftState::new(vec![id], 0, &cfg);
+            connect_peers(&mut node, &mut state);
+            (node, state, cancelled)
+        };
 
-        assert!(!state1.is_primary());
+        let (mut node, mut state, cancelled) = new_node(0);
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap();
+        assert_eq!(cancelled.load(Ordering::SeqCst), 1);
+        state.mode = PbftMode::Normal;
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap();
+        assert_eq!(cancelled.load(Ordering::SeqCst), 1);
+
+        // Forced view changes cancel the block too
+        let (mut node, mut state, cancelled) = new_node(0);
+        node.force_view_change(&mut state, ViewChangeReason::ForcedRotation);
+        assert_eq!(cancelled.load(Ordering::SeqCst), 1);
+        assert!(!state.is_primary());
+
+        // A secondary never started a block, so it has nothing to cancel
+        let (mut node, mut state, cancelled) = new_node(1);
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap();
+        assert_eq!(cancelled.load(Ordering::SeqCst), 0);
+    }
 
-        node1
-            .msg_log
-            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new());
+    /// Make sure that shutting down stops every timer, and that a primary cancels the block it
+    /// started
+    #[test]
+    fn shutdown() {
+        let cfg = mock_config(4);
+        for id in 0..2 {
+            let cancelled = Arc::new(AtomicUsize::new(0));
+            let service = Box::new(MockService {
+                cancelled: Arc::clone(&cancelled),
+                ..MockService::new(mock_config(4).peers)
+            });
+            let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+            node.set_signature_verifier(Arc::new(MockVerifier));
+            let mut state = PbftState::new(vec![id], 0, &cfg);
+            connect_peers(&mut node, &mut state);
+            state.set_phase(PbftPhase::Preparing);
+            state.faulty_primary_timeout.start();
+            state.commit_timeout.start();
+            state.block_publish_timeout.start();
+            state.view_change_timeout.start();
+
+            let expected_cancels = if id == 0 { 1 } else { 0 };
+            node.shutdown(&mut state);
+            assert_eq!(cancelled.load(Ordering::SeqCst), expected_cancels);
+            assert!(!state.faulty_primary_timeout.is_running());
+            assert!(!state.commit_timeout.is_running());
+            assert!(!state.block_publish_timeout.is_running());
+            assert!(!state.view_change_timeout.is_running());
+            assert!(!state.phase_timeout.is_running());
+
+            // Shutting down again is harmless
+            node.shutdown(&mut state);
+            assert_eq!(cancelled.load(Ordering::SeqCst), expected_cancels);
+        }
+    }
 
-        // Receive 3 `ViewChange` messages
-        for peer in 0..3 {
-            // It takes f + 1 `ViewChange` messages to trigger a view change, if it wasn't started
-            // by `propose_view_change()`
-            if peer < 2 {
-                assert_eq!(state1.mode, PbftMode::Normal);
-            } else {
-                assert_eq!(state1.mode, PbftMode::ViewChanging);
-            }
-            let info = make_msg_info(&PbftMessageType::ViewChange, 1, 0, vec![peer]);
-            let mut vc_msg = PbftViewChange::new();
-            vc_msg.set_info(info);
-            vc_msg.set_seal(PbftSeal::new());
+    /// Make sure that PrePrepares for later sequence numbers are backlogged while the working
+    /// block may still commit, but start a view change once it's past the commit deadline
+    #[test]
+    fn pre_prepare_backpressure() {
+        let mut cfg = mock_config(4);
+        cfg.commit_timeout = Duration::from_millis(50);
+        let mut node = mock_node();
+        node.msg_log
+            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new())
+            .unwrap();
+        let mut state = PbftState::new(vec![1], 0, &cfg);
+        let clock = MockClock::new();
+        state.set_clock(clock.clone());
+        state.phase = PbftPhase::Committing;
+        state.working_block = Some(pbft_block_from_block(mock_block(1)));
+        state.commit_timeout.start();
+
+        let pre_prepare = |seq_num, signer| {
+            mock_msg(
+                &PbftMessageType::PrePrepare,
+                0,
+                seq_num,
+                mock_block(seq_num),
+                vec![signer],
+            )
+        };
 
-            node1
-                .on_peer_message(ParsedMessage::from_view_change_message(vc_msg), &mut state1)
-                .unwrap_or_else(handle_pbft_err);
+        node.on_peer_message(pre_prepare(2, 0), &mut state).unwrap();
+        assert_eq!(node.msg_log.backlog_len(), 1);
+        assert_eq!(state.mode, PbftMode::Normal);
+
+        // A PrePrepare from a node that isn't primary can't start a view change
+        clock.advance(Duration::from_millis(60));
+        match node.on_peer_message(pre_prepare(3, 2), &mut state) {
+            Err(PbftError::NotFromPrimary) => {}
+            res => panic!("Expected NotFromPrimary, got {:?}", res),
         }
+        assert_eq!(state.mode, PbftMode::Normal);
 
-        assert!(state1.is_primary());
-        assert_eq!(state1.view, 1);
+        node.on_peer_message(pre_prepare(3, 0), &mut state).unwrap();
+        assert_eq!(node.msg_log.backlog_len(), 1);
+        assert_eq!(state.mode, PbftMode::ViewChanging);
+        assert_eq!(
+            node.last_view_change_reason(),
+            Some(ViewChangeReason::CommitTimeout)
+        );
     }
 
-    /// Make sure that view changes start correctly
+    /// Make sure that the primary doesn't publish blocks more often than the minimum interval
+    /// allows
     #[test]
-    fn propose_view_change() {
-        let mut node1 = mock_node(vec![1]);
-        let cfg = mock_config(4);
-        let mut state1 = PbftState::new(vec![], 0, &cfg);
-        assert_eq!(state1.mode, PbftMode::Normal);
-
-        node1
-            .msg_log
-            .add_consensus_seal(mock_block_id(0), 0, PbftSeal::new());
+    fn min_block_publish_interval() {
+        let mut cfg = mock_config(4);
+        cfg.min_block_publish_interval = Duration::from_millis(100);
+        let finalized = Arc::new(AtomicUsize::new(0));
+        let service = Box::new(MockService {
+            finalized: Arc::clone(&finalized),
+            ..MockService::new(mock_config(4).peers)
+        });
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        let clock = MockClock::new();
+        state.set_clock(clock.clone());
+        connect_peers(&mut node, &mut state);
 
-        node1
-            .propose_view_change(&mut state1)
-            .unwrap_or_else(handle_pbft_err);
+        for _ in 0..10 {
+            node.try_publish(&mut state).unwrap();
+        }
+        assert_eq!(finalized.load(Ordering::SeqCst), 1);
 
-        assert_eq!(state1.mode, PbftMode::ViewChanging);
+        clock.advance(Duration::from_millis(150));
+        for _ in 0..10 {
+            node.try_publish(&mut state).unwrap();
+        }
+        assert_eq!(finalized.load(Ordering::SeqCst), 2);
     }
 
-    /// Test that try_publish adds in the consensus seal
+    /// Make sure that a chain configured to seal from block 1 builds a seal for block 1 when
+    /// publishing, and verifies the seal of a block 1 it receives
     #[test]
-    fn try_publish() {
-        let mut node0 = mock_node(vec![0]);
-        let cfg = mock_config(4);
-        let mut state0 = PbftState::new(vec![0], 0, &cfg);
-        let block0 = mock_block(1);
-        let pbft_block0 = pbft_block_from_block(block0);
+    fn sealed_from_block_one() {
+        let mut cfg = mock_config(4);
+        cfg.first_sealed_block = 1;
+        let service = Box::new(MockService::new(mock_config(4).peers));
+        let mut node = PbftNode::new(&cfg, service, Arc::new(PbftMetrics::new()));
+        node.set_signature_verifier(Arc::new(MockVerifier));
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        connect_peers(&mut node, &mut state);
+        assert_eq!(state.seq_num, 1);
 
-        for i in 0..3 {
+        // Without any commits for the block before, there's nothing to build the seal from
+        assert!(node.try_publish(&mut state).is_err());
+        for i in 1..4 {
             let mut info = PbftMessageInfo::new();
             info.set_msg_type("Commit".into());
             info.set_view(0);
@@ -1392,15 +9687,256 @@ mod tests {
 
             let mut msg = PbftMessage::new();
             msg.set_info(info);
-            node0
-                .msg_log
-                .add_message(ParsedMessage::from_pbft_message(msg), &state0)
+            node.msg_log
+                .add_message(ParsedMessage::from_pbft_message(msg), &state)
                 .unwrap();
         }
+        node.try_publish(&mut state).unwrap();
+
+        // An unsealed block 1 is rejected, and a sealed one is verified
+        assert!(node
+            .verify_consensus_seal(&mock_block(1), &mut state)
+            .is_err());
+        let mut builder_state = PbftState::new(vec![1], 0, &mock_config(4));
+        builder_state.seq_num = 1;
+        let sealed = mock_block_with_seal(1, &mut mock_node(), &mut builder_state);
+        assert!(node
+            .verify_consensus_seal(&sealed, &mut state)
+            .unwrap()
+            .is_some());
+
+        // By default, block 1 isn't checked at all
+        let mut node = mock_node();
+        assert!(node
+            .verify_consensus_seal(&mock_block(1), &mut state)
+            .unwrap()
+            .is_none());
+    }
 
-        state0.phase = PbftPhase::PrePreparing;
-        state0.working_block = Some(pbft_block0.clone());
+    /// Make sure that `verify_chain_seals` accepts a valid chain and identifies the first block of
+    /// a tampered one
+    #[test]
+    fn verify_chain_seals() {
+        let cfg = mock_config(4);
+        let mut blocks = mock_sealed_chain(5);
+
+        let mut state = PbftState::new(vec![1], 5, &cfg);
+        let mut node = mock_chain_node(&cfg, &blocks);
+        node.verify_chain_seals(1, 5, &mut state).unwrap();
+        node.verify_chain_seals(2, 3, &mut state).unwrap();
+        assert!(node.verify_chain_seals(3, 6, &mut state).is_err());
+
+        // Tamper with block 3's summary so that its seal no longer matches
+        blocks[2].summary = vec![4, 5, 6];
+        let mut node = mock_chain_node(&cfg, &blocks);
+        node.verify_chain_seals(1, 2, &mut state).unwrap();
+        match node.verify_chain_seals(1, 5, &mut state) {
+            Err(PbftError::ChainVerificationFailed(3, _)) => {}
+            res => panic!("Expected block 3 to fail verification, got {:?}", res),
+        }
+    }
+
+    /// Make sure that `verify_chain_seals` checks each block's seal against the membership as of
+    /// the block it seals, and doesn't trust seals that were already verified
+    #[test]
+    fn verify_chain_seals_membership_change() {
+        let old_cfg = mock_config(4);
+        let new_cfg = mock_config(7);
+        let peers_setting = |peers: &[PeerId], observers: &[PeerId]| {
+            let hex_ids = |ids: &[PeerId]| {
+                serde_json::to_string(&ids.iter().map(hex::encode).collect::<Vec<_>>()).unwrap()
+            };
+            let mut settings = HashMap::new();
+            settings.insert(DEFAULT_PEERS_SETTING_KEY.to_string(), hex_ids(peers));
+            settings.insert(OBSERVERS_SETTING_KEY.to_string(), hex_ids(observers));
+            settings
+        };
+
+        // Blocks 2 and 3 are sealed by the original 4 nodes, then 3 more join and blocks 4 and 5
+        // are sealed by the larger network, which needs more votes
+        let mut blocks = mock_sealed_chain(3);
+        let mut builder_state = PbftState::new(vec![1], 0, &new_cfg);
+        for (num, voters) in &[(4, [0, 2, 3, 4, 5]), (5, [0, 2, 3, 4, 6])] {
+            builder_state.seq_num = *num;
+            blocks.push(mock_block_with_seal_from(
+                *num,
+                &mut mock_node(),
+                &mut builder_state,
+                voters,
+            ));
+        }
+
+        let chain_node = |settings_at: HashMap<BlockId, HashMap<String, String>>| {
+            let service = Box::new(MockService {
+                chain: std::iter::once(mock_block_id(0))
+                    .chain(blocks.iter().map(|b| b.block_id.clone()))
+                    .collect(),
+                blocks: blocks
+                    .iter()
+                    .map(|b| (b.block_id.clone(), b.clone()))
+                    .collect(),
+                settings_at,
+                ..MockService::new(new_cfg.peers.clone())
+            });
+            let mut node = PbftNode::new(&new_cfg, service, Arc::new(PbftMetrics::new()));
+            node.set_signature_verifier(Arc::new(MockVerifier));
+            node
+        };
+        let old_membership: HashMap<BlockId, HashMap<String, String>> = (1..=2)
+            .map(|num| (mock_block_id(num), peers_setting(&old_cfg.peers, &[])))
+            .collect();
+
+        let mut state = PbftState::new(vec![1], 5, &new_cfg);
+        assert_eq!(state.f, 2);
+        let mut node = chain_node(old_membership.clone());
+        node.verify_chain_seals(2, 5, &mut state).unwrap();
+
+        // Checked against today's membership, the 3 votes for the older blocks aren't enough
+        let mut node = chain_node(HashMap::new());
+        match node.verify_chain_seals(2, 5, &mut state) {
+            Err(PbftError::ChainVerificationFailed(2, _)) => {}
+            res => panic!("Expected block 2 to fail verification, got {:?}", res),
+        }
+
+        // Node 6 was an observer as of block 4, so its vote doesn't count for block 5
+        let mut with_observer = old_membership.clone();
+        with_observer.insert(mock_block_id(4), peers_setting(&new_cfg.peers, &[vec![6]]));
+        let mut node = chain_node(with_observer);
+        match node.verify_chain_seals(2, 5, &mut state) {
+            Err(PbftError::ChainVerificationFailed(5, _)) => {}
+            res => panic!("Expected block 5 to fail verification, got {:?}", res),
+        }
 
-        node0.try_publish(&mut state0).unwrap();
+        // A seal that was verified and stored under the old membership is taken as is in normal
+        // operation, but audited again
+        let mut node = chain_node(old_membership);
+        node.replay_missing_seals_for(3, 3, &mut state).unwrap();
+        node.service = chain_node(HashMap::new()).service;
+        assert!(node
+            .verify_consensus_seal(&blocks[2], &mut state)
+            .unwrap()
+            .is_some());
+        match node.verify_chain_seals(3, 3, &mut state) {
+            Err(PbftError::ChainVerificationFailed(3, _)) => {}
+            res => panic!("Expected block 3 to fail verification, got {:?}", res),
+        }
+    }
+
+    /// Make sure that the chain head's seal can be retrieved once the blocks are in, and that
+    /// there isn't one for the genesis block or block 1
+    #[test]
+    fn head_seal() {
+        let cfg = mock_config(4);
+        let blocks = mock_sealed_chain(5);
+        let mut node = mock_chain_node(&cfg, &blocks);
+
+        for head in 0..2 {
+            match node.head_seal(&PbftState::new(vec![1], head, &cfg)) {
+                Err(PbftError::NoConsensusSeal(num)) => assert_eq!(num, head),
+                res => panic!("Expected NoConsensusSeal, got {:?}", res),
+            }
+        }
+
+        let mut state = PbftState::new(vec![1], 5, &cfg);
+        node.replay_missing_seals_for(2, 5, &mut state).unwrap();
+        let seal = node.head_seal(&state).unwrap();
+        assert_eq!(seal.previous_id, mock_block_id(4));
+        assert!(!seal.get_previous_commit_votes().is_empty());
+        assert_eq!(seal, parse_seal(&blocks[4]).unwrap());
+    }
+
+    /// Make sure that seals which have been removed from the log can be restored from the chain
+    #[test]
+    fn replay_missing_seals_for() {
+        let mut cfg = mock_config(4);
+        cfg.gc_retain_depth = 1;
+        let blocks = mock_sealed_chain(5);
+
+        let mut state = PbftState::new(vec![1], 5, &cfg);
+        let mut node = mock_chain_node(&cfg, &blocks);
+        assert!(node.msg_log.get_consensus_seal(3).is_err());
+
+        // Block 1 isn't sealed, so there are seals for blocks 2 through 5
+        assert_eq!(node.replay_missing_seals_for(1, 5, &mut state).unwrap(), 4);
+        assert_eq!(node.msg_log.seal_count(), 4);
+
+        // Trim every seal but the head's
+        node.msg_log.garbage_collect(6, &mock_block_id(5));
+        assert_eq!(node.msg_log.seal_count(), 1);
+        assert!(node.msg_log.get_consensus_seal(3).is_err());
+
+        assert_eq!(node.replay_missing_seals_for(2, 4, &mut state).unwrap(), 3);
+        assert_eq!(node.msg_log.seal_count(), 4);
+        for seq in 2..=5 {
+            assert!(node.msg_log.get_consensus_seal(seq).is_ok());
+        }
+
+        // Seals that are already in the log aren't restored again
+        assert_eq!(node.replay_missing_seals_for(1, 5, &mut state).unwrap(), 0);
+        assert!(node.replay_missing_seals_for(4, 6, &mut state).is_err());
+    }
+
+    /// Make sure that `force_resync` brings a node with corrupted state back in line with the
+    /// chain head
+    #[test]
+    fn force_resync() {
+        let cfg = mock_config(4);
+        let blocks = mock_sealed_chain(5);
+        let mut node = mock_chain_node(&cfg, &blocks);
+        let mut state = PbftState::new(vec![1], 5, &cfg);
+
+        // Corrupt the state and the log
+        state.seq_num = 42;
+        state.view = 7;
+        state.phase = PbftPhase::Committing;
+        state.mode = PbftMode::ViewChanging;
+        state.working_block = Some(pbft_block_from_block(mock_block(3)));
+        state.upgrade_role();
+        node.msg_log
+            .add_message(
+                mock_msg(&PbftMessageType::Commit, 7, 42, mock_block(3), vec![0]),
+                &state,
+            )
+            .unwrap();
+
+        node.force_resync(&mut state).unwrap();
+
+        // The view isn't moved back to the seal's
+        assert_eq!(state.seq_num, 6);
+        assert_eq!(state.view, 7);
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+        assert_eq!(state.mode, PbftMode::Normal);
+        assert_eq!(state.working_block, None);
+        assert!(!state.is_primary());
+
+        // The log only holds what was recovered from the chain head's seal
+        assert!(node
+            .msg_log
+            .get_messages_of_type_seq(&PbftMessageType::Commit, 42)
+            .is_empty());
+        assert_eq!(
+            node.msg_log
+                .get_messages_of_type_seq(&PbftMessageType::Commit, 4)
+                .len(),
+            3
+        );
+        assert!(node.msg_log.get_consensus_seal(5).is_ok());
+
+        // The node is able to propose a view change again
+        node.propose_view_change(&mut state, ViewChangeReason::FaultyPrimaryTimeout)
+            .unwrap();
+        assert_eq!(state.mode, PbftMode::ViewChanging);
+
+        // A chain head whose seal doesn't check out isn't resynced to
+        let mut blocks = mock_sealed_chain(5);
+        blocks[4].summary = vec![9];
+        let mut node = mock_chain_node(&cfg, &blocks);
+        let mut state = PbftState::new(vec![1], 5, &cfg);
+        state.seq_num = 42;
+        match node.force_resync(&mut state) {
+            Err(PbftError::SealSummaryMismatch(_, _)) => {}
+            res => panic!("Expected SealSummaryMismatch, got {:?}", res),
+        }
+        assert_eq!(state.seq_num, 42);
     }
 }
@@ -0,0 +1,295 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Recording the updates a node receives, so that a run can be replayed for debugging
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use sawtooth_sdk::consensus::engine::{
+    Block, BlockId, PeerId, PeerInfo, PeerMessage, PeerMessageHeader, Update,
+};
+use serde_json;
+
+use crate::error::PbftError;
+use crate::timing;
+
+/// Told about every update a node handles, and every tick of the engine's working timer, just
+/// before it's handled
+///
+/// Nothing is recorded by default; a recorder can be given to the node with
+/// `PbftNode::set_update_recorder`, or to the engine with `PbftEngine::set_update_recording`.
+pub trait UpdateRecorder: Send {
+    fn record(&mut self, update: &UpdateRecord) -> Result<(), PbftError>;
+}
+
+/// An update as it was recorded, along with when it was received
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedUpdate {
+    /// When the update was received, in milliseconds since the Unix epoch
+    pub timestamp: u64,
+
+    pub update: UpdateRecord,
+}
+
+/// Serializable copy of an `Update`; converts to and from one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UpdateRecord {
+    PeerConnected(PeerId),
+    PeerDisconnected(PeerId),
+    PeerMessage {
+        signer_id: PeerId,
+        content_sha512: Vec<u8>,
+        message_type: String,
+        name: String,
+        version: String,
+        header_bytes: Vec<u8>,
+        header_signature: Vec<u8>,
+        content: Vec<u8>,
+        sender_id: PeerId,
+    },
+    BlockNew {
+        block_id: BlockId,
+        previous_id: BlockId,
+        signer_id: PeerId,
+        block_num: u64,
+        payload: Vec<u8>,
+        summary: Vec<u8>,
+    },
+    BlockValid(BlockId),
+    BlockInvalid(BlockId),
+    BlockCommit(BlockId),
+    Shutdown,
+
+    /// The engine's working timer went off. This isn't an update from the validator, but it's
+    /// when the node publishes blocks and checks its timeouts, so a replay needs it to take the
+    /// same steps; the timeouts themselves still go by the clock, though, so a replay can only
+    /// follow a recording whose timeouts expired at the same ticks.
+    Tick,
+}
+
+impl<'a> From<&'a Update> for UpdateRecord {
+    fn from(update: &'a Update) -> Self {
+        match update {
+            Update::PeerConnected(info) => UpdateRecord::PeerConnected(info.peer_id.clone()),
+            Update::PeerDisconnected(peer_id) => UpdateRecord::PeerDisconnected(peer_id.clone()),
+            Update::PeerMessage(message, sender_id) => UpdateRecord::PeerMessage {
+                signer_id: message.header.signer_id.clone(),
+                content_sha512: message.header.content_sha512.clone(),
+                message_type: message.header.message_type.clone(),
+                name: message.header.name.clone(),
+                version: message.header.version.clone(),
+                header_bytes: message.header_bytes.clone(),
+                header_signature: message.header_signature.clone(),
+                content: message.content.clone(),
+                sender_id: sender_id.clone(),
+            },
+            Update::BlockNew(block) => UpdateRecord::BlockNew {
+                block_id: block.block_id.clone(),
+                previous_id: block.previous_id.clone(),
+                signer_id: block.signer_id.clone(),
+                block_num: block.block_num,
+                payload: block.payload.clone(),
+                summary: block.summary.clone(),
+            },
+            Update::BlockValid(block_id) => UpdateRecord::BlockValid(block_id.clone()),
+            Update::BlockInvalid(block_id) => UpdateRecord::BlockInvalid(block_id.clone()),
+            Update::BlockCommit(block_id) => UpdateRecord::BlockCommit(block_id.clone()),
+            Update::Shutdown => UpdateRecord::Shutdown,
+        }
+    }
+}
+
+impl TryFrom<UpdateRecord> for Update {
+    type Error = PbftError;
+
+    fn try_from(record: UpdateRecord) -> Result<Self, PbftError> {
+        Ok(match record {
+            UpdateRecord::PeerConnected(peer_id) => Update::PeerConnected(PeerInfo { peer_id }),
+            UpdateRecord::PeerDisconnected(peer_id) => Update::PeerDisconnected(peer_id),
+            UpdateRecord::PeerMessage {
+                signer_id,
+                content_sha512,
+                message_type,
+                name,
+                version,
+                header_bytes,
+                header_signature,
+                content,
+                sender_id,
+            } => Update::PeerMessage(
+                PeerMessage {
+                    header: PeerMessageHeader {
+                        signer_id,
+                        content_sha512,
+                        message_type,
+                        name,
+                        version,
+                    },
+                    header_bytes,
+                    header_signature,
+                    content,
+                },
+                sender_id,
+            ),
+            UpdateRecord::BlockNew {
+                block_id,
+                previous_id,
+                signer_id,
+                block_num,
+                payload,
+                summary,
+            } => Update::BlockNew(Block {
+                block_id,
+                previous_id,
+                signer_id,
+                block_num,
+                payload,
+                summary,
+            }),
+            UpdateRecord::BlockValid(block_id) => Update::BlockValid(block_id),
+            UpdateRecord::BlockInvalid(block_id) => Update::BlockInvalid(block_id),
+            UpdateRecord::BlockCommit(block_id) => Update::BlockCommit(block_id),
+            UpdateRecord::Shutdown => Update::Shutdown,
+            UpdateRecord::Tick => {
+                return Err(PbftError::InternalError(
+                    "A timer tick isn't an update".into(),
+                ))
+            }
+        })
+    }
+}
+
+/// Writes each update to a file as a line of JSON, as soon as it's received
+pub struct FileRecorder {
+    writer: BufWriter<File>,
+}
+
+impl FileRecorder {
+    /// Start a new recording at the given path, replacing any file that's already there
+    pub fn create(path: &Path) -> Result<Self, PbftError> {
+        let file = File::create(path).map_err(|err| {
+            PbftError::InternalError(format!(
+                "Couldn't create update recording at {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        Ok(FileRecorder {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl UpdateRecorder for FileRecorder {
+    fn record(&mut self, update: &UpdateRecord) -> Result<(), PbftError> {
+        let recorded = RecordedUpdate {
+            timestamp: timing::unix_millis(),
+            update: update.clone(),
+        };
+        let line = serde_json::to_string(&recorded).map_err(|err| {
+            PbftError::InternalError(format!("Couldn't serialize update: {}", err))
+        })?;
+
+        // Flushed right away, so the recording is complete up to a crash
+        writeln!(self.writer, "{}", line)
+            .and_then(|_| self.writer.flush())
+            .map_err(|err| PbftError::InternalError(format!("Couldn't record update: {}", err)))
+    }
+}
+
+/// Read the updates recorded by a `FileRecorder`, in the order they were received
+pub fn read_recording(path: &Path) -> Result<Vec<RecordedUpdate>, PbftError> {
+    let file = File::open(path).map_err(|err| {
+        PbftError::InternalError(format!(
+            "Couldn't open update recording at {}: {}",
+            path.display(),
+            err
+        ))
+    })?;
+
+    BufReader::new(file)
+        .lines()
+        .map(|line| {
+            let line = line.map_err(|err| {
+                PbftError::InternalError(format!("Couldn't read update recording: {}", err))
+            })?;
+            serde_json::from_str(&line).map_err(|err| {
+                PbftError::InternalError(format!("Couldn't parse recorded update: {}", err))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Make sure that every kind of update comes back from a recording the way it went in
+    #[test]
+    fn round_trip() {
+        let message = PeerMessage {
+            header: PeerMessageHeader {
+                signer_id: vec![1],
+                message_type: "Prepare".into(),
+                ..Default::default()
+            },
+            content: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let block = Block {
+            block_id: vec![2],
+            block_num: 2,
+            ..Default::default()
+        };
+
+        let updates = vec![
+            Update::PeerConnected(PeerInfo { peer_id: vec![1] }),
+            Update::PeerDisconnected(vec![1]),
+            Update::PeerMessage(message, vec![1]),
+            Update::BlockNew(block),
+            Update::BlockValid(vec![2]),
+            Update::BlockInvalid(vec![2]),
+            Update::BlockCommit(vec![2]),
+            Update::Shutdown,
+        ];
+
+        let path = ::std::env::temp_dir().join(format!("pbft_updates_{}", ::std::process::id()));
+        let mut recorder = FileRecorder::create(&path).unwrap();
+        for update in &updates {
+            recorder.record(&update.into()).unwrap();
+        }
+        recorder.record(&UpdateRecord::Tick).unwrap();
+        let mut recorded = read_recording(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(recorded.len(), updates.len() + 1);
+        assert_eq!(recorded.pop().unwrap().update, UpdateRecord::Tick);
+        for (recorded, update) in recorded.into_iter().zip(updates.iter()) {
+            let expected = UpdateRecord::from(update);
+            assert_eq!(recorded.update, expected);
+            assert_eq!(
+                UpdateRecord::from(&Update::try_from(recorded.update).unwrap()),
+                expected
+            );
+        }
+        assert!(Update::try_from(UpdateRecord::Tick).is_err());
+    }
+}
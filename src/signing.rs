@@ -0,0 +1,98 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! How the signatures on peers' votes are checked
+
+use std::sync::Arc;
+
+use hex;
+use sawtooth_sdk::signing::{create_context, secp256k1::Secp256k1PublicKey};
+
+use crate::error::PbftError;
+
+/// Checks signatures made with one signing algorithm
+///
+/// The algorithm is picked by name with `verifier_for`; another verifier (for instance, one
+/// backed by an HSM) can be given to the node with `PbftNode::set_signature_verifier`.
+pub trait SignatureVerifier: Send + Sync {
+    /// Check that `signature` is a signature of `message` by the holder of `public_key`
+    ///
+    /// Returns `Ok(false)` if the signature doesn't match, and an `InvalidPublicKey` error if
+    /// `public_key` isn't a key for this algorithm at all.
+    fn verify(
+        &self,
+        signature: &[u8],
+        message: &[u8],
+        public_key: &[u8],
+    ) -> Result<bool, PbftError>;
+}
+
+/// Name of the signing algorithm used when none is configured
+pub const DEFAULT_SIGNING_ALGORITHM: &str = "secp256k1";
+
+/// Get the verifier for the signing algorithm with the given name
+pub fn verifier_for(algorithm: &str) -> Result<Arc<SignatureVerifier>, PbftError> {
+    match algorithm {
+        "secp256k1" => Ok(Arc::new(Secp256k1Verifier)),
+        _ => Err(PbftError::InvalidConfig(format!(
+            "Unsupported signing algorithm: {}",
+            algorithm
+        ))),
+    }
+}
+
+/// Verifies secp256k1 signatures, as used by Sawtooth validators
+pub struct Secp256k1Verifier;
+
+impl SignatureVerifier for Secp256k1Verifier {
+    fn verify(
+        &self,
+        signature: &[u8],
+        message: &[u8],
+        public_key: &[u8],
+    ) -> Result<bool, PbftError> {
+        let invalid_key = |reason: String| PbftError::InvalidPublicKey(public_key.to_vec(), reason);
+
+        // Keys are either compressed (33 bytes) or uncompressed (65 bytes)
+        if public_key.len() != 33 && public_key.len() != 65 {
+            return Err(invalid_key(format!("{} bytes long", public_key.len())));
+        }
+        let key = Secp256k1PublicKey::from_hex(&hex::encode(public_key))
+            .map_err(|err| invalid_key(format!("{:?}", err)))?;
+
+        let context = create_context(DEFAULT_SIGNING_ALGORITHM)
+            .map_err(|err| PbftError::InternalError(format!("Couldn't create context: {}", err)))?;
+
+        context
+            .verify(&hex::encode(signature), message, &key)
+            .map_err(|err| {
+                PbftError::InternalError(format!("Error while verifying header: {:?}", err))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make sure that only known signing algorithms can be picked
+    #[test]
+    fn supported_algorithms() {
+        assert!(verifier_for("secp256k1").is_ok());
+        assert!(verifier_for("ed25519").is_err());
+    }
+}
@@ -17,22 +17,34 @@
 
 //! Information about a PBFT node's state
 
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use hex;
-use sawtooth_sdk::consensus::engine::PeerId;
+use itertools::Itertools;
+use protobuf::Message;
+use sawtooth_sdk::consensus::engine::{BlockId, PeerId};
 
-use crate::config::PbftConfig;
+use crate::config::{dedup_peers, PbftConfig, PhaseTimeouts};
+use crate::error::PbftError;
+use crate::hash::hash_sha256;
 use crate::message_type::PbftMessageType;
-use crate::protos::pbft_message::PbftBlock;
-use crate::timing::Timeout;
+use crate::protos::pbft_message::{PbftBlock, PbftPrimaryStrikes, PbftSeal, PbftStateSnapshot};
+use crate::timing::{system_clock, Clock, Timeout};
 
-// Possible roles for a node
-// Primary is in charge of making consensus decisions
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
-enum PbftNodeRole {
+/// Possible roles for a node, as reported by `PbftState::role`
+///
+/// The primary is in charge of making consensus decisions. Observers follow the chain without
+/// voting; a node is only ever stored as `Primary` or `Secondary`, and is reported as an observer
+/// if it's in the observer list.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum NodeRole {
     Primary,
     Secondary,
+    Observer,
 }
 
 /// Phases of the PBFT algorithm, in `Normal` mode
@@ -45,6 +57,17 @@ pub enum PbftPhase {
     Finished,
 }
 
+/// Holds the callback, if any, that's told about every phase change with the old and new phases
+#[derive(Default)]
+struct PhaseListener(Option<Box<FnMut(PbftPhase, PbftPhase) + Send>>);
+
+impl fmt::Debug for PhaseListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = if self.0.is_some() { "set" } else { "unset" };
+        write!(f, "PhaseListener({})", status)
+    }
+}
+
 /// Modes that the PBFT algorithm can possibly be in
 #[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum PbftMode {
@@ -52,8 +75,49 @@ pub enum PbftMode {
     ViewChanging,
 }
 
+/// Hex-encode an ID for display, truncated to its first 6 characters unless `full` is set
+fn display_id(id: &[u8], full: bool) -> String {
+    let encoded = hex::encode(id);
+    if full {
+        encoded
+    } else {
+        encoded.get(..6).unwrap_or(&encoded).to_string()
+    }
+}
+
+/// Pick a node's shift for its forced view changes, in `0..=jitter`
+///
+/// The shift is derived from a hash of the node's ID, so it's spread out across nodes but stays
+/// the same every time the node starts.
+fn forced_view_change_offset(id: &[u8], jitter: u64) -> u64 {
+    if jitter == 0 {
+        return 0;
+    }
+    let hash = hash_sha256(id);
+    let value = hash[..8]
+        .iter()
+        .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+    value % (jitter + 1)
+}
+
+/// Maximum number of faulty nodes a network of `num_peers` voting nodes can tolerate
+///
+/// PBFT needs `3f + 1` nodes to tolerate `f` faults. Networks whose size isn't exactly `3f + 1`
+/// round down: a 5-node network still has `f = 1`, tolerating one fault with one node to spare,
+/// and it takes 7 nodes to tolerate two. Fewer than 4 nodes can't tolerate any faults, which is
+/// an `InsufficientPeers` error.
+pub fn compute_f(num_peers: usize) -> Result<u64, PbftError> {
+    if num_peers < 4 {
+        return Err(PbftError::InsufficientPeers(num_peers));
+    }
+    Ok(((num_peers - 1) / 3) as u64)
+}
+
+/// Formats a short summary of the state. IDs are truncated by default; use the alternate flag
+/// (`{:#}`) to show them in full.
 impl fmt::Display for PbftState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let full_ids = f.alternate();
         let ast = if self.is_primary() { "*" } else { " " };
         let mode = match self.mode {
             PbftMode::Normal => "N",
@@ -72,7 +136,7 @@ impl fmt::Display for PbftState {
             Some(ref block) => format!(
                 "{}/{}",
                 block.block_num,
-                &hex::encode(block.get_block_id())[..6]
+                display_id(block.get_block_id(), full_ids)
             ),
             None => String::from("~none~"),
         };
@@ -86,7 +150,7 @@ impl fmt::Display for PbftState {
             self.seq_num,
             wb,
             ast,
-            &hex::encode(self.id.clone())[..6],
+            display_id(&self.id, full_ids),
         )
     }
 }
@@ -104,18 +168,40 @@ pub struct PbftState {
     /// The current view (where the primary's ID is p = v mod network_node_ids.len())
     pub view: u64,
 
+    /// When this node entered its current view
+    #[serde(skip, default = "Instant::now")]
+    view_entered: Instant,
+
     /// Current phase of the algorithm
     pub phase: PbftPhase,
 
     /// Is this node primary or secondary?
-    role: PbftNodeRole,
+    role: NodeRole,
 
     /// Normal operation or view changing
     pub mode: PbftMode,
 
-    /// Map of peers in the network, including ourselves
+    /// The consensus seal this node sent in its `ViewChange`, so that the `ViewChange` can be sent
+    /// again if the node restarts before the view change is done; only meaningful while view
+    /// changing
+    #[serde(default)]
+    pub view_change_seal: Option<PbftSeal>,
+
+    /// Map of peers in the network, including ourselves, sorted by ID so that every node agrees on
+    /// the primary for each view
     pub peer_ids: Vec<PeerId>,
 
+    /// Peers that have been moved out of the voting set; they follow consensus, but their votes
+    /// don't count and they're never primary
+    #[serde(default)]
+    pub observers: Vec<PeerId>,
+
+    /// Peers that the validator currently has a connection to, whether they're members or not, so
+    /// that a peer is counted as soon as it joins; this is only known while the node is running, so
+    /// it isn't stored
+    #[serde(skip)]
+    pub live_peers: HashSet<PeerId>,
+
     /// The maximum number of faulty nodes in the network
     pub f: u64,
 
@@ -123,48 +209,226 @@ pub struct PbftState {
     /// node will initiate a view change.
     pub faulty_primary_timeout: Timeout,
 
+    /// The faulty primary timeout to use when view changes are succeeding
+    #[serde(default)]
+    pub base_faulty_primary_timeout: Duration,
+
+    /// How much the faulty primary timeout grows after each failed view change
+    #[serde(default)]
+    pub view_change_backoff_factor: u32,
+
+    /// Upper bound for the faulty primary timeout
+    #[serde(default)]
+    pub max_faulty_primary_timeout: Duration,
+
+    /// Timer used to make sure the working block gets committed in a timely manner once this
+    /// node has it; if not, this node will initiate a view change.
+    #[serde(default)]
+    pub commit_timeout: Timeout,
+
+    /// Timer started when this node publishes a block; it doesn't publish another one until the
+    /// timer expires
+    #[serde(default)]
+    pub block_publish_timeout: Timeout,
+
+    /// How many view changes this node has started since it last committed a block
+    #[serde(default)]
+    pub view_change_attempts: u32,
+
+    /// The view this node is changing to; see `view_change_target()`
+    #[serde(default)]
+    target_view: u64,
+
+    /// Timer used to make sure a view change completes; if not, this node gives up on the target
+    /// view and tries the next one
+    #[serde(default)]
+    pub view_change_timeout: Timeout,
+
+    /// How long the view change timeout lasts when changing to the next view; it's multiplied by
+    /// the number of views being skipped
+    #[serde(default)]
+    pub view_change_duration: Duration,
+
+    /// How many blocks to commit before forcing a view change (0 disables forced view changes)
     pub forced_view_change_period: u64,
 
+    /// How many blocks after each multiple of `forced_view_change_period` this node forces its
+    /// view change
+    #[serde(default)]
+    pub forced_view_change_offset: u64,
+
+    /// Configured number of `ViewChange`s that make this node join a view change early (0 uses
+    /// `f + 1`); see `view_change_join_threshold()`
+    #[serde(default)]
+    pub view_change_join_threshold: u64,
+
     /// The current block this node is working on
     pub working_block: Option<PbftBlock>,
+
+    /// The most recent block this node committed
+    #[serde(default)]
+    pub last_committed_block: Option<BlockId>,
+
+    /// Highest sequence number that `f + 1` peers have sent `Commit`s for; if it's ahead of
+    /// `seq_num`, this node has fallen behind
+    #[serde(default)]
+    pub highest_seen_seq_num: u64,
+
+    /// The block a multi-block catch-up is heading for, while some of the blocks before it are
+    /// still to be committed
+    #[serde(default)]
+    pub catchup_target: Option<BlockId>,
+
+    /// How many completed view changes each peer has been the target of, keyed by the
+    /// hex-encoded `PeerId`
+    #[serde(default)]
+    pub faulty_primary_strikes: HashMap<String, u64>,
+
+    /// Number of strikes after which a peer is reported as chronically faulty (0 disables)
+    #[serde(default)]
+    pub faulty_primary_strike_threshold: u64,
+
+    /// Peers that are skipped over in primary rotation, but still vote
+    #[serde(default)]
+    pub primary_ineligible_peers: Vec<PeerId>,
+
+    /// How long this node may stay in each phase before it's considered stuck
+    #[serde(default)]
+    pub phase_timeouts: PhaseTimeouts,
+
+    /// Watchdog timer for the current phase; restarted whenever the phase changes
+    #[serde(default)]
+    pub phase_timeout: Timeout,
+
+    /// Told about every phase change; see `set_phase_callback`
+    #[serde(skip)]
+    phase_listener: PhaseListener,
+
+    /// Identifier of the PBFT network this node belongs to
+    #[serde(default)]
+    pub network_id: String,
+
+    /// Whether this node may run as the only node in the network
+    #[serde(default)]
+    pub allow_single_node: bool,
+
+    /// Whether this node only verifies the chain; it's never primary
+    #[serde(default)]
+    pub verify_only: bool,
+
+    /// Sequence number of the last stable checkpoint, or of the last block this node committed if
+    /// that's later; nothing is accepted more than `watermark_window` sequence numbers past it
+    #[serde(default)]
+    pub low_watermark: u64,
+
+    /// How far past the low watermark sequence numbers are accepted (0 for no limit)
+    #[serde(default)]
+    pub watermark_window: u64,
+
+    /// Where the node's timers get the time from; see `set_clock`
+    #[serde(skip, default = "system_clock")]
+    clock: Arc<Clock>,
 }
 
 impl PbftState {
     /// Construct the initial state for a PBFT node
     /// # Panics
-    /// Panics if the network this node is on does not have enough nodes to be Byzantine fault
-    /// tolernant.
+    /// Panics if the network this node is on does not have enough voting nodes to be Byzantine
+    /// fault tolernant, unless it is a single-node network and `allow_single_node` is set.
+    /// Observers from the config don't vote, so they don't count.
     #[allow(clippy::needless_pass_by_value)]
     pub fn new(id: PeerId, head_block_num: u64, config: &PbftConfig) -> Self {
+        let (observers, mut peer_ids): (Vec<PeerId>, Vec<PeerId>) = dedup_peers(&config.peers)
+            .into_iter()
+            .partition(|peer| config.observers.contains(peer));
+        peer_ids.sort();
+
+        let mut phase_timeout = Timeout::new(config.phase_timeouts.pre_preparing);
+        phase_timeout.start();
+
         // Maximum number of faulty nodes in this network. Panic if there are not enough nodes.
-        let f = ((config.peers.len() - 1) / 3) as u64;
+        let f = match compute_f(peer_ids.len()) {
+            Ok(f) => f,
+            Err(_) if config.allow_single_node && peer_ids.len() == 1 => 0,
+            Err(err) => panic!("This network is not fault tolerant: {}", err),
+        };
         if f == 0 {
-            panic!("This network does not contain enough nodes to be fault tolerant");
+            warn!(
+                "RUNNING AS A SINGLE NODE; consensus is disabled and blocks are committed without \
+                 any votes. This is insecure and only meant for development!"
+            );
         }
 
-        PbftState {
+        let mut state = PbftState {
             id: id.clone(),
             seq_num: head_block_num + 1,
-            view: 0, // Node ID 0 is default primary
+            view: config.initial_view,
+            view_entered: Instant::now(),
             phase: PbftPhase::PrePreparing,
-            role: if config.peers[0] == id {
-                PbftNodeRole::Primary
-            } else {
-                PbftNodeRole::Secondary
-            },
+            role: NodeRole::Secondary,
             mode: PbftMode::Normal,
+            view_change_seal: None,
             f,
-            peer_ids: config.peers.clone(),
+            peer_ids,
+            observers,
+            live_peers: HashSet::new(),
             faulty_primary_timeout: Timeout::new(config.faulty_primary_timeout),
+            base_faulty_primary_timeout: config.faulty_primary_timeout,
+            view_change_backoff_factor: config.view_change_backoff_factor,
+            max_faulty_primary_timeout: config.max_faulty_primary_timeout,
+            commit_timeout: Timeout::new(config.commit_timeout),
+            block_publish_timeout: Timeout::new(config.min_block_publish_interval),
+            view_change_attempts: 0,
+            target_view: 0,
+            view_change_timeout: Timeout::new(config.view_change_duration),
+            view_change_duration: config.view_change_duration,
             forced_view_change_period: config.forced_view_change_period,
+            forced_view_change_offset: forced_view_change_offset(
+                &id,
+                config.forced_view_change_jitter,
+            ),
+            view_change_join_threshold: config.view_change_join_threshold,
             working_block: None,
+            last_committed_block: None,
+            highest_seen_seq_num: 0,
+            catchup_target: None,
+            faulty_primary_strikes: HashMap::new(),
+            faulty_primary_strike_threshold: config.faulty_primary_strike_threshold,
+            primary_ineligible_peers: config.primary_ineligible_peers.clone(),
+            phase_timeouts: config.phase_timeouts.clone(),
+            phase_timeout,
+            phase_listener: PhaseListener::default(),
+            network_id: config.network_id.clone(),
+            allow_single_node: config.allow_single_node,
+            verify_only: config.verify_only,
+            low_watermark: head_block_num,
+            watermark_window: config.watermark_window,
+            clock: system_clock(),
+        };
+
+        // The primary of the initial view (by default, the first eligible node) starts out as the
+        // primary
+        if state.get_primary_id_for_view(state.view) == state.id {
+            state.upgrade_role();
         }
+        state
     }
 
     pub fn peers(&self) -> &Vec<PeerId> {
         &self.peer_ids
     }
 
+    /// Get the sequence number before the current one, whose block's seal is needed to build the
+    /// next seal or to propose a view change
+    ///
+    /// The sequence number starts just past the chain head, so it's only ever 0 if the state is
+    /// corrupt; that's reported as an error rather than wrapping around.
+    pub fn previous_seq_num(&self) -> Result<u64, PbftError> {
+        self.seq_num.checked_sub(1).ok_or_else(|| {
+            PbftError::InternalError("Sequence number is 0, so there's no previous block".into())
+        })
+    }
+
     /// Check to see what type of message this node is expecting or sending, based on the current
     /// phase
     pub fn check_msg_type(&self) -> PbftMessageType {
@@ -177,30 +441,282 @@ impl PbftState {
         }
     }
 
+    /// How many `ViewChange` messages for a later view make this node join the view change
+    ///
+    /// Never less than `f + 1`, even if the network has grown since the threshold was configured.
+    pub fn view_change_join_threshold(&self) -> u64 {
+        self.view_change_join_threshold.max(self.f + 1)
+    }
+
+    /// The view this node is trying to change to, if it's in the middle of a view change
+    pub fn view_change_target(&self) -> Option<u64> {
+        if self.mode == PbftMode::ViewChanging {
+            Some(self.target_view.max(self.view + 1))
+        } else {
+            None
+        }
+    }
+
+    /// Start changing to the view after the current one, and start the view change timeout
+    pub fn begin_view_change(&mut self) {
+        self.mode = PbftMode::ViewChanging;
+        self.target_view = self.view + 1;
+        self.restart_view_change_timeout();
+    }
+
+    /// Give up on the view this node is changing to and aim for the one after it instead;
+    /// returns the new target view
+    ///
+    /// The view change timeout is restarted, and lasts `view_change_duration` for each view away
+    /// from the current one, so that the other nodes have longer to catch up each time.
+    pub fn skip_view_change_target(&mut self) -> u64 {
+        self.target_view = self.view_change_target().unwrap_or(self.view + 1) + 1;
+        self.mode = PbftMode::ViewChanging;
+        self.restart_view_change_timeout();
+        self.target_view
+    }
+
+    /// Start the view change timeout over, for as long as the distance to the target view calls
+    /// for
+    pub fn restart_view_change_timeout(&mut self) {
+        let target = self.view_change_target().unwrap_or(self.view + 1);
+        let duration = self
+            .view_change_duration
+            .checked_mul((target - self.view) as u32)
+            .unwrap_or(self.max_faulty_primary_timeout);
+        self.view_change_timeout.set_duration(duration);
+        self.view_change_timeout.start();
+    }
+
     /// Obtain the ID for the primary node in the network
     pub fn get_primary_id(&self) -> PeerId {
-        let primary_index = (self.view % (self.peer_ids.len() as u64)) as usize;
-        self.peer_ids[primary_index].clone()
+        self.get_primary_id_for_view(self.view)
+    }
+
+    /// Obtain the ID for the primary node at the given view
+    ///
+    /// The primary rotates through the peers that are eligible to be primary, so that each of them
+    /// is primary for one view in turn; if no peer is eligible, it falls back to every peer. Only
+    /// the on-chain settings are used, so every node agrees on the primary; faulty primary strikes
+    /// are this node's own and don't count.
+    ///
+    /// The rotation is over the current set of peers, so for a view from before a membership
+    /// change this won't necessarily be the peer that was primary at the time.
+    pub fn get_primary_id_for_view(&self, view: u64) -> PeerId {
+        let eligible: Vec<&PeerId> = self
+            .peer_ids
+            .iter()
+            .filter(|id| self.is_primary_eligible(id))
+            .collect();
+
+        let rotation = if !eligible.is_empty() {
+            eligible
+        } else {
+            self.peer_ids.iter().collect()
+        };
+
+        rotation[(view % (rotation.len() as u64)) as usize].clone()
+    }
+
+    /// Tell if the given node may ever be made primary
+    pub fn is_primary_eligible(&self, id: &[u8]) -> bool {
+        !self.primary_ineligible_peers.iter().any(|peer| peer == id)
+    }
+
+    /// Record a strike against a node that was the target of a completed view change
+    pub fn add_faulty_primary_strike(&mut self, id: &[u8]) {
+        let strikes = self
+            .faulty_primary_strikes
+            .entry(hex::encode(id))
+            .or_insert(0);
+        *strikes += 1;
+        let strikes = *strikes;
+
+        if self.faulty_primary_strike_threshold > 0
+            && strikes == self.faulty_primary_strike_threshold
+        {
+            warn!(
+                "{}: Node {} has been the target of {} view changes; consider making it \
+                 ineligible to be primary",
+                self,
+                hex::encode(id),
+                strikes
+            );
+        }
+    }
+
+    /// Get the number of strikes recorded against the given node
+    pub fn get_faulty_primary_strikes(&self, id: &[u8]) -> u64 {
+        *self
+            .faulty_primary_strikes
+            .get(&hex::encode(id))
+            .unwrap_or(&0)
+    }
+
+    /// Clear all strikes against a node
+    pub fn reinstate_primary(&mut self, id: &[u8]) {
+        self.faulty_primary_strikes.remove(&hex::encode(id));
+    }
+
+    /// Tell if the given node has accumulated enough strikes to be reported as chronically faulty
+    pub fn is_chronically_faulty(&self, id: &[u8]) -> bool {
+        self.faulty_primary_strike_threshold > 0
+            && self.get_faulty_primary_strikes(id) >= self.faulty_primary_strike_threshold
+    }
+
+    /// Tell if this node is the only one in the network and `allow_single_node` is set, so that
+    /// consensus is skipped
+    pub fn is_single_node(&self) -> bool {
+        self.allow_single_node && self.peer_ids.len() == 1
     }
 
-    /// Tell if this node is currently the primary
+    /// Tell if enough voting nodes are connected for the network to reach consensus
+    ///
+    /// This node counts itself, so `2f` of the other voting nodes must be live for a quorum of
+    /// `2f + 1`.
+    pub fn has_live_quorum(&self) -> bool {
+        let live = self
+            .live_peers
+            .iter()
+            .filter(|peer| **peer != self.id && self.peer_ids.contains(peer))
+            .count() as u64;
+        live >= 2 * self.f
+    }
+
+    /// Highest sequence number that messages and blocks are accepted for, if watermarks are
+    /// enabled
+    pub fn high_watermark(&self) -> Option<u64> {
+        if self.watermark_window > 0 {
+            Some(self.low_watermark + self.watermark_window)
+        } else {
+            None
+        }
+    }
+
+    /// Check that the sequence number isn't past the high watermark
+    ///
+    /// Sequence numbers below the low watermark are left for the caller to treat as old, since a
+    /// node that's catching up still needs them.
+    pub fn check_watermark(&self, seq_num: u64) -> Result<(), PbftError> {
+        match self.high_watermark() {
+            Some(high) if seq_num > high => Err(PbftError::AboveHighWatermark(seq_num, high)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Move the low watermark up to a newly stable checkpoint or committed block; it never moves
+    /// back
+    pub fn advance_low_watermark(&mut self, seq_num: u64) {
+        if seq_num > self.low_watermark {
+            self.low_watermark = seq_num;
+        }
+    }
+
+    /// Get this node's current role
+    ///
+    /// Only `upgrade_role` and `downgrade_role` change it (along with the observer list, for
+    /// `Observer`).
+    pub fn role(&self) -> NodeRole {
+        if self.is_observer() {
+            NodeRole::Observer
+        } else {
+            self.role
+        }
+    }
+
+    /// Tell if this node is currently the primary; an observer never is
     pub fn is_primary(&self) -> bool {
-        self.role == PbftNodeRole::Primary
+        self.role() == NodeRole::Primary
     }
 
-    /// Upgrade this node to primary
+    /// Upgrade this node to primary; observers and verify-only nodes are never primary, so they
+    /// stay secondary
     pub fn upgrade_role(&mut self) {
-        self.role = PbftNodeRole::Primary;
+        if self.is_observer() || self.verify_only {
+            return;
+        }
+        self.role = NodeRole::Primary;
     }
 
     /// Downgrade this node to secondary
     pub fn downgrade_role(&mut self) {
-        self.role = PbftNodeRole::Secondary;
+        self.role = NodeRole::Secondary;
+    }
+
+    /// Tell if this node is an observer, and so doesn't vote
+    pub fn is_observer(&self) -> bool {
+        self.observers.contains(&self.id)
+    }
+
+    /// Move a peer out of the voting set, making it an observer
+    ///
+    /// `f` is recomputed for the smaller voting set, and this node's role is updated in case the
+    /// primary changed. Fails if the peer isn't voting, or if the remaining peers wouldn't be
+    /// enough to be fault tolerant.
+    pub fn downgrade_to_observer(&mut self, id: &[u8]) -> Result<(), PbftError> {
+        if !self.peer_ids.iter().any(|peer| peer == id) {
+            return Err(PbftError::NodeNotFound);
+        }
+
+        let peer_ids: Vec<PeerId> = self
+            .peer_ids
+            .iter()
+            .filter(|peer| *peer != id)
+            .cloned()
+            .collect();
+        let f = compute_f(peer_ids.len()).map_err(|_| {
+            PbftError::InvalidConfig(format!(
+                "Making {} an observer would leave {} voting nodes, which isn't fault tolerant",
+                hex::encode(id),
+                peer_ids.len()
+            ))
+        })?;
+
+        warn!("{}: Node {} is now an observer", self, hex::encode(id));
+        self.peer_ids = peer_ids;
+        self.observers.push(id.to_vec());
+        self.update_voting_set(f);
+
+        Ok(())
+    }
+
+    /// Move an observer back into the voting set
+    ///
+    /// `f` is recomputed for the larger voting set, and this node's role is updated in case the
+    /// primary changed. Fails if the peer isn't an observer.
+    pub fn promote_from_observer(&mut self, id: &[u8]) -> Result<(), PbftError> {
+        let pos = self
+            .observers
+            .iter()
+            .position(|peer| peer == id)
+            .ok_or(PbftError::NodeNotFound)?;
+
+        warn!("{}: Node {} is voting again", self, hex::encode(id));
+        let peer = self.observers.remove(pos);
+        self.peer_ids.push(peer);
+        self.peer_ids.sort();
+        let f = compute_f(self.peer_ids.len()).unwrap_or(0);
+        self.update_voting_set(f);
+
+        Ok(())
+    }
+
+    /// Apply a new fault tolerance after the voting set changed, and take the right role for it
+    fn update_voting_set(&mut self, f: u64) {
+        self.f = f;
+        if self.get_primary_id() == self.id {
+            self.upgrade_role();
+        } else {
+            self.downgrade_role();
+        }
     }
 
     /// Go to a phase and return new phase, if successfully changed
     /// Enforces sequential ordering of PBFT phases in normal mode.
-    pub fn switch_phase(&mut self, desired_phase: PbftPhase) -> Option<PbftPhase> {
+    ///
+    /// Any other transition is a `PhaseTransition` error, including "switching" to the phase the
+    /// node is already in; callers for which that is harmless should check for `from == to`.
+    pub fn switch_phase(&mut self, desired_phase: PbftPhase) -> Result<PbftPhase, PbftError> {
         let next = match self.phase {
             PbftPhase::PrePreparing => PbftPhase::Preparing,
             PbftPhase::Preparing => PbftPhase::Checking,
@@ -210,27 +726,429 @@ impl PbftState {
         };
         if desired_phase == next {
             debug!("{}: Changing to {:?}", self, desired_phase);
-            self.phase = desired_phase.clone();
-            Some(desired_phase)
+            self.set_phase(desired_phase.clone());
+            Ok(desired_phase)
         } else {
             debug!("{}: Didn't change to {:?}", self, desired_phase);
-            None
+            Err(PbftError::PhaseTransition(
+                self.phase.clone(),
+                desired_phase,
+            ))
+        }
+    }
+
+    /// Go straight to the given phase, without enforcing the ordering of phases, and restart the
+    /// phase watchdog
+    pub fn set_phase(&mut self, phase: PbftPhase) {
+        self.phase_timeout =
+            Timeout::with_clock(self.phase_timeout_for(&phase), Arc::clone(&self.clock));
+        self.phase_timeout.start();
+        let old = mem::replace(&mut self.phase, phase);
+        if old != self.phase {
+            if let Some(ref mut callback) = self.phase_listener.0 {
+                callback(old, self.phase.clone());
+            }
+        }
+    }
+
+    /// Call the given function with the old and new phases whenever this node's phase changes,
+    /// replacing any callback set before
+    ///
+    /// Rejected transitions, and "changes" to the phase the node is already in, aren't reported.
+    /// The callback isn't saved with the state.
+    pub fn set_phase_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(PbftPhase, PbftPhase) + Send + 'static,
+    {
+        self.phase_listener.0 = Some(Box::new(callback));
+    }
+
+    /// Stop reporting phase changes
+    pub fn clear_phase_callback(&mut self) {
+        self.phase_listener.0 = None;
+    }
+
+    /// Have the node's timers get the time from the given clock, instead of the system's; time
+    /// that has already passed carries over. The clock isn't saved with the state.
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        let in_view = self.elapsed_in_view();
+        self.view_entered = clock.now() - in_view;
+        for timeout in &mut [
+            &mut self.faulty_primary_timeout,
+            &mut self.commit_timeout,
+            &mut self.block_publish_timeout,
+            &mut self.view_change_timeout,
+            &mut self.phase_timeout,
+        ] {
+            timeout.set_clock(Arc::clone(&clock));
         }
+        self.clock = clock;
     }
 
+    /// Move to the given view; the time spent in the view is only reset if it actually changes
+    pub fn set_view(&mut self, view: u64) {
+        if view != self.view {
+            self.view = view;
+            self.view_entered = self.clock.now();
+        }
+    }
+
+    /// Get how long this node has been in its current view
+    pub fn elapsed_in_view(&self) -> Duration {
+        self.clock.now() - self.view_entered
+    }
+
+    /// Get how long this node may stay in the given phase before it's considered stuck
+    pub fn phase_timeout_for(&self, phase: &PbftPhase) -> Duration {
+        match phase {
+            PbftPhase::PrePreparing => self.phase_timeouts.pre_preparing,
+            PbftPhase::Preparing => self.phase_timeouts.preparing,
+            PbftPhase::Checking => self.phase_timeouts.checking,
+            PbftPhase::Committing => self.phase_timeouts.committing,
+            PbftPhase::Finished => self.phase_timeouts.finished,
+        }
+    }
+
+    /// Record that this node has started a view change, and back off the faulty primary timeout
+    ///
+    /// Every view change after the first one since the last committed block means the previous
+    /// one failed, so the timeout becomes `base * factor^(attempts - 1)`, up to the maximum.
+    pub fn record_view_change_attempt(&mut self) {
+        self.view_change_attempts = self.view_change_attempts.saturating_add(1);
+
+        let mut timeout = self.base_faulty_primary_timeout;
+        for _ in 1..self.view_change_attempts {
+            if timeout >= self.max_faulty_primary_timeout {
+                break;
+            }
+            timeout = timeout
+                .checked_mul(self.view_change_backoff_factor)
+                .unwrap_or(self.max_faulty_primary_timeout);
+        }
+        let timeout = ::std::cmp::min(timeout, self.max_faulty_primary_timeout);
+
+        if timeout != self.faulty_primary_timeout.duration() {
+            warn!(
+                "{}: {} view changes without a commit; faulty primary timeout is now {:?}",
+                self, self.view_change_attempts, timeout
+            );
+        }
+        self.faulty_primary_timeout.set_duration(timeout);
+    }
+
+    /// Forget about previous view changes and go back to the configured faulty primary timeout,
+    /// once the network is making progress again
+    pub fn reset_view_change_backoff(&mut self) {
+        self.view_change_attempts = 0;
+        self.faulty_primary_timeout
+            .set_duration(self.base_faulty_primary_timeout);
+    }
+
+    /// Check if this node has been in its current phase for longer than that phase's timeout
+    pub fn check_phase_timeout_expired(&mut self) -> bool {
+        self.phase_timeout.check_expired()
+    }
+
+    /// Check if this node is due to force a view change at the current sequence number
+    ///
+    /// This happens every `forced_view_change_period` blocks, shifted by this node's
+    /// `forced_view_change_offset`, unless the period is 0.
     pub fn at_forced_view_change(&self) -> bool {
-        self.seq_num > 0 && self.seq_num % self.forced_view_change_period == 0
+        self.forced_view_change_period > 0
+            && self.seq_num > self.forced_view_change_offset
+            && (self.seq_num - self.forced_view_change_offset) % self.forced_view_change_period == 0
+    }
+
+    /// Check that the state is internally consistent
+    ///
+    /// Returns a description of the first inconsistency found. Meant to be called through
+    /// `debug_assert!` after state transitions, so that bugs surface early in tests and debug
+    /// builds.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        if self.peer_ids.is_empty() {
+            return Err("No peers in the network".into());
+        }
+
+        let expected_f = compute_f(self.peer_ids.len()).unwrap_or(0);
+        if self.f != expected_f {
+            return Err(format!(
+                "f is {} but should be {} for {} peers",
+                self.f,
+                expected_f,
+                self.peer_ids.len()
+            ));
+        }
+
+        if self.seq_num == 0 {
+            return Err("Sequence number is 0".into());
+        }
+
+        // The role is only updated once a view change completes
+        if self.mode == PbftMode::Normal {
+            let primary_id = self.get_primary_id();
+            if self.is_primary() && self.id != primary_id {
+                return Err(format!(
+                    "Node is primary, but the primary for view {} is {}",
+                    self.view,
+                    hex::encode(&primary_id)
+                ));
+            }
+            if !self.is_primary() && self.id == primary_id && !self.verify_only {
+                return Err(format!(
+                    "Node is secondary, but it is the primary for view {}",
+                    self.view
+                ));
+            }
+        }
+
+        match self.working_block {
+            Some(ref block) if block.get_block_num() != self.seq_num => {
+                return Err(format!(
+                    "Working block is #{}, but the sequence number is {}",
+                    block.get_block_num(),
+                    self.seq_num
+                ));
+            }
+            None if self.phase != PbftPhase::PrePreparing => {
+                return Err(format!("In phase {:?} with no working block", self.phase));
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Serialize the state into a compact binary snapshot
+    ///
+    /// This is much smaller and quicker to produce than the JSON form, so it's meant for
+    /// persisting the state often. Everything the JSON form stores is kept, except for the values
+    /// that come from the on-chain settings; those are filled back in by `deserialize_compact`.
+    /// Timers are stored as whether they were running, along with the durations that don't come
+    /// straight from the settings.
+    pub fn serialize_compact(&self) -> Vec<u8> {
+        let mut snapshot = PbftStateSnapshot::new();
+        snapshot.set_id(self.id.clone());
+        snapshot.set_seq_num(self.seq_num);
+        snapshot.set_view(self.view);
+        snapshot.set_phase(format!("{:?}", self.phase));
+        snapshot.set_is_primary(self.is_primary());
+        snapshot.set_view_changing(self.mode == PbftMode::ViewChanging);
+        snapshot.set_peer_ids(self.peer_ids.iter().cloned().collect());
+        snapshot.set_observers(self.observers.iter().cloned().collect());
+        if let Some(ref block) = self.working_block {
+            snapshot.set_working_block(block.clone());
+        }
+        snapshot.set_faulty_primary_strikes(
+            self.faulty_primary_strikes
+                .iter()
+                .sorted()
+                .into_iter()
+                .filter_map(|(id, strikes)| {
+                    let mut entry = PbftPrimaryStrikes::new();
+                    entry.set_peer_id(hex::decode(id).ok()?);
+                    entry.set_strikes(*strikes);
+                    Some(entry)
+                })
+                .collect(),
+        );
+        snapshot.set_faulty_primary_timeout_active(self.faulty_primary_timeout.is_running());
+        snapshot.set_target_view(self.target_view);
+        if let (PbftMode::ViewChanging, Some(ref seal)) = (&self.mode, &self.view_change_seal) {
+            snapshot.set_view_change_seal(seal.clone());
+        }
+        if let Some(ref block_id) = self.last_committed_block {
+            snapshot.set_last_committed_block(block_id.clone());
+        }
+        snapshot.set_highest_seen_seq_num(self.highest_seen_seq_num);
+        if let Some(ref block_id) = self.catchup_target {
+            snapshot.set_catchup_target(block_id.clone());
+        }
+        snapshot.set_low_watermark(self.low_watermark);
+        snapshot.set_view_change_attempts(self.view_change_attempts);
+        snapshot.set_faulty_primary_timeout_millis(
+            self.faulty_primary_timeout.duration().as_millis() as u64,
+        );
+        snapshot
+            .set_view_change_timeout_millis(self.view_change_timeout.duration().as_millis() as u64);
+        snapshot.set_commit_timeout_active(self.commit_timeout.is_running());
+        snapshot.set_view_change_timeout_active(self.view_change_timeout.is_running());
+        snapshot.set_block_publish_timeout_active(self.block_publish_timeout.is_running());
+
+        snapshot
+            .write_to_bytes()
+            .expect("Couldn't serialize state snapshot")
+    }
+
+    /// Reconstruct a state from a snapshot made by `serialize_compact`
+    ///
+    /// Settings are taken from `config`. Time already spent on a timer can't be carried over, so
+    /// the timers that were running are restarted, as is the phase watchdog.
+    pub fn deserialize_compact(bytes: &[u8], config: &PbftConfig) -> Result<Self, PbftError> {
+        let snapshot: PbftStateSnapshot = protobuf::parse_from_bytes(bytes)
+            .map_err(|err| PbftError::SerializationError("parsing state snapshot", err))?;
+
+        let phase = match snapshot.get_phase() {
+            "PrePreparing" => PbftPhase::PrePreparing,
+            "Preparing" => PbftPhase::Preparing,
+            "Checking" => PbftPhase::Checking,
+            "Committing" => PbftPhase::Committing,
+            "Finished" => PbftPhase::Finished,
+            other => {
+                return Err(PbftError::InternalError(format!(
+                    "Unknown phase in state snapshot: {}",
+                    other
+                )))
+            }
+        };
+
+        let mut peer_ids: Vec<PeerId> = snapshot.get_peer_ids().to_vec();
+        peer_ids.sort();
+        if peer_ids.is_empty() {
+            return Err(PbftError::InternalError(
+                "State snapshot has no peers".into(),
+            ));
+        }
+
+        let mut state = PbftState::new(snapshot.get_id().to_vec(), 0, config);
+
+        // Membership may have changed since the node started, so the peers come from the snapshot
+        state.f = compute_f(peer_ids.len()).unwrap_or(0);
+        state.peer_ids = peer_ids;
+        state.observers = snapshot.get_observers().to_vec();
+        state.seq_num = snapshot.get_seq_num();
+        state.low_watermark = snapshot.get_low_watermark();
+        state.highest_seen_seq_num = snapshot.get_highest_seen_seq_num();
+        state.last_committed_block = Some(snapshot.get_last_committed_block().to_vec())
+            .filter(|block_id| !block_id.is_empty());
+        state.catchup_target =
+            Some(snapshot.get_catchup_target().to_vec()).filter(|block_id| !block_id.is_empty());
+        state.view_change_attempts = snapshot.get_view_change_attempts();
+        state.set_view(snapshot.get_view());
+        state.set_phase(phase);
+        if snapshot.get_is_primary() {
+            state.upgrade_role();
+        } else {
+            state.downgrade_role();
+        }
+        state.mode = if snapshot.get_view_changing() {
+            PbftMode::ViewChanging
+        } else {
+            PbftMode::Normal
+        };
+        if snapshot.has_view_change_seal() {
+            state.view_change_seal = Some(snapshot.get_view_change_seal().clone());
+        }
+        state.target_view = snapshot.get_target_view();
+        state.working_block = if snapshot.has_working_block() {
+            Some(snapshot.get_working_block().clone())
+        } else {
+            None
+        };
+        state.faulty_primary_strikes = snapshot
+            .get_faulty_primary_strikes()
+            .iter()
+            .map(|entry| (hex::encode(entry.get_peer_id()), entry.get_strikes()))
+            .collect();
+        // Snapshots stored before the durations were added have them as 0, so the configured ones stay
+        if snapshot.get_faulty_primary_timeout_millis() > 0 {
+            state
+                .faulty_primary_timeout
+                .set_duration(Duration::from_millis(
+                    snapshot.get_faulty_primary_timeout_millis(),
+                ));
+        }
+        if snapshot.get_view_change_timeout_millis() > 0 {
+            state
+                .view_change_timeout
+                .set_duration(Duration::from_millis(
+                    snapshot.get_view_change_timeout_millis(),
+                ));
+        }
+        if snapshot.get_faulty_primary_timeout_active() {
+            state.faulty_primary_timeout.start();
+        }
+        if snapshot.get_commit_timeout_active() {
+            state.commit_timeout.start();
+        }
+        if snapshot.get_view_change_timeout_active() {
+            state.view_change_timeout.start();
+        }
+        if snapshot.get_block_publish_timeout_active() {
+            state.block_publish_timeout.start();
+        }
+
+        Ok(state)
+    }
+
+    /// Take the settings that come from the configuration again, for a state that was loaded from
+    /// storage
+    ///
+    /// The peers are sorted, in case they weren't stored in order, and the node's role is worked
+    /// out again from them.
+    ///
+    /// The on-chain settings may have changed while the node was down, and a state stored by an
+    /// older version of this engine has none of the settings added since. Timers keep whether
+    /// they were running, but last as long as the configuration says; the faulty primary and view
+    /// change timeouts grow with failed view changes, so they're only given a duration if they were
+    /// stored without one.
+    pub fn apply_config(&mut self, config: &PbftConfig) {
+        self.base_faulty_primary_timeout = config.faulty_primary_timeout;
+        self.view_change_backoff_factor = config.view_change_backoff_factor;
+        self.max_faulty_primary_timeout = config.max_faulty_primary_timeout;
+        self.view_change_duration = config.view_change_duration;
+        self.forced_view_change_period = config.forced_view_change_period;
+        self.forced_view_change_offset =
+            forced_view_change_offset(&self.id, config.forced_view_change_jitter);
+        self.view_change_join_threshold = config.view_change_join_threshold;
+        self.faulty_primary_strike_threshold = config.faulty_primary_strike_threshold;
+        self.primary_ineligible_peers = config.primary_ineligible_peers.clone();
+        self.phase_timeouts = config.phase_timeouts.clone();
+        self.network_id = config.network_id.clone();
+        self.allow_single_node = config.allow_single_node;
+        self.verify_only = config.verify_only;
+        self.watermark_window = config.watermark_window;
+
+        self.commit_timeout.set_duration(config.commit_timeout);
+        self.block_publish_timeout
+            .set_duration(config.min_block_publish_interval);
+        if self.faulty_primary_timeout.duration() == Duration::from_secs(0) {
+            self.faulty_primary_timeout
+                .set_duration(config.faulty_primary_timeout);
+        }
+        if self.view_change_timeout.duration() == Duration::from_secs(0) {
+            self.view_change_timeout
+                .set_duration(config.view_change_duration);
+        }
+        let phase_timeout = self.phase_timeout_for(&self.phase);
+        self.phase_timeout.set_duration(phase_timeout);
+
+        self.peer_ids.sort();
+        let f = self.f;
+        self.update_voting_set(f);
+    }
+
+    /// Stop every timer, so that a node resumed from this state starts them over cleanly
+    pub fn stop_timeouts(&mut self) {
+        self.faulty_primary_timeout.stop();
+        self.commit_timeout.stop();
+        self.block_publish_timeout.stop();
+        self.view_change_timeout.stop();
+        self.phase_timeout.stop();
     }
 
     /// Discard the current working block, and reset phase/mode
     ///
-    /// Used after a view change has occured
+    /// Used after a view change has occured. The faulty primary timeout starts over, even if it
+    /// was paused for the view change, since the new view's primary is owed the whole of it.
     pub fn discard_current_block(&mut self) {
         warn!("PbftState::reset: {}", self);
 
         self.working_block = None;
-        self.phase = PbftPhase::PrePreparing;
+        self.commit_timeout.stop();
+        self.set_phase(PbftPhase::PrePreparing);
         self.mode = PbftMode::Normal;
+        self.view_change_seal = None;
+        self.view_change_timeout.stop();
         self.faulty_primary_timeout.start();
     }
 }
@@ -239,6 +1157,133 @@ impl PbftState {
 mod tests {
     use super::*;
     use crate::config::mock_config;
+    use crate::timing::MockClock;
+    use serde_json;
+    use std::sync::Mutex;
+
+    /// Make sure that jitter shifts each node's forced view changes by its own offset, without
+    /// changing how often they happen
+    #[test]
+    fn forced_view_change_jitter() {
+        let mut config = mock_config(4);
+        config.forced_view_change_period = 10;
+        assert_eq!(
+            PbftState::new(vec![0], 0, &config).forced_view_change_offset,
+            0
+        );
+
+        config.forced_view_change_jitter = 9;
+        assert!(config.validate().is_ok());
+
+        let forced_at = |id: u8| {
+            let mut state = PbftState::new(vec![id], 0, &config);
+            assert!(state.forced_view_change_offset <= 9);
+            (1..110)
+                .filter(|seq_num| {
+                    state.seq_num = *seq_num;
+                    state.at_forced_view_change()
+                })
+                .collect::<Vec<u64>>()
+        };
+
+        // The offsets come from hashing the IDs, so find two nodes that got different ones
+        let schedules: Vec<_> = (0..4).map(forced_at).collect();
+        let other = schedules
+            .iter()
+            .find(|schedule| schedule[0] != schedules[0][0])
+            .expect("All nodes got the same offset");
+
+        for schedule in &[&schedules[0], other] {
+            assert_eq!(schedule.len(), 10);
+            assert!(schedule.windows(2).all(|pair| pair[1] - pair[0] == 10));
+        }
+
+        assert_eq!(schedules[0], forced_at(0));
+
+        // A loaded state follows the configured jitter, not the one it was stored with
+        let mut state = (0..4)
+            .map(|id| PbftState::new(vec![id], 0, &config))
+            .find(|state| state.forced_view_change_offset != 0)
+            .unwrap();
+        let offset = state.forced_view_change_offset;
+        state.forced_view_change_offset = 0;
+        state.apply_config(&config);
+        assert_eq!(state.forced_view_change_offset, offset);
+
+        config.forced_view_change_jitter = 0;
+        state.apply_config(&config);
+        assert_eq!(state.forced_view_change_offset, 0);
+
+        config.forced_view_change_jitter = 10;
+        assert!(config.validate().is_err());
+    }
+
+    /// Make sure that the phase callback is told about each legal phase change in order, and not
+    /// about rejected ones
+    #[test]
+    fn phase_callback() {
+        let mut state = PbftState::new(vec![0], 0, &mock_config(4));
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&changes);
+        state.set_phase_callback(move |old, new| recorded.lock().unwrap().push((old, new)));
+
+        for phase in &[
+            PbftPhase::Preparing,
+            PbftPhase::Checking,
+            PbftPhase::Committing,
+        ] {
+            state.switch_phase(phase.clone()).unwrap();
+        }
+        assert!(state.switch_phase(PbftPhase::Preparing).is_err());
+        assert!(state.switch_phase(PbftPhase::Committing).is_err());
+        state.switch_phase(PbftPhase::Finished).unwrap();
+
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![
+                (PbftPhase::PrePreparing, PbftPhase::Preparing),
+                (PbftPhase::Preparing, PbftPhase::Checking),
+                (PbftPhase::Checking, PbftPhase::Committing),
+                (PbftPhase::Committing, PbftPhase::Finished),
+            ]
+        );
+
+        state.clear_phase_callback();
+        state.switch_phase(PbftPhase::PrePreparing).unwrap();
+        assert_eq!(changes.lock().unwrap().len(), 4);
+    }
+
+    /// Make sure that the primary for each view doesn't depend on the order the peers are listed in
+    #[test]
+    fn primary_with_shuffled_peers() {
+        let mut config = mock_config(4);
+        config.peers = vec![vec![3], vec![0], vec![2], vec![1]];
+        let sorted: Vec<PeerId> = (0..4).map(|i| vec![i]).collect();
+
+        let state = PbftState::new(vec![0], 0, &config);
+        assert_eq!(state.peer_ids, sorted);
+        assert!(state.is_primary());
+        for view in 0..8 {
+            assert_eq!(
+                state.get_primary_id_for_view(view),
+                sorted[view as usize % 4]
+            );
+        }
+    }
+
+    /// Make sure that a forced view change period of 0 never forces a view change
+    #[test]
+    fn forced_view_change_disabled() {
+        let mut config = mock_config(4);
+        config.forced_view_change_period = 0;
+        assert!(config.validate().is_ok());
+
+        let mut state = PbftState::new(vec![0], 0, &config);
+        for seq_num in 0..1000 {
+            state.seq_num = seq_num;
+            assert!(!state.at_forced_view_change(), "{}", seq_num);
+        }
+    }
 
     /// Check that state responds to having an inadequately sized network
     #[test]
@@ -276,6 +1321,172 @@ mod tests {
         assert_eq!(state1.get_primary_id(), state1.peer_ids[0]);
     }
 
+    /// Make sure that the primary rotates through the peers in order, wrapping around after the
+    /// last one, and that the current view's primary is the one picked for that view
+    #[test]
+    fn primary_rotation() {
+        let config = mock_config(4);
+        let mut state = PbftState::new(vec![0], 0, &config);
+
+        for view in 0..12 {
+            assert_eq!(state.get_primary_id_for_view(view), vec![(view % 4) as u8]);
+        }
+
+        state.set_view(6);
+        assert_eq!(state.get_primary_id(), state.get_primary_id_for_view(6));
+        assert_eq!(state.get_primary_id(), vec![2]);
+    }
+
+    /// Make sure that a node that isn't eligible to be primary is never picked as primary, but still
+    /// counts toward `f`
+    #[test]
+    fn primary_ineligible_peer() {
+        let mut config = mock_config(4);
+        config.primary_ineligible_peers = vec![vec![0]];
+        assert!(config.validate().is_ok());
+
+        let state = PbftState::new(vec![0], 0, &config);
+        assert_eq!(state.f, 1);
+        assert!(!state.is_primary());
+        assert!(!state.is_primary_eligible(&[0]));
+
+        for view in 0..100 {
+            assert_ne!(state.get_primary_id_for_view(view), vec![0]);
+        }
+        assert_eq!(state.get_primary_id_for_view(0), vec![1]);
+        assert_eq!(state.get_primary_id_for_view(4), vec![2]);
+        assert!(PbftState::new(vec![1], 0, &config).is_primary());
+
+        // Consecutive views rotate through the eligible peers, each getting one view in turn
+        for view in 0..100 {
+            let mut primaries: Vec<PeerId> = (view..view + 3)
+                .map(|view| state.get_primary_id_for_view(view))
+                .collect();
+            primaries.sort();
+            assert_eq!(primaries, vec![vec![1], vec![2], vec![3]]);
+        }
+
+        config.primary_ineligible_peers = config.peers.clone();
+        assert!(config.validate().is_err());
+    }
+
+    /// Make sure that moving a peer out of the voting set shrinks `f` and the primary rotation
+    #[test]
+    fn downgrade_to_observer() {
+        let config = mock_config(7);
+        let mut state = PbftState::new(vec![1], 0, &config);
+        assert_eq!(state.f, 2);
+
+        // The primary of view 0 stops voting, so this node takes over
+        state.downgrade_to_observer(&[0]).unwrap();
+        assert_eq!(state.peer_ids.len(), 6);
+        assert_eq!(state.observers, vec![vec![0]]);
+        assert_eq!(state.f, 1);
+        assert!(state.is_primary());
+        assert!(state.check_invariants().is_ok());
+        for view in 0..12 {
+            assert_ne!(state.get_primary_id_for_view(view), vec![0]);
+        }
+
+        // Only voting peers can be made observers
+        assert!(state.downgrade_to_observer(&[0]).is_err());
+        assert!(state.downgrade_to_observer(&[9]).is_err());
+
+        // A node can make itself an observer too
+        state.downgrade_to_observer(&[1]).unwrap();
+        assert!(state.is_observer());
+        assert!(!state.is_primary());
+        assert_eq!(state.f, 1);
+    }
+
+    /// Make sure that observers from the config don't count toward `f` and are never made primary
+    #[test]
+    fn configured_observer() {
+        let mut config = mock_config(5);
+        config.observers = vec![vec![4]];
+        let mut state = PbftState::new(vec![4], 0, &config);
+
+        assert_eq!(state.f, 1);
+        assert_eq!(state.peer_ids.len(), 4);
+        assert!(state.is_observer());
+        for view in 0..50 {
+            assert_ne!(state.get_primary_id_for_view(view), vec![4]);
+        }
+
+        state.upgrade_role();
+        assert!(!state.is_primary());
+    }
+
+    /// Make sure that an observer that's moved back into the voting set counts toward `f` again
+    #[test]
+    fn promote_from_observer() {
+        let config = mock_config(7);
+        let mut state = PbftState::new(vec![1], 0, &config);
+        state.downgrade_to_observer(&[0]).unwrap();
+        assert!(state.is_primary());
+
+        state.promote_from_observer(&[0]).unwrap();
+        assert_eq!(state.peer_ids.len(), 7);
+        assert!(state.observers.is_empty());
+        assert_eq!(state.f, 2);
+        assert!(state.check_invariants().is_ok());
+
+        // Only observers can be promoted
+        assert!(state.promote_from_observer(&[0]).is_err());
+        assert!(state.promote_from_observer(&[9]).is_err());
+    }
+
+    /// Make sure that peers can't be made observers if the rest wouldn't be fault tolerant
+    #[test]
+    fn observer_fault_tolerance() {
+        let config = mock_config(5);
+        let mut state = PbftState::new(vec![0], 0, &config);
+
+        state.downgrade_to_observer(&[4]).unwrap();
+        assert_eq!(state.f, 1);
+
+        // Three voting nodes can't tolerate any faults, even if this node is the one leaving
+        for id in 0..4 {
+            assert!(state.downgrade_to_observer(&[id]).is_err());
+        }
+        assert_eq!(state.peer_ids.len(), 4);
+        assert_eq!(state.observers, vec![vec![4]]);
+        assert_eq!(state.f, 1);
+    }
+
+    /// Make sure that duplicate peers are ignored rather than counted toward `f`
+    #[test]
+    fn duplicate_peers() {
+        let mut config = mock_config(4);
+        config.peers.extend(vec![vec![1], vec![2], vec![3]]);
+
+        let state = PbftState::new(vec![0], 0, &config);
+        assert_eq!(state.peer_ids, (0..4).map(|i| vec![i]).collect::<Vec<_>>());
+        assert_eq!(state.f, 1);
+    }
+
+    /// Make sure that formatting works with IDs shorter than the truncation length, and that the
+    /// alternate flag shows full IDs
+    #[test]
+    fn display_ids() {
+        let config = mock_config(4);
+        let mut state = PbftState::new(vec![0], 0, &config);
+        let mut block = PbftBlock::new();
+        block.set_block_id(vec![1, 2]);
+        state.working_block = Some(block);
+
+        assert_eq!(format!("{}", state), "(PP N 0, seq 1, wb 0/0102), Node *00");
+
+        state.id = vec![0xab, 0xcd, 0xef, 0x01];
+        state
+            .working_block
+            .as_mut()
+            .unwrap()
+            .set_block_id(vec![0xff; 4]);
+        assert!(format!("{}", state).ends_with("wb 0/ffffff), Node *abcdef"));
+        assert!(format!("{:#}", state).ends_with("wb 0/ffffffff), Node *abcdef01"));
+    }
+
     /// Make sure that nodes transition from primary to secondary and back smoothly
     #[test]
     fn role_changes() {
@@ -289,6 +1500,56 @@ mod tests {
         assert!(state.is_primary());
     }
 
+    /// Make sure that a node starting in a configured view takes the role it has in that view
+    #[test]
+    fn initial_view() {
+        let mut config = mock_config(4);
+        config.initial_view = 1;
+
+        for id in 0..4 {
+            let state = PbftState::new(vec![id], 0, &config);
+            assert_eq!(state.view, 1);
+            assert_eq!(state.is_primary(), id == 1);
+        }
+
+        // Ineligible peers are still skipped over
+        config.primary_ineligible_peers = vec![vec![1]];
+        assert!(PbftState::new(vec![2], 0, &config).is_primary());
+        assert!(!PbftState::new(vec![1], 0, &config).is_primary());
+    }
+
+    /// Make sure that `role()` follows `upgrade_role` and `downgrade_role`, and reports observers
+    #[test]
+    fn role() {
+        let mut config = mock_config(5);
+        let mut state = PbftState::new(vec![0], 0, &config);
+        assert_eq!(state.role(), NodeRole::Primary);
+
+        state.downgrade_role();
+        assert_eq!(state.role(), NodeRole::Secondary);
+        state.upgrade_role();
+        assert_eq!(state.role(), NodeRole::Primary);
+
+        config.observers = vec![vec![4]];
+        let mut observer = PbftState::new(vec![4], 0, &config);
+        assert_eq!(observer.role(), NodeRole::Observer);
+        observer.upgrade_role();
+        assert_eq!(observer.role(), NodeRole::Observer);
+        assert!(!observer.is_primary());
+
+        // An observer isn't made primary, even if it's first in the list of peers, and doesn't
+        // come back as primary from a snapshot
+        config.observers = vec![vec![0]];
+        let mut observer = PbftState::new(vec![0], 0, &config);
+        assert!(!observer.is_primary());
+        assert!(PbftState::new(vec![1], 0, &config).is_primary());
+        observer.role = NodeRole::Primary;
+        assert!(!observer.is_primary());
+        let restored =
+            PbftState::deserialize_compact(&observer.serialize_compact(), &config).unwrap();
+        assert_eq!(restored.role, NodeRole::Secondary);
+    }
+
     /// Make sure that a normal PBFT cycle works properly
     /// `PrePreparing` => `Preparing` => `Committing` => `Finished` => `PrePreparing`
     /// Also make sure that no illegal phase changes are allowed to happen
@@ -298,13 +1559,411 @@ mod tests {
         let config = mock_config(4);
         let mut state = PbftState::new(vec![0], 0, &config);
 
-        assert!(state.switch_phase(PbftPhase::Preparing).is_some());
-        assert!(state.switch_phase(PbftPhase::Checking).is_some());
-        assert!(state.switch_phase(PbftPhase::Committing).is_some());
-        assert!(state.switch_phase(PbftPhase::Finished).is_some());
-        assert!(state.switch_phase(PbftPhase::PrePreparing).is_some());
+        assert_eq!(
+            state.switch_phase(PbftPhase::Preparing).ok(),
+            Some(PbftPhase::Preparing)
+        );
+        assert!(state.switch_phase(PbftPhase::Checking).is_ok());
+        assert!(state.switch_phase(PbftPhase::Committing).is_ok());
+        assert!(state.switch_phase(PbftPhase::Finished).is_ok());
+        assert!(state.switch_phase(PbftPhase::PrePreparing).is_ok());
+
+        match state.switch_phase(PbftPhase::Finished) {
+            Err(PbftError::PhaseTransition(PbftPhase::PrePreparing, PbftPhase::Finished)) => {}
+            res => panic!("Expected PhaseTransition error, got {:?}", res),
+        }
+        match state.switch_phase(PbftPhase::Checking) {
+            Err(PbftError::PhaseTransition(PbftPhase::PrePreparing, PbftPhase::Checking)) => {}
+            res => panic!("Expected PhaseTransition error, got {:?}", res),
+        }
+        match state.switch_phase(PbftPhase::PrePreparing) {
+            Err(PbftError::PhaseTransition(ref from, ref to)) if from == to => {}
+            res => panic!("Expected PhaseTransition error, got {:?}", res),
+        }
+        assert_eq!(state.phase, PbftPhase::PrePreparing);
+    }
+
+    /// Make sure that the faulty primary timeout grows with each failed view change, up to the
+    /// configured maximum, and goes back to the base value when reset
+    #[test]
+    fn view_change_backoff() {
+        let mut config = mock_config(4);
+        config.faulty_primary_timeout = Duration::from_secs(10);
+        config.view_change_backoff_factor = 2;
+        config.max_faulty_primary_timeout = Duration::from_secs(50);
+        let mut state = PbftState::new(vec![0], 0, &config);
+
+        let expected = [10, 20, 40, 50, 50];
+        for secs in expected.iter() {
+            state.record_view_change_attempt();
+            assert_eq!(
+                state.faulty_primary_timeout.duration(),
+                Duration::from_secs(*secs)
+            );
+        }
+        assert_eq!(state.view_change_attempts, 5);
+
+        state.reset_view_change_backoff();
+        assert_eq!(state.view_change_attempts, 0);
+        assert_eq!(
+            state.faulty_primary_timeout.duration(),
+            Duration::from_secs(10)
+        );
+    }
+
+    /// Make sure that each phase uses its own configured timeout, and that the phase watchdog is
+    /// restarted with the right one on every phase change
+    #[test]
+    fn phase_timeouts() {
+        let mut config = mock_config(4);
+        config.phase_timeouts = PhaseTimeouts {
+            pre_preparing: Duration::from_millis(1000),
+            preparing: Duration::from_millis(10),
+            checking: Duration::from_millis(2000),
+            committing: Duration::from_millis(3000),
+            finished: Duration::from_millis(4000),
+        };
+        let mut state = PbftState::new(vec![0], 0, &config);
+        let clock = MockClock::new();
+        state.set_clock(clock.clone());
+
+        assert_eq!(
+            state.phase_timeout_for(&PbftPhase::PrePreparing),
+            Duration::from_millis(1000)
+        );
+        assert_eq!(
+            state.phase_timeout_for(&PbftPhase::Preparing),
+            Duration::from_millis(10)
+        );
+        assert_eq!(
+            state.phase_timeout_for(&PbftPhase::Checking),
+            Duration::from_millis(2000)
+        );
+        assert_eq!(
+            state.phase_timeout_for(&PbftPhase::Committing),
+            Duration::from_millis(3000)
+        );
+        assert_eq!(
+            state.phase_timeout_for(&PbftPhase::Finished),
+            Duration::from_millis(4000)
+        );
+
+        state.switch_phase(PbftPhase::Preparing).unwrap();
+        clock.advance(Duration::from_millis(10));
+        assert!(!state.check_phase_timeout_expired());
+        clock.advance(Duration::from_millis(1));
+        assert!(state.check_phase_timeout_expired());
+
+        state.switch_phase(PbftPhase::Checking).unwrap();
+        assert!(!state.check_phase_timeout_expired());
+    }
+
+    /// Make sure that a node that keeps getting view-changed away from is reported once it reaches
+    /// the strike threshold, without changing the primary rotation that every node must agree on
+    #[test]
+    fn faulty_primary_strikes() {
+        let mut config = mock_config(4);
+        config.faulty_primary_strike_threshold = 3;
+        let mut state = PbftState::new(vec![0], 0, &config);
+
+        for _ in 0..2 {
+            state.add_faulty_primary_strike(&[1]);
+            assert!(!state.is_chronically_faulty(&[1]));
+        }
+
+        state.add_faulty_primary_strike(&[1]);
+        assert_eq!(state.get_faulty_primary_strikes(&[1]), 3);
+        assert!(state.is_chronically_faulty(&[1]));
+        for view in 0..4 {
+            assert_eq!(state.get_primary_id_for_view(view), vec![view as u8]);
+        }
+
+        state.reinstate_primary(&[1]);
+        assert!(!state.is_chronically_faulty(&[1]));
 
-        assert!(state.switch_phase(PbftPhase::Finished).is_none());
-        assert!(state.switch_phase(PbftPhase::Checking).is_none());
+        // Nothing is reported when the threshold is disabled
+        state.faulty_primary_strike_threshold = 0;
+        for _ in 0..5 {
+            state.add_faulty_primary_strike(&[1]);
+        }
+        assert!(!state.is_chronically_faulty(&[1]));
+    }
+
+    /// Make sure that the invariant checker accepts a fresh state and flags inconsistent ones
+    #[test]
+    fn check_invariants() {
+        let cfg = mock_config(4);
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        assert!(state.check_invariants().is_ok());
+
+        // Primary role, but the view maps to another node
+        state.view = 1;
+        assert!(state.check_invariants().is_err());
+        state.downgrade_role();
+        assert!(state.check_invariants().is_ok());
+
+        // Past PrePreparing with no working block
+        state.phase = PbftPhase::Finished;
+        assert!(state.check_invariants().is_err());
+
+        let mut block = PbftBlock::new();
+        block.set_block_num(1);
+        state.working_block = Some(block);
+        assert!(state.check_invariants().is_ok());
+
+        // Working block doesn't match the sequence number
+        state.seq_num = 2;
+        assert!(state.check_invariants().is_err());
+    }
+
+    /// Make sure that the compact binary snapshot reconstructs the same state
+    #[test]
+    fn compact_round_trip() {
+        let cfg = mock_config(4);
+        let mut state = PbftState::new(vec![1], 10, &cfg);
+        state.view = 5;
+        state.phase = PbftPhase::Committing;
+        state.mode = PbftMode::ViewChanging;
+        state.upgrade_role();
+        let mut block = PbftBlock::new();
+        block.set_block_id(vec![1, 2, 3]);
+        block.set_block_num(11);
+        state.working_block = Some(block);
+        state.add_faulty_primary_strike(&[2]);
+        state.add_faulty_primary_strike(&[2]);
+        state.faulty_primary_timeout.start();
+        state.begin_view_change();
+        state.skip_view_change_target();
+        state.last_committed_block = Some(vec![9, 9]);
+        state.highest_seen_seq_num = 14;
+        state.catchup_target = Some(vec![4, 4]);
+        state.low_watermark = 8;
+        state.view_change_attempts = 3;
+        state
+            .faulty_primary_timeout
+            .set_duration(Duration::from_secs(120));
+        state
+            .view_change_timeout
+            .set_duration(Duration::from_secs(7));
+        state.commit_timeout.start();
+
+        let bytes = state.serialize_compact();
+        let restored = PbftState::deserialize_compact(&bytes, &cfg).unwrap();
+
+        assert_eq!(restored.id, state.id);
+        assert_eq!(restored.seq_num, state.seq_num);
+        assert_eq!(restored.view, state.view);
+        assert_eq!(restored.phase, state.phase);
+        assert_eq!(restored.is_primary(), state.is_primary());
+        assert_eq!(restored.mode, state.mode);
+        assert_eq!(restored.peer_ids, state.peer_ids);
+        assert_eq!(restored.f, state.f);
+        assert_eq!(restored.working_block, state.working_block);
+        assert_eq!(
+            restored.faulty_primary_strikes,
+            state.faulty_primary_strikes
+        );
+        assert!(restored.faulty_primary_timeout.is_running());
+        assert_eq!(restored.view_change_target(), state.view_change_target());
+        assert_eq!(restored.last_committed_block, state.last_committed_block);
+        assert_eq!(restored.highest_seen_seq_num, 14);
+        assert_eq!(restored.catchup_target, state.catchup_target);
+        assert_eq!(restored.low_watermark, 8);
+        assert_eq!(restored.view_change_attempts, 3);
+        assert_eq!(
+            restored.faulty_primary_timeout.duration(),
+            Duration::from_secs(120)
+        );
+        assert_eq!(
+            restored.view_change_timeout.duration(),
+            Duration::from_secs(7)
+        );
+        assert!(restored.commit_timeout.is_running());
+        assert!(!restored.block_publish_timeout.is_running());
+        assert_eq!(restored.serialize_compact(), bytes);
+
+        assert!(PbftState::deserialize_compact(&[0xff, 0xff], &cfg).is_err());
+    }
+
+    /// Benchmark the compact snapshot against the JSON form; the snapshot has to come out smaller
+    /// and quicker to produce
+    ///
+    /// Timing depends on the machine and how busy it is, so this only runs when asked for (with
+    /// `cargo test -- --ignored`).
+    #[test]
+    #[ignore]
+    fn bench_serialize_compact() {
+        const ROUNDS: u32 = 1000;
+        let cfg = mock_config(16);
+        let mut state = PbftState::new(vec![1], 10, &cfg);
+        let mut block = PbftBlock::new();
+        block.set_block_id(vec![7; 64]);
+        block.set_signer_id(vec![1; 33]);
+        block.set_block_num(11);
+        state.working_block = Some(block);
+        for peer in 0..16 {
+            state.add_faulty_primary_strike(&[peer]);
+        }
+
+        let compact = state.serialize_compact();
+        let json = serde_json::to_vec(&state).unwrap();
+        assert!(
+            compact.len() < json.len(),
+            "Snapshot is {} bytes, JSON is {}",
+            compact.len(),
+            json.len()
+        );
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            state.serialize_compact();
+        }
+        let compact_time = start.elapsed() / ROUNDS;
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            serde_json::to_vec(&state).unwrap();
+        }
+        let json_time = start.elapsed() / ROUNDS;
+        assert!(
+            compact_time < json_time,
+            "Snapshot took {:?}, JSON took {:?}",
+            compact_time,
+            json_time
+        );
+    }
+
+    /// Make sure that a state stored before the newer fields existed can still be loaded, and
+    /// that it gets its settings and timer durations from the configuration
+    #[test]
+    fn load_old_format() {
+        let mut cfg = mock_config(4);
+        cfg.network_id = "test-net".into();
+        let state = PbftState::new(vec![1], 10, &cfg);
+        cfg.watermark_window = 8;
+
+        let old_fields = [
+            "id",
+            "seq_num",
+            "view",
+            "phase",
+            "role",
+            "mode",
+            "peer_ids",
+            "f",
+            "faulty_primary_timeout",
+            "forced_view_change_period",
+            "working_block",
+        ];
+        let mut value = serde_json::to_value(&state).unwrap();
+        value
+            .as_object_mut()
+            .unwrap()
+            .retain(|field, _| old_fields.contains(&field.as_str()));
+        let mut loaded: PbftState = serde_json::from_value(value).unwrap();
+        loaded.apply_config(&cfg);
+
+        assert_eq!(loaded.seq_num, state.seq_num);
+        assert_eq!(loaded.peer_ids, state.peer_ids);
+        assert_eq!(loaded.network_id, "test-net");
+        assert_eq!(loaded.watermark_window, cfg.watermark_window);
+        assert_eq!(
+            loaded.phase_timeouts.committing,
+            cfg.phase_timeouts.committing
+        );
+        assert_eq!(loaded.commit_timeout.duration(), cfg.commit_timeout);
+        assert_eq!(
+            loaded.view_change_timeout.duration(),
+            cfg.view_change_duration
+        );
+        assert_eq!(
+            loaded.phase_timeout.duration(),
+            loaded.phase_timeout_for(&loaded.phase)
+        );
+    }
+
+    /// Make sure that a loaded state's peers are put back in order, and that its role follows
+    /// from the sorted peers
+    #[test]
+    fn apply_config_sorts_peers() {
+        let cfg = mock_config(4);
+        let mut state = PbftState::new(vec![0], 0, &cfg);
+        assert!(state.is_primary());
+
+        state.peer_ids.reverse();
+        state.upgrade_role();
+        state.apply_config(&cfg);
+
+        assert_eq!(state.peer_ids, cfg.peers);
+        assert!(state.is_primary());
+
+        let mut state = PbftState::new(vec![3], 0, &cfg);
+        state.peer_ids.reverse();
+        state.upgrade_role();
+        state.apply_config(&cfg);
+
+        assert_eq!(state.peer_ids, cfg.peers);
+        assert!(!state.is_primary());
+    }
+
+    /// The time spent in a view should grow while the view stays the same, and reset when it
+    /// changes
+    #[test]
+    fn elapsed_in_view() {
+        let config = mock_config(4);
+        let mut state = PbftState::new(vec![0], 0, &config);
+        let clock = MockClock::new();
+        state.set_clock(clock.clone());
+
+        clock.advance(Duration::from_millis(20));
+        let before = state.elapsed_in_view();
+        assert!(before >= Duration::from_millis(20));
+
+        // Setting the same view doesn't reset the timer
+        state.set_view(0);
+        assert_eq!(state.elapsed_in_view(), before);
+
+        state.set_view(1);
+        assert_eq!(state.view, 1);
+        assert_eq!(state.elapsed_in_view(), Duration::from_secs(0));
+    }
+
+    /// Make sure that the faulty primary timeout can't expire while it's paused for a view change,
+    /// and that the new view's primary gets all of it rather than what the old one left
+    #[test]
+    fn faulty_primary_timeout_view_change() {
+        let config = mock_config(4);
+        let mut state = PbftState::new(vec![1], 0, &config);
+        let clock = MockClock::new();
+        state.set_clock(clock.clone());
+
+        state.faulty_primary_timeout.start();
+        clock.advance(config.faulty_primary_timeout / 2);
+        state.begin_view_change();
+        state.faulty_primary_timeout.pause();
+        assert!(!state.faulty_primary_timeout.is_running());
+
+        clock.advance(config.faulty_primary_timeout);
+        assert!(!state.faulty_primary_timeout.check_expired());
+
+        state.discard_current_block();
+        assert!(state.faulty_primary_timeout.is_running());
+        assert_eq!(
+            state.faulty_primary_timeout.remaining(),
+            config.faulty_primary_timeout
+        );
+    }
+
+    /// Make sure that `f` rounds down for networks that aren't exactly `3f + 1` nodes, and that
+    /// networks too small to tolerate a fault are an error
+    #[test]
+    fn compute_f_for_network_sizes() {
+        match compute_f(1) {
+            Err(PbftError::InsufficientPeers(1)) => {}
+            res => panic!("Expected InsufficientPeers, got {:?}", res),
+        }
+        assert_eq!(compute_f(4).unwrap(), 1);
+        assert_eq!(compute_f(5).unwrap(), 1);
+        assert_eq!(compute_f(7).unwrap(), 2);
+        assert_eq!(compute_f(10).unwrap(), 3);
     }
 }
@@ -17,38 +17,182 @@
 
 //! Timing-related structures
 
-use std::time::{Duration, Instant};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use serde_millis;
 
+/// Source of the current time for timers
+///
+/// Timers use the `SystemClock` unless they're given another one, which lets tests move time
+/// forward without sleeping.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The real monotonic clock
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Get a handle to the real monotonic clock
+pub fn system_clock() -> Arc<Clock> {
+    Arc::new(SystemClock)
+}
+
+/// A clock that only moves when it's told to
+#[cfg(test)]
+#[derive(Debug)]
+pub(crate) struct MockClock {
+    now: std::sync::Mutex<Instant>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> Arc<Self> {
+        Arc::new(MockClock {
+            now: std::sync::Mutex::new(Instant::now()),
+        })
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Get the current wall-clock time, in milliseconds since the UNIX epoch
+pub fn unix_millis() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0));
+    since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_millis())
+}
+
+/// Watches for the wall clock moving differently from the monotonic clock
+///
+/// Between two checks, both clocks should advance by the same amount. If they don't, the system
+/// was suspended, or its wall clock was stepped (for instance by NTP), and timeouts or message
+/// timestamps may misbehave around that point.
+pub struct ClockDriftDetector {
+    threshold: Duration,
+    last: Option<(Instant, u64)>,
+    last_drift: Option<i64>,
+}
+
+impl ClockDriftDetector {
+    pub fn new(threshold: Duration) -> Self {
+        ClockDriftDetector {
+            threshold,
+            last: None,
+            last_drift: None,
+        }
+    }
+
+    /// Compare both clocks against the previous check
+    pub fn check(&mut self) -> Option<i64> {
+        self.check_at(Instant::now(), unix_millis())
+    }
+
+    /// Compare the given monotonic and wall-clock (ms since the epoch) times against the previous
+    /// check. Returns how far the wall clock moved beyond the monotonic clock, in ms, if that's
+    /// more than the threshold.
+    pub fn check_at(&mut self, now: Instant, now_millis: u64) -> Option<i64> {
+        let previous = self.last.replace((now, now_millis));
+        let (last, last_millis) = previous?;
+
+        let monotonic = now - last;
+        let monotonic_millis =
+            (monotonic.as_secs() * 1000 + u64::from(monotonic.subsec_millis())) as i64;
+        let drift = now_millis as i64 - last_millis as i64 - monotonic_millis;
+
+        let threshold =
+            (self.threshold.as_secs() * 1000 + u64::from(self.threshold.subsec_millis())) as i64;
+        if drift.abs() > threshold {
+            warn!(
+                "Wall clock moved {} ms more than the monotonic clock since the last check; \
+                 timeouts may fire early or late",
+                drift
+            );
+            self.last_drift = Some(drift);
+            Some(drift)
+        } else {
+            None
+        }
+    }
+
+    /// The most recent drift that exceeded the threshold, in ms, if there has been one
+    pub fn last_drift(&self) -> Option<i64> {
+        self.last_drift
+    }
+}
+
 /// Encapsulates calling a function every so often
 pub struct Ticker {
     last: Instant,
     timeout: Duration,
+    clock: Arc<Clock>,
 }
 
 impl Ticker {
     pub fn new(period: Duration) -> Self {
+        Self::with_clock(period, system_clock())
+    }
+
+    /// Create a ticker that gets the time from the given clock
+    pub fn with_clock(period: Duration, clock: Arc<Clock>) -> Self {
         Ticker {
-            last: Instant::now(),
+            last: clock.now(),
             timeout: period,
+            clock,
         }
     }
 
     // Do some work if the timeout has expired
     pub fn tick<T: FnMut()>(&mut self, mut callback: T) {
-        let elapsed = Instant::now() - self.last;
+        let elapsed = self.clock.now() - self.last;
         if elapsed >= self.timeout {
             callback();
-            self.last = Instant::now();
+            self.last = self.clock.now();
         }
     }
+
+    /// Restart the current interval from now, delaying the next tick
+    pub fn reset(&mut self) {
+        self.last = self.clock.now();
+    }
+
+    /// Change the interval between ticks; takes effect from the next call to `tick`
+    ///
+    /// The current interval isn't restarted, so if the new period is shorter than the time since
+    /// the last tick, the next call to `tick` fires right away.
+    pub fn set_period(&mut self, period: Duration) {
+        self.timeout = period;
+    }
+
+    /// Get the current interval between ticks
+    pub fn period(&self) -> Duration {
+        self.timeout
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 enum TimeoutState {
     Active,
     Inactive,
+    Paused,
     Expired,
 }
 
@@ -60,36 +204,155 @@ pub struct Timeout {
     duration: Duration,
     #[serde(with = "serde_millis")]
     start: Instant,
+    /// How long the timer had been running when it was paused
+    #[serde(default)]
+    elapsed: Duration,
+    /// How far `extend` has pushed the deadline back since the timer was started
+    #[serde(default)]
+    extension: Duration,
+    #[serde(skip, default = "system_clock")]
+    clock: Arc<Clock>,
+}
+
+/// A stopped timer that lasts no time at all, for states stored before the timer existed; the
+/// real duration is filled in from the configuration when the state is loaded
+impl Default for Timeout {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(0))
+    }
 }
 
 impl Timeout {
     pub fn new(duration: Duration) -> Self {
+        Self::with_clock(duration, system_clock())
+    }
+
+    /// Create a timer that gets the time from the given clock
+    pub fn with_clock(duration: Duration, clock: Arc<Clock>) -> Self {
         Timeout {
             state: TimeoutState::Inactive,
             duration,
-            start: Instant::now(),
+            start: clock.now(),
+            elapsed: Duration::from_secs(0),
+            extension: Duration::from_secs(0),
+            clock,
         }
     }
 
     /// Update the timer state, and check if the timer is expired
     pub fn check_expired(&mut self) -> bool {
-        if self.state == TimeoutState::Active && Instant::now() - self.start > self.duration {
+        if self.state == TimeoutState::Active && self.clock.now() - self.start > self.deadline() {
             self.state = TimeoutState::Expired;
         }
         match self.state {
-            TimeoutState::Active | TimeoutState::Inactive => false,
+            TimeoutState::Active | TimeoutState::Inactive | TimeoutState::Paused => false,
             TimeoutState::Expired => true,
         }
     }
 
     pub fn start(&mut self) {
         self.state = TimeoutState::Active;
-        self.start = Instant::now();
+        self.start = self.clock.now();
+        self.elapsed = Duration::from_secs(0);
+        self.extension = Duration::from_secs(0);
+    }
+
+    /// Get the time from the given clock from now on; time that has already passed on the timer
+    /// carries over
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        let elapsed = self.clock.now() - self.start;
+        self.start = clock.now() - elapsed;
+        self.clock = clock;
     }
 
     pub fn stop(&mut self) {
         self.state = TimeoutState::Inactive;
-        self.start = Instant::now();
+        self.start = self.clock.now();
+        self.elapsed = Duration::from_secs(0);
+        self.extension = Duration::from_secs(0);
+    }
+
+    /// Push the deadline back by `by`, keeping the time that has already elapsed; does nothing
+    /// unless the timer is active
+    ///
+    /// Unlike `start`, this doesn't reset the timer, so it's meant for deadlines that should slide
+    /// forward a bit on partial progress. The extension lasts until the timer is next started or
+    /// stopped.
+    pub fn extend(&mut self, by: Duration) {
+        if self.state == TimeoutState::Active {
+            self.extension += by;
+        } else {
+            debug!("Not extending timeout in state {:?}", self.state);
+        }
+    }
+
+    /// Stop counting, but remember how long the timer has been running so `resume` can pick up
+    /// from there; does nothing unless the timer is active
+    pub fn pause(&mut self) {
+        if self.state == TimeoutState::Active {
+            self.elapsed = self.clock.now() - self.start;
+            self.state = TimeoutState::Paused;
+        }
+    }
+
+    /// Continue counting from where the timer was paused; does nothing unless it's paused
+    pub fn resume(&mut self) {
+        if self.state == TimeoutState::Paused {
+            self.start = self.clock.now() - self.elapsed;
+            self.elapsed = Duration::from_secs(0);
+            self.state = TimeoutState::Active;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == TimeoutState::Paused
+    }
+
+    /// Tell if the timer has been started and not stopped or paused since (it may have expired)
+    pub fn is_running(&self) -> bool {
+        self.state == TimeoutState::Active || self.state == TimeoutState::Expired
+    }
+
+    /// Get the time the timer was last started, if it's running
+    pub fn started_at(&self) -> Option<Instant> {
+        if self.is_running() {
+            Some(self.start)
+        } else {
+            None
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// How long after being started the timer expires, including any extensions
+    fn deadline(&self) -> Duration {
+        self.duration + self.extension
+    }
+
+    /// Change how long the timer lasts; takes effect from the next call to `start`
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration = duration;
+    }
+
+    /// Get how long is left until the timer expires, without updating its state
+    ///
+    /// An inactive timer has its full duration remaining; an expired one has none; a paused one
+    /// has whatever was left when it was paused.
+    pub fn remaining(&self) -> Duration {
+        match self.state {
+            TimeoutState::Active => self
+                .deadline()
+                .checked_sub(self.clock.now() - self.start)
+                .unwrap_or_else(|| Duration::from_secs(0)),
+            TimeoutState::Expired => Duration::from_secs(0),
+            TimeoutState::Inactive => self.duration,
+            TimeoutState::Paused => self
+                .deadline()
+                .checked_sub(self.elapsed)
+                .unwrap_or_else(|| Duration::from_secs(0)),
+        }
     }
 }
 
@@ -131,6 +394,68 @@ mod tests {
         assert_tolerance!(end_time - start_time, time, Duration::from_millis(1));
     }
 
+    /// Shorten and lengthen the ticker's period, and make sure the next tick fires accordingly
+    #[test]
+    fn ticker_set_period() {
+        let mut t = Ticker::new(Duration::from_secs(60));
+        t.set_period(Duration::from_millis(0));
+        assert_eq!(t.period(), Duration::from_millis(0));
+
+        let mut triggered = false;
+        t.tick(|| triggered = true);
+        assert!(triggered);
+
+        t.set_period(Duration::from_secs(60));
+        triggered = false;
+        t.tick(|| triggered = true);
+        assert!(!triggered);
+    }
+
+    /// Shorten the ticker's period partway through an interval, to less than the time that has
+    /// already passed, and make sure the next tick fires without waiting for a new interval
+    #[test]
+    fn ticker_shorten_mid_interval() {
+        let clock = MockClock::new();
+        let mut t = Ticker::with_clock(Duration::from_secs(60), clock.clone());
+        clock.advance(Duration::from_millis(30));
+
+        let mut triggered = false;
+        t.tick(|| triggered = true);
+        assert!(!triggered);
+
+        t.set_period(Duration::from_millis(20));
+        t.tick(|| triggered = true);
+        assert!(triggered);
+
+        // The interval restarts after the tick
+        triggered = false;
+        t.tick(|| triggered = true);
+        assert!(!triggered);
+    }
+
+    /// Make sure that resetting the ticker pushes back the next tick
+    #[test]
+    fn ticker_reset() {
+        let clock = MockClock::new();
+        let mut t = Ticker::with_clock(Duration::from_millis(50), clock.clone());
+        clock.advance(Duration::from_millis(30));
+        t.reset();
+        clock.advance(Duration::from_millis(30));
+
+        // 60ms have passed since the ticker was created, but only 30ms since it was reset
+        let mut triggered = false;
+        t.tick(|| triggered = true);
+        assert!(!triggered);
+
+        clock.advance(Duration::from_millis(19));
+        t.tick(|| triggered = true);
+        assert!(!triggered);
+
+        clock.advance(Duration::from_millis(1));
+        t.tick(|| triggered = true);
+        assert!(triggered);
+    }
+
     /// Create a Timeout that lasts for 100ms and check that it expires anytime after 100ms have
     /// passed. Check whether `.start()` and `.stop()` work as expected.
     #[test]
@@ -150,4 +475,118 @@ mod tests {
         t.stop();
         assert_eq!(t.state, TimeoutState::Inactive);
     }
+
+    /// Check the time remaining on an inactive, running, and expired Timeout
+    #[test]
+    fn timeout_remaining() {
+        let clock = MockClock::new();
+        let mut t = Timeout::with_clock(Duration::from_millis(100), clock.clone());
+        assert_eq!(t.remaining(), Duration::from_millis(100));
+
+        t.start();
+        clock.advance(Duration::from_millis(40));
+        assert_eq!(t.remaining(), Duration::from_millis(60));
+        assert_eq!(t.state, TimeoutState::Active);
+
+        clock.advance(Duration::from_millis(70));
+        assert_eq!(t.remaining(), Duration::from_secs(0));
+        assert!(t.check_expired());
+        assert_eq!(t.remaining(), Duration::from_secs(0));
+
+        t.stop();
+        assert_eq!(t.remaining(), Duration::from_millis(100));
+    }
+
+    /// Pause a running Timeout while time passes, and make sure the time spent paused doesn't count
+    /// towards it expiring
+    #[test]
+    fn timeout_pause_resume() {
+        let clock = MockClock::new();
+        let mut t = Timeout::with_clock(Duration::from_millis(100), clock.clone());
+        t.start();
+        clock.advance(Duration::from_millis(40));
+
+        t.pause();
+        assert!(t.is_paused());
+        assert!(!t.is_running());
+        assert_eq!(t.remaining(), Duration::from_millis(60));
+
+        clock.advance(Duration::from_millis(80));
+        assert!(!t.check_expired());
+        assert_eq!(t.remaining(), Duration::from_millis(60));
+
+        t.resume();
+        assert_eq!(t.state, TimeoutState::Active);
+        assert_eq!(t.remaining(), Duration::from_millis(60));
+        assert!(!t.check_expired());
+
+        clock.advance(Duration::from_millis(60));
+        assert!(!t.check_expired());
+        clock.advance(Duration::from_millis(1));
+        assert!(t.check_expired());
+
+        // Only an active timer can be paused, and only a paused one resumed
+        t.pause();
+        assert!(t.check_expired());
+        t.stop();
+        t.resume();
+        assert_eq!(t.state, TimeoutState::Inactive);
+    }
+
+    /// Extend a running Timeout, and make sure it no longer expires at its original deadline but
+    /// still expires at the extended one; extending a timer that isn't active does nothing
+    #[test]
+    fn timeout_extend() {
+        let clock = MockClock::new();
+        let mut t = Timeout::with_clock(Duration::from_millis(100), clock.clone());
+        t.start();
+        clock.advance(Duration::from_millis(40));
+
+        t.extend(Duration::from_millis(100));
+        assert_eq!(t.remaining(), Duration::from_millis(160));
+
+        clock.advance(Duration::from_millis(80));
+        assert!(!t.check_expired());
+
+        clock.advance(Duration::from_millis(81));
+        assert!(t.check_expired());
+
+        // Expired and inactive timers aren't extended, and restarting drops the extension
+        t.extend(Duration::from_millis(100));
+        assert!(t.check_expired());
+        t.stop();
+        t.extend(Duration::from_millis(100));
+        assert_eq!(t.remaining(), Duration::from_millis(100));
+        t.start();
+        assert_eq!(t.remaining(), Duration::from_millis(100));
+    }
+
+    /// Simulate the wall clock jumping ahead and back between checks, and make sure the drift is
+    /// only reported once it's past the threshold
+    #[test]
+    fn clock_drift() {
+        let mut detector = ClockDriftDetector::new(Duration::from_millis(500));
+        let start = Instant::now();
+
+        assert_eq!(detector.check_at(start, 10_000), None);
+        assert_eq!(
+            detector.check_at(start + Duration::from_secs(1), 11_100),
+            None
+        );
+        assert_eq!(detector.last_drift(), None);
+
+        // The wall clock moves 5s while only 1s passes
+        assert_eq!(
+            detector.check_at(start + Duration::from_secs(2), 16_100),
+            Some(4000)
+        );
+        assert_eq!(detector.last_drift(), Some(4000));
+
+        // The wall clock is stepped back
+        assert_eq!(
+            detector.check_at(start + Duration::from_secs(3), 15_100),
+            Some(-2000)
+        );
+        assert_eq!(detector.last_drift(), Some(-2000));
+    }
 }
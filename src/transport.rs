@@ -0,0 +1,49 @@
+/*
+ * Copyright 2018 Bitwise IO, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! How a node sends messages to its peers
+
+use sawtooth_sdk::consensus::engine::{Error, PeerId};
+use sawtooth_sdk::consensus::service::Service;
+
+/// Sends consensus messages to peers
+///
+/// By default a node sends messages through its validator `Service`; another transport can be
+/// given to the node with `PbftNode::set_transport`, for instance to connect nodes in memory.
+pub trait MessageTransport {
+    /// Send a message to every peer
+    fn broadcast(&mut self, message_type: &str, payload: Vec<u8>) -> Result<(), Error>;
+
+    /// Send a message to a single peer
+    fn send_to(&mut self, peer: &PeerId, message_type: &str, payload: Vec<u8>)
+        -> Result<(), Error>;
+}
+
+impl MessageTransport for Box<Service> {
+    fn broadcast(&mut self, message_type: &str, payload: Vec<u8>) -> Result<(), Error> {
+        Service::broadcast(&mut **self, message_type, payload)
+    }
+
+    fn send_to(
+        &mut self,
+        peer: &PeerId,
+        message_type: &str,
+        payload: Vec<u8>,
+    ) -> Result<(), Error> {
+        Service::send_to(&mut **self, peer, message_type, payload)
+    }
+}